@@ -4,7 +4,7 @@ use clap::{Parser, Subcommand, ValueEnum};
 use futures_util::StreamExt;
 use reqwest::Client;
 use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
-use rip_kernel::Event as FrameEvent;
+use rip_kernel::{Event as FrameEvent, EventKind as FrameKind};
 use rip_provider_openresponses::{
     extract_reasoning_deltas, extract_text_deltas, extract_tool_call_argument_deltas,
 };
@@ -33,9 +33,35 @@ enum Commands {
         headless: bool,
         #[arg(long, value_enum, default_value_t = OutputView::Raw)]
         view: OutputView,
+        /// Start the session in plan mode (read-only tools only).
+        #[arg(long)]
+        plan: bool,
+    },
+    /// Switch a running session between plan and default mode.
+    Mode {
+        session_id: String,
+        #[arg(value_enum)]
+        mode: SessionMode,
+        #[arg(long, default_value = "http://127.0.0.1:7341")]
+        server: String,
     },
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+enum SessionMode {
+    Default,
+    Plan,
+}
+
+impl SessionMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            SessionMode::Default => "default",
+            SessionMode::Plan => "plan",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
 enum OutputView {
     Raw,
@@ -59,20 +85,36 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
             server,
             headless,
             view,
+            plan,
         } => {
             if !headless {
                 eprintln!("interactive mode not implemented; falling back to headless");
             }
-            run_headless(prompt, server, view).await?;
+            run_headless(prompt, server, view, plan).await?;
+        }
+        Commands::Mode {
+            session_id,
+            mode,
+            server,
+        } => {
+            set_mode(&Client::new(), &server, &session_id, mode).await?;
         }
     }
 
     Ok(())
 }
 
-async fn run_headless(prompt: String, server: String, view: OutputView) -> anyhow::Result<()> {
+async fn run_headless(
+    prompt: String,
+    server: String,
+    view: OutputView,
+    plan: bool,
+) -> anyhow::Result<()> {
     let client = Client::new();
     let session_id = create_session(&client, &server).await?;
+    if plan {
+        set_mode(&client, &server, &session_id, SessionMode::Plan).await?;
+    }
     send_input(&client, &server, &session_id, &prompt).await?;
     stream_events(&client, &server, &session_id, view).await?;
     Ok(())
@@ -108,13 +150,34 @@ async fn send_input(
     Ok(())
 }
 
+async fn set_mode(
+    client: &Client,
+    server: &str,
+    session_id: &str,
+    mode: SessionMode,
+) -> anyhow::Result<()> {
+    let url = format!("{server}/sessions/{session_id}/mode");
+    let response = client
+        .post(url)
+        .json(&serde_json::json!({ "mode": mode.as_str() }))
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("set mode failed: {status}");
+    }
+    Ok(())
+}
+
 async fn stream_events(
     client: &Client,
     server: &str,
     session_id: &str,
     view: OutputView,
 ) -> anyhow::Result<()> {
-    let url = format!("{server}/sessions/{session_id}/events");
+    // From the first frame, so nothing sent before the stream opened is lost;
+    // reconnects resume after the last `id` via `Last-Event-ID`.
+    let url = format!("{server}/sessions/{session_id}/events?from_seq=0");
     let mut stream = client.get(url).eventsource()?;
     let stdout = io::stdout();
     let mut handle = stdout.lock();
@@ -122,8 +185,14 @@ async fn stream_events(
     while let Some(next) = stream.next().await {
         match next {
             Ok(Event::Open) => {}
+            // `lagged` notices are followed by the frames they cover.
+            Ok(Event::Message(msg)) if msg.event != "message" => {}
             Ok(Event::Message(msg)) => {
-                render_message(view, &msg.data, &mut handle)?;
+                let frame = render_message(view, &msg.data, &mut handle)?;
+                // The stream stays open for later inputs; this run is over.
+                if matches!(frame.kind, FrameKind::SessionEnded { .. }) {
+                    break;
+                }
             }
             Err(EventSourceError::StreamEnded) => break,
             Err(err) => return Err(err.into()),
@@ -133,7 +202,11 @@ async fn stream_events(
     Ok(())
 }
 
-fn render_message(view: OutputView, payload: &str, out: &mut dyn Write) -> anyhow::Result<()> {
+fn render_message(
+    view: OutputView,
+    payload: &str,
+    out: &mut dyn Write,
+) -> anyhow::Result<FrameEvent> {
     let frame: FrameEvent = serde_json::from_str(payload)
         .map_err(|err| anyhow::anyhow!("invalid event frame: {err}"))?;
 
@@ -155,7 +228,7 @@ fn render_message(view: OutputView, payload: &str, out: &mut dyn Write) -> anyho
             out.flush()?;
         }
     }
-    Ok(())
+    Ok(frame)
 }
 
 #[cfg(test)]
@@ -237,6 +310,26 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn stream_events_replays_from_start_and_skips_notices() {
+        let server = MockServer::start();
+        let payload = session_started_frame();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/sessions/s1/events")
+                .query_param("from_seq", "0");
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(format!(
+                    "event: lagged\ndata: {{\"missed\":1,\"replay_from\":0}}\n\nid: 0\ndata: {payload}\n\n"
+                ));
+        });
+        let client = Client::new();
+        let result = stream_events(&client, &server.base_url(), "s1", OutputView::Raw).await;
+        assert!(result.is_ok());
+        mock.assert();
+    }
+
     #[tokio::test]
     async fn run_headless_with_interactive_flag() {
         let server = MockServer::start();
@@ -263,6 +356,7 @@ mod tests {
                 server: server.base_url(),
                 headless: false,
                 view: OutputView::Raw,
+                plan: false,
             },
         };
         let result = run(cli).await;
@@ -274,6 +368,7 @@ mod tests {
         let cli = Cli::parse_from(["rip", "run", "hello"]);
         match cli.command {
             Commands::Run { prompt, .. } => assert_eq!(prompt, "hello"),
+            _ => panic!("expected run"),
         }
     }
 
@@ -285,6 +380,7 @@ mod tests {
                 assert!(headless);
                 assert_eq!(view, OutputView::Raw);
             }
+            _ => panic!("expected run"),
         }
     }
 
@@ -293,6 +389,7 @@ mod tests {
         let cli = Cli::parse_from(["rip", "run", "hello", "--server", "http://local"]);
         match cli.command {
             Commands::Run { server, .. } => assert_eq!(server, "http://local"),
+            _ => panic!("expected run"),
         }
     }
 
@@ -384,6 +481,74 @@ mod tests {
         let cli = Cli::parse_from(["rip", "run", "hello", "--headless", "false"]);
         match cli.command {
             Commands::Run { headless, .. } => assert!(!headless),
+            _ => panic!("expected run"),
         }
     }
+
+    #[test]
+    fn cli_parses_mode() {
+        let cli = Cli::parse_from(["rip", "mode", "s1", "plan"]);
+        match cli.command {
+            Commands::Mode {
+                session_id, mode, ..
+            } => {
+                assert_eq!(session_id, "s1");
+                assert_eq!(mode, SessionMode::Plan);
+            }
+            _ => panic!("expected mode"),
+        }
+    }
+
+    #[tokio::test]
+    async fn run_with_plan_sets_mode_before_input() {
+        let server = MockServer::start();
+        let _create = server.mock(|when, then| {
+            when.method(POST).path("/sessions");
+            then.status(201)
+                .header("content-type", "application/json")
+                .body(r#"{"session_id":"abc"}"#);
+        });
+        let mode = server.mock(|when, then| {
+            when.method(POST)
+                .path("/sessions/abc/mode")
+                .json_body(serde_json::json!({ "mode": "plan" }));
+            then.status(204);
+        });
+        let _input = server.mock(|when, then| {
+            when.method(POST).path("/sessions/abc/input");
+            then.status(202);
+        });
+        let _events = server.mock(|when, then| {
+            when.method(GET).path("/sessions/abc/events");
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(format!("data: {}\n\n", session_started_frame()));
+        });
+
+        let cli = Cli::parse_from([
+            "rip",
+            "run",
+            "hello",
+            "--plan",
+            "--server",
+            &server.base_url(),
+        ]);
+        run(cli).await.expect("run");
+        mode.assert();
+    }
+
+    #[tokio::test]
+    async fn set_mode_failure() {
+        let server = MockServer::start();
+        let _mock = server.mock(|when, then| {
+            when.method(POST).path("/sessions/s1/mode");
+            then.status(404);
+        });
+
+        let client = Client::new();
+        let err = set_mode(&client, &server.base_url(), "s1", SessionMode::Default)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("set mode failed"));
+    }
 }
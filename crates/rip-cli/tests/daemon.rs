@@ -0,0 +1,135 @@
+//! `rip run` end to end against a real `ripd` backed by a scripted provider.
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+const ANSWER: &str = "event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"m\",\"output_index\":0,\"content_index\":0,\"delta\":\"hello from ripd\"}\n\n\
+data: [DONE]\n\n";
+
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Builds `ripd` into the target dir `rip` was built in and returns its path.
+fn ripd_binary() -> PathBuf {
+    let rip = PathBuf::from(env!("CARGO_BIN_EXE_rip"));
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut build = Command::new(cargo);
+    build.args(["build", "--quiet", "-p", "ripd", "--bin", "ripd"]);
+    if rip.parent().and_then(Path::file_name) == Some("release".as_ref()) {
+        build.arg("--release");
+    }
+    let status = build.status().expect("run cargo");
+    assert!(status.success(), "building ripd failed");
+    rip.with_file_name(format!("ripd{}", std::env::consts::EXE_SUFFIX))
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    listener.local_addr().expect("addr").to_string()
+}
+
+fn start_ripd(root: &Path, addr: &str) -> Daemon {
+    let script = root.join("script.json");
+    std::fs::write(&script, json!({ "turns": [{ "sse": ANSWER }] }).to_string())
+        .expect("write script");
+    let workspace = root.join("workspace");
+    std::fs::create_dir_all(&workspace).expect("workspace");
+
+    let mut command = Command::new(ripd_binary());
+    // Only this test's config: no user file, no inherited `RIP_*` layer.
+    for (key, _) in std::env::vars() {
+        if key.starts_with("RIP_") {
+            command.env_remove(key);
+        }
+    }
+    command
+        .current_dir(root)
+        .env("HOME", root)
+        .env("XDG_CONFIG_HOME", root.join("config"))
+        .env("RIP_PROVIDER_SCRIPT", &script)
+        .arg("--bind")
+        .arg(addr)
+        .arg("--data-dir")
+        .arg(root.join("data"))
+        .arg("--workspace-root")
+        .arg(&workspace)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut daemon = Daemon(command.spawn().expect("spawn ripd"));
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while TcpStream::connect(addr).is_err() {
+        if let Some(status) = daemon.0.try_wait().expect("ripd status") {
+            panic!("ripd exited early: {status}");
+        }
+        assert!(Instant::now() < deadline, "ripd did not start listening");
+        sleep(Duration::from_millis(50));
+    }
+    daemon
+}
+
+fn run_with_timeout(command: &mut Command, limit: Duration) -> Output {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn rip");
+    let deadline = Instant::now() + limit;
+    while child.try_wait().expect("rip status").is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("rip did not exit within {limit:?}");
+        }
+        sleep(Duration::from_millis(50));
+    }
+    child.wait_with_output().expect("rip output")
+}
+
+#[test]
+fn run_streams_a_scripted_answer_from_ripd() {
+    let dir = tempfile::tempdir().expect("tmp");
+    let addr = free_addr();
+    let _daemon = start_ripd(dir.path(), &addr);
+
+    let output = run_with_timeout(
+        Command::new(env!("CARGO_BIN_EXE_rip")).args([
+            "run",
+            "say hi",
+            "--server",
+            &format!("http://{addr}"),
+            "--view",
+            "output",
+        ]),
+        Duration::from_secs(30),
+    );
+    assert!(
+        output.status.success(),
+        "rip failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.trim(), "hello from ripd");
+
+    let log = std::fs::read_to_string(dir.path().join("data").join("events.jsonl")).expect("log");
+    let frames: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("frame"))
+        .collect();
+    assert_eq!(frames[0]["type"], "session_started");
+    assert_eq!(frames[0]["input"], "say hi");
+    let last = frames.last().expect("frames");
+    assert_eq!(last["type"], "session_ended");
+    assert_eq!(last["reason"], "completed");
+}
@@ -0,0 +1,724 @@
+//! Layered configuration for ripd.
+//!
+//! Values come from, lowest to highest precedence: built-in defaults, the
+//! user file (`~/.config/rip/config.toml`), the project file
+//! (`.rip/config.toml`), `RIP_*` environment variables and command-line
+//! overrides. Tables merge key by key; any other value (arrays included)
+//! replaces what a lower layer set. Every value remembers the layer that set
+//! it, so `ripd --print-config` can explain where a setting came from.
+//!
+//! The project file travels with the repository, so unless the project is
+//! trusted it cannot set the keys that decide what tools may do
+//! (`PROJECT_PROTECTED_KEYS`); such values are ignored and reported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use rip_tools::{PermissionConfig, ResourceLimits};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const DEFAULT_BIND: &str = "127.0.0.1:7341";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RipConfig {
+    pub server: ServerConfig,
+    /// Where `events.jsonl` and session snapshots are written.
+    pub data_dir: PathBuf,
+    /// Root the builtin tools work in; defaults to the current directory.
+    pub workspace_root: Option<PathBuf>,
+    /// Default model; overrides the one in the provider router config.
+    pub model: Option<String>,
+    pub provider: ProviderConfig,
+    pub tools: ToolsConfig,
+    pub permissions: PermissionConfig,
+}
+
+impl Default for RipConfig {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            data_dir: PathBuf::from("data"),
+            workspace_root: None,
+            model: None,
+            provider: ProviderConfig::default(),
+            tools: ToolsConfig::default(),
+            permissions: PermissionConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server listens on.
+    pub bind: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.to_string(),
+        }
+    }
+}
+
+/// Where model providers come from; without either ripd runs the built-in
+/// echo loop.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// JSON provider router config.
+    pub config: Option<PathBuf>,
+    /// `ScriptedProvider` JSON script (offline, deterministic); takes
+    /// precedence over `config`.
+    pub script: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// Tool calls that may run at once.
+    pub concurrency: usize,
+    /// Output budget per call, in bytes; unset keeps the builtin default.
+    pub max_bytes: Option<usize>,
+    /// Entries `ls`/`glob`/`grep` return at most.
+    pub max_results: Option<usize>,
+    /// Deepest directory level walkers descend to.
+    pub max_depth: Option<usize>,
+    /// Milliseconds a `bash` call may run unless it passes its own
+    /// `timeout_ms` (default: ten minutes).
+    pub timeout_ms: Option<u64>,
+    /// Keep one `bash` shell per session.
+    pub persistent_shell: bool,
+    /// Sandbox profile for shell tools (`workspace-write`,
+    /// `workspace-write-network` or `off`).
+    pub sandbox: Option<String>,
+    /// Resource limits keyed by tool name (`bash`, `bash_background`).
+    pub limits: BTreeMap<String, ResourceLimits>,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            max_bytes: None,
+            max_results: None,
+            max_depth: None,
+            timeout_ms: Some(600_000),
+            persistent_shell: false,
+            sandbox: None,
+            limits: BTreeMap::new(),
+        }
+    }
+}
+
+/// The layer a value came from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "layer", rename_all = "snake_case")]
+pub enum ConfigSource {
+    Default,
+    User { path: PathBuf },
+    Project { path: PathBuf },
+    Env { var: String },
+    Cli { flag: String },
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::User { path } => write!(f, "user config {}", path.display()),
+            ConfigSource::Project { path } => write!(f, "project config {}", path.display()),
+            ConfigSource::Env { var } => write!(f, "env {var}"),
+            ConfigSource::Cli { flag } => write!(f, "flag {flag}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum EnvKind {
+    Text,
+    Flag,
+    /// Names a JSON file whose contents become the value.
+    JsonFile,
+}
+
+/// `RIP_*` variables and the keys they set.
+const ENV_VARS: &[(&str, &str, EnvKind)] = &[
+    ("RIP_BIND", "server.bind", EnvKind::Text),
+    ("RIP_DATA_DIR", "data_dir", EnvKind::Text),
+    ("RIP_WORKSPACE_ROOT", "workspace_root", EnvKind::Text),
+    ("RIP_MODEL", "model", EnvKind::Text),
+    ("RIP_PROVIDER_CONFIG", "provider.config", EnvKind::Text),
+    ("RIP_PROVIDER_SCRIPT", "provider.script", EnvKind::Text),
+    ("RIP_SANDBOX", "tools.sandbox", EnvKind::Text),
+    (
+        "RIP_PERSISTENT_SHELL",
+        "tools.persistent_shell",
+        EnvKind::Flag,
+    ),
+    ("RIP_PERMISSIONS", "permissions", EnvKind::JsonFile),
+];
+
+/// Keys (and the tables under them) an untrusted project file may not set.
+pub const PROJECT_PROTECTED_KEYS: &[&str] = &[
+    "server.bind",
+    "data_dir",
+    "workspace_root",
+    "provider",
+    "tools.sandbox",
+    "tools.limits",
+    "permissions",
+];
+
+/// Collects the layers; `load` merges them.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigLoader {
+    user_file: Option<PathBuf>,
+    project_file: Option<PathBuf>,
+    trust_project: bool,
+    env: Vec<(String, String)>,
+    cli: Vec<(String, String, Value)>,
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `env` layer plus the user file under its `XDG_CONFIG_HOME` (or
+    /// `$HOME/.config`); the project file is added once the workspace root
+    /// is known.
+    pub fn standard<K, V>(env: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let loader = Self::new().env(env);
+        let var = |name: &str| {
+            loader
+                .env
+                .iter()
+                .find(|(key, value)| key == name && !value.is_empty())
+                .map(|(_, value)| PathBuf::from(value))
+        };
+        let config_home =
+            var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")));
+        match config_home {
+            Some(config_home) => loader.user_file(config_home.join("rip").join("config.toml")),
+            None => loader,
+        }
+    }
+
+    /// A missing file is skipped.
+    pub fn user_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.user_file = Some(path.into());
+        self
+    }
+
+    pub fn project_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.project_file = Some(path.into());
+        self
+    }
+
+    /// Lets the project file set `PROJECT_PROTECTED_KEYS` too.
+    pub fn trust_project(mut self, trust: bool) -> Self {
+        self.trust_project = trust;
+        self
+    }
+
+    pub fn env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env = vars
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        self
+    }
+
+    /// Sets `key` (dotted, e.g. `server.bind`) from command-line `flag`.
+    pub fn cli(mut self, flag: &str, key: &str, value: impl Into<Value>) -> Self {
+        self.cli
+            .push((flag.to_string(), key.to_string(), value.into()));
+        self
+    }
+
+    /// Applies a `--set key=value` override; the value is read as TOML
+    /// (`1024`, `true`, `["a", "b"]`) and falls back to a plain string.
+    pub fn cli_override(self, assignment: &str) -> Result<Self, String> {
+        let (key, raw) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("invalid override (expected key=value): {assignment}"))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!(
+                "invalid override (expected key=value): {assignment}"
+            ));
+        }
+        let value = match toml::from_str::<toml::Table>(&format!("value = {raw}")) {
+            Ok(mut table) => toml_to_json(table.remove("value").expect("parsed key"))?,
+            Err(_) => Value::String(raw.to_string()),
+        };
+        Ok(self.cli("--set", key, value))
+    }
+
+    pub fn load(&self) -> Result<LoadedConfig, String> {
+        let mut layers = Vec::new();
+        let mut ignored = BTreeMap::new();
+        if let Some(path) = &self.user_file {
+            if let Some(value) = read_toml(path)? {
+                layers.push((ConfigSource::User { path: path.clone() }, value));
+            }
+        }
+        if let Some(path) = &self.project_file {
+            if let Some(mut value) = read_toml(path)? {
+                let source = ConfigSource::Project { path: path.clone() };
+                if !self.trust_project {
+                    for key in PROJECT_PROTECTED_KEYS {
+                        if remove_key(&mut value, key) {
+                            ignored.insert(key.to_string(), source.clone());
+                        }
+                    }
+                }
+                layers.push((source, value));
+            }
+        }
+        for (var, key, kind) in ENV_VARS {
+            let Some((_, raw)) = self.env.iter().find(|(name, _)| name == var) else {
+                continue;
+            };
+            let value = env_value(var, raw, *kind)?;
+            let source = ConfigSource::Env {
+                var: var.to_string(),
+            };
+            layers.push((source, nest(key, value)));
+        }
+        for (flag, key, value) in &self.cli {
+            let source = ConfigSource::Cli { flag: flag.clone() };
+            layers.push((source, nest(key, value.clone())));
+        }
+
+        let mut merged = Map::new();
+        let mut sources = BTreeMap::new();
+        for (source, value) in layers {
+            // Checked on its own first so an unknown key or a wrong type
+            // names the layer it came from.
+            serde_json::from_value::<RipConfig>(value.clone())
+                .map_err(|err| format!("{source}: {err}"))?;
+            let Value::Object(table) = value else {
+                return Err(format!("{source}: expected a table"));
+            };
+            merge(&mut merged, table, "", &source, &mut sources);
+        }
+        let config = serde_json::from_value(Value::Object(merged))
+            .map_err(|err| format!("config: {err}"))?;
+        Ok(LoadedConfig {
+            config,
+            sources,
+            ignored,
+        })
+    }
+}
+
+/// The merged config and the layer behind each value a layer set.
+#[derive(Clone, Debug, Serialize)]
+pub struct LoadedConfig {
+    pub config: RipConfig,
+    sources: BTreeMap<String, ConfigSource>,
+    ignored: BTreeMap<String, ConfigSource>,
+}
+
+impl LoadedConfig {
+    /// The layer that set `key` (or a table containing it); `Default` when
+    /// none did.
+    pub fn source(&self, key: &str) -> ConfigSource {
+        let mut key = key;
+        loop {
+            if let Some(source) = self.sources.get(key) {
+                return source.clone();
+            }
+            match key.rsplit_once('.') {
+                Some((parent, _)) => key = parent,
+                None => return ConfigSource::Default,
+            }
+        }
+    }
+
+    /// Every key a layer set, with its layer.
+    pub fn sources(&self) -> &BTreeMap<String, ConfigSource> {
+        &self.sources
+    }
+
+    /// Protected keys an untrusted project file tried to set.
+    pub fn ignored(&self) -> &BTreeMap<String, ConfigSource> {
+        &self.ignored
+    }
+}
+
+/// JSON Schema for config files (`schemas/rip-config/config.schema.json`).
+pub fn json_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(RipConfig)).expect("config schema")
+}
+
+fn read_toml(path: &Path) -> Result<Option<Value>, String> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("{}: {err}", path.display())),
+    };
+    let table: toml::Table =
+        toml::from_str(&raw).map_err(|err| format!("{}: {err}", path.display()))?;
+    toml_to_json(toml::Value::Table(table))
+        .map(Some)
+        .map_err(|err| format!("{}: {err}", path.display()))
+}
+
+fn toml_to_json(value: toml::Value) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|err| err.to_string())
+}
+
+fn env_value(var: &str, raw: &str, kind: EnvKind) -> Result<Value, String> {
+    match kind {
+        EnvKind::Text => Ok(Value::String(raw.to_string())),
+        EnvKind::Flag => match raw {
+            "1" | "true" => Ok(Value::Bool(true)),
+            "" | "0" | "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("env {var}: expected 1/0 or true/false, got {raw}")),
+        },
+        EnvKind::JsonFile => {
+            let contents =
+                std::fs::read_to_string(raw).map_err(|err| format!("env {var}: {raw}: {err}"))?;
+            serde_json::from_str(&contents).map_err(|err| format!("env {var}: {raw}: {err}"))
+        }
+    }
+}
+
+/// `value` placed at dotted `key`.
+fn nest(key: &str, value: Value) -> Value {
+    key.rsplit('.').fold(value, |value, part| {
+        let mut table = Map::new();
+        table.insert(part.to_string(), value);
+        Value::Object(table)
+    })
+}
+
+fn merge(
+    base: &mut Map<String, Value>,
+    layer: Map<String, Value>,
+    prefix: &str,
+    source: &ConfigSource,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    for (name, value) in layer {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{prefix}.{name}")
+        };
+        match value {
+            Value::Object(table) => {
+                let slot = base
+                    .entry(name)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if !slot.is_object() {
+                    forget(sources, &key);
+                    *slot = Value::Object(Map::new());
+                }
+                let Value::Object(slot) = slot else {
+                    unreachable!("replaced above")
+                };
+                merge(slot, table, &key, source, sources);
+            }
+            value => {
+                forget(sources, &key);
+                sources.insert(key, source.clone());
+                base.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Removes dotted `key` from `value`; true when it was there.
+fn remove_key(value: &mut Value, key: &str) -> bool {
+    let (parent, name) = match key.rsplit_once('.') {
+        Some((parent, name)) => (parent.split('.').collect::<Vec<_>>(), name),
+        None => (Vec::new(), key),
+    };
+    let table = parent
+        .into_iter()
+        .try_fold(value, |value, part| value.get_mut(part));
+    table
+        .and_then(Value::as_object_mut)
+        .is_some_and(|table| table.remove(name).is_some())
+}
+
+/// Drops the sources recorded for `key` and everything under it.
+fn forget(sources: &mut BTreeMap<String, ConfigSource>, key: &str) {
+    let nested = format!("{key}.");
+    sources.retain(|set, _| set != key && !set.starts_with(&nested));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().expect("parent")).expect("mkdir");
+        std::fs::write(path, contents).expect("write");
+    }
+
+    #[test]
+    fn defaults_without_layers() {
+        let loaded = ConfigLoader::new().load().expect("load");
+        assert_eq!(loaded.config, RipConfig::default());
+        assert_eq!(loaded.config.server.bind, DEFAULT_BIND);
+        assert_eq!(loaded.source("server.bind"), ConfigSource::Default);
+        assert!(loaded.sources().is_empty());
+    }
+
+    #[test]
+    fn later_layers_win_key_by_key() {
+        let dir = tempdir().expect("tmp");
+        let user = dir.path().join("home/rip/config.toml");
+        let project = dir.path().join("project/.rip/config.toml");
+        write(
+            &user,
+            "model = \"gpt-4o\"\ndata_dir = \"/var/rip\"\n\n[tools]\nmax_bytes = 1024\n\n[tools.limits.bash]\nmax_pids = 64\n",
+        );
+        write(
+            &project,
+            "[server]\nbind = \"0.0.0.0:8080\"\n\n[tools.limits.bash]\nmax_cpu_seconds = 30\n\n[permissions]\ndefault = \"ask\"\nallow = [\"bash(git *)\"]\n",
+        );
+
+        let loaded = ConfigLoader::new()
+            .user_file(&user)
+            .project_file(&project)
+            .trust_project(true)
+            .env([("RIP_DATA_DIR", "/tmp/rip"), ("RIP_PERSISTENT_SHELL", "1")])
+            .cli("--model", "model", "local/llama3")
+            .cli_override("tools.max_bytes=2048")
+            .expect("override")
+            .load()
+            .expect("load");
+        let config = &loaded.config;
+
+        assert_eq!(config.server.bind, "0.0.0.0:8080");
+        assert_eq!(config.data_dir, PathBuf::from("/tmp/rip"));
+        assert_eq!(config.model.as_deref(), Some("local/llama3"));
+        assert_eq!(config.tools.max_bytes, Some(2048));
+        assert!(config.tools.persistent_shell);
+        let bash = &config.tools.limits["bash"];
+        assert_eq!(bash.max_pids, Some(64));
+        assert_eq!(bash.max_cpu_seconds, Some(30));
+        assert_eq!(config.permissions.allow, vec!["bash(git *)"]);
+
+        assert_eq!(
+            loaded.source("server.bind"),
+            ConfigSource::Project {
+                path: project.clone()
+            }
+        );
+        assert_eq!(
+            loaded.source("data_dir"),
+            ConfigSource::Env {
+                var: "RIP_DATA_DIR".to_string()
+            }
+        );
+        assert_eq!(
+            loaded.source("model"),
+            ConfigSource::Cli {
+                flag: "--model".to_string()
+            }
+        );
+        assert_eq!(
+            loaded.source("tools.max_bytes"),
+            ConfigSource::Cli {
+                flag: "--set".to_string()
+            }
+        );
+        assert_eq!(
+            loaded.source("tools.limits.bash.max_pids"),
+            ConfigSource::User { path: user }
+        );
+        assert_eq!(
+            loaded.source("tools.limits.bash.max_cpu_seconds"),
+            ConfigSource::Project { path: project }
+        );
+        assert_eq!(loaded.source("tools.max_depth"), ConfigSource::Default);
+    }
+
+    #[test]
+    fn permissions_file_from_env_merges_over_files() {
+        let dir = tempdir().expect("tmp");
+        let project = dir.path().join(".rip/config.toml");
+        write(&project, "[permissions]\nask = [\"bash\"]\n");
+        let rules = dir.path().join("permissions.json");
+        write(&rules, r#"{"default":"deny","allow":["read"]}"#);
+
+        let loaded = ConfigLoader::new()
+            .project_file(&project)
+            .trust_project(true)
+            .env([("RIP_PERMISSIONS", rules.to_string_lossy().into_owned())])
+            .load()
+            .expect("load");
+        let permissions = &loaded.config.permissions;
+        assert_eq!(permissions.default, rip_tools::PermissionDecision::Deny);
+        assert_eq!(permissions.allow, vec!["read"]);
+        assert_eq!(permissions.ask, vec!["bash"]);
+        let source = ConfigSource::Env {
+            var: "RIP_PERMISSIONS".to_string(),
+        };
+        assert_eq!(loaded.source("permissions.default"), source);
+        assert_eq!(
+            loaded.source("permissions.ask"),
+            ConfigSource::Project { path: project }
+        );
+    }
+
+    #[test]
+    fn untrusted_projects_cannot_set_protected_keys() {
+        let dir = tempdir().expect("tmp");
+        let user = dir.path().join("home/rip/config.toml");
+        let project = dir.path().join(".rip/config.toml");
+        write(&user, "[tools]\nsandbox = \"workspace-write\"\n");
+        write(
+            &project,
+            "model = \"local/llama3\"\n\n[tools]\nsandbox = \"off\"\nmax_bytes = 512\n\n[tools.limits.bash]\nmax_pids = 4096\n\n[permissions]\ndefault = \"allow\"\n",
+        );
+
+        let loader = ConfigLoader::new().user_file(&user).project_file(&project);
+        let loaded = loader.load().expect("load");
+        assert_eq!(
+            loaded.config.tools.sandbox.as_deref(),
+            Some("workspace-write")
+        );
+        assert!(loaded.config.tools.limits.is_empty());
+        assert_eq!(loaded.config.permissions, PermissionConfig::default());
+        assert_eq!(loaded.config.model.as_deref(), Some("local/llama3"));
+        assert_eq!(loaded.config.tools.max_bytes, Some(512));
+        assert_eq!(
+            loaded.ignored().keys().collect::<Vec<_>>(),
+            ["permissions", "tools.limits", "tools.sandbox"]
+        );
+
+        let trusted = loader.trust_project(true).load().expect("load");
+        assert_eq!(trusted.config.tools.sandbox.as_deref(), Some("off"));
+        assert!(trusted.ignored().is_empty());
+    }
+
+    #[test]
+    fn untrusted_projects_cannot_move_the_server_or_its_data() {
+        let dir = tempdir().expect("tmp");
+        let user = dir.path().join("home/rip/config.toml");
+        let project = dir.path().join(".rip/config.toml");
+        write(&user, "data_dir = \"/var/rip\"\n");
+        write(
+            &project,
+            "data_dir = \"/etc/cron.d\"\n\n[server]\nbind = \"0.0.0.0:7341\"\n",
+        );
+
+        let loader = ConfigLoader::new().user_file(&user).project_file(&project);
+        let loaded = loader.load().expect("load");
+        assert_eq!(loaded.config.server.bind, DEFAULT_BIND);
+        assert_eq!(loaded.config.data_dir, PathBuf::from("/var/rip"));
+        assert_eq!(loaded.source("data_dir"), ConfigSource::User { path: user });
+        assert_eq!(
+            loaded.ignored().keys().collect::<Vec<_>>(),
+            ["data_dir", "server.bind"]
+        );
+
+        let trusted = loader.trust_project(true).load().expect("load");
+        assert_eq!(trusted.config.server.bind, "0.0.0.0:7341");
+    }
+
+    #[test]
+    fn standard_reads_the_user_file_from_the_given_env() {
+        let dir = tempdir().expect("tmp");
+        write(
+            &dir.path().join("home/.config/rip/config.toml"),
+            "model = \"from-home\"\n",
+        );
+        write(
+            &dir.path().join("xdg/rip/config.toml"),
+            "model = \"from-xdg\"\n",
+        );
+        let home = dir.path().join("home").to_string_lossy().into_owned();
+        let xdg = dir.path().join("xdg").to_string_lossy().into_owned();
+
+        let loaded = ConfigLoader::standard([("HOME", home.clone())])
+            .load()
+            .expect("load");
+        assert_eq!(loaded.config.model.as_deref(), Some("from-home"));
+        let loaded = ConfigLoader::standard([("HOME", home), ("XDG_CONFIG_HOME", xdg)])
+            .load()
+            .expect("load");
+        assert_eq!(loaded.config.model.as_deref(), Some("from-xdg"));
+        let loaded = ConfigLoader::standard(Vec::<(String, String)>::new())
+            .load()
+            .expect("load");
+        assert_eq!(loaded.config, RipConfig::default());
+    }
+
+    #[test]
+    fn errors_name_the_layer() {
+        let dir = tempdir().expect("tmp");
+        let project = dir.path().join(".rip/config.toml");
+        write(&project, "[server]\nbnd = \"x\"\n");
+        let err = ConfigLoader::new()
+            .project_file(&project)
+            .load()
+            .unwrap_err();
+        assert!(err.starts_with("project config"), "{err}");
+        assert!(err.contains("bnd"), "{err}");
+
+        let err = ConfigLoader::new()
+            .env([("RIP_PERSISTENT_SHELL", "yes")])
+            .load()
+            .unwrap_err();
+        assert!(err.contains("RIP_PERSISTENT_SHELL"), "{err}");
+
+        let err = ConfigLoader::new()
+            .cli_override("tools.concurrency=many")
+            .expect("override")
+            .load()
+            .unwrap_err();
+        assert!(err.starts_with("flag --set"), "{err}");
+        assert!(ConfigLoader::new().cli_override("novalue").is_err());
+    }
+
+    #[test]
+    fn missing_files_are_skipped() {
+        let dir = tempdir().expect("tmp");
+        let loaded = ConfigLoader::new()
+            .user_file(dir.path().join("absent.toml"))
+            .project_file(dir.path().join(".rip/config.toml"))
+            .load()
+            .expect("load");
+        assert_eq!(loaded.config, RipConfig::default());
+    }
+
+    #[test]
+    fn json_schema_snapshot() {
+        let rendered = format!(
+            "{}\n",
+            serde_json::to_string_pretty(&json_schema()).expect("schema json")
+        );
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../schemas/rip-config/config.schema.json");
+        if std::env::var("RIP_UPDATE_CONFIG_SCHEMA").is_ok() {
+            std::fs::create_dir_all(path.parent().expect("parent")).expect("mkdir");
+            std::fs::write(&path, &rendered).expect("write schema");
+            return;
+        }
+        let existing = std::fs::read_to_string(&path).expect("read schema");
+        assert_eq!(existing, rendered);
+    }
+}
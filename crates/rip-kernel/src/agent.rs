@@ -0,0 +1,183 @@
+use futures_util::stream::BoxStream;
+use serde_json::{json, Map, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{EventKind, ProviderEventStatus};
+
+#[derive(Debug, Clone)]
+pub struct ModelRequest {
+    pub session_id: String,
+    pub model: Option<String>,
+    pub input: Vec<Value>,
+    pub tools: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCall {
+    pub call_id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+pub trait ProviderAdapter: Send + Sync {
+    fn stream(&self, request: ModelRequest) -> BoxStream<'static, EventKind>;
+}
+
+pub trait ToolDispatcher: Send + Sync {
+    /// Runs one call. When `cancel` fires the stream should stop the tool and
+    /// end with a `tool_failed` frame rather than just stopping.
+    fn dispatch(
+        &self,
+        session_id: &str,
+        call: ToolCall,
+        cancel: CancellationToken,
+    ) -> BoxStream<'static, EventKind>;
+
+    fn tools(&self) -> Vec<Value> {
+        Vec::new()
+    }
+
+    /// The tools offered to the model for `session_id`; dispatchers with
+    /// per-session restrictions (such as plan mode) narrow `tools()` here.
+    fn session_tools(&self, _session_id: &str) -> Vec<Value> {
+        self.tools()
+    }
+
+    /// Releases what the dispatcher keeps for `session_id` (shells,
+    /// background processes) once the session is cancelled or over.
+    fn end_session(&self, _session_id: &str) {}
+}
+
+pub(crate) fn user_message(input: &str) -> Value {
+    json!({
+        "type": "message",
+        "role": "user",
+        "content": input
+    })
+}
+
+pub(crate) fn function_call_output(call_id: &str, output: Value) -> Value {
+    json!({
+        "type": "function_call_output",
+        "call_id": call_id,
+        "output": output.to_string()
+    })
+}
+
+fn provider_data(kind: &EventKind) -> Option<&Map<String, Value>> {
+    match kind {
+        EventKind::ProviderEvent {
+            status: ProviderEventStatus::Event,
+            data: Some(Value::Object(obj)),
+            ..
+        } => Some(obj),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_provider_failure(kind: &EventKind) -> bool {
+    provider_data(kind)
+        .and_then(|obj| obj.get("type"))
+        .and_then(|value| value.as_str())
+        .map(|value| value == "error" || value == "response.failed")
+        .unwrap_or(false)
+}
+
+/// Returns the completed output item carried by `response.output_item.done`,
+/// limited to items that must be echoed back as input on the next turn.
+pub(crate) fn completed_item(kind: &EventKind) -> Option<Value> {
+    let obj = provider_data(kind)?;
+    if obj.get("type").and_then(|value| value.as_str()) != Some("response.output_item.done") {
+        return None;
+    }
+    let item = obj.get("item")?;
+    match item.get("type").and_then(|value| value.as_str()) {
+        Some("message") => Some(item.clone()),
+        Some("function_call") => {
+            let call = tool_call(item)?;
+            Some(json!({
+                "type": "function_call",
+                "call_id": call.call_id,
+                "name": call.name,
+                "arguments": call.arguments
+            }))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn tool_call(item: &Value) -> Option<ToolCall> {
+    if item.get("type").and_then(|value| value.as_str()) != Some("function_call") {
+        return None;
+    }
+    let call_id = item.get("call_id").and_then(|value| value.as_str())?;
+    let name = item.get("name").and_then(|value| value.as_str())?;
+    let arguments = item
+        .get("arguments")
+        .and_then(|value| value.as_str())
+        .unwrap_or("{}");
+    Some(ToolCall {
+        call_id: call_id.to_string(),
+        name: name.to_string(),
+        arguments: arguments.to_string(),
+    })
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ToolOutputCollector {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    exit_code: Option<i32>,
+    artifacts: Option<Value>,
+    error: Option<String>,
+    signal: Option<String>,
+}
+
+impl ToolOutputCollector {
+    pub(crate) fn observe(&mut self, kind: &EventKind) {
+        match kind {
+            EventKind::ToolStdout { chunk, .. } => self.stdout.push(chunk.clone()),
+            EventKind::ToolStderr { chunk, .. } => self.stderr.push(chunk.clone()),
+            EventKind::ToolEnded {
+                exit_code,
+                artifacts,
+                ..
+            } => {
+                self.exit_code = Some(*exit_code);
+                self.artifacts = artifacts.clone();
+            }
+            EventKind::ToolFailed { error, signal, .. } => {
+                self.error = Some(error.clone());
+                self.signal = signal.clone();
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn failed(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn into_output(self) -> Value {
+        if let Some(error) = self.error {
+            let mut output = json!({
+                "error": error,
+                "stdout": self.stdout.concat(),
+                "stderr": self.stderr.concat()
+            });
+            if let Some(signal) = self.signal {
+                output["signal"] = Value::String(signal);
+            }
+            return output;
+        }
+        json!({
+            "exit_code": self.exit_code,
+            "stdout": self.stdout.concat(),
+            "stderr": self.stderr.concat(),
+            "artifacts": self.artifacts
+        })
+    }
+}
@@ -1,15 +1,27 @@
+mod agent;
 mod commands;
 mod hooks;
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use futures_util::future::{self, Either};
+use futures_util::stream::{BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
+use agent::{
+    completed_item, function_call_output, is_provider_failure, tool_call, user_message,
+    ToolOutputCollector,
+};
+pub use agent::{ModelRequest, ProviderAdapter, ToolCall, ToolDispatcher};
 pub use commands::{Command, CommandContext, CommandHandler, CommandRegistry, CommandResult};
 pub use hooks::{Hook, HookContext, HookEngine, HookEventKind, HookHandler, HookOutcome};
+pub use tokio_util::sync::CancellationToken;
+
+pub const DEFAULT_MAX_TURNS: usize = 32;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
@@ -47,13 +59,27 @@ pub enum EventKind {
         args: Value,
         timeout_ms: Option<u64>,
     },
+    /// The call waits for a client to approve or deny it.
+    ToolPermissionRequested {
+        tool_id: String,
+        name: String,
+        args: Value,
+        /// The `ask` rule that matched; none when no rule did.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rule: Option<String>,
+    },
     ToolStdout {
         tool_id: String,
         chunk: String,
+        /// Background job that produced the chunk, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        job_id: Option<String>,
     },
     ToolStderr {
         tool_id: String,
         chunk: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        job_id: Option<String>,
     },
     ToolEnded {
         tool_id: String,
@@ -64,6 +90,17 @@ pub enum EventKind {
     ToolFailed {
         tool_id: String,
         error: String,
+        /// Signal sent to stop the tool's process, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<String>,
+        /// Resource limit that was exceeded (`error: "limit_exceeded"`).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<String>,
+        /// Output captured before the failure (tail only).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stdout: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stderr: Option<String>,
     },
     ProviderEvent {
         provider: String,
@@ -80,6 +117,9 @@ pub enum EventKind {
 pub struct Runtime {
     hooks: Arc<HookEngine>,
     commands: Arc<CommandRegistry>,
+    provider: Option<Arc<dyn ProviderAdapter>>,
+    tools: Option<Arc<dyn ToolDispatcher>>,
+    max_turns: usize,
 }
 
 impl Default for Runtime {
@@ -93,11 +133,41 @@ impl Runtime {
         Self {
             hooks: Arc::new(HookEngine::new()),
             commands: Arc::new(CommandRegistry::new()),
+            provider: None,
+            tools: None,
+            max_turns: DEFAULT_MAX_TURNS,
         }
     }
 
+    pub fn with_provider(mut self, provider: Arc<dyn ProviderAdapter>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Arc<dyn ToolDispatcher>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    pub fn with_max_turns(mut self, max_turns: usize) -> Self {
+        self.max_turns = max_turns.max(1);
+        self
+    }
+
     pub fn start_session(&self, input: String) -> Session {
-        Session::new(input, self.hooks.clone())
+        let mut session = Session::new(input, self.hooks.clone());
+        session.provider = self.provider.clone();
+        session.tools = self.tools.clone();
+        session.max_turns = self.max_turns;
+        session
+    }
+
+    /// Tears down the tool state of `session_id`; see
+    /// `ToolDispatcher::end_session`.
+    pub fn end_session(&self, session_id: &str) {
+        if let Some(tools) = &self.tools {
+            tools.end_session(session_id);
+        }
     }
 
     pub fn register_hook<F>(&self, name: impl Into<String>, event: HookEventKind, handler: F)
@@ -136,12 +206,30 @@ pub struct Session {
     seq: u64,
     stage: Stage,
     hooks: Arc<HookEngine>,
+    provider: Option<Arc<dyn ProviderAdapter>>,
+    tools: Option<Arc<dyn ToolDispatcher>>,
+    model: Option<String>,
+    max_turns: usize,
+    turns: usize,
+    transcript: Vec<Value>,
+    pending: VecDeque<EventKind>,
+    provider_stream: Option<BoxStream<'static, EventKind>>,
+    tool_calls: VecDeque<ToolCall>,
+    tool_stream: Option<BoxStream<'static, EventKind>>,
+    /// Stops the in-flight tool without cancelling the session.
+    tool_cancel: Option<CancellationToken>,
+    tool_output: Option<(String, ToolOutputCollector)>,
+    end_reason: String,
+    cancel: CancellationToken,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Stage {
     Start,
-    Output,
+    Echo,
+    Request,
+    Provider,
+    Tools,
     End,
     Done,
 }
@@ -150,10 +238,24 @@ impl Session {
     pub fn new(input: String, hooks: Arc<HookEngine>) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
+            transcript: vec![user_message(&input)],
             input,
             seq: 0,
             stage: Stage::Start,
             hooks,
+            provider: None,
+            tools: None,
+            model: None,
+            max_turns: DEFAULT_MAX_TURNS,
+            turns: 0,
+            pending: VecDeque::new(),
+            provider_stream: None,
+            tool_calls: VecDeque::new(),
+            tool_stream: None,
+            tool_cancel: None,
+            tool_output: None,
+            end_reason: "completed".to_string(),
+            cancel: CancellationToken::new(),
         }
     }
 
@@ -161,31 +263,230 @@ impl Session {
         &self.id
     }
 
-    pub fn next_event(&mut self) -> Option<Event> {
-        let (next_stage, kind) = match self.stage {
-            Stage::Start => (
-                Stage::Output,
-                EventKind::SessionStarted {
-                    input: self.input.clone(),
-                },
-            ),
-            Stage::Output => (
-                Stage::End,
-                EventKind::OutputTextDelta {
-                    delta: format!("ack: {}", self.input),
-                },
-            ),
-            Stage::End => (
-                Stage::Done,
-                EventKind::SessionEnded {
-                    reason: "completed".to_string(),
-                },
-            ),
-            Stage::Done => return None,
+    /// Runs under an externally assigned session id (e.g. the server's).
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// Continues `seq` numbering after frames emitted by earlier runs.
+    pub fn with_start_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// The `seq` the next emitted frame will carry.
+    pub fn next_seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Continues a conversation: `transcript` (from an earlier run's
+    /// `transcript()`) is sent ahead of this input on every turn.
+    pub fn with_transcript(mut self, transcript: Vec<Value>) -> Self {
+        let current = std::mem::replace(&mut self.transcript, transcript);
+        self.transcript.extend(current);
+        self
+    }
+
+    /// Input items so far: user inputs, completed model items and tool outputs.
+    pub fn transcript(&self) -> &[Value] {
+        &self.transcript
+    }
+
+    /// Stops the session when `cancel` fires: the provider stream is dropped,
+    /// an in-flight tool is stopped, and the session ends with `cancelled`.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Overrides the provider's default model for every turn of this session.
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(kind) = self.pending.pop_front() {
+                return Some(self.emit(kind));
+            }
+            if self.cancel.is_cancelled() && self.end_reason != "cancelled" {
+                self.on_cancel();
+            }
+
+            match self.stage {
+                Stage::Start => {
+                    self.pending.push_back(EventKind::SessionStarted {
+                        input: self.input.clone(),
+                    });
+                    self.stage = if self.provider.is_some() {
+                        Stage::Request
+                    } else {
+                        Stage::Echo
+                    };
+                }
+                Stage::Echo => {
+                    self.pending.push_back(EventKind::OutputTextDelta {
+                        delta: format!("ack: {}", self.input),
+                    });
+                    self.stage = Stage::End;
+                }
+                Stage::Request => self.start_turn(),
+                Stage::Provider => self.poll_provider().await,
+                Stage::Tools => self.poll_tools().await,
+                Stage::End => {
+                    self.pending.push_back(EventKind::SessionEnded {
+                        reason: self.end_reason.clone(),
+                    });
+                    self.stage = Stage::Done;
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    fn on_cancel(&mut self) {
+        if matches!(self.stage, Stage::End | Stage::Done) {
+            return;
+        }
+        if self.stage == Stage::Start {
+            // Cancelled before it began: still bracket the run.
+            self.pending.push_back(EventKind::SessionStarted {
+                input: self.input.clone(),
+            });
+        }
+        self.end_reason = "cancelled".to_string();
+        self.provider_stream = None;
+        self.tool_calls.clear();
+        // An in-flight tool sees the same token and reports its own failure.
+        self.stage = if self.tool_stream.is_some() {
+            Stage::Tools
+        } else {
+            Stage::End
+        };
+    }
+
+    fn start_turn(&mut self) {
+        let provider = match &self.provider {
+            Some(provider) => provider.clone(),
+            None => {
+                self.stage = Stage::End;
+                return;
+            }
+        };
+        if self.turns >= self.max_turns {
+            self.end_reason = "max_turns".to_string();
+            self.stage = Stage::End;
+            return;
+        }
+        self.turns += 1;
+
+        let request = ModelRequest {
+            session_id: self.id.clone(),
+            model: self.model.clone(),
+            input: self.transcript.clone(),
+            tools: self
+                .tools
+                .as_ref()
+                .map(|tools| tools.session_tools(&self.id))
+                .unwrap_or_default(),
+        };
+        self.provider_stream = Some(provider.stream(request));
+        self.stage = Stage::Provider;
+    }
+
+    async fn poll_provider(&mut self) {
+        let cancel = self.cancel.clone();
+        let next = match self.provider_stream.as_mut() {
+            Some(stream) => {
+                let cancelled = std::pin::pin!(cancel.cancelled());
+                match future::select(stream.next(), cancelled).await {
+                    Either::Left((next, _)) => next,
+                    // Picked up by `on_cancel` on the next loop iteration.
+                    Either::Right(_) => return,
+                }
+            }
+            None => None,
+        };
+
+        match next {
+            Some(kind) => {
+                if is_provider_failure(&kind) {
+                    self.end_reason = "provider_error".to_string();
+                }
+                if let Some(item) = completed_item(&kind) {
+                    if let Some(call) = tool_call(&item) {
+                        self.tool_calls.push_back(call);
+                    }
+                    self.transcript.push(item);
+                }
+                self.pending.push_back(kind);
+            }
+            None => {
+                self.provider_stream = None;
+                self.stage = if self.tool_calls.is_empty() || self.end_reason != "completed" {
+                    Stage::End
+                } else {
+                    Stage::Tools
+                };
+            }
+        }
+    }
+
+    async fn poll_tools(&mut self) {
+        if self.tool_stream.is_none() {
+            let call = match self.tool_calls.pop_front() {
+                Some(call) => call,
+                None => {
+                    self.stage = if self.end_reason == "cancelled" {
+                        Stage::End
+                    } else {
+                        Stage::Request
+                    };
+                    return;
+                }
+            };
+            match &self.tools {
+                Some(tools) => {
+                    let cancel = self.cancel.child_token();
+                    self.tool_stream = Some(tools.dispatch(&self.id, call.clone(), cancel.clone()));
+                    self.tool_cancel = Some(cancel);
+                    self.tool_output = Some((call.call_id, ToolOutputCollector::default()));
+                }
+                None => {
+                    let output = ToolOutputCollector::failed("no tool runtime configured");
+                    self.transcript
+                        .push(function_call_output(&call.call_id, output.into_output()));
+                    return;
+                }
+            }
+        }
+
+        let next = match self.tool_stream.as_mut() {
+            Some(stream) => stream.next().await,
+            None => None,
         };
 
-        self.stage = next_stage;
+        match next {
+            Some(kind) => {
+                if let Some((_, collector)) = self.tool_output.as_mut() {
+                    collector.observe(&kind);
+                }
+                self.pending.push_back(kind);
+            }
+            None => {
+                self.tool_stream = None;
+                self.tool_cancel = None;
+                if let Some((call_id, collector)) = self.tool_output.take() {
+                    self.transcript
+                        .push(function_call_output(&call_id, collector.into_output()));
+                }
+            }
+        }
+    }
 
+    fn emit(&mut self, kind: EventKind) -> Event {
         let timestamp_ms = now_ms();
         let event = Event {
             id: Uuid::new_v4().to_string(),
@@ -203,6 +504,7 @@ impl Session {
             EventKind::SessionEnded { .. } => (Some(HookEventKind::SessionEnded), None),
             EventKind::ProviderEvent { .. }
             | EventKind::ToolStarted { .. }
+            | EventKind::ToolPermissionRequested { .. }
             | EventKind::ToolStdout { .. }
             | EventKind::ToolStderr { .. }
             | EventKind::ToolEnded { .. }
@@ -218,29 +520,83 @@ impl Session {
                 output,
             };
 
-            match self.hooks.run(&ctx) {
-                HookOutcome::Continue => {
-                    self.seq += 1;
-                    Some(event)
+            if let HookOutcome::Abort { reason } = self.hooks.run(&ctx) {
+                self.stage = Stage::Done;
+                self.pending.clear();
+                self.provider_stream = None;
+                // Dropping the stream alone would leave the tool running.
+                if let Some(cancel) = self.tool_cancel.take() {
+                    cancel.cancel();
                 }
-                HookOutcome::Abort { reason } => {
-                    self.stage = Stage::Done;
-                    let abort_event = Event {
-                        id: Uuid::new_v4().to_string(),
-                        session_id: self.id.clone(),
-                        timestamp_ms: now_ms(),
-                        seq: self.seq,
-                        kind: EventKind::SessionEnded { reason },
-                    };
-                    self.seq += 1;
-                    Some(abort_event)
+                self.tool_stream = None;
+                let abort_event = Event {
+                    id: Uuid::new_v4().to_string(),
+                    session_id: self.id.clone(),
+                    timestamp_ms: now_ms(),
+                    seq: self.seq,
+                    kind: EventKind::SessionEnded { reason },
+                };
+                self.seq += 1;
+                return abort_event;
+            }
+        }
+
+        self.seq += 1;
+        event
+    }
+}
+
+/// The transcript the runs behind `events` built up, so a session restored
+/// from its frames continues the same conversation (see `with_transcript`).
+pub fn transcript_from_events(events: &[Event]) -> Vec<Value> {
+    fn finish(transcript: &mut Vec<Value>, output: &mut Option<(String, ToolOutputCollector)>) {
+        if let Some((call_id, collector)) = output.take() {
+            transcript.push(function_call_output(&call_id, collector.into_output()));
+        }
+    }
+
+    let mut transcript = Vec::new();
+    let mut calls = VecDeque::new();
+    let mut output: Option<(String, ToolOutputCollector)> = None;
+    for event in events {
+        match &event.kind {
+            EventKind::SessionStarted { input } => {
+                finish(&mut transcript, &mut output);
+                calls.clear();
+                transcript.push(user_message(input));
+            }
+            EventKind::ProviderEvent { .. } => {
+                if let Some(item) = completed_item(&event.kind) {
+                    if let Some(call) = tool_call(&item) {
+                        calls.push_back(call.call_id);
+                    }
+                    transcript.push(item);
                 }
             }
-        } else {
-            self.seq += 1;
-            Some(event)
+            // Calls run one at a time, in the order the model made them.
+            EventKind::ToolStarted { .. } => {
+                finish(&mut transcript, &mut output);
+                output = calls
+                    .pop_front()
+                    .map(|call_id| (call_id, ToolOutputCollector::default()));
+            }
+            EventKind::ToolStdout { .. } | EventKind::ToolStderr { .. } => {
+                if let Some((_, collector)) = output.as_mut() {
+                    collector.observe(&event.kind);
+                }
+            }
+            EventKind::ToolEnded { .. } | EventKind::ToolFailed { .. } => {
+                if let Some((_, collector)) = output.as_mut() {
+                    collector.observe(&event.kind);
+                }
+                finish(&mut transcript, &mut output);
+            }
+            EventKind::SessionEnded { .. } => finish(&mut transcript, &mut output),
+            EventKind::OutputTextDelta { .. } | EventKind::ToolPermissionRequested { .. } => {}
         }
     }
+    finish(&mut transcript, &mut output);
+    transcript
 }
 
 fn now_ms() -> u64 {
@@ -254,13 +610,13 @@ fn now_ms() -> u64 {
 mod tests {
     use super::*;
 
-    #[test]
-    fn session_emits_three_events_in_order() {
+    #[tokio::test]
+    async fn session_emits_three_events_in_order() {
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
 
         let mut events = Vec::new();
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             events.push(event);
         }
 
@@ -274,29 +630,29 @@ mod tests {
         matches!(events[2].kind, EventKind::SessionEnded { .. });
     }
 
-    #[test]
-    fn event_serializes_to_json() {
+    #[tokio::test]
+    async fn event_serializes_to_json() {
         let runtime = Runtime::new();
         let mut session = runtime.start_session("test".to_string());
-        let event = session.next_event().expect("event");
+        let event = session.next_event().await.expect("event");
         let json = serde_json::to_string(&event).expect("json");
         assert!(json.contains("session_started"));
         assert!(json.contains("input"));
     }
 
-    #[test]
-    fn session_started_includes_input() {
+    #[tokio::test]
+    async fn session_started_includes_input() {
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
-        let event = session.next_event().expect("event");
+        let event = session.next_event().await.expect("event");
         match event.kind {
             EventKind::SessionStarted { input } => assert_eq!(input, "hello"),
             _ => panic!("expected session_started"),
         }
     }
 
-    #[test]
-    fn hook_abort_ends_session_early() {
+    #[tokio::test]
+    async fn hook_abort_ends_session_early() {
         let runtime = Runtime::new();
         runtime.register_hook("abort-on-output", HookEventKind::Output, |_| {
             HookOutcome::Abort {
@@ -306,7 +662,7 @@ mod tests {
 
         let mut session = runtime.start_session("hello".to_string());
         let mut events = Vec::new();
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             events.push(event);
         }
 
@@ -381,8 +737,8 @@ mod tests {
         assert!(result.is_err());
     }
 
-    #[test]
-    fn hooks_run_in_order() {
+    #[tokio::test]
+    async fn hooks_run_in_order() {
         let runtime = Runtime::new();
         let order: Arc<std::sync::Mutex<Vec<&'static str>>> =
             Arc::new(std::sync::Mutex::new(Vec::new()));
@@ -399,7 +755,7 @@ mod tests {
         });
 
         let mut session = runtime.start_session("hello".to_string());
-        session.next_event();
+        session.next_event().await;
 
         let recorded = order.lock().expect("lock").clone();
         assert_eq!(recorded, vec!["first", "second"]);
@@ -421,4 +777,546 @@ mod tests {
         };
         assert_eq!(hooks.run(&ctx), HookOutcome::Continue);
     }
+
+    struct ScriptProvider {
+        turns: std::sync::Mutex<VecDeque<Vec<EventKind>>>,
+        requests: std::sync::Mutex<Vec<ModelRequest>>,
+    }
+
+    impl ScriptProvider {
+        fn new(turns: Vec<Vec<EventKind>>) -> Arc<Self> {
+            Arc::new(Self {
+                turns: std::sync::Mutex::new(turns.into()),
+                requests: std::sync::Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    impl ProviderAdapter for ScriptProvider {
+        fn stream(&self, request: ModelRequest) -> BoxStream<'static, EventKind> {
+            self.requests.lock().expect("lock").push(request);
+            let frames = self
+                .turns
+                .lock()
+                .expect("lock")
+                .pop_front()
+                .unwrap_or_default();
+            futures_util::stream::iter(frames).boxed()
+        }
+    }
+
+    struct EchoTools;
+
+    impl ToolDispatcher for EchoTools {
+        fn dispatch(
+            &self,
+            _session_id: &str,
+            call: ToolCall,
+            _cancel: CancellationToken,
+        ) -> BoxStream<'static, EventKind> {
+            futures_util::stream::iter(vec![
+                EventKind::ToolStarted {
+                    tool_id: "t1".to_string(),
+                    name: call.name.clone(),
+                    args: serde_json::json!({}),
+                    timeout_ms: None,
+                },
+                EventKind::ToolStdout {
+                    tool_id: "t1".to_string(),
+                    chunk: format!("ran {}", call.arguments),
+                    job_id: None,
+                },
+                EventKind::ToolEnded {
+                    tool_id: "t1".to_string(),
+                    exit_code: 0,
+                    duration_ms: 0,
+                    artifacts: None,
+                },
+            ])
+            .boxed()
+        }
+
+        fn tools(&self) -> Vec<Value> {
+            vec![serde_json::json!({"type": "function", "name": "echo"})]
+        }
+    }
+
+    fn provider_frame(data: Value) -> EventKind {
+        EventKind::ProviderEvent {
+            provider: "test".to_string(),
+            status: ProviderEventStatus::Event,
+            event_name: data
+                .get("type")
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string()),
+            data: Some(data),
+            raw: None,
+            errors: Vec::new(),
+            response_errors: Vec::new(),
+        }
+    }
+
+    fn function_call_frame(call_id: &str) -> EventKind {
+        provider_frame(serde_json::json!({
+            "type": "response.output_item.done",
+            "output_index": 0,
+            "sequence_number": 1,
+            "item": {
+                "type": "function_call",
+                "id": "fc_1",
+                "call_id": call_id,
+                "name": "echo",
+                "arguments": "{\"msg\":\"hi\"}",
+                "status": "completed"
+            }
+        }))
+    }
+
+    fn completed_frame() -> EventKind {
+        provider_frame(serde_json::json!({
+            "type": "response.completed",
+            "sequence_number": 2,
+            "response": {}
+        }))
+    }
+
+    async fn collect(session: &mut Session) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(event) = session.next_event().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn session_continues_external_id_and_seq() {
+        let runtime = Runtime::new();
+        let mut session = runtime
+            .start_session("again".to_string())
+            .with_id("server-session")
+            .with_start_seq(7);
+        let events = collect(&mut session).await;
+
+        assert_eq!(events.len(), 3);
+        for (idx, event) in events.iter().enumerate() {
+            assert_eq!(event.session_id, "server-session");
+            assert_eq!(event.seq, 7 + idx as u64);
+        }
+        assert_eq!(session.next_seq(), 10);
+    }
+
+    #[tokio::test]
+    async fn provider_turn_streams_frames_and_completes() {
+        let provider = ScriptProvider::new(vec![vec![
+            EventKind::OutputTextDelta {
+                delta: "hello".to_string(),
+            },
+            completed_frame(),
+        ]]);
+        let runtime = Runtime::new().with_provider(provider.clone());
+        let mut session = runtime.start_session("hi".to_string());
+        let events = collect(&mut session).await;
+
+        assert_eq!(events.len(), 4);
+        for (idx, event) in events.iter().enumerate() {
+            assert_eq!(event.seq, idx as u64);
+        }
+        assert!(matches!(events[1].kind, EventKind::OutputTextDelta { .. }));
+        match &events[3].kind {
+            EventKind::SessionEnded { reason } => assert_eq!(reason, "completed"),
+            _ => panic!("expected session_ended"),
+        }
+
+        let requests = provider.requests.lock().expect("lock");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].session_id, session.id());
+        assert_eq!(requests[0].model, None);
+        assert_eq!(requests[0].input[0]["role"], "user");
+        assert_eq!(requests[0].input[0]["content"], "hi");
+    }
+
+    #[tokio::test]
+    async fn function_call_dispatches_tool_and_feeds_output() {
+        let provider = ScriptProvider::new(vec![
+            vec![function_call_frame("call_1"), completed_frame()],
+            vec![completed_frame()],
+        ]);
+        let runtime = Runtime::new()
+            .with_provider(provider.clone())
+            .with_tools(Arc::new(EchoTools));
+        let mut session = runtime.start_session("run it".to_string());
+        let events = collect(&mut session).await;
+
+        for (idx, event) in events.iter().enumerate() {
+            assert_eq!(event.seq, idx as u64);
+        }
+        assert!(events
+            .iter()
+            .any(|event| matches!(event.kind, EventKind::ToolStdout { .. })));
+        assert!(matches!(
+            events.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { reason }) if reason == "completed"
+        ));
+
+        let requests = provider.requests.lock().expect("lock");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].tools.len(), 1);
+        let input = &requests[1].input;
+        assert_eq!(input.len(), 3);
+        assert_eq!(input[1]["type"], "function_call");
+        assert_eq!(input[1]["call_id"], "call_1");
+        assert_eq!(input[2]["type"], "function_call_output");
+        assert_eq!(input[2]["call_id"], "call_1");
+        let output: Value =
+            serde_json::from_str(input[2]["output"].as_str().expect("output")).expect("json");
+        assert_eq!(output["exit_code"], 0);
+        assert_eq!(output["stdout"], "ran {\"msg\":\"hi\"}");
+    }
+
+    #[tokio::test]
+    async fn transcript_carries_over_to_the_next_input() {
+        let provider = ScriptProvider::new(vec![
+            vec![provider_frame(serde_json::json!({
+                "type": "response.output_item.done",
+                "output_index": 0,
+                "sequence_number": 1,
+                "item": {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "output_text", "text": "hello"}]
+                }
+            }))],
+            vec![completed_frame()],
+        ]);
+        let runtime = Runtime::new().with_provider(provider.clone());
+        let mut first = runtime.start_session("first".to_string());
+        collect(&mut first).await;
+        assert_eq!(first.transcript().len(), 2);
+
+        let mut second = runtime
+            .start_session("second".to_string())
+            .with_transcript(first.transcript().to_vec());
+        collect(&mut second).await;
+
+        let requests = provider.requests.lock().expect("lock");
+        let input = &requests[1].input;
+        assert_eq!(input.len(), 3);
+        assert_eq!(input[0]["content"], "first");
+        assert_eq!(input[1]["role"], "assistant");
+        assert_eq!(input[2]["content"], "second");
+        assert_eq!(second.transcript().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn transcript_is_rebuilt_from_events() {
+        let provider = ScriptProvider::new(vec![
+            vec![function_call_frame("call_1"), completed_frame()],
+            vec![provider_frame(serde_json::json!({
+                "type": "response.output_item.done",
+                "output_index": 0,
+                "sequence_number": 1,
+                "item": {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "output_text", "text": "done"}]
+                }
+            }))],
+            vec![completed_frame()],
+        ]);
+        let runtime = Runtime::new()
+            .with_provider(provider)
+            .with_tools(Arc::new(EchoTools));
+        let mut first = runtime.start_session("run it".to_string());
+        let mut events = collect(&mut first).await;
+        let mut second = runtime
+            .start_session("again".to_string())
+            .with_start_seq(first.next_seq())
+            .with_transcript(first.transcript().to_vec());
+        events.extend(collect(&mut second).await);
+
+        assert_eq!(first.transcript().len(), 4);
+        assert_eq!(transcript_from_events(&events), second.transcript());
+        assert!(transcript_from_events(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn function_call_without_tools_reports_error_output() {
+        let provider = ScriptProvider::new(vec![
+            vec![function_call_frame("call_1")],
+            vec![completed_frame()],
+        ]);
+        let runtime = Runtime::new().with_provider(provider.clone());
+        let mut session = runtime
+            .start_session("run it".to_string())
+            .with_model("local/llama3");
+        collect(&mut session).await;
+
+        let requests = provider.requests.lock().expect("lock");
+        assert!(requests
+            .iter()
+            .all(|request| request.model.as_deref() == Some("local/llama3")));
+        let output = requests[1].input[2]["output"].as_str().expect("output");
+        assert!(output.contains("no tool runtime configured"));
+    }
+
+    #[tokio::test]
+    async fn max_turns_ends_session() {
+        let provider = ScriptProvider::new(vec![
+            vec![function_call_frame("call_1")],
+            vec![function_call_frame("call_2")],
+            vec![function_call_frame("call_3")],
+        ]);
+        let runtime = Runtime::new()
+            .with_provider(provider.clone())
+            .with_tools(Arc::new(EchoTools))
+            .with_max_turns(2);
+        let mut session = runtime.start_session("loop".to_string());
+        let events = collect(&mut session).await;
+
+        assert_eq!(provider.requests.lock().expect("lock").len(), 2);
+        assert!(matches!(
+            events.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { reason }) if reason == "max_turns"
+        ));
+    }
+
+    #[tokio::test]
+    async fn provider_failure_stops_loop() {
+        let provider = ScriptProvider::new(vec![vec![
+            function_call_frame("call_1"),
+            provider_frame(serde_json::json!({
+                "type": "response.failed",
+                "sequence_number": 2,
+                "response": {}
+            })),
+        ]]);
+        let runtime = Runtime::new()
+            .with_provider(provider.clone())
+            .with_tools(Arc::new(EchoTools));
+        let mut session = runtime.start_session("fail".to_string());
+        let events = collect(&mut session).await;
+
+        assert_eq!(provider.requests.lock().expect("lock").len(), 1);
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event.kind, EventKind::ToolStarted { .. })));
+        assert!(matches!(
+            events.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { reason }) if reason == "provider_error"
+        ));
+    }
+
+    struct HangingProvider;
+
+    impl ProviderAdapter for HangingProvider {
+        fn stream(&self, _request: ModelRequest) -> BoxStream<'static, EventKind> {
+            futures_util::stream::iter(vec![EventKind::OutputTextDelta {
+                delta: "thinking".to_string(),
+            }])
+            .chain(futures_util::stream::pending())
+            .boxed()
+        }
+    }
+
+    /// Runs until cancelled, then reports the failure like a real runner.
+    struct BlockingTools;
+
+    impl ToolDispatcher for BlockingTools {
+        fn dispatch(
+            &self,
+            _session_id: &str,
+            _call: ToolCall,
+            cancel: CancellationToken,
+        ) -> BoxStream<'static, EventKind> {
+            let started = futures_util::stream::iter(vec![EventKind::ToolStarted {
+                tool_id: "t1".to_string(),
+                name: "block".to_string(),
+                args: serde_json::json!({}),
+                timeout_ms: None,
+            }]);
+            let failed = futures_util::stream::once(async move {
+                cancel.cancelled().await;
+                EventKind::ToolFailed {
+                    tool_id: "t1".to_string(),
+                    error: "cancelled".to_string(),
+                    signal: None,
+                    limit: None,
+                    stdout: None,
+                    stderr: None,
+                }
+            });
+            started.chain(failed).boxed()
+        }
+    }
+
+    /// Says something mid-call, then runs until its token is cancelled.
+    struct ChattyTools {
+        cancels: Arc<std::sync::Mutex<Vec<CancellationToken>>>,
+    }
+
+    impl ToolDispatcher for ChattyTools {
+        fn dispatch(
+            &self,
+            _session_id: &str,
+            _call: ToolCall,
+            cancel: CancellationToken,
+        ) -> BoxStream<'static, EventKind> {
+            self.cancels.lock().expect("lock").push(cancel.clone());
+            let delta = futures_util::stream::iter(vec![EventKind::OutputTextDelta {
+                delta: "working".to_string(),
+            }]);
+            let failed = futures_util::stream::once(async move {
+                cancel.cancelled().await;
+                EventKind::ToolFailed {
+                    tool_id: "t1".to_string(),
+                    error: "cancelled".to_string(),
+                    signal: None,
+                    limit: None,
+                    stdout: None,
+                    stderr: None,
+                }
+            });
+            delta.chain(failed).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_abort_cancels_the_running_tool() {
+        let provider =
+            ScriptProvider::new(vec![vec![function_call_frame("call_1"), completed_frame()]]);
+        let cancels = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let runtime = Runtime::new()
+            .with_provider(provider)
+            .with_tools(Arc::new(ChattyTools {
+                cancels: cancels.clone(),
+            }));
+        runtime.register_hook("abort-on-output", HookEventKind::Output, |_| {
+            HookOutcome::Abort {
+                reason: "stop".to_string(),
+            }
+        });
+        let cancel = CancellationToken::new();
+        let mut session = runtime
+            .start_session("run it".to_string())
+            .with_cancel(cancel.clone());
+        let events = collect(&mut session).await;
+
+        assert!(matches!(
+            events.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { reason }) if reason == "stop"
+        ));
+        let cancels = cancels.lock().expect("lock");
+        assert_eq!(cancels.len(), 1);
+        assert!(cancels[0].is_cancelled());
+        assert!(!cancel.is_cancelled());
+    }
+
+    #[test]
+    fn tool_output_keeps_chunks_as_streamed() {
+        let mut collector = ToolOutputCollector::default();
+        for chunk in ["hel", "lo\nwor", "ld\n"] {
+            collector.observe(&EventKind::ToolStdout {
+                tool_id: "t1".to_string(),
+                chunk: chunk.to_string(),
+                job_id: None,
+            });
+        }
+        collector.observe(&EventKind::ToolStderr {
+            tool_id: "t1".to_string(),
+            chunk: "warn".to_string(),
+            job_id: None,
+        });
+        collector.observe(&EventKind::ToolEnded {
+            tool_id: "t1".to_string(),
+            exit_code: 0,
+            duration_ms: 0,
+            artifacts: None,
+        });
+
+        let output = collector.into_output();
+        assert_eq!(output["stdout"], "hello\nworld\n");
+        assert_eq!(output["stderr"], "warn");
+    }
+
+    #[tokio::test]
+    async fn cancel_before_start_still_opens_the_run() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let mut session = Runtime::new()
+            .start_session("late".to_string())
+            .with_cancel(cancel);
+        let events = collect(&mut session).await;
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0].kind,
+            EventKind::SessionStarted { input } if input == "late"
+        ));
+        assert!(matches!(
+            &events[1].kind,
+            EventKind::SessionEnded { reason } if reason == "cancelled"
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancel_interrupts_provider_stream() {
+        let cancel = CancellationToken::new();
+        let runtime = Runtime::new().with_provider(Arc::new(HangingProvider));
+        let mut session = runtime
+            .start_session("hang".to_string())
+            .with_cancel(cancel.clone());
+
+        let mut events = Vec::new();
+        while let Some(event) = session.next_event().await {
+            if matches!(event.kind, EventKind::OutputTextDelta { .. }) {
+                cancel.cancel();
+            }
+            events.push(event);
+        }
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[2].kind,
+            EventKind::SessionEnded { reason } if reason == "cancelled"
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancel_waits_for_in_flight_tool_failure() {
+        let provider = ScriptProvider::new(vec![
+            vec![function_call_frame("call_1"), completed_frame()],
+            vec![completed_frame()],
+        ]);
+        let cancel = CancellationToken::new();
+        let runtime = Runtime::new()
+            .with_provider(provider.clone())
+            .with_tools(Arc::new(BlockingTools));
+        let mut session = runtime
+            .start_session("run it".to_string())
+            .with_cancel(cancel.clone());
+
+        let mut events = Vec::new();
+        while let Some(event) = session.next_event().await {
+            if matches!(event.kind, EventKind::ToolStarted { .. }) {
+                cancel.cancel();
+            }
+            events.push(event);
+        }
+
+        let kinds: Vec<&EventKind> = events
+            .iter()
+            .rev()
+            .take(2)
+            .map(|event| &event.kind)
+            .collect();
+        assert!(matches!(
+            kinds[1],
+            EventKind::ToolFailed { error, .. } if error == "cancelled"
+        ));
+        assert!(matches!(
+            kinds[0],
+            EventKind::SessionEnded { reason } if reason == "cancelled"
+        ));
+        assert_eq!(provider.requests.lock().expect("lock").len(), 1);
+    }
 }
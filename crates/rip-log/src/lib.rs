@@ -163,15 +163,15 @@ mod tests {
     use rip_kernel::{EventKind, Runtime};
     use tempfile::tempdir;
 
-    #[test]
-    fn append_and_replay_events() {
+    #[tokio::test]
+    async fn append_and_replay_events() {
         let dir = tempdir().expect("tmp");
         let log_path = dir.path().join("events.jsonl");
         let log = EventLog::new(&log_path).expect("log");
 
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             log.append(&event).expect("append");
         }
 
@@ -180,13 +180,13 @@ mod tests {
         matches!(events[0].kind, EventKind::SessionStarted { .. });
     }
 
-    #[test]
-    fn write_snapshot_creates_file() {
+    #[tokio::test]
+    async fn write_snapshot_creates_file() {
         let dir = tempdir().expect("tmp");
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
         let mut events = Vec::new();
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             events.push(event);
         }
 
@@ -236,8 +236,8 @@ mod tests {
         assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
 
-    #[test]
-    fn verify_snapshot_matches_replay() {
+    #[tokio::test]
+    async fn verify_snapshot_matches_replay() {
         let dir = tempdir().expect("tmp");
         let log_path = dir.path().join("events.jsonl");
         let log = EventLog::new(&log_path).expect("log");
@@ -245,7 +245,7 @@ mod tests {
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
         let mut events = Vec::new();
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             log.append(&event).expect("append");
             events.push(event);
         }
@@ -254,8 +254,8 @@ mod tests {
         verify_snapshot(&log, snapshot_path).expect("verify");
     }
 
-    #[test]
-    fn verify_snapshot_detects_mismatch() {
+    #[tokio::test]
+    async fn verify_snapshot_detects_mismatch() {
         let dir = tempdir().expect("tmp");
         let log_path = dir.path().join("events.jsonl");
         let log = EventLog::new(&log_path).expect("log");
@@ -263,7 +263,7 @@ mod tests {
         let runtime = Runtime::new();
         let mut session = runtime.start_session("hello".to_string());
         let mut events = Vec::new();
-        while let Some(event) = session.next_event() {
+        while let Some(event) = session.next_event().await {
             log.append(&event).expect("append");
             events.push(event);
         }
@@ -342,14 +342,13 @@ pub fn validate_item_param(value: &Value) -> Result<(), Vec<String>> {
         "message" => {
             let context = "ItemParam(message)";
             match require_field(map, "role", context, &mut errors) {
-                Some(Value::String(role)) => {
-                    if !MESSAGE_ROLES.contains(&role.as_str()) {
-                        errors.push(format!(
-                            "{context}.role must be one of {}",
-                            MESSAGE_ROLES.join(", ")
-                        ));
-                    }
+                Some(Value::String(role)) if !MESSAGE_ROLES.contains(&role.as_str()) => {
+                    errors.push(format!(
+                        "{context}.role must be one of {}",
+                        MESSAGE_ROLES.join(", ")
+                    ));
                 }
+                Some(Value::String(_)) => {}
                 Some(_) => errors.push(format!("{context}.role must be a string")),
                 None => {}
             }
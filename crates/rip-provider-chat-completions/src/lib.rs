@@ -0,0 +1,118 @@
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde_json::Value;
+
+use rip_provider_openresponses::{
+    CreateResponsePayload, EndpointConfig, ParsedEvent, ParsedEventKind, Provider, ProviderClient,
+    ProviderClientBuilder, SseDecoder,
+};
+
+mod mapper;
+mod request;
+pub use mapper::ChatStreamMapper;
+pub use request::chat_request;
+
+pub const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+
+/// `Provider` for servers that only speak streaming `/chat/completions`.
+///
+/// Replies are re-encoded as OpenResponses stream events so the usual
+/// `EventFrameMapper` pipeline applies unchanged.
+#[derive(Debug, Clone)]
+pub struct ChatCompletionsProvider {
+    client: ProviderClient,
+}
+
+impl ChatCompletionsProvider {
+    /// `client` must target the chat-completions path (see `client_builder`).
+    pub fn new(client: ProviderClient) -> Self {
+        Self { client }
+    }
+
+    pub fn client_builder(base_url: impl Into<String>) -> ProviderClientBuilder {
+        ProviderClient::builder(base_url).path(CHAT_COMPLETIONS_PATH)
+    }
+
+    pub fn from_endpoint(endpoint: &EndpointConfig) -> Result<Self, String> {
+        let client = endpoint
+            .client_builder()?
+            .path(CHAT_COMPLETIONS_PATH)
+            .build()?;
+        Ok(Self::new(client))
+    }
+}
+
+impl Provider for ChatCompletionsProvider {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        let client = self.client.clone();
+        let body = chat_request(&payload);
+        let model = body
+            .get("model")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        stream::once(async move {
+            match client.stream_json(&body).await {
+                Ok(chunks) => translate(chunks, ChatStreamMapper::new(model)),
+                Err(err) => {
+                    let mut mapper = ChatStreamMapper::new(model);
+                    let error = serde_json::json!({
+                        "error": {"code": "request_failed", "message": err}
+                    });
+                    stream::iter(encode(mapper.push(&error))).boxed()
+                }
+            }
+        })
+        .flatten()
+        .boxed()
+    }
+}
+
+fn translate(
+    chunks: BoxStream<'static, ParsedEvent>,
+    mut mapper: ChatStreamMapper,
+) -> BoxStream<'static, ParsedEvent> {
+    chunks
+        .map(Some)
+        .chain(stream::once(async { None }))
+        .flat_map(move |chunk| {
+            let events = match chunk {
+                Some(chunk) => match chunk.kind {
+                    ParsedEventKind::Done => {
+                        let mut events = encode(mapper.finish());
+                        events.push(chunk);
+                        events
+                    }
+                    ParsedEventKind::InvalidJson => vec![chunk],
+                    ParsedEventKind::Event => match chunk.data.as_ref() {
+                        // Transport errors from the client are already OpenResponses events.
+                        Some(data)
+                            if data.get("type").and_then(|v| v.as_str()) == Some("error") =>
+                        {
+                            mapper.push(&serde_json::json!({"error": data["error"]}));
+                            vec![chunk]
+                        }
+                        Some(data) => encode(mapper.push(data)),
+                        None => Vec::new(),
+                    },
+                },
+                None => encode(mapper.finish()),
+            };
+            stream::iter(events)
+        })
+        .boxed()
+}
+
+/// Runs synthetic events through `SseDecoder` so they carry the same
+/// validation results as events read off the wire.
+fn encode(events: Vec<Value>) -> Vec<ParsedEvent> {
+    let mut sse = String::new();
+    for event in events {
+        let name = event
+            .get("type")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default();
+        sse.push_str(&format!("event: {name}\ndata: {event}\n\n"));
+    }
+    SseDecoder::new().push(&sse)
+}
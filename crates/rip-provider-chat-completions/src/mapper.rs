@@ -0,0 +1,417 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+#[derive(Debug)]
+struct TextItem {
+    id: String,
+    output_index: u64,
+    text: String,
+}
+
+#[derive(Debug)]
+struct ToolItem {
+    id: String,
+    output_index: u64,
+    call_id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Converts chat-completions stream chunks into synthetic OpenResponses
+/// stream events (`response.*` JSON objects with a `type` and `sequence_number`).
+#[derive(Debug)]
+pub struct ChatStreamMapper {
+    response_id: String,
+    created_at: u64,
+    model: String,
+    seq: u64,
+    started: bool,
+    finished: bool,
+    next_output_index: u64,
+    text: Option<TextItem>,
+    tools: BTreeMap<u64, ToolItem>,
+    finish_reason: Option<String>,
+    usage: Option<Value>,
+}
+
+impl ChatStreamMapper {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            response_id: format!("resp_{}", Uuid::new_v4().simple()),
+            created_at: now_secs(),
+            model: model.into(),
+            seq: 0,
+            started: false,
+            finished: false,
+            next_output_index: 0,
+            text: None,
+            tools: BTreeMap::new(),
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &Value) -> Vec<Value> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+
+        if let Some(error) = chunk.get("error") {
+            events.push(self.error(error));
+            self.finished = true;
+            return events;
+        }
+
+        if let Some(model) = chunk.get("model").and_then(|value| value.as_str()) {
+            if self.model.is_empty() {
+                self.model = model.to_string();
+            }
+        }
+        if !self.started {
+            self.started = true;
+            let response = self.response("in_progress", Vec::new());
+            events.push(self.event("response.created", json!({ "response": response })));
+        }
+        if let Some(usage) = chunk.get("usage").filter(|value| !value.is_null()) {
+            self.usage = Some(usage.clone());
+        }
+
+        let choices = chunk.get("choices").and_then(|value| value.as_array());
+        for choice in choices.into_iter().flatten() {
+            if choice
+                .get("index")
+                .and_then(|value| value.as_u64())
+                .unwrap_or(0)
+                != 0
+            {
+                continue;
+            }
+            if let Some(delta) = choice.get("delta") {
+                if let Some(text) = delta.get("content").and_then(|value| value.as_str()) {
+                    if !text.is_empty() {
+                        self.push_text(text, &mut events);
+                    }
+                }
+                let calls = delta.get("tool_calls").and_then(|value| value.as_array());
+                for call in calls.into_iter().flatten() {
+                    self.push_tool_call(call, &mut events);
+                }
+            }
+            if let Some(reason) = choice.get("finish_reason").and_then(|value| value.as_str()) {
+                self.finish_reason = Some(reason.to_string());
+            }
+        }
+        events
+    }
+
+    /// Closes open items and emits the terminal `response.*` event.
+    pub fn finish(&mut self) -> Vec<Value> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.finished = true;
+        if !self.started {
+            let response = self.response("in_progress", Vec::new());
+            events.push(self.event("response.created", json!({ "response": response })));
+        }
+
+        let mut output = Vec::new();
+        if let Some(text) = self.text.take() {
+            events.push(self.event(
+                "response.output_text.done",
+                json!({
+                    "item_id": text.id,
+                    "output_index": text.output_index,
+                    "content_index": 0,
+                    "text": text.text,
+                    "logprobs": []
+                }),
+            ));
+            let item = json!({
+                "type": "message",
+                "id": text.id,
+                "role": "assistant",
+                "status": "completed",
+                "content": [{"type": "output_text", "text": text.text, "annotations": []}]
+            });
+            output.push((text.output_index, item));
+        }
+        for (_, tool) in std::mem::take(&mut self.tools) {
+            events.push(self.event(
+                "response.function_call_arguments.done",
+                json!({
+                    "item_id": tool.id,
+                    "output_index": tool.output_index,
+                    "arguments": tool.arguments
+                }),
+            ));
+            let item = json!({
+                "type": "function_call",
+                "id": tool.id,
+                "call_id": tool.call_id,
+                "name": tool.name,
+                "arguments": tool.arguments,
+                "status": "completed"
+            });
+            output.push((tool.output_index, item));
+        }
+
+        output.sort_by_key(|(index, _)| *index);
+        for (index, item) in &output {
+            events.push(self.event(
+                "response.output_item.done",
+                json!({ "output_index": index, "item": item }),
+            ));
+        }
+
+        let items = output.into_iter().map(|(_, item)| item).collect();
+        if self.finish_reason.as_deref() == Some("length") {
+            let mut response = self.response("incomplete", items);
+            response["incomplete_details"] = json!({"reason": "max_output_tokens"});
+            events.push(self.event("response.incomplete", json!({ "response": response })));
+        } else {
+            let response = self.response("completed", items);
+            events.push(self.event("response.completed", json!({ "response": response })));
+        }
+        events
+    }
+
+    fn push_text(&mut self, delta: &str, events: &mut Vec<Value>) {
+        if self.text.is_none() {
+            let item = TextItem {
+                id: format!("msg_{}", Uuid::new_v4().simple()),
+                output_index: self.take_output_index(),
+                text: String::new(),
+            };
+            events.push(self.event(
+                "response.output_item.added",
+                json!({
+                    "output_index": item.output_index,
+                    "item": {
+                        "type": "message",
+                        "id": item.id,
+                        "role": "assistant",
+                        "status": "in_progress",
+                        "content": []
+                    }
+                }),
+            ));
+            self.text = Some(item);
+        }
+
+        let (item_id, output_index) = match self.text.as_mut() {
+            Some(text) => {
+                text.text.push_str(delta);
+                (text.id.clone(), text.output_index)
+            }
+            None => return,
+        };
+        events.push(self.event(
+            "response.output_text.delta",
+            json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": 0,
+                "delta": delta,
+                "logprobs": []
+            }),
+        ));
+    }
+
+    fn push_tool_call(&mut self, call: &Value, events: &mut Vec<Value>) {
+        let index = call
+            .get("index")
+            .and_then(|value| value.as_u64())
+            .unwrap_or(0);
+        let function = call.get("function");
+        let name = function
+            .and_then(|value| value.get("name"))
+            .and_then(|value| value.as_str());
+        let arguments = function
+            .and_then(|value| value.get("arguments"))
+            .and_then(|value| value.as_str())
+            .unwrap_or("");
+
+        if !self.tools.contains_key(&index) {
+            let item = ToolItem {
+                id: format!("fc_{}", Uuid::new_v4().simple()),
+                output_index: self.take_output_index(),
+                call_id: call
+                    .get("id")
+                    .and_then(|value| value.as_str())
+                    .map(|value| value.to_string())
+                    .unwrap_or_else(|| format!("call_{}", Uuid::new_v4().simple())),
+                name: name.unwrap_or_default().to_string(),
+                arguments: String::new(),
+            };
+            events.push(self.event(
+                "response.output_item.added",
+                json!({
+                    "output_index": item.output_index,
+                    "item": {
+                        "type": "function_call",
+                        "id": item.id,
+                        "call_id": item.call_id,
+                        "name": item.name,
+                        "arguments": "",
+                        "status": "in_progress"
+                    }
+                }),
+            ));
+            self.tools.insert(index, item);
+        }
+
+        let (item_id, output_index) = match self.tools.get_mut(&index) {
+            Some(tool) => {
+                if tool.name.is_empty() {
+                    tool.name = name.unwrap_or_default().to_string();
+                }
+                tool.arguments.push_str(arguments);
+                (tool.id.clone(), tool.output_index)
+            }
+            None => return,
+        };
+        if !arguments.is_empty() {
+            events.push(self.event(
+                "response.function_call_arguments.delta",
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "delta": arguments
+                }),
+            ));
+        }
+    }
+
+    fn error(&mut self, error: &Value) -> Value {
+        let message = error
+            .get("message")
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| error.to_string());
+        let code = match error.get("code") {
+            Some(Value::String(code)) => Value::String(code.clone()),
+            Some(Value::Number(code)) => Value::String(code.to_string()),
+            _ => Value::Null,
+        };
+        self.event(
+            "error",
+            json!({
+                "error": {
+                    "type": error.get("type").cloned().unwrap_or_else(|| json!("provider_error")),
+                    "code": code,
+                    "message": message,
+                    "param": error.get("param").cloned().unwrap_or(Value::Null)
+                }
+            }),
+        )
+    }
+
+    fn response(&self, status: &str, output: Vec<Value>) -> Value {
+        json!({
+            "id": self.response_id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": output,
+            "usage": self.usage.as_ref().map(|usage| json!({
+                "input_tokens": usage.get("prompt_tokens").cloned().unwrap_or(json!(0)),
+                "output_tokens": usage.get("completion_tokens").cloned().unwrap_or(json!(0)),
+                "total_tokens": usage.get("total_tokens").cloned().unwrap_or(json!(0))
+            }))
+        })
+    }
+
+    fn event(&mut self, event_type: &str, mut body: Value) -> Value {
+        self.seq += 1;
+        if let Value::Object(obj) = &mut body {
+            obj.insert("type".to_string(), Value::String(event_type.to_string()));
+            obj.insert(
+                "sequence_number".to_string(),
+                Value::Number(self.seq.into()),
+            );
+        }
+        body
+    }
+
+    fn take_output_index(&mut self) -> u64 {
+        let index = self.next_output_index;
+        self.next_output_index += 1;
+        index
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(events: &[Value]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| event["type"].as_str().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn text_deltas_become_output_text_events() {
+        let mut mapper = ChatStreamMapper::new("m");
+        let mut events = mapper.push(
+            &json!({"choices": [{"index": 0, "delta": {"role": "assistant", "content": "he"}}]}),
+        );
+        events.extend(mapper.push(&json!({"choices": [{"index": 0, "delta": {"content": "llo"}, "finish_reason": "stop"}]})));
+        events.extend(mapper.finish());
+
+        assert_eq!(
+            types(&events),
+            vec![
+                "response.created",
+                "response.output_item.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.output_item.done",
+                "response.completed"
+            ]
+        );
+        for (idx, event) in events.iter().enumerate() {
+            assert_eq!(event["sequence_number"], idx as u64 + 1);
+        }
+        assert_eq!(events[5]["item"]["content"][0]["text"], "hello");
+        assert_eq!(events[6]["response"]["status"], "completed");
+    }
+
+    #[test]
+    fn length_finish_reason_is_incomplete() {
+        let mut mapper = ChatStreamMapper::new("m");
+        mapper.push(&json!({"choices": [{"index": 0, "delta": {"content": "x"}, "finish_reason": "length"}]}));
+        let events = mapper.finish();
+        let last = events.last().expect("last");
+        assert_eq!(last["type"], "response.incomplete");
+        assert_eq!(
+            last["response"]["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+    }
+
+    #[test]
+    fn error_chunk_emits_error_event_and_stops() {
+        let mut mapper = ChatStreamMapper::new("m");
+        let events = mapper.push(&json!({"error": {"message": "overloaded", "code": 503}}));
+        assert_eq!(types(&events), vec!["error"]);
+        assert_eq!(events[0]["error"]["code"], "503");
+        assert!(mapper.finish().is_empty());
+    }
+}
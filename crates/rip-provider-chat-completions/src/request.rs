@@ -0,0 +1,178 @@
+use serde_json::{json, Map, Value};
+
+use rip_provider_openresponses::CreateResponsePayload;
+
+/// Translates an OpenResponses create body into a streaming
+/// `/chat/completions` request.
+pub fn chat_request(payload: &CreateResponsePayload) -> Value {
+    let body = payload.body();
+    let mut out = Map::new();
+
+    if let Some(model) = body.get("model") {
+        out.insert("model".to_string(), model.clone());
+    }
+    out.insert("messages".to_string(), Value::Array(messages(body)));
+
+    if let Some(tools) = body.get("tools").and_then(|value| value.as_array()) {
+        let tools = tools.iter().filter_map(chat_tool).collect::<Vec<_>>();
+        if !tools.is_empty() {
+            out.insert("tools".to_string(), Value::Array(tools));
+        }
+    }
+    if let Some(choice) = body.get("tool_choice").and_then(chat_tool_choice) {
+        out.insert("tool_choice".to_string(), choice);
+    }
+    if let Some(value) = body.get("parallel_tool_calls") {
+        out.insert("parallel_tool_calls".to_string(), value.clone());
+    }
+    if let Some(value) = body
+        .get("max_output_tokens")
+        .filter(|value| !value.is_null())
+    {
+        out.insert("max_tokens".to_string(), value.clone());
+    }
+    for key in ["temperature", "top_p", "user"] {
+        if let Some(value) = body.get(key).filter(|value| !value.is_null()) {
+            out.insert(key.to_string(), value.clone());
+        }
+    }
+    out.insert("stream".to_string(), Value::Bool(true));
+
+    Value::Object(out)
+}
+
+fn messages(body: &Value) -> Vec<Value> {
+    let mut messages = Vec::new();
+    if let Some(instructions) = body.get("instructions").and_then(|value| value.as_str()) {
+        messages.push(json!({"role": "system", "content": instructions}));
+    }
+
+    match body.get("input") {
+        Some(Value::String(text)) => messages.push(json!({"role": "user", "content": text})),
+        Some(Value::Array(items)) => {
+            for item in items {
+                push_item(&mut messages, item);
+            }
+        }
+        _ => {}
+    }
+    messages
+}
+
+fn push_item(messages: &mut Vec<Value>, item: &Value) {
+    let item_type = item
+        .get("type")
+        .and_then(|value| value.as_str())
+        .unwrap_or("message");
+    match item_type {
+        "message" => {
+            let role = match item.get("role").and_then(|value| value.as_str()) {
+                Some("developer") => "system",
+                Some(role) => role,
+                None => "user",
+            };
+            let content = item.get("content").map(chat_content).unwrap_or_default();
+            messages.push(json!({"role": role, "content": content}));
+        }
+        "function_call" => {
+            let call = json!({
+                "id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": item.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": item.get("arguments").cloned().unwrap_or_else(|| json!("{}"))
+                }
+            });
+            // Consecutive calls belong to one assistant turn.
+            if let Some(calls) = messages
+                .last_mut()
+                .filter(|last| last["role"] == "assistant" && last["content"].is_null())
+                .and_then(|last| last.get_mut("tool_calls"))
+                .and_then(|calls| calls.as_array_mut())
+            {
+                calls.push(call);
+                return;
+            }
+            messages.push(json!({
+                "role": "assistant",
+                "content": Value::Null,
+                "tool_calls": [call]
+            }));
+        }
+        "function_call_output" => {
+            let output = match item.get("output") {
+                Some(Value::String(text)) => text.clone(),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                "content": output
+            }));
+        }
+        _ => {}
+    }
+}
+
+fn chat_content(content: &Value) -> Value {
+    let parts = match content {
+        Value::Array(parts) => parts,
+        other => return other.clone(),
+    };
+
+    let mut text = Vec::new();
+    let mut chat_parts = Vec::new();
+    let mut text_only = true;
+    for part in parts {
+        match part.get("type").and_then(|value| value.as_str()) {
+            Some("input_text") | Some("output_text") | Some("text") => {
+                let value = part
+                    .get("text")
+                    .and_then(|value| value.as_str())
+                    .unwrap_or("");
+                text.push(value.to_string());
+                chat_parts.push(json!({"type": "text", "text": value}));
+            }
+            Some("input_image") => {
+                text_only = false;
+                chat_parts.push(json!({
+                    "type": "image_url",
+                    "image_url": {"url": part.get("image_url").cloned().unwrap_or(Value::Null)}
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    if text_only {
+        Value::String(text.join(""))
+    } else {
+        Value::Array(chat_parts)
+    }
+}
+
+fn chat_tool(tool: &Value) -> Option<Value> {
+    if tool.get("type").and_then(|value| value.as_str()) != Some("function") {
+        return None;
+    }
+    let mut function = Map::new();
+    for key in ["name", "description", "parameters", "strict"] {
+        if let Some(value) = tool.get(key).filter(|value| !value.is_null()) {
+            function.insert(key.to_string(), value.clone());
+        }
+    }
+    Some(json!({"type": "function", "function": function}))
+}
+
+fn chat_tool_choice(choice: &Value) -> Option<Value> {
+    match choice {
+        Value::String(_) => Some(choice.clone()),
+        Value::Object(obj)
+            if obj.get("type").and_then(|value| value.as_str()) == Some("function") =>
+        {
+            Some(json!({"type": "function", "function": {"name": obj.get("name")?}}))
+        }
+        _ => None,
+    }
+}
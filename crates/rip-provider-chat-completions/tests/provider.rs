@@ -0,0 +1,169 @@
+use futures_util::StreamExt;
+use httpmock::Method::POST;
+use httpmock::MockServer;
+use rip_kernel::{Event, EventKind};
+use rip_provider_chat_completions::{chat_request, ChatCompletionsProvider};
+use rip_provider_openresponses::{
+    extract_text_deltas, extract_tool_call_argument_deltas, CreateResponseBuilder, EndpointConfig,
+    EventFrameMapper, ParsedEvent, ParsedEventKind, Provider,
+};
+use serde_json::{json, Value};
+
+fn event_types(events: &[ParsedEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|event| match &event.data {
+            Some(data) => data["type"].as_str().unwrap_or_default().to_string(),
+            None => event.raw.clone(),
+        })
+        .collect()
+}
+
+#[test]
+fn chat_request_translates_responses_body() {
+    let payload = CreateResponseBuilder::new()
+        .model("llama3")
+        .insert_raw("instructions", json!("be brief"))
+        .input_items_raw(vec![
+            json!({"type": "message", "role": "user", "content": [{"type": "input_text", "text": "list"}]}),
+            json!({"type": "function_call", "call_id": "call_1", "name": "ls", "arguments": "{}"}),
+            json!({"type": "function_call", "call_id": "call_2", "name": "read", "arguments": "{\"path\":\"a\"}"}),
+            json!({"type": "function_call_output", "call_id": "call_1", "output": "a\nb"}),
+            json!({"type": "function_call_output", "call_id": "call_2", "output": "hi"}),
+        ])
+        .tools_raw(vec![
+            json!({"type": "function", "name": "ls", "description": "List", "parameters": {"type": "object"}}),
+            json!({"type": "web_search"}),
+        ])
+        .tool_choice_raw(json!({"type": "function", "name": "ls"}))
+        .insert_raw("max_output_tokens", json!(64))
+        .build();
+
+    let body = chat_request(&payload);
+    assert_eq!(body["model"], "llama3");
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["max_tokens"], 64);
+
+    let messages = body["messages"].as_array().expect("messages");
+    assert_eq!(messages.len(), 5);
+    assert_eq!(
+        messages[0],
+        json!({"role": "system", "content": "be brief"})
+    );
+    assert_eq!(messages[1], json!({"role": "user", "content": "list"}));
+    assert_eq!(messages[2]["role"], "assistant");
+    assert_eq!(
+        messages[2]["tool_calls"].as_array().expect("calls").len(),
+        2
+    );
+    assert_eq!(messages[2]["tool_calls"][1]["function"]["name"], "read");
+    assert_eq!(
+        messages[3],
+        json!({"role": "tool", "tool_call_id": "call_1", "content": "a\nb"})
+    );
+
+    let tools = body["tools"].as_array().expect("tools");
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0]["function"]["name"], "ls");
+    assert_eq!(tools[0]["function"]["parameters"]["type"], "object");
+    assert_eq!(
+        body["tool_choice"],
+        json!({"type": "function", "function": {"name": "ls"}})
+    );
+}
+
+#[tokio::test]
+async fn streams_chat_chunks_as_openresponses_events() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(r#"{"model":"llama3","stream":true}"#);
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(include_str!("../fixtures/text_and_tool.sse"));
+    });
+
+    let provider = ChatCompletionsProvider::new(
+        ChatCompletionsProvider::client_builder(server.url("/v1"))
+            .build()
+            .expect("client"),
+    );
+    let payload = CreateResponseBuilder::new()
+        .model("llama3")
+        .input_text("hi")
+        .build();
+    let events: Vec<ParsedEvent> = provider.stream(payload).collect().await;
+    mock.assert();
+
+    assert_eq!(
+        event_types(&events),
+        vec![
+            "response.created",
+            "response.output_item.added",
+            "response.output_text.delta",
+            "response.output_text.delta",
+            "response.output_item.added",
+            "response.function_call_arguments.delta",
+            "response.function_call_arguments.delta",
+            "response.output_text.done",
+            "response.function_call_arguments.done",
+            "response.output_item.done",
+            "response.output_item.done",
+            "response.completed",
+            "[DONE]",
+        ]
+    );
+    assert_eq!(events.last().expect("done").kind, ParsedEventKind::Done);
+
+    let completed = events[11].data.as_ref().expect("data");
+    assert_eq!(completed["response"]["usage"]["total_tokens"], 12);
+    let call = &completed["response"]["output"][1];
+    assert_eq!(call["call_id"], "call_1");
+    assert_eq!(call["name"], "ls");
+    assert_eq!(call["arguments"], "{\"path\":\".\"}");
+
+    let mut mapper = EventFrameMapper::new("s1");
+    let frames: Vec<Event> = events.iter().flat_map(|event| mapper.map(event)).collect();
+    assert_eq!(
+        extract_text_deltas(&frames),
+        vec!["Let me ".to_string(), "check.".to_string()]
+    );
+    assert_eq!(
+        extract_tool_call_argument_deltas(&frames),
+        vec!["{\"path\":".to_string(), "\".\"}".to_string()]
+    );
+    assert_eq!(
+        frames
+            .iter()
+            .filter(|frame| matches!(frame.kind, EventKind::OutputTextDelta { .. }))
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn request_failure_becomes_error_event() {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(404).body("no such model");
+    });
+
+    let endpoint = EndpointConfig {
+        base_url: server.url("/v1"),
+        ..EndpointConfig::default()
+    };
+    let provider = ChatCompletionsProvider::from_endpoint(&endpoint).expect("provider");
+    let payload = CreateResponseBuilder::new().model("missing").build();
+    let events: Vec<ParsedEvent> = provider.stream(payload).collect().await;
+
+    assert_eq!(events.len(), 1);
+    let data: &Value = events[0].data.as_ref().expect("data");
+    assert_eq!(data["type"], "error");
+    assert_eq!(data["error"]["code"], "request_failed");
+    assert!(data["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("no such model"));
+}
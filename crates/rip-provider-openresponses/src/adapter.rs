@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use rip_kernel::{EventKind, ModelRequest, ProviderAdapter};
+use serde_json::Value;
+
+use crate::{CreateResponseBuilder, CreateResponsePayload, EventFrameMapper, Provider, ToolParam};
+
+#[derive(Clone)]
+pub struct OpenResponsesAdapter {
+    model: Option<String>,
+    provider: Arc<dyn Provider>,
+}
+
+impl OpenResponsesAdapter {
+    pub fn new(provider: Arc<dyn Provider>) -> Self {
+        Self {
+            model: None,
+            provider,
+        }
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn build_payload(&self, request: &ModelRequest) -> CreateResponsePayload {
+        let mut builder = CreateResponseBuilder::new();
+        if let Some(model) = request.model.as_ref().or(self.model.as_ref()) {
+            builder = builder.model(model.clone());
+        }
+        builder = builder.input_items_raw(request.input.clone());
+        if !request.tools.is_empty() {
+            let tools = request.tools.iter().cloned().map(ToolParam::new).collect();
+            builder = builder.tools(tools);
+        }
+        builder.insert_raw("stream", Value::Bool(true)).build()
+    }
+}
+
+impl ProviderAdapter for OpenResponsesAdapter {
+    fn stream(&self, request: ModelRequest) -> BoxStream<'static, EventKind> {
+        let payload = self.build_payload(&request);
+        let mut mapper = EventFrameMapper::new(request.session_id);
+
+        self.provider
+            .stream(payload)
+            .flat_map(move |parsed| {
+                let frames = mapper
+                    .map(&parsed)
+                    .into_iter()
+                    .map(|event| event.kind)
+                    .collect::<Vec<_>>();
+                stream::iter(frames)
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use crate::provider::error_event;
+use crate::{CreateResponsePayload, ParsedEvent, SseDecoder};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde_json::Value;
+
+/// Streams `POST {base_url}{path}` replies as parsed SSE events.
+///
+/// `base_url` includes the API version prefix, e.g. `https://api.openai.com/v1`;
+/// `path` defaults to `/responses`.
+#[derive(Debug, Clone)]
+pub struct ProviderClient {
+    client: reqwest::Client,
+    endpoint: String,
+    api_key: Option<String>,
+    headers: Vec<(String, String)>,
+    request_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderClientBuilder {
+    base_url: String,
+    path: String,
+    api_key: Option<String>,
+    headers: Vec<(String, String)>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl ProviderClientBuilder {
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Upper bound on waiting for the response headers.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Upper bound on the gap between two body chunks once streaming started.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn build(self) -> Result<ProviderClient, String> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        let client = builder.build().map_err(|err| err.to_string())?;
+        Ok(ProviderClient {
+            client,
+            endpoint: format!(
+                "{}/{}",
+                self.base_url.trim_end_matches('/'),
+                self.path.trim_start_matches('/')
+            ),
+            api_key: self.api_key,
+            headers: self.headers,
+            request_timeout: self.request_timeout,
+            idle_timeout: self.idle_timeout,
+        })
+    }
+}
+
+impl ProviderClient {
+    pub fn builder(base_url: impl Into<String>) -> ProviderClientBuilder {
+        ProviderClientBuilder {
+            base_url: base_url.into(),
+            path: "/responses".to_string(),
+            api_key: None,
+            headers: Vec::new(),
+            connect_timeout: None,
+            request_timeout: None,
+            idle_timeout: None,
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Sends the payload and returns the decoded event stream.
+    ///
+    /// Connection and non-2xx failures are returned as `Err`; failures after the
+    /// stream started are yielded as a final `error` event.
+    pub async fn stream(
+        &self,
+        payload: &CreateResponsePayload,
+    ) -> Result<BoxStream<'static, ParsedEvent>, String> {
+        self.stream_json(payload.body()).await
+    }
+
+    /// Like `stream`, for endpoints that take a non-OpenResponses body.
+    pub async fn stream_json(
+        &self,
+        body: &Value,
+    ) -> Result<BoxStream<'static, ParsedEvent>, String> {
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .json(body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        let send = request.send();
+        let response = match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| format!("request timed out after {}ms", timeout.as_millis()))?,
+            None => send.await,
+        }
+        .map_err(|err| format!("request failed: {err}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("provider returned {status}: {body}"));
+        }
+
+        let state = StreamState {
+            body: response.bytes_stream().boxed(),
+            decoder: SseDecoder::new(),
+            pending: Vec::new(),
+            idle_timeout: self.idle_timeout,
+            finished: false,
+        };
+        Ok(stream::unfold(state, next_events)
+            .flat_map(stream::iter)
+            .boxed())
+    }
+}
+
+struct StreamState {
+    body: BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+    decoder: SseDecoder,
+    pending: Vec<u8>,
+    idle_timeout: Option<Duration>,
+    finished: bool,
+}
+
+async fn next_events(mut state: StreamState) -> Option<(Vec<ParsedEvent>, StreamState)> {
+    if state.finished {
+        return None;
+    }
+
+    let next = match state.idle_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, state.body.next()).await {
+            Ok(next) => next,
+            Err(_) => {
+                state.finished = true;
+                let message = format!("no data received for {}ms", timeout.as_millis());
+                return Some((vec![error_event("idle_timeout", &message)], state));
+            }
+        },
+        None => state.body.next().await,
+    };
+
+    match next {
+        Some(Ok(bytes)) => {
+            state.pending.extend_from_slice(&bytes);
+            let text = take_utf8(&mut state.pending);
+            let events = state.decoder.push(&text);
+            Some((events, state))
+        }
+        Some(Err(err)) => {
+            state.finished = true;
+            let mut events = state.decoder.finish();
+            events.push(error_event("stream_error", &err.to_string()));
+            Some((events, state))
+        }
+        None => {
+            state.finished = true;
+            let text = String::from_utf8_lossy(&state.pending).into_owned();
+            state.pending.clear();
+            let mut events = state.decoder.push(&text);
+            events.extend(state.decoder.finish());
+            Some((events, state))
+        }
+    }
+}
+
+/// Drains the longest valid UTF-8 prefix, keeping a split code point for the next chunk.
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(_) => {
+            let text = String::from_utf8_lossy(pending).into_owned();
+            pending.clear();
+            return text;
+        }
+    };
+    let rest = pending.split_off(valid);
+    String::from_utf8(std::mem::replace(pending, rest)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_utf8_keeps_split_code_point() {
+        let bytes = "héllo".as_bytes();
+        let mut pending = bytes[..2].to_vec();
+        assert_eq!(take_utf8(&mut pending), "h");
+        assert_eq!(pending, vec![bytes[1]]);
+
+        pending.extend_from_slice(&bytes[2..]);
+        assert_eq!(take_utf8(&mut pending), "éllo");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn error_event_is_terminal_error() {
+        let event = error_event("idle_timeout", "no data");
+        let data = event.data.expect("data");
+        assert_eq!(event.event.as_deref(), Some("error"));
+        assert_eq!(data["type"], "error");
+        assert_eq!(data["error"]["code"], "idle_timeout");
+        assert_eq!(data["error"]["message"], "no data");
+    }
+}
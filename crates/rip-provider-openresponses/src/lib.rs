@@ -4,13 +4,23 @@ use uuid::Uuid;
 
 use rip_kernel::{Event, EventKind, ProviderEventStatus};
 
+mod adapter;
+mod client;
+mod provider;
 mod request;
+mod scripted;
 mod stream_transformers;
+pub use adapter::OpenResponsesAdapter;
+pub use client::{ProviderClient, ProviderClientBuilder};
+pub use provider::{
+    EndpointApi, EndpointConfig, Provider, ProviderRouter, RouteConfig, RouterConfig,
+};
 pub use request::{
     CreateResponseBuilder, CreateResponsePayload, ItemParam, SpecificToolChoiceParam,
     ToolChoiceParam, ToolChoiceValue, ToolParam,
 };
 use rip_openresponses::{validate_response_resource, validate_stream_event};
+pub use scripted::{ScriptedProvider, ScriptedTurn, TurnMatcher};
 pub use stream_transformers::{
     extract_reasoning_deltas, extract_text_deltas, extract_tool_call_argument_deltas,
 };
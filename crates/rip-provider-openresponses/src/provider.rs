@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{CreateResponsePayload, ParsedEvent, ProviderClient, ProviderClientBuilder};
+
+/// A model backend: streams one response for a `CreateResponsePayload`.
+///
+/// Failures are reported in-band as a terminal `error` event so callers only
+/// deal with a single stream.
+pub trait Provider: Send + Sync {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent>;
+}
+
+impl<F> Provider for F
+where
+    F: Fn(CreateResponsePayload) -> BoxStream<'static, ParsedEvent> + Send + Sync,
+{
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        self(payload)
+    }
+}
+
+impl Provider for ProviderClient {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        let client = self.clone();
+        stream::once(async move {
+            match ProviderClient::stream(&client, &payload).await {
+                Ok(events) => events,
+                Err(err) => stream::iter(vec![error_event("request_failed", &err)]).boxed(),
+            }
+        })
+        .flatten()
+        .boxed()
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    prefix: String,
+    strip_prefix: bool,
+    provider: Arc<dyn Provider>,
+}
+
+/// Picks a provider by the longest matching `model` prefix, falling back to
+/// the default provider when no route matches.
+#[derive(Clone, Default)]
+pub struct ProviderRouter {
+    routes: Vec<Route>,
+    fallback: Option<Arc<dyn Provider>>,
+}
+
+impl ProviderRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, prefix: impl Into<String>, provider: Arc<dyn Provider>) -> Self {
+        self.push_route(prefix.into(), false, provider);
+        self
+    }
+
+    /// Like `route`, but removes the prefix from `model` before forwarding
+    /// (`local/llama3` is sent as `llama3`).
+    pub fn route_stripped(
+        mut self,
+        prefix: impl Into<String>,
+        provider: Arc<dyn Provider>,
+    ) -> Self {
+        self.push_route(prefix.into(), true, provider);
+        self
+    }
+
+    pub fn fallback(mut self, provider: Arc<dyn Provider>) -> Self {
+        self.fallback = Some(provider);
+        self
+    }
+
+    pub fn from_config(config: &RouterConfig) -> Result<Self, String> {
+        Self::from_config_with(config, |endpoint| match endpoint.api {
+            EndpointApi::Responses => Ok(Arc::new(endpoint.build_client()?)),
+            other => Err(format!("no provider factory for {other:?} endpoints")),
+        })
+    }
+
+    /// Builds routes with a caller-supplied factory, for endpoint APIs
+    /// implemented outside this crate.
+    pub fn from_config_with<F>(config: &RouterConfig, build: F) -> Result<Self, String>
+    where
+        F: Fn(&EndpointConfig) -> Result<Arc<dyn Provider>, String>,
+    {
+        let mut router = Self::new();
+        for route in &config.routes {
+            let provider = build(&route.endpoint)?;
+            router.push_route(route.prefix.clone(), route.strip_prefix, provider);
+        }
+        if let Some(endpoint) = &config.default {
+            router.fallback = Some(build(endpoint)?);
+        }
+        Ok(router)
+    }
+
+    fn push_route(&mut self, prefix: String, strip_prefix: bool, provider: Arc<dyn Provider>) {
+        self.routes.push(Route {
+            prefix,
+            strip_prefix,
+            provider,
+        });
+        self.routes
+            .sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+    }
+
+    fn select(&self, model: &str) -> Option<(Arc<dyn Provider>, Option<String>)> {
+        for route in &self.routes {
+            if let Some(rest) = model.strip_prefix(route.prefix.as_str()) {
+                let rewritten = route.strip_prefix.then(|| rest.to_string());
+                return Some((route.provider.clone(), rewritten));
+            }
+        }
+        self.fallback.clone().map(|provider| (provider, None))
+    }
+}
+
+impl Provider for ProviderRouter {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        let model = payload
+            .body()
+            .get("model")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        match self.select(&model) {
+            Some((provider, None)) => provider.stream(payload),
+            Some((provider, Some(model))) => {
+                let mut body = payload.into_body();
+                if let Value::Object(obj) = &mut body {
+                    obj.insert("model".to_string(), Value::String(model));
+                }
+                provider.stream(CreateResponsePayload::new(body))
+            }
+            None => {
+                let message = format!("no provider configured for model '{model}'");
+                stream::iter(vec![error_event("no_provider", &message)]).boxed()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RouterConfig {
+    /// Model used when a session does not pick one.
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub default: Option<EndpointConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    pub prefix: String,
+    #[serde(default)]
+    pub strip_prefix: bool,
+    #[serde(flatten)]
+    pub endpoint: EndpointConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointApi {
+    #[default]
+    Responses,
+    ChatCompletions,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EndpointConfig {
+    pub base_url: String,
+    #[serde(default)]
+    pub api: EndpointApi,
+    /// Name of the environment variable holding the API key.
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub idle_timeout_ms: Option<u64>,
+}
+
+impl EndpointConfig {
+    pub fn build_client(&self) -> Result<ProviderClient, String> {
+        self.client_builder()?.build()
+    }
+
+    pub fn client_builder(&self) -> Result<ProviderClientBuilder, String> {
+        let mut builder = ProviderClient::builder(self.base_url.clone());
+        if let Some(var) = &self.api_key_env {
+            let key = std::env::var(var).map_err(|_| format!("missing api key env var {var}"))?;
+            builder = builder.api_key(key);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name.clone(), value.clone());
+        }
+        if let Some(ms) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.request_timeout_ms {
+            builder = builder.request_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.idle_timeout_ms {
+            builder = builder.idle_timeout(Duration::from_millis(ms));
+        }
+        Ok(builder)
+    }
+}
+
+pub(crate) fn error_event(code: &str, message: &str) -> ParsedEvent {
+    let data = json!({
+        "type": "error",
+        "sequence_number": 0,
+        "error": {
+            "type": "provider_error",
+            "code": code,
+            "message": message,
+            "param": Value::Null
+        }
+    });
+    ParsedEvent::event(data.to_string(), Some("error".to_string()), data)
+}
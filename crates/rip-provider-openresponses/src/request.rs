@@ -56,6 +56,23 @@ impl ToolParam {
         Self::new(value)
     }
 
+    /// Sets `description` (function and custom tools).
+    pub fn description(self, description: impl Into<String>) -> Self {
+        self.with_field("description", Value::String(description.into()))
+    }
+
+    /// Sets the JSON Schema for a function tool's arguments.
+    pub fn parameters(self, parameters: Value) -> Self {
+        self.with_field("parameters", parameters)
+    }
+
+    fn with_field(mut self, key: &str, value: Value) -> Self {
+        if let Value::Object(obj) = &mut self.value {
+            obj.insert(key.to_string(), value);
+        }
+        Self::new(self.value)
+    }
+
     pub fn code_interpreter(container: impl Into<Value>) -> Self {
         let mut obj = Map::new();
         obj.insert(
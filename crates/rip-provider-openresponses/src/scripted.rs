@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::provider::error_event;
+use crate::{CreateResponsePayload, ParsedEvent, Provider, SseDecoder};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TurnMatcher {
+    Any,
+    /// The latest user message contains the text.
+    InputContains(String),
+    /// The latest `function_call_output` contains the text.
+    ToolOutputContains(String),
+}
+
+impl TurnMatcher {
+    fn matches(&self, payload: &CreateResponsePayload) -> bool {
+        match self {
+            TurnMatcher::Any => true,
+            TurnMatcher::InputContains(text) => {
+                latest_user_text(payload.body()).is_some_and(|input| input.contains(text.as_str()))
+            }
+            TurnMatcher::ToolOutputContains(text) => latest_tool_output(payload.body())
+                .is_some_and(|output| output.contains(text.as_str())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptedTurn {
+    pub matcher: TurnMatcher,
+    pub sse: String,
+}
+
+/// Replays recorded SSE payloads, one per request, with no network.
+///
+/// Each request consumes the first remaining turn whose matcher accepts the
+/// payload; when none does, the stream is a single `error` event.
+#[derive(Debug, Default)]
+pub struct ScriptedProvider {
+    turns: Mutex<VecDeque<ScriptedTurn>>,
+    requests: Mutex<Vec<CreateResponsePayload>>,
+}
+
+impl ScriptedProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn turn(self, sse: impl Into<String>) -> Self {
+        self.push(TurnMatcher::Any, sse)
+    }
+
+    pub fn on_input(self, text: impl Into<String>, sse: impl Into<String>) -> Self {
+        self.push(TurnMatcher::InputContains(text.into()), sse)
+    }
+
+    pub fn on_tool_output(self, text: impl Into<String>, sse: impl Into<String>) -> Self {
+        self.push(TurnMatcher::ToolOutputContains(text.into()), sse)
+    }
+
+    pub fn push(self, matcher: TurnMatcher, sse: impl Into<String>) -> Self {
+        self.turns
+            .lock()
+            .expect("scripted turns")
+            .push_back(ScriptedTurn {
+                matcher,
+                sse: sse.into(),
+            });
+        self
+    }
+
+    /// Loads a JSON script: `{"turns": [{"sse" | "sse_file" | "events", "input_contains"?, "tool_output_contains"?}]}`.
+    /// `sse_file` is resolved relative to the script.
+    pub fn from_script(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)
+            .map_err(|err| format!("read {}: {err}", path.display()))?;
+        let script: Script =
+            serde_json::from_str(&raw).map_err(|err| format!("parse {}: {err}", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut provider = Self::new();
+        for (idx, turn) in script.turns.into_iter().enumerate() {
+            let sse = match (turn.sse, turn.sse_file, turn.events) {
+                (Some(sse), None, None) => sse,
+                (None, Some(file), None) => {
+                    let file = base.join(file);
+                    std::fs::read_to_string(&file)
+                        .map_err(|err| format!("read {}: {err}", file.display()))?
+                }
+                (None, None, Some(events)) => events_to_sse(&events),
+                _ => {
+                    return Err(format!(
+                        "turn {idx}: exactly one of sse, sse_file, events is required"
+                    ))
+                }
+            };
+            let matcher = match (turn.input_contains, turn.tool_output_contains) {
+                (Some(text), None) => TurnMatcher::InputContains(text),
+                (None, Some(text)) => TurnMatcher::ToolOutputContains(text),
+                (None, None) => TurnMatcher::Any,
+                _ => {
+                    return Err(format!(
+                        "turn {idx}: input_contains and tool_output_contains are exclusive"
+                    ))
+                }
+            };
+            provider = provider.push(matcher, sse);
+        }
+        Ok(provider)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.turns.lock().expect("scripted turns").len()
+    }
+
+    /// Payloads received so far, in order.
+    pub fn requests(&self) -> Vec<CreateResponsePayload> {
+        self.requests.lock().expect("scripted requests").clone()
+    }
+}
+
+impl Provider for ScriptedProvider {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        let turn = {
+            let mut turns = self.turns.lock().expect("scripted turns");
+            turns
+                .iter()
+                .position(|turn| turn.matcher.matches(&payload))
+                .and_then(|idx| turns.remove(idx))
+        };
+        self.requests
+            .lock()
+            .expect("scripted requests")
+            .push(payload);
+
+        let events = match turn {
+            Some(turn) => {
+                let mut decoder = SseDecoder::new();
+                let mut events = decoder.push(&turn.sse);
+                events.extend(decoder.finish());
+                events
+            }
+            None => vec![error_event(
+                "script_exhausted",
+                "no scripted turn matches this request",
+            )],
+        };
+        stream::iter(events).boxed()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Script {
+    turns: Vec<ScriptTurn>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptTurn {
+    #[serde(default)]
+    sse: Option<String>,
+    #[serde(default)]
+    sse_file: Option<String>,
+    #[serde(default)]
+    events: Option<Vec<Value>>,
+    #[serde(default)]
+    input_contains: Option<String>,
+    #[serde(default)]
+    tool_output_contains: Option<String>,
+}
+
+fn events_to_sse(events: &[Value]) -> String {
+    let mut sse = String::new();
+    for event in events {
+        if let Some(name) = event.get("type").and_then(|value| value.as_str()) {
+            sse.push_str(&format!("event: {name}\n"));
+        }
+        sse.push_str(&format!("data: {event}\n\n"));
+    }
+    sse.push_str("data: [DONE]\n\n");
+    sse
+}
+
+fn latest_user_text(body: &Value) -> Option<String> {
+    match body.get("input")? {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(items) => items
+            .iter()
+            .rev()
+            .find(|item| {
+                item.get("role").and_then(|value| value.as_str()) == Some("user")
+                    && item
+                        .get("type")
+                        .and_then(|value| value.as_str())
+                        .unwrap_or("message")
+                        == "message"
+            })
+            .and_then(|item| item.get("content"))
+            .map(content_text),
+        _ => None,
+    }
+}
+
+fn latest_tool_output(body: &Value) -> Option<String> {
+    let item = body.get("input")?.as_array()?.last()?;
+    if item.get("type").and_then(|value| value.as_str()) != Some("function_call_output") {
+        return None;
+    }
+    item.get("output").map(content_text)
+}
+
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(|value| value.as_str()))
+            .collect::<Vec<_>>()
+            .join(""),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex};
+
+use futures_util::stream::{self, StreamExt};
+use rip_kernel::{EventKind, ModelRequest, ProviderAdapter, Runtime};
+use rip_provider_openresponses::{
+    extract_text_deltas, CreateResponsePayload, OpenResponsesAdapter, SseDecoder,
+};
+use serde_json::json;
+
+const TEXT_TURN: &str = "event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"msg_1\",\"output_index\":0,\"content_index\":0,\"delta\":\"done\"}\n\n\
+data: [DONE]\n\n";
+
+const TOOL_TURN: &str = "event: response.output_item.done\n\
+data: {\"type\":\"response.output_item.done\",\"sequence_number\":1,\"output_index\":0,\"item\":{\"type\":\"function_call\",\"id\":\"fc_1\",\"call_id\":\"call_1\",\"name\":\"ls\",\"arguments\":\"{}\",\"status\":\"completed\"}}\n\n\
+data: [DONE]\n\n";
+
+fn scripted_adapter(
+    turns: Vec<&'static str>,
+    payloads: Arc<Mutex<Vec<CreateResponsePayload>>>,
+) -> OpenResponsesAdapter {
+    let turns = Arc::new(Mutex::new(turns));
+    OpenResponsesAdapter::new(Arc::new(move |payload| {
+        payloads.lock().expect("lock").push(payload);
+        let sse = turns.lock().expect("lock").remove(0);
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in sse.as_bytes().chunks(17) {
+            events.extend(decoder.push(&String::from_utf8_lossy(chunk)));
+        }
+        events.extend(decoder.finish());
+        stream::iter(events).boxed()
+    }))
+    .model("gpt-test")
+}
+
+#[test]
+fn build_payload_uses_request_builder() {
+    let adapter = scripted_adapter(Vec::new(), Arc::new(Mutex::new(Vec::new())));
+    let payload = adapter.build_payload(&ModelRequest {
+        session_id: "s1".to_string(),
+        model: None,
+        input: vec![json!({"type": "message", "role": "user", "content": "hi"})],
+        tools: vec![json!({"type": "function", "name": "ls"})],
+    });
+
+    assert_eq!(payload.body()["model"], "gpt-test");
+    assert_eq!(payload.body()["stream"], true);
+    assert_eq!(payload.body()["tools"][0]["name"], "ls");
+
+    let payload = adapter.build_payload(&ModelRequest {
+        session_id: "s1".to_string(),
+        model: Some("local/llama3".to_string()),
+        input: Vec::new(),
+        tools: Vec::new(),
+    });
+    assert_eq!(payload.body()["model"], "local/llama3");
+}
+
+#[test]
+fn build_payload_keeps_tool_schemas() {
+    let adapter = scripted_adapter(Vec::new(), Arc::new(Mutex::new(Vec::new())));
+    let parameters = json!({
+        "type": "object",
+        "properties": {"path": {"type": "string"}},
+        "required": ["path"]
+    });
+    let payload = adapter.build_payload(&ModelRequest {
+        session_id: "s1".to_string(),
+        model: None,
+        input: Vec::new(),
+        tools: vec![json!({
+            "type": "function",
+            "name": "read",
+            "description": "Read a file",
+            "parameters": parameters
+        })],
+    });
+
+    let tool = &payload.body()["tools"][0];
+    assert_eq!(tool["description"], "Read a file");
+    assert_eq!(tool["parameters"], parameters);
+}
+
+#[tokio::test]
+async fn adapter_maps_provider_events_into_frames() {
+    let adapter = scripted_adapter(vec![TEXT_TURN], Arc::new(Mutex::new(Vec::new())));
+    let frames: Vec<EventKind> = adapter
+        .stream(ModelRequest {
+            session_id: "s1".to_string(),
+            model: None,
+            input: Vec::new(),
+            tools: Vec::new(),
+        })
+        .collect()
+        .await;
+
+    assert_eq!(frames.len(), 3);
+    assert!(matches!(frames[0], EventKind::ProviderEvent { .. }));
+    assert!(matches!(
+        &frames[1],
+        EventKind::OutputTextDelta { delta } if delta == "done"
+    ));
+    assert!(matches!(frames[2], EventKind::ProviderEvent { .. }));
+}
+
+#[tokio::test]
+async fn runtime_loops_until_model_stops() {
+    let payloads = Arc::new(Mutex::new(Vec::new()));
+    let adapter = scripted_adapter(vec![TOOL_TURN, TEXT_TURN], payloads.clone());
+    let runtime = Runtime::new().with_provider(Arc::new(adapter));
+    let mut session = runtime.start_session("list files".to_string());
+
+    let mut events = Vec::new();
+    while let Some(event) = session.next_event().await {
+        events.push(event);
+    }
+
+    assert_eq!(extract_text_deltas(&events), vec!["done".to_string()]);
+    assert!(matches!(
+        events.last().map(|event| &event.kind),
+        Some(EventKind::SessionEnded { reason }) if reason == "completed"
+    ));
+
+    let payloads = payloads.lock().expect("lock");
+    assert_eq!(payloads.len(), 2);
+    let input = payloads[1].body()["input"].as_array().expect("input");
+    assert_eq!(input[1]["type"], "function_call");
+    assert_eq!(input[2]["type"], "function_call_output");
+    assert_eq!(input[2]["call_id"], "call_1");
+}
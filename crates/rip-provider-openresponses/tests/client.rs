@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use httpmock::Method::POST;
+use httpmock::MockServer;
+use rip_provider_openresponses::{
+    CreateResponseBuilder, ParsedEvent, ParsedEventKind, ProviderClient, SseDecoder,
+};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+
+fn payload() -> rip_provider_openresponses::CreateResponsePayload {
+    CreateResponseBuilder::new()
+        .model("gpt-test")
+        .input_text("hi")
+        .insert_raw("stream", json!(true))
+        .build()
+}
+
+fn event_type(event: &ParsedEvent) -> Option<&str> {
+    event
+        .data
+        .as_ref()
+        .and_then(|value| value.get("type"))
+        .and_then(|value| value.as_str())
+}
+
+#[tokio::test]
+async fn streams_fixture_from_mock_server() {
+    let sse = include_str!("../fixtures/openresponses/stream_all.sse");
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/responses")
+            .header("authorization", "Bearer sk-test")
+            .header("x-extra", "1")
+            .json_body_partial(r#"{"model":"gpt-test","stream":true}"#);
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(sse);
+    });
+
+    let client = ProviderClient::builder(server.url("/v1"))
+        .api_key("sk-test")
+        .header("x-extra", "1")
+        .connect_timeout(Duration::from_secs(5))
+        .request_timeout(Duration::from_secs(5))
+        .idle_timeout(Duration::from_secs(5))
+        .build()
+        .expect("client");
+    assert_eq!(client.endpoint(), server.url("/v1/responses"));
+
+    let events: Vec<ParsedEvent> = client
+        .stream(&payload())
+        .await
+        .expect("stream")
+        .collect()
+        .await;
+    mock.assert();
+
+    let mut decoder = SseDecoder::new();
+    let mut expected = decoder.push(sse);
+    expected.extend(decoder.finish());
+    assert_eq!(events.len(), expected.len());
+    for (actual, expected) in events.iter().zip(expected.iter()) {
+        assert_eq!(actual.kind, expected.kind);
+        assert_eq!(actual.raw, expected.raw);
+    }
+}
+
+#[tokio::test]
+async fn non_success_status_is_error() {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/responses");
+        then.status(401).body("bad key");
+    });
+
+    let client = ProviderClient::builder(server.url("/v1"))
+        .build()
+        .expect("client");
+    let err = match client.stream(&payload()).await {
+        Ok(_) => panic!("expected error"),
+        Err(err) => err,
+    };
+    assert!(err.contains("401"), "{err}");
+    assert!(err.contains("bad key"), "{err}");
+}
+
+#[tokio::test]
+async fn request_timeout_is_error() {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/responses");
+        then.status(200).delay(Duration::from_millis(500)).body("");
+    });
+
+    let client = ProviderClient::builder(server.url("/v1"))
+        .request_timeout(Duration::from_millis(50))
+        .build()
+        .expect("client");
+    let err = match client.stream(&payload()).await {
+        Ok(_) => panic!("expected timeout"),
+        Err(err) => err,
+    };
+    assert!(err.contains("timed out"), "{err}");
+}
+
+#[tokio::test]
+async fn stalled_stream_yields_idle_timeout_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.expect("accept");
+        let mut buf = vec![0u8; 8192];
+        let _ = tokio::io::AsyncReadExt::read(&mut socket, &mut buf).await;
+        let event = "data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"i\",\"output_index\":0,\"content_index\":0,\"delta\":\"h\"}\n\n";
+        let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+        let chunk = format!("{:x}\r\n{}\r\n", event.len(), event);
+        socket.write_all(head.as_bytes()).await.expect("write");
+        socket.write_all(chunk.as_bytes()).await.expect("write");
+        socket.flush().await.expect("flush");
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    });
+
+    let client = ProviderClient::builder(format!("http://{addr}/v1"))
+        .idle_timeout(Duration::from_millis(100))
+        .build()
+        .expect("client");
+    let events: Vec<ParsedEvent> = client
+        .stream(&payload())
+        .await
+        .expect("stream")
+        .collect()
+        .await;
+    server.abort();
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(event_type(&events[0]), Some("response.output_text.delta"));
+    assert_eq!(events[1].kind, ParsedEventKind::Event);
+    assert_eq!(event_type(&events[1]), Some("error"));
+    assert_eq!(
+        events[1].data.as_ref().unwrap()["error"]["code"],
+        json!("idle_timeout")
+    );
+}
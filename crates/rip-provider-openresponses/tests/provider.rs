@@ -0,0 +1,170 @@
+use std::sync::{Arc, Mutex};
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use httpmock::Method::POST;
+use httpmock::MockServer;
+use rip_provider_openresponses::{
+    CreateResponseBuilder, CreateResponsePayload, EndpointApi, ParsedEvent, Provider,
+    ProviderRouter, RouterConfig, SseDecoder,
+};
+use serde_json::json;
+
+const DONE: &str = "data: [DONE]\n\n";
+
+struct Recording {
+    name: &'static str,
+    models: Arc<Mutex<Vec<String>>>,
+}
+
+impl Provider for Recording {
+    fn stream(&self, payload: CreateResponsePayload) -> BoxStream<'static, ParsedEvent> {
+        let model = payload.body()["model"].as_str().unwrap_or_default();
+        self.models
+            .lock()
+            .expect("lock")
+            .push(format!("{}:{model}", self.name));
+        stream::iter(SseDecoder::new().push(DONE)).boxed()
+    }
+}
+
+fn payload(model: &str) -> CreateResponsePayload {
+    CreateResponseBuilder::new()
+        .model(model)
+        .input_text("hi")
+        .build()
+}
+
+#[tokio::test]
+async fn router_picks_longest_prefix_and_strips() {
+    let models = Arc::new(Mutex::new(Vec::new()));
+    let provider = |name| -> Arc<dyn Provider> {
+        Arc::new(Recording {
+            name,
+            models: models.clone(),
+        })
+    };
+    let router = ProviderRouter::new()
+        .route("gpt-", provider("remote"))
+        .route_stripped("local/", provider("local"))
+        .route("gpt-4o", provider("mini"))
+        .fallback(provider("default"));
+
+    for model in ["gpt-4o-mini", "gpt-5", "local/llama3", "claude"] {
+        let events: Vec<ParsedEvent> = router.stream(payload(model)).collect().await;
+        assert_eq!(events.len(), 1);
+    }
+
+    assert_eq!(
+        *models.lock().expect("lock"),
+        vec![
+            "mini:gpt-4o-mini",
+            "remote:gpt-5",
+            "local:llama3",
+            "default:claude"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn router_without_match_yields_error_event() {
+    let router = ProviderRouter::new();
+    let events: Vec<ParsedEvent> = router.stream(payload("unknown")).collect().await;
+
+    assert_eq!(events.len(), 1);
+    let data = events[0].data.as_ref().expect("data");
+    assert_eq!(data["type"], "error");
+    assert_eq!(data["error"]["code"], "no_provider");
+    assert!(data["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("unknown"));
+}
+
+#[tokio::test]
+async fn router_from_config_streams_from_configured_endpoint() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/responses")
+            .header("authorization", "Bearer local-key")
+            .json_body_partial(r#"{"model":"llama3"}"#);
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(DONE);
+    });
+
+    std::env::set_var("RIP_TEST_ROUTER_KEY", "local-key");
+    let config: RouterConfig = serde_json::from_value(json!({
+        "routes": [{
+            "prefix": "local/",
+            "strip_prefix": true,
+            "base_url": server.url("/v1"),
+            "api_key_env": "RIP_TEST_ROUTER_KEY",
+            "idle_timeout_ms": 5000
+        }]
+    }))
+    .expect("config");
+    let router = ProviderRouter::from_config(&config).expect("router");
+
+    let events: Vec<ParsedEvent> = router.stream(payload("local/llama3")).collect().await;
+    mock.assert();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].raw, "[DONE]");
+}
+
+#[test]
+fn router_from_config_requires_api_key_env() {
+    let config: RouterConfig = serde_json::from_value(json!({
+        "default": {
+            "base_url": "http://127.0.0.1:1/v1",
+            "api_key_env": "RIP_TEST_ROUTER_MISSING_KEY"
+        }
+    }))
+    .expect("config");
+    let err = match ProviderRouter::from_config(&config) {
+        Ok(_) => panic!("expected error"),
+        Err(err) => err,
+    };
+    assert!(err.contains("RIP_TEST_ROUTER_MISSING_KEY"));
+}
+
+#[test]
+fn router_from_config_needs_factory_for_chat_completions() {
+    let config: RouterConfig = serde_json::from_value(json!({
+        "default": { "base_url": "http://127.0.0.1:1/v1", "api": "chat_completions" }
+    }))
+    .expect("config");
+    assert!(ProviderRouter::from_config(&config).is_err());
+
+    let built = Arc::new(Mutex::new(Vec::new()));
+    let seen = built.clone();
+    let router = ProviderRouter::from_config_with(&config, move |endpoint| {
+        seen.lock().expect("lock").push(endpoint.api);
+        Ok(Arc::new(endpoint.build_client()?) as Arc<dyn Provider>)
+    });
+    assert!(router.is_ok());
+    assert_eq!(
+        *built.lock().expect("lock"),
+        vec![EndpointApi::ChatCompletions]
+    );
+}
+
+#[tokio::test]
+async fn client_provider_reports_request_failure_in_band() {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/responses");
+        then.status(500).body("boom");
+    });
+    let config: RouterConfig = serde_json::from_value(json!({
+        "default": { "base_url": server.url("/v1") }
+    }))
+    .expect("config");
+    let router = ProviderRouter::from_config(&config).expect("router");
+
+    let events: Vec<ParsedEvent> = router.stream(payload("any")).collect().await;
+    assert_eq!(events.len(), 1);
+    let data = events[0].data.as_ref().expect("data");
+    assert_eq!(data["error"]["code"], "request_failed");
+    assert!(data["error"]["message"].as_str().unwrap().contains("500"));
+}
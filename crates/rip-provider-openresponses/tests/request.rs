@@ -60,6 +60,21 @@ fn tool_param_function_is_valid() {
     assert!(tool.errors().is_empty());
 }
 
+#[test]
+fn tool_param_function_with_parameters_is_valid() {
+    let schema = json!({
+        "type": "object",
+        "properties": {"path": {"type": "string"}},
+        "required": ["path"]
+    });
+    let tool = ToolParam::function("read")
+        .description("Read a file")
+        .parameters(schema.clone());
+    assert!(tool.errors().is_empty(), "{:?}", tool.errors());
+    assert_eq!(tool.value()["description"], "Read a file");
+    assert_eq!(tool.value()["parameters"], schema);
+}
+
 #[test]
 fn tool_param_variants_are_valid() {
     let tools = vec![
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use rip_kernel::{CancellationToken, EventKind, Runtime, ToolCall, ToolDispatcher};
+use rip_provider_openresponses::{
+    extract_text_deltas, CreateResponseBuilder, OpenResponsesAdapter, ParsedEvent, ParsedEventKind,
+    Provider, ScriptedProvider,
+};
+use serde_json::json;
+
+const HELLO: &str = "event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"m\",\"output_index\":0,\"content_index\":0,\"delta\":\"hello\"}\n\n\
+data: [DONE]\n\n";
+
+const BYE: &str = "event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"m\",\"output_index\":0,\"content_index\":0,\"delta\":\"bye\"}\n\n\
+data: [DONE]\n\n";
+
+struct FakeLs;
+
+impl ToolDispatcher for FakeLs {
+    fn dispatch(
+        &self,
+        _session_id: &str,
+        call: ToolCall,
+        _cancel: CancellationToken,
+    ) -> BoxStream<'static, EventKind> {
+        stream::iter(vec![
+            EventKind::ToolStarted {
+                tool_id: "t1".to_string(),
+                name: call.name,
+                args: json!({}),
+                timeout_ms: None,
+            },
+            EventKind::ToolStdout {
+                tool_id: "t1".to_string(),
+                chunk: "README.md".to_string(),
+                job_id: None,
+            },
+            EventKind::ToolEnded {
+                tool_id: "t1".to_string(),
+                exit_code: 0,
+                duration_ms: 0,
+                artifacts: None,
+            },
+        ])
+        .boxed()
+    }
+}
+
+fn text(events: &[ParsedEvent]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|event| event.data.as_ref()?.get("delta")?.as_str())
+        .map(|value| value.to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn replays_turns_in_order_then_reports_exhaustion() {
+    let provider = ScriptedProvider::new().turn(HELLO).turn(BYE);
+    let payload = || CreateResponseBuilder::new().input_text("hi").build();
+
+    let first: Vec<ParsedEvent> = provider.stream(payload()).collect().await;
+    let second: Vec<ParsedEvent> = provider.stream(payload()).collect().await;
+    assert_eq!(text(&first), vec!["hello"]);
+    assert_eq!(text(&second), vec!["bye"]);
+    assert_eq!(first.last().expect("done").kind, ParsedEventKind::Done);
+    assert_eq!(provider.remaining(), 0);
+
+    let third: Vec<ParsedEvent> = provider.stream(payload()).collect().await;
+    assert_eq!(third.len(), 1);
+    let data = third[0].data.as_ref().expect("data");
+    assert_eq!(data["type"], "error");
+    assert_eq!(data["error"]["code"], "script_exhausted");
+    assert_eq!(provider.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn keyed_turns_match_latest_input() {
+    let provider = ScriptedProvider::new()
+        .on_input("goodbye", BYE)
+        .on_input("hello", HELLO);
+
+    let events: Vec<ParsedEvent> = provider
+        .stream(CreateResponseBuilder::new().input_text("say hello").build())
+        .collect()
+        .await;
+    assert_eq!(text(&events), vec!["hello"]);
+
+    let events: Vec<ParsedEvent> = provider
+        .stream(
+            CreateResponseBuilder::new()
+                .input_items_raw(vec![
+                    json!({"type": "message", "role": "user", "content": "say hello"}),
+                    json!({"type": "message", "role": "user", "content": [{"type": "input_text", "text": "goodbye"}]}),
+                ])
+                .build(),
+        )
+        .collect()
+        .await;
+    assert_eq!(text(&events), vec!["bye"]);
+}
+
+#[tokio::test]
+async fn script_fixture_drives_tool_loop() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/scripted/tool_loop.json"
+    );
+    let provider = Arc::new(ScriptedProvider::from_script(path).expect("script"));
+    let adapter = OpenResponsesAdapter::new(provider.clone()).model("scripted");
+    let runtime = Runtime::new()
+        .with_provider(Arc::new(adapter))
+        .with_tools(Arc::new(FakeLs));
+    let mut session = runtime.start_session("please list files".to_string());
+
+    let mut events = Vec::new();
+    while let Some(event) = session.next_event().await {
+        events.push(event);
+    }
+
+    assert_eq!(extract_text_deltas(&events), vec!["found README.md"]);
+    assert!(events
+        .iter()
+        .any(|event| matches!(&event.kind, EventKind::ToolStarted { name, .. } if name == "ls")));
+    assert!(matches!(
+        events.last().map(|event| &event.kind),
+        Some(EventKind::SessionEnded { reason }) if reason == "completed"
+    ));
+    assert_eq!(provider.remaining(), 0);
+    assert_eq!(provider.requests().len(), 2);
+}
+
+#[test]
+fn script_rejects_ambiguous_turns() {
+    let dir = tempfile::tempdir().expect("tmp");
+    let path = dir.path().join("bad.json");
+    std::fs::write(
+        &path,
+        r#"{"turns":[{"sse":"data: [DONE]\n\n","events":[]}]}"#,
+    )
+    .expect("write");
+
+    let err = ScriptedProvider::from_script(&path).expect_err("ambiguous");
+    assert!(err.contains("turn 0"), "{err}");
+}
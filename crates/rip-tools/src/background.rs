@@ -0,0 +1,415 @@
+//! Process table behind `bash_background`, `bash_output` and `bash_kill`.
+//!
+//! Each job runs in its own process group; a detached task reads its stdout
+//! and stderr into a ring buffer bounded by bytes and records the exit
+//! status. `bash_output` drains whatever was buffered since the last read.
+//! A job is removed from the table once its exit has been reported; exited
+//! jobs nobody asks about are reaped as they exit, keeping only the newest
+//! `MAX_FINISHED_JOBS` per session.
+
+use std::collections::{HashMap, VecDeque};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use rip_kernel::CancellationToken;
+use serde_json::{json, Value};
+use tokio::process::{Child, Command};
+
+use crate::builtins::stream_lines;
+use crate::limits::{ExitOutcome, LimitGuard};
+use crate::process::{ProcessGroup, TERMINATE_GRACE};
+use crate::spill::OutputStream;
+
+/// Jobs one session may have running at once.
+pub(crate) const MAX_RUNNING_JOBS: usize = 16;
+
+/// Exited, unreported jobs kept per session for `bash_output`.
+pub(crate) const MAX_FINISHED_JOBS: usize = 16;
+
+#[derive(Default)]
+pub(crate) struct BackgroundJobs {
+    next_id: AtomicU64,
+    table: Arc<Mutex<JobTable>>,
+}
+
+#[derive(Default)]
+struct JobTable {
+    jobs: HashMap<String, Arc<Job>>,
+    /// Ids of exited jobs still in `jobs`, oldest exit first.
+    finished: VecDeque<String>,
+}
+
+impl JobTable {
+    /// Records that `job` exited and drops the oldest exited jobs of its
+    /// session past `MAX_FINISHED_JOBS`.
+    fn reap(&mut self, job: &Job) {
+        if !self.jobs.contains_key(&job.id) {
+            return;
+        }
+        self.finished.push_back(job.id.clone());
+        let mut kept = self
+            .finished
+            .iter()
+            .filter(|id| {
+                self.jobs
+                    .get(*id)
+                    .is_some_and(|other| other.session_id == job.session_id)
+            })
+            .count();
+        let jobs = &mut self.jobs;
+        self.finished.retain(|id| {
+            let same_session = jobs
+                .get(id)
+                .is_some_and(|other| other.session_id == job.session_id);
+            if same_session && kept > MAX_FINISHED_JOBS {
+                kept -= 1;
+                jobs.remove(id);
+                return false;
+            }
+            true
+        });
+    }
+}
+
+pub(crate) enum StartError {
+    /// The session already has `MAX_RUNNING_JOBS` jobs running.
+    TooMany,
+    Spawn(std::io::Error),
+}
+
+pub(crate) struct Job {
+    pub(crate) id: String,
+    pub(crate) pid: Option<u32>,
+    session_id: String,
+    kill: CancellationToken,
+    exited: CancellationToken,
+    state: Mutex<JobState>,
+}
+
+struct JobState {
+    lines: VecDeque<(OutputStream, String)>,
+    buffered: usize,
+    capacity: usize,
+    dropped_lines: usize,
+    status: JobStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum JobStatus {
+    Running,
+    Exited(i32),
+    Killed(&'static str),
+    LimitExceeded(&'static str),
+    Failed(String),
+}
+
+impl JobStatus {
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            JobStatus::Running => json!({ "state": "running" }),
+            JobStatus::Exited(code) => json!({ "state": "exited", "exit_code": code }),
+            JobStatus::Killed(signal) => json!({ "state": "killed", "signal": signal }),
+            JobStatus::LimitExceeded(limit) => json!({ "state": "limit_exceeded", "limit": limit }),
+            JobStatus::Failed(error) => json!({ "state": "failed", "error": error }),
+        }
+    }
+}
+
+/// Output buffered since the previous drain.
+pub(crate) struct Drained {
+    pub(crate) lines: Vec<(OutputStream, String)>,
+    pub(crate) dropped_lines: usize,
+    pub(crate) status: JobStatus,
+}
+
+impl BackgroundJobs {
+    /// Spawns `cmd` as a new job unless the session is at
+    /// `MAX_RUNNING_JOBS`; output past `capacity` bytes evicts the oldest
+    /// buffered lines. The check and the insert happen under one lock.
+    pub(crate) fn start(
+        &self,
+        session_id: &str,
+        mut cmd: Command,
+        limits: LimitGuard,
+        capacity: usize,
+    ) -> Result<Arc<Job>, StartError> {
+        let mut table = self.table.lock().expect("background jobs mutex");
+        let running = table
+            .jobs
+            .values()
+            .filter(|job| job.session_id == session_id && job.status() == JobStatus::Running)
+            .count();
+        if running >= MAX_RUNNING_JOBS {
+            return Err(StartError::TooMany);
+        }
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        ProcessGroup::configure(&mut cmd);
+        let child = cmd.spawn().map_err(StartError::Spawn)?;
+
+        let id = format!("job-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let job = Arc::new(Job {
+            id: id.clone(),
+            pid: child.id(),
+            session_id: session_id.to_string(),
+            kill: CancellationToken::new(),
+            exited: CancellationToken::new(),
+            state: Mutex::new(JobState {
+                lines: VecDeque::new(),
+                buffered: 0,
+                capacity,
+                dropped_lines: 0,
+                status: JobStatus::Running,
+            }),
+        });
+        table.jobs.insert(id, job.clone());
+        let table = Arc::downgrade(&self.table);
+        tokio::spawn(supervise(job.clone(), child, limits, table));
+        Ok(job)
+    }
+
+    /// The job `job_id`, if it exists and belongs to `session_id`.
+    pub(crate) fn get(&self, session_id: &str, job_id: &str) -> Option<Arc<Job>> {
+        let table = self.table.lock().expect("background jobs mutex");
+        table
+            .jobs
+            .get(job_id)
+            .filter(|job| job.session_id == session_id)
+            .cloned()
+    }
+
+    /// Stops every job of `session_id` (SIGTERM, then SIGKILL, by its
+    /// supervisor) and forgets them.
+    pub(crate) fn end_session(&self, session_id: &str) {
+        let mut table = self.table.lock().expect("background jobs mutex");
+        table.jobs.retain(|_, job| {
+            if job.session_id != session_id {
+                return true;
+            }
+            job.kill.cancel();
+            false
+        });
+        let jobs = &table.jobs;
+        let finished = table
+            .finished
+            .iter()
+            .filter(|id| jobs.contains_key(*id))
+            .cloned()
+            .collect();
+        table.finished = finished;
+    }
+
+    pub(crate) fn remove(&self, job_id: &str) {
+        let mut table = self.table.lock().expect("background jobs mutex");
+        table.jobs.remove(job_id);
+        table.finished.retain(|id| id != job_id);
+    }
+}
+
+impl Drop for BackgroundJobs {
+    fn drop(&mut self) {
+        let table = self.table.lock().expect("background jobs mutex");
+        for job in table.jobs.values() {
+            job.kill.cancel();
+        }
+    }
+}
+
+impl Job {
+    pub(crate) fn status(&self) -> JobStatus {
+        self.state().status.clone()
+    }
+
+    pub(crate) fn drain(&self) -> Drained {
+        let mut state = self.state();
+        state.buffered = 0;
+        Drained {
+            lines: state.lines.drain(..).collect(),
+            dropped_lines: std::mem::take(&mut state.dropped_lines),
+            status: state.status.clone(),
+        }
+    }
+
+    /// Waits up to `timeout` for the job to exit; returns whether it did.
+    pub(crate) async fn wait(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.exited.cancelled())
+            .await
+            .is_ok()
+    }
+
+    /// Stops the job's process group (SIGTERM, then SIGKILL) and waits for
+    /// the exit to be recorded.
+    pub(crate) async fn kill(&self) -> JobStatus {
+        self.kill.cancel();
+        self.wait(TERMINATE_GRACE + Duration::from_secs(1)).await;
+        self.status()
+    }
+
+    fn push(&self, stream: OutputStream, line: String) {
+        let mut state = self.state();
+        state.buffered += line.len() + 1;
+        state.lines.push_back((stream, line));
+        while state.buffered > state.capacity {
+            let Some((_, dropped)) = state.lines.pop_front() else {
+                break;
+            };
+            state.buffered -= dropped.len() + 1;
+            state.dropped_lines += 1;
+        }
+    }
+
+    fn finish(&self, status: JobStatus) {
+        self.state().status = status;
+        self.exited.cancel();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, JobState> {
+        self.state.lock().expect("job state mutex")
+    }
+}
+
+async fn supervise(
+    job: Arc<Job>,
+    mut child: Child,
+    limits: LimitGuard,
+    table: Weak<Mutex<JobTable>>,
+) {
+    let mut group = ProcessGroup::new(&child);
+    let pid = child.id();
+    let stdout = child.stdout.take().expect("piped stdout");
+    let stderr = child.stderr.take().expect("piped stderr");
+
+    let waiter = async {
+        let status = tokio::select! {
+            status = child.wait() => match status {
+                Ok(status) => match limits.classify(ExitOutcome::from(status)) {
+                    Some(limit) => JobStatus::LimitExceeded(limit.as_str()),
+                    None => JobStatus::Exited(status.code().unwrap_or(1)),
+                },
+                Err(err) => JobStatus::Failed(format!("wait failed: {err}")),
+            },
+            limit = limits.exceeded(pid) => {
+                group.terminate(&mut child, TERMINATE_GRACE).await;
+                let _ = child.wait().await;
+                JobStatus::LimitExceeded(limit.as_str())
+            }
+            _ = job.kill.cancelled() => {
+                let signal = group.terminate(&mut child, TERMINATE_GRACE).await;
+                let _ = child.wait().await;
+                JobStatus::Killed(signal)
+            }
+        };
+        // Report the exit right away; descendants holding the pipes open
+        // can keep the readers below running for longer.
+        job.finish(status);
+        if let Some(table) = table.upgrade() {
+            table.lock().expect("background jobs mutex").reap(&job);
+        }
+    };
+    tokio::join!(
+        waiter,
+        stream_lines(stdout, |line| job.push(OutputStream::Stdout, line)),
+        stream_lines(stderr, |line| job.push(OutputStream::Stderr, line)),
+    );
+    group.disarm();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(capacity: usize) -> Job {
+        session_job("job-1", "s1", capacity)
+    }
+
+    fn session_job(id: &str, session_id: &str, capacity: usize) -> Job {
+        Job {
+            id: id.to_string(),
+            pid: None,
+            session_id: session_id.to_string(),
+            kill: CancellationToken::new(),
+            exited: CancellationToken::new(),
+            state: Mutex::new(JobState {
+                lines: VecDeque::new(),
+                buffered: 0,
+                capacity,
+                dropped_lines: 0,
+                status: JobStatus::Running,
+            }),
+        }
+    }
+
+    #[test]
+    fn ring_buffer_evicts_oldest_lines() {
+        let job = job(12);
+        for idx in 0..5 {
+            job.push(OutputStream::Stdout, format!("line{idx}"));
+        }
+        job.push(OutputStream::Stderr, "err".to_string());
+
+        let drained = job.drain();
+        assert_eq!(
+            drained.lines,
+            vec![
+                (OutputStream::Stdout, "line4".to_string()),
+                (OutputStream::Stderr, "err".to_string()),
+            ]
+        );
+        assert_eq!(drained.dropped_lines, 4);
+        assert_eq!(drained.status, JobStatus::Running);
+
+        let drained = job.drain();
+        assert!(drained.lines.is_empty());
+        assert_eq!(drained.dropped_lines, 0);
+    }
+
+    #[test]
+    fn exited_jobs_past_the_cap_are_reaped() {
+        let mut table = JobTable::default();
+        let other = Arc::new(session_job("other", "s2", 64));
+        table.jobs.insert(other.id.clone(), other.clone());
+        table.reap(&other);
+        for idx in 0..MAX_FINISHED_JOBS + 2 {
+            let job = Arc::new(session_job(&format!("job-{idx}"), "s1", 64));
+            table.jobs.insert(job.id.clone(), job.clone());
+            table.reap(&job);
+        }
+
+        assert_eq!(table.jobs.len(), MAX_FINISHED_JOBS + 1);
+        assert!(!table.jobs.contains_key("job-0"));
+        assert!(!table.jobs.contains_key("job-1"));
+        assert!(table.jobs.contains_key("job-2"));
+        assert!(table.jobs.contains_key("other"));
+        assert_eq!(table.finished.len(), table.jobs.len());
+    }
+
+    #[test]
+    fn ending_a_session_kills_and_forgets_its_jobs() {
+        let jobs = BackgroundJobs::default();
+        let mut running = Vec::new();
+        {
+            let mut table = jobs.table.lock().expect("lock");
+            for (id, session_id) in [("job-1", "s1"), ("job-2", "s1"), ("job-3", "s2")] {
+                let job = Arc::new(session_job(id, session_id, 64));
+                table.jobs.insert(job.id.clone(), job.clone());
+                running.push(job);
+            }
+            let exited = running[1].clone();
+            table.reap(&exited);
+        }
+
+        jobs.end_session("s1");
+
+        assert!(running[0].kill.is_cancelled());
+        assert!(running[1].kill.is_cancelled());
+        assert!(!running[2].kill.is_cancelled());
+        assert!(jobs.get("s1", "job-1").is_none());
+        assert!(jobs.get("s1", "job-2").is_none());
+        assert!(jobs.get("s2", "job-3").is_some());
+        assert!(jobs.table.lock().expect("lock").finished.is_empty());
+    }
+}
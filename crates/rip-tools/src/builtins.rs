@@ -1,19 +1,34 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
-use std::path::{Component, Path, PathBuf};
-use std::process::Command;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
 use regex::RegexBuilder;
+use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader as AsyncBufReader};
+use tokio::process::Command;
 use tokio::task::spawn_blocking;
 
-use crate::{ToolInvocation, ToolOutput, ToolRegistry};
+use crate::background::{BackgroundJobs, Job, JobStatus, StartError, MAX_RUNNING_JOBS};
+use crate::limits::{ExitOutcome, Limit, LimitGuard, ResourceLimits};
+use crate::patch::{apply_hunks, parse_patch, render, FilePatch};
+use crate::permissions::{command_subjects, PermissionSubject};
+use crate::process::{ProcessGroup, TERMINATE_GRACE};
+use crate::resolve::{resolve, WorkspacePath, ESCAPES_ROOT};
+use crate::sandbox::{self, SandboxProfile};
+use crate::shell::{is_env_name, shell_quote, CommandOutcome, PersistentShell, ShellSessions};
+use crate::spill::{OutputSpill, OutputStream};
+use crate::{ToolInvocation, ToolOutput, ToolRegistry, ToolSink, ToolSpec};
 
 #[derive(Clone, Debug)]
 pub struct BuiltinToolConfig {
@@ -23,6 +38,20 @@ pub struct BuiltinToolConfig {
     pub max_depth: usize,
     pub follow_symlinks: bool,
     pub include_hidden: bool,
+    /// Apply `.gitignore`, `.ignore` and `.ripignore` rules in `ls` and
+    /// `grep` too (`glob` always does).
+    pub respect_ignore_files: bool,
+    /// Run `bash` commands in one long-lived shell per session so `cd` and
+    /// exported variables carry over between calls.
+    pub persistent_shell: bool,
+    /// Run `bash`, persistent shells and background jobs inside this Linux
+    /// sandbox.
+    pub sandbox: Option<SandboxProfile>,
+    /// Resource limits keyed by tool name (`bash`, `bash_background`).
+    pub resource_limits: HashMap<String, ResourceLimits>,
+    /// Milliseconds a `bash` call may run when it does not pass its own
+    /// `timeout_ms` (unset: no limit).
+    pub default_timeout_ms: Option<u64>,
 }
 
 impl Default for BuiltinToolConfig {
@@ -35,79 +64,331 @@ impl Default for BuiltinToolConfig {
             max_depth: 64,
             follow_symlinks: false,
             include_hidden: false,
+            respect_ignore_files: false,
+            persistent_shell: false,
+            sandbox: None,
+            resource_limits: HashMap::new(),
+            default_timeout_ms: None,
         }
     }
 }
 
 pub fn register_builtin_tools(registry: &ToolRegistry, config: BuiltinToolConfig) {
+    register_permission_subjects(registry, &config.workspace_root);
     let read_config = config.clone();
-    registry.register(
-        "read",
-        std::sync::Arc::new(move |invocation| {
-            let cfg = read_config.clone();
+    registry
+        .register_with_spec(
+            "read",
+            ToolSpec::new(
+                "Read a text file from the workspace, optionally limited to a line range.",
+                args_schema::<ReadArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, _sink| {
+                let cfg = read_config.clone();
+                Box::pin(async move {
+                    spawn_blocking(move || run_read(invocation, &cfg))
+                        .await
+                        .unwrap_or_else(|_| ToolOutput::failure(vec!["read panicked".to_string()]))
+                })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let write_config = config.clone();
+    registry
+        .register_with_spec(
+            "write",
+            ToolSpec::new(
+                "Write (or append) text to a file in the workspace, creating parent directories.",
+                args_schema::<WriteArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, _sink| {
+                let cfg = write_config.clone();
+                Box::pin(async move {
+                    spawn_blocking(move || run_write(invocation, &cfg))
+                        .await
+                        .unwrap_or_else(|_| ToolOutput::failure(vec!["write panicked".to_string()]))
+                })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let edit_config = config.clone();
+    registry.register_with_spec(
+        "edit",
+        ToolSpec::new(
+            "Replace an exact string in a workspace file. `old_string` must match exactly once unless `replace_all` is set.",
+            args_schema::<EditArgs>(),
+        ),
+        std::sync::Arc::new(move |invocation, _sink| {
+            let cfg = edit_config.clone();
             Box::pin(async move {
-                spawn_blocking(move || run_read(invocation, &cfg))
+                spawn_blocking(move || run_edit(invocation, &cfg))
                     .await
-                    .unwrap_or_else(|_| ToolOutput::failure(vec!["read panicked".to_string()]))
+                    .unwrap_or_else(|_| ToolOutput::failure(vec!["edit panicked".to_string()]))
             })
         }),
-    );
-
-    let write_config = config.clone();
-    registry.register(
-        "write",
-        std::sync::Arc::new(move |invocation| {
-            let cfg = write_config.clone();
+    ).expect("builtin tool schema");
+
+    let patch_config = config.clone();
+    registry.register_with_spec(
+        "apply_patch",
+        ToolSpec::new(
+            "Apply a unified diff or a `*** Begin Patch` envelope (Add/Update/Delete File) to the workspace. All files change or none do.",
+            args_schema::<ApplyPatchArgs>(),
+        ),
+        std::sync::Arc::new(move |invocation, _sink| {
+            let cfg = patch_config.clone();
             Box::pin(async move {
-                spawn_blocking(move || run_write(invocation, &cfg))
+                spawn_blocking(move || run_apply_patch(invocation, &cfg))
                     .await
-                    .unwrap_or_else(|_| ToolOutput::failure(vec!["write panicked".to_string()]))
+                    .unwrap_or_else(|_| {
+                        ToolOutput::failure(vec!["apply_patch panicked".to_string()])
+                    })
             })
         }),
-    );
+    ).expect("builtin tool schema");
 
     let ls_config = config.clone();
-    registry.register(
-        "ls",
-        std::sync::Arc::new(move |invocation| {
-            let cfg = ls_config.clone();
+    registry
+        .register_with_spec(
+            "ls",
+            ToolSpec::new(
+                "List directory entries in the workspace, optionally recursively.",
+                args_schema::<LsArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, _sink| {
+                let cfg = ls_config.clone();
+                Box::pin(async move {
+                    spawn_blocking(move || run_ls(invocation, &cfg))
+                        .await
+                        .unwrap_or_else(|_| ToolOutput::failure(vec!["ls panicked".to_string()]))
+                })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let glob_config = config.clone();
+    registry.register_with_spec(
+        "glob",
+        ToolSpec::new(
+            "Find workspace files matching a glob such as `src/**/*.rs`, newest first; honours .gitignore/.ignore/.ripignore.",
+            args_schema::<GlobArgs>(),
+        ),
+        std::sync::Arc::new(move |invocation, _sink| {
+            let cfg = glob_config.clone();
             Box::pin(async move {
-                spawn_blocking(move || run_ls(invocation, &cfg))
+                spawn_blocking(move || run_glob(invocation, &cfg))
                     .await
-                    .unwrap_or_else(|_| ToolOutput::failure(vec!["ls panicked".to_string()]))
+                    .unwrap_or_else(|_| ToolOutput::failure(vec!["glob panicked".to_string()]))
             })
         }),
-    );
+    ).expect("builtin tool schema");
 
     let grep_config = config.clone();
-    registry.register(
-        "grep",
-        std::sync::Arc::new(move |invocation| {
-            let cfg = grep_config.clone();
-            Box::pin(async move {
-                spawn_blocking(move || run_grep(invocation, &cfg))
-                    .await
-                    .unwrap_or_else(|_| ToolOutput::failure(vec!["grep panicked".to_string()]))
-            })
-        }),
-    );
+    registry
+        .register_with_spec(
+            "grep",
+            ToolSpec::new(
+                "Search workspace files for a pattern; prints path:line:text for each match.",
+                args_schema::<GrepArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, _sink| {
+                let cfg = grep_config.clone();
+                Box::pin(async move {
+                    spawn_blocking(move || run_grep(invocation, &cfg))
+                        .await
+                        .unwrap_or_else(|_| ToolOutput::failure(vec!["grep panicked".to_string()]))
+                })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let bash_description = if config.persistent_shell {
+        "Run a shell command in this session's persistent shell; the working directory and \
+         exported variables carry over between calls. Pass `restart` to start a fresh shell."
+    } else {
+        "Run a shell command in the workspace and return its output and exit code."
+    };
+    if let Some(timeout_ms) = config.default_timeout_ms {
+        registry.set_default_timeout("bash", timeout_ms);
+    }
+    let bash_config = config.clone();
+    let shells = std::sync::Arc::new(ShellSessions::default());
+    let bash_shells = shells.clone();
+    registry
+        .register_with_spec(
+            "bash",
+            ToolSpec::new(bash_description, args_schema::<ShellArgs>()),
+            std::sync::Arc::new(move |invocation, sink| {
+                let cfg = bash_config.clone();
+                let shells = bash_shells.clone();
+                Box::pin(async move {
+                    if cfg.persistent_shell {
+                        run_persistent_bash(invocation, cfg, &shells, sink).await
+                    } else {
+                        run_bash(invocation, cfg, sink).await
+                    }
+                })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let jobs = std::sync::Arc::new(BackgroundJobs::default());
+    let background_config = config;
+    let background_jobs = jobs.clone();
+    registry
+        .register_with_spec(
+            "bash_background",
+            ToolSpec::new(
+                "Start a long-running shell command (dev server, watcher) in the background and \
+             return its job id without waiting for it to finish.",
+                args_schema::<BashBackgroundArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, sink| {
+                let cfg = background_config.clone();
+                let jobs = background_jobs.clone();
+                Box::pin(async move { run_bash_background(invocation, &cfg, &jobs, sink) })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    let output_jobs = jobs.clone();
+    let teardown_jobs = jobs.clone();
+    registry
+        .register_with_spec(
+            "bash_output",
+            ToolSpec::new(
+                "Read output a background job produced since the last read, and its status.",
+                args_schema::<BashOutputArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, sink| {
+                let jobs = output_jobs.clone();
+                Box::pin(async move { run_bash_output(invocation, &jobs, sink).await })
+            }),
+        )
+        .expect("builtin tool schema");
+
+    registry
+        .register_with_spec(
+            "bash_kill",
+            ToolSpec::new(
+                "Stop a background job and return its remaining output.",
+                args_schema::<BashKillArgs>(),
+            ),
+            std::sync::Arc::new(move |invocation, sink| {
+                let jobs = jobs.clone();
+                Box::pin(async move { run_bash_kill(invocation, &jobs, sink).await })
+            }),
+        )
+        .expect("builtin tool schema");
+    registry.register_alias("shell", "bash");
+    registry.on_session_end(std::sync::Arc::new(move |session_id: &str| {
+        shells.end_session(session_id);
+        teardown_jobs.end_session(session_id);
+    }));
+    for name in ["read", "ls", "glob", "grep"] {
+        registry.mark_read_only(name);
+    }
+}
 
-    let bash_config = config;
-    registry.register(
-        "bash",
-        std::sync::Arc::new(move |invocation| {
-            let cfg = bash_config.clone();
-            Box::pin(run_bash(invocation, cfg))
+/// What `tool(pattern)` permission rules see: shell commands and their
+/// redirection targets for the bash tools, workspace-relative paths for the
+/// file tools. `bash` rules also cover `bash_background`, and `write` rules
+/// every tool that changes files.
+fn register_permission_subjects(registry: &ToolRegistry, root: &Path) {
+    for name in ["bash", "bash_background"] {
+        let root = root.to_path_buf();
+        registry.register_permission_subjects(
+            name,
+            std::sync::Arc::new(move |args: &Value| {
+                let cwd = Path::new(args.get("cwd").and_then(Value::as_str).unwrap_or(""));
+                command_subjects(args.get("command").and_then(Value::as_str).unwrap_or(""))
+                    .into_iter()
+                    .map(|subject| match subject {
+                        PermissionSubject::Redirect { tool, path } => PermissionSubject::Redirect {
+                            tool,
+                            path: subject_path(&root, &cwd.join(path).to_string_lossy()),
+                        },
+                        subject => subject,
+                    })
+                    .collect()
+            }),
+        );
+    }
+    registry.set_permission_family("bash_background", "bash");
+    registry.set_permission_family("edit", "write");
+    registry.set_permission_family("apply_patch", "write");
+    for (name, default) in [
+        ("read", None),
+        ("write", None),
+        ("edit", None),
+        ("ls", Some(".")),
+        ("glob", Some(".")),
+        ("grep", Some(".")),
+    ] {
+        let root = root.to_path_buf();
+        registry.register_permission_subjects(
+            name,
+            std::sync::Arc::new(move |args: &Value| {
+                args.get("path")
+                    .and_then(Value::as_str)
+                    .or(default)
+                    .map(|raw| vec![path_subject(&root, raw)])
+                    .unwrap_or_default()
+            }),
+        );
+    }
+    let root = root.to_path_buf();
+    registry.register_permission_subjects(
+        "apply_patch",
+        std::sync::Arc::new(move |args: &Value| {
+            let text = args.get("patch").and_then(Value::as_str).unwrap_or("");
+            let Ok(patches) = parse_patch(text) else {
+                return vec![PermissionSubject::Unparsed(text.to_string())];
+            };
+            patches
+                .iter()
+                .flat_map(|patch| match patch {
+                    FilePatch::Add { path, .. } | FilePatch::Delete { path } => vec![path],
+                    FilePatch::Update { path, move_to, .. } => {
+                        std::iter::once(path).chain(move_to).collect()
+                    }
+                })
+                .map(|raw| path_subject(&root, raw))
+                .collect()
         }),
     );
-    registry.register_alias("shell", "bash");
 }
 
-#[derive(Deserialize)]
+/// `raw` as the workspace-relative path it resolves to, so `./x` and links
+/// match the same rules as the file they reach; unresolvable paths are
+/// matched as written.
+fn path_subject(root: &Path, raw: &str) -> PermissionSubject {
+    PermissionSubject::Path(subject_path(root, raw))
+}
+
+fn subject_path(root: &Path, raw: &str) -> String {
+    let path = match resolve(root, raw) {
+        Ok(resolved) => normalize_rel_path(root, &resolved.path),
+        Err(_) => raw.to_string(),
+    };
+    if path.is_empty() {
+        ".".to_string()
+    } else {
+        path
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct ReadArgs {
+    /// File path relative to the workspace root.
     path: String,
+    /// First line to return (1-based, inclusive).
     start_line: Option<usize>,
+    /// Last line to return (1-based, inclusive).
     end_line: Option<usize>,
+    /// Maximum bytes to return.
     max_bytes: Option<usize>,
 }
 
@@ -133,12 +414,12 @@ fn run_read(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
         }
     }
 
-    let path = match resolve_path(&config.workspace_root, &args.path) {
-        Ok(path) => path,
+    let target = match resolve(&config.workspace_root, &args.path) {
+        Ok(target) => target,
         Err(err) => return ToolOutput::failure(vec![err]),
     };
 
-    let file = match File::open(&path) {
+    let file = match target.open() {
         Ok(file) => file,
         Err(err) => return ToolOutput::failure(vec![format!("read failed: {err}")]),
     };
@@ -185,7 +466,7 @@ fn run_read(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
         stderr: Vec::new(),
         exit_code: 0,
         artifacts: Some(json!({
-            "path": normalize_rel_path(&config.workspace_root, &path),
+            "path": normalize_rel_path(&config.workspace_root, &target.path),
             "bytes": used_bytes,
             "truncated": truncated,
             "start_line": args.start_line,
@@ -194,12 +475,17 @@ fn run_read(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct WriteArgs {
+    /// File path relative to the workspace root.
     path: String,
+    /// Text to write.
     content: String,
+    /// Append instead of replacing the file.
     append: Option<bool>,
+    /// Create the file if missing (default true).
     create: Option<bool>,
+    /// Write through a temp file and rename (default true).
     atomic: Option<bool>,
 }
 
@@ -209,8 +495,8 @@ fn run_write(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutp
         Err(err) => return err,
     };
 
-    let path = match resolve_path(&config.workspace_root, &args.path) {
-        Ok(path) => path,
+    let target = match resolve(&config.workspace_root, &args.path) {
+        Ok(target) => target,
         Err(err) => return ToolOutput::failure(vec![err]),
     };
 
@@ -218,59 +504,366 @@ fn run_write(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutp
     let append = args.append.unwrap_or(false);
     let atomic = args.atomic.unwrap_or(true);
 
-    if let Some(parent) = path.parent() {
-        if let Err(err) = fs::create_dir_all(parent) {
-            return ToolOutput::failure(vec![format!("write failed: {err}")]);
-        }
-    }
-
-    let bytes_written = if append {
-        let mut file = match OpenOptions::new().create(create).append(true).open(&path) {
-            Ok(file) => file,
-            Err(err) => return ToolOutput::failure(vec![format!("write failed: {err}")]),
-        };
-        if let Err(err) = file.write_all(args.content.as_bytes()) {
-            return ToolOutput::failure(vec![format!("write failed: {err}")]);
-        }
-        args.content.len()
-    } else if atomic {
-        let tmp_path = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
-        if let Err(err) = fs::write(&tmp_path, args.content.as_bytes()) {
-            return ToolOutput::failure(vec![format!("write failed: {err}")]);
-        }
-        if path.exists() {
-            if let Err(err) = fs::remove_file(&path) {
-                return ToolOutput::failure(vec![format!("write failed: {err}")]);
-            }
-        }
-        if let Err(err) = fs::rename(&tmp_path, &path) {
-            return ToolOutput::failure(vec![format!("write failed: {err}")]);
-        }
-        args.content.len()
+    let written = if append || !atomic {
+        target
+            .open_in_place(append, create || !append)
+            .and_then(|mut file| file.write_all(args.content.as_bytes()))
     } else {
-        if let Err(err) = fs::write(&path, args.content.as_bytes()) {
-            return ToolOutput::failure(vec![format!("write failed: {err}")]);
-        }
-        args.content.len()
+        target.replace(&args.content)
     };
+    if let Err(err) = written {
+        return ToolOutput::failure(vec![format!("write failed: {err}")]);
+    }
+    let bytes_written = args.content.len();
 
     ToolOutput {
         stdout: vec![format!("wrote {bytes_written} bytes")],
         stderr: Vec::new(),
         exit_code: 0,
         artifacts: Some(json!({
-            "path": normalize_rel_path(&config.workspace_root, &path),
+            "path": normalize_rel_path(&config.workspace_root, &target.path),
             "bytes_written": bytes_written
         })),
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+struct EditArgs {
+    /// File path relative to the workspace root.
+    path: String,
+    /// Exact text to replace, including whitespace and indentation.
+    old_string: String,
+    /// Replacement text.
+    new_string: String,
+    /// Replace every occurrence instead of requiring a unique match (default false).
+    replace_all: Option<bool>,
+}
+
+fn run_edit(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput {
+    let args: EditArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+    if args.old_string.is_empty() {
+        return ToolOutput::invalid_args("old_string must not be empty");
+    }
+    if args.old_string == args.new_string {
+        return ToolOutput::invalid_args("old_string and new_string are identical");
+    }
+
+    let target = match resolve(&config.workspace_root, &args.path) {
+        Ok(target) => target,
+        Err(err) => return ToolOutput::failure(vec![err]),
+    };
+    let rel = normalize_rel_path(&config.workspace_root, &target.path);
+    let original = match target.read_to_string() {
+        Ok(text) => text,
+        Err(err) => return ToolOutput::failure(vec![format!("edit failed: {err}")]),
+    };
+
+    let matches: Vec<usize> = original
+        .match_indices(&args.old_string)
+        .map(|(offset, _)| offset)
+        .collect();
+    let replace_all = args.replace_all.unwrap_or(false);
+    if matches.is_empty() || (matches.len() > 1 && !replace_all) {
+        let message = if matches.is_empty() {
+            format!("old_string not found in {rel}")
+        } else {
+            format!(
+                "old_string matches {} times in {rel}; add surrounding context or set replace_all",
+                matches.len()
+            )
+        };
+        let lines: Vec<usize> = matches
+            .iter()
+            .map(|&offset| original[..offset].matches('\n').count() + 1)
+            .collect();
+        return ToolOutput {
+            artifacts: Some(json!({ "path": rel, "matches": matches.len(), "lines": lines })),
+            ..ToolOutput::invalid_args(message)
+        };
+    }
+
+    let updated = original.replace(&args.old_string, &args.new_string);
+    if let Err(err) = target.replace(&updated) {
+        return ToolOutput::failure(vec![format!("edit failed: {err}")]);
+    }
+
+    let diff = similar::TextDiff::from_lines(&original, &updated)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{rel}"), &format!("b/{rel}"))
+        .to_string();
+    let noun = if matches.len() == 1 {
+        "occurrence"
+    } else {
+        "occurrences"
+    };
+    ToolOutput {
+        stdout: vec![format!("replaced {} {noun} in {rel}", matches.len())],
+        stderr: Vec::new(),
+        exit_code: 0,
+        artifacts: Some(json!({
+            "path": rel,
+            "replacements": matches.len(),
+            "diff": diff
+        })),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ApplyPatchArgs {
+    /// Unified diff, or a `*** Begin Patch` ... `*** End Patch` envelope.
+    patch: String,
+}
+
+/// A file touched by a patch: its content on disk and after the patch so far.
+struct StagedFile {
+    target: WorkspacePath,
+    original: Option<String>,
+    current: Option<String>,
+}
+
+fn run_apply_patch(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput {
+    let args: ApplyPatchArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+    let patches = match parse_patch(&args.patch) {
+        Ok(patches) => patches,
+        Err(err) => return ToolOutput::invalid_args(format!("invalid patch: {err}")),
+    };
+
+    let root = &config.workspace_root;
+    let mut staged = Vec::new();
+    let mut reports = Vec::new();
+    let mut summary = Vec::new();
+    let mut errors = Vec::new();
+    for patch in patches {
+        let (report, line) = match stage_file_patch(root, &mut staged, patch) {
+            Ok(staged) => staged,
+            Err((report, err)) => {
+                errors.push(err);
+                (report, None)
+            }
+        };
+        reports.push(report);
+        summary.extend(line);
+    }
+
+    if !errors.is_empty() {
+        errors.push("patch not applied; no files were changed".to_string());
+        return ToolOutput {
+            stdout: Vec::new(),
+            stderr: errors,
+            exit_code: 1,
+            artifacts: Some(json!({ "applied": false, "files": reports })),
+        };
+    }
+    if let Err(err) = commit_staged(&staged) {
+        return ToolOutput {
+            stdout: Vec::new(),
+            stderr: vec![format!("apply_patch failed: {err}")],
+            exit_code: 1,
+            artifacts: Some(json!({ "applied": false, "files": reports })),
+        };
+    }
+
+    ToolOutput {
+        stdout: summary,
+        stderr: Vec::new(),
+        exit_code: 0,
+        artifacts: Some(json!({ "applied": true, "files": reports })),
+    }
+}
+
+type StageResult = Result<(Value, Option<String>), (Value, String)>;
+
+/// Applies one file section to the in-memory state; returns its report and
+/// a summary line, or the report and an error.
+fn stage_file_patch(root: &Path, staged: &mut Vec<StagedFile>, patch: FilePatch) -> StageResult {
+    match patch {
+        FilePatch::Add {
+            path,
+            lines,
+            newline,
+        } => {
+            let mut report = json!({ "path": path, "action": "add" });
+            let idx = stage(root, staged, &path).map_err(|err| fail(&mut report, &path, err))?;
+            if staged[idx].current.is_some() {
+                return Err(fail(&mut report, &path, "file already exists".to_string()));
+            }
+            staged[idx].current = Some(render(&lines, "\n", newline));
+            Ok((report, Some(format!("A {path}"))))
+        }
+        FilePatch::Delete { path } => {
+            let mut report = json!({ "path": path, "action": "delete" });
+            let idx = stage(root, staged, &path).map_err(|err| fail(&mut report, &path, err))?;
+            if staged[idx].current.take().is_none() {
+                return Err(fail(&mut report, &path, "file does not exist".to_string()));
+            }
+            Ok((report, Some(format!("D {path}"))))
+        }
+        FilePatch::Update {
+            path,
+            move_to,
+            hunks,
+        } => {
+            let mut report = json!({ "path": path, "action": "update", "move_to": move_to });
+            let idx = stage(root, staged, &path).map_err(|err| fail(&mut report, &path, err))?;
+            let Some(current) = staged[idx].current.as_deref() else {
+                return Err(fail(&mut report, &path, "file does not exist".to_string()));
+            };
+
+            let (text, outcomes) = apply_hunks(current, &hunks);
+            let rejected: Vec<String> = outcomes
+                .iter()
+                .enumerate()
+                .filter_map(|(n, outcome)| {
+                    outcome
+                        .as_ref()
+                        .err()
+                        .map(|err| format!("hunk {}: {err}", n + 1))
+                })
+                .collect();
+            report["hunks"] = Value::Array(
+                outcomes
+                    .iter()
+                    .map(|outcome| match outcome {
+                        Ok(applied) => json!({
+                            "status": "applied",
+                            "line": applied.line,
+                            "fuzz": applied.fuzz
+                        }),
+                        Err(err) => json!({ "status": "rejected", "reason": err }),
+                    })
+                    .collect(),
+            );
+            report["hunks_applied"] = json!(outcomes.len() - rejected.len());
+            report["hunks_rejected"] = json!(rejected.len());
+            if !rejected.is_empty() {
+                return Err(fail(&mut report, &path, rejected.join("; ")));
+            }
+
+            match move_to {
+                Some(dest) => {
+                    let dest_idx =
+                        stage(root, staged, &dest).map_err(|err| fail(&mut report, &dest, err))?;
+                    if dest_idx != idx && staged[dest_idx].current.is_some() {
+                        return Err(fail(
+                            &mut report,
+                            &dest,
+                            "move destination already exists".to_string(),
+                        ));
+                    }
+                    staged[idx].current = None;
+                    staged[dest_idx].current = Some(text);
+                    Ok((report, Some(format!("M {path} -> {dest}"))))
+                }
+                None => {
+                    staged[idx].current = Some(text);
+                    Ok((report, Some(format!("M {path}"))))
+                }
+            }
+        }
+    }
+}
+
+fn fail(report: &mut Value, path: &str, err: String) -> (Value, String) {
+    report["error"] = Value::String(err.clone());
+    (report.take(), format!("{path}: {err}"))
+}
+
+/// Index of `raw` in `staged`, reading the file on first use.
+fn stage(root: &Path, staged: &mut Vec<StagedFile>, raw: &str) -> Result<usize, String> {
+    let target = resolve(root, raw)?;
+    if let Some(idx) = staged
+        .iter()
+        .position(|file| file.target.path == target.path)
+    {
+        return Ok(idx);
+    }
+    let original = match target.read_to_string() {
+        Ok(text) => Some(text),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(format!("read failed: {err}")),
+    };
+    staged.push(StagedFile {
+        target,
+        current: original.clone(),
+        original,
+    });
+    Ok(staged.len() - 1)
+}
+
+/// Writes every changed file through a temp file, then renames them into
+/// place; if any step fails, files already replaced are restored.
+fn commit_staged(staged: &[StagedFile]) -> Result<(), String> {
+    let changed: Vec<&StagedFile> = staged
+        .iter()
+        .filter(|file| file.original != file.current)
+        .collect();
+
+    let mut temps = Vec::new();
+    for file in &changed {
+        let Some(content) = &file.current else {
+            continue;
+        };
+        match file.target.write_temp(content) {
+            Ok(tmp) => temps.push((tmp, *file)),
+            Err(err) => {
+                remove_temps(&temps);
+                return Err(format!("{}: {err}", file.target.path.display()));
+            }
+        }
+    }
+
+    let mut done = Vec::new();
+    for (pos, (tmp, file)) in temps.iter().enumerate() {
+        if let Err(err) = file.target.rename_temp(tmp) {
+            remove_temps(&temps[pos..]);
+            restore(&done);
+            return Err(format!("{}: {err}", file.target.path.display()));
+        }
+        done.push(*file);
+    }
+    for file in changed.iter().filter(|file| file.current.is_none()) {
+        if let Err(err) = file.target.remove() {
+            restore(&done);
+            return Err(format!("{}: {err}", file.target.path.display()));
+        }
+        done.push(*file);
+    }
+    Ok(())
+}
+
+fn remove_temps(temps: &[(OsString, &StagedFile)]) {
+    for (tmp, file) in temps {
+        file.target.remove_temp(tmp);
+    }
+}
+
+fn restore(done: &[&StagedFile]) {
+    for file in done {
+        let _ = match &file.original {
+            Some(text) => file.target.replace(text),
+            None => file.target.remove(),
+        };
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct LsArgs {
+    /// Directory relative to the workspace root (default ".").
     path: Option<String>,
+    /// Walk subdirectories.
     recursive: Option<bool>,
+    /// Depth limit when recursive.
     max_depth: Option<usize>,
+    /// Glob patterns entries must match.
     include: Option<Vec<String>>,
+    /// Glob patterns to skip.
     exclude: Option<Vec<String>>,
     include_hidden: Option<bool>,
     follow_symlinks: Option<bool>,
@@ -302,10 +895,13 @@ fn run_ls(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput
         Err(err) => return ToolOutput::invalid_args(err),
     };
 
-    let mut builder = WalkBuilder::new(&root_path);
-    builder
-        .hidden(!include_hidden)
-        .follow_links(follow_symlinks);
+    let mut builder = workspace_walker(
+        config,
+        &root_path,
+        include_hidden,
+        follow_symlinks,
+        config.respect_ignore_files,
+    );
     if recursive {
         builder.max_depth(Some(max_depth));
     } else {
@@ -344,21 +940,265 @@ fn run_ls(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+struct GlobArgs {
+    /// Glob matched against paths relative to `path`, e.g. `src/**/*.rs`.
+    pattern: String,
+    /// Directory to search, relative to the workspace root (default ".").
+    path: Option<String>,
+    /// Return at most this many paths (newest first).
+    max_results: Option<usize>,
+    include_hidden: Option<bool>,
+    follow_symlinks: Option<bool>,
+    /// Also return files excluded by ignore files (default false).
+    no_ignore: Option<bool>,
+}
+
+fn run_glob(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput {
+    let args: GlobArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+
+    let root = args.path.unwrap_or_else(|| ".".to_string());
+    let root_path = match resolve_path(&config.workspace_root, &root) {
+        Ok(path) => path,
+        Err(err) => return ToolOutput::failure(vec![err]),
+    };
+    let max_results = args.max_results.unwrap_or(config.max_results);
+    let include_hidden = args.include_hidden.unwrap_or(config.include_hidden);
+    let follow_symlinks = args.follow_symlinks.unwrap_or(config.follow_symlinks);
+
+    let matcher = match build_globset(Some(std::slice::from_ref(&args.pattern))) {
+        Ok(Some(set)) => set,
+        Ok(None) => return ToolOutput::invalid_args("pattern must not be empty"),
+        Err(err) => return ToolOutput::invalid_args(err),
+    };
+
+    let mut builder = workspace_walker(
+        config,
+        &root_path,
+        include_hidden,
+        follow_symlinks,
+        !args.no_ignore.unwrap_or(false),
+    );
+    builder.max_depth(Some(config.max_depth));
+
+    let mut found = Vec::new();
+    let mut errors = Vec::new();
+    for entry in builder.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push(err.to_string());
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let path = entry.path();
+        if !matcher.is_match(normalize_rel_path(&root_path, path)) {
+            continue;
+        }
+        let metadata = workspace_entry(&config.workspace_root, path)
+            .and_then(|entry| entry.metadata().map_err(|err| err.to_string()));
+        let modified = match metadata {
+            Ok(metadata) if metadata.is_file() => metadata
+                .modified()
+                .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
+            // Swapped for something else (or moved out) since the walk.
+            _ => continue,
+        };
+        found.push((modified, normalize_rel_path(&config.workspace_root, path)));
+    }
+
+    found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    let total = found.len();
+    found.truncate(max_results);
+
+    ToolOutput {
+        stdout: found.into_iter().map(|(_, path)| path).collect(),
+        stderr: errors,
+        exit_code: 0,
+        artifacts: Some(json!({
+            "root": normalize_rel_path(&config.workspace_root, &root_path),
+            "matches": total,
+            "truncated": total > max_results
+        })),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct GrepArgs {
+    /// Regex (or literal text when `regex` is false) to search for.
     pattern: String,
+    /// File or directory relative to the workspace root (default ".").
     path: Option<String>,
+    /// Treat `pattern` as a regex (default true).
     regex: Option<bool>,
+    /// Match case (default true).
     case_sensitive: Option<bool>,
+    /// Glob patterns files must match.
     include: Option<Vec<String>>,
+    /// Glob patterns to skip.
     exclude: Option<Vec<String>>,
+    /// File types to search, using ripgrep names such as `rust`, `py` or `ts`.
+    types: Option<Vec<String>>,
+    /// Lines of context to show before each match.
+    before: Option<usize>,
+    /// Lines of context to show after each match.
+    after: Option<usize>,
+    /// Context on both sides, unless `before`/`after` are set.
+    context: Option<usize>,
+    /// Let matches span lines; `.` then also matches newlines.
+    multiline: Option<bool>,
+    /// What to print (default `content`).
+    output_mode: Option<GrepOutputMode>,
+    /// Stop after this many matches (files in `files_with_matches` and `count` modes).
     max_results: Option<usize>,
+    /// Bytes to scan per file.
     max_bytes: Option<usize>,
     max_depth: Option<usize>,
     include_hidden: Option<bool>,
     follow_symlinks: Option<bool>,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum GrepOutputMode {
+    /// `path:line:text` for matches, `path-line-text` for context.
+    #[default]
+    Content,
+    /// One path per matching file.
+    FilesWithMatches,
+    /// `path:count` per matching file.
+    Count,
+}
+
+/// Matches in one file: the lines read and the (first, last) line index of each hit.
+#[derive(Default)]
+struct FileHits {
+    lines: Vec<String>,
+    hits: Vec<(usize, usize)>,
+    error: Option<String>,
+}
+
+struct GrepSearch {
+    workspace_root: PathBuf,
+    regex: regex::Regex,
+    multiline: bool,
+    max_bytes: usize,
+}
+
+impl GrepSearch {
+    fn search(&self, path: &Path, rel: &str) -> FileHits {
+        let path = match workspace_entry(&self.workspace_root, path) {
+            Ok(path) => path,
+            Err(err) => {
+                return FileHits {
+                    error: Some(format!("{rel}: {err}")),
+                    ..FileHits::default()
+                }
+            }
+        };
+        let result = if self.multiline {
+            self.search_multiline(path)
+        } else {
+            self.search_lines(path)
+        };
+        match result {
+            Ok(mut found) => {
+                found.error = found.error.map(|err| format!("{rel}: {err}"));
+                found
+            }
+            Err(err) => FileHits {
+                error: Some(format!("{rel}: {err}")),
+                ..FileHits::default()
+            },
+        }
+    }
+
+    fn search_lines(&self, path: WorkspacePath) -> std::io::Result<FileHits> {
+        let mut reader = BufReader::new(path.open()?);
+        let mut buffer = String::new();
+        let mut bytes_read = 0usize;
+        let mut found = FileHits::default();
+        loop {
+            buffer.clear();
+            let read = match reader.read_line(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if found.lines.is_empty() => return Err(err),
+                Err(err) => {
+                    found.error = Some(err.to_string());
+                    break;
+                }
+            };
+            bytes_read += read;
+            if bytes_read > self.max_bytes || buffer.contains('\0') {
+                break;
+            }
+            let line = buffer.trim_end_matches(['\r', '\n']);
+            if self.regex.is_match(line) {
+                found.hits.push((found.lines.len(), found.lines.len()));
+            }
+            found.lines.push(line.to_string());
+        }
+        if found.hits.is_empty() {
+            found.lines.clear();
+        }
+        Ok(found)
+    }
+
+    fn search_multiline(&self, path: WorkspacePath) -> std::io::Result<FileHits> {
+        let mut bytes = Vec::new();
+        path.open()?
+            .take(self.max_bytes as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.contains(&0) {
+            return Ok(FileHits::default());
+        }
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text,
+            // A character cut at the byte budget is fine; anything else is not text.
+            Err(err) if err.error_len().is_none() => {
+                std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default()
+            }
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ))
+            }
+        };
+
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+        let hits: Vec<(usize, usize)> = self
+            .regex
+            .find_iter(text)
+            .map(|found| {
+                let last = found.end().saturating_sub(1).max(found.start());
+                (line_of(found.start()), line_of(last))
+            })
+            .collect();
+        if hits.is_empty() {
+            return Ok(FileHits::default());
+        }
+        Ok(FileHits {
+            lines: text
+                .lines()
+                .map(|line| line.trim_end_matches('\r').to_string())
+                .collect(),
+            hits,
+            error: None,
+        })
+    }
+}
+
 fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutput {
     let args: GrepArgs = match parse_args(invocation.args) {
         Ok(args) => args,
@@ -373,6 +1213,10 @@ fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
 
     let regex_enabled = args.regex.unwrap_or(true);
     let case_sensitive = args.case_sensitive.unwrap_or(true);
+    let multiline = args.multiline.unwrap_or(false);
+    let mode = args.output_mode.unwrap_or_default();
+    let before = args.before.or(args.context).unwrap_or(0);
+    let after = args.after.or(args.context).unwrap_or(0);
     let max_results = args.max_results.unwrap_or(config.max_results);
     let max_bytes = args.max_bytes.unwrap_or(config.max_bytes);
     let max_depth = args.max_depth.unwrap_or(config.max_depth);
@@ -387,6 +1231,10 @@ fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
         Ok(set) => set,
         Err(err) => return ToolOutput::invalid_args(err),
     };
+    let types = match build_types(args.types.as_deref()) {
+        Ok(types) => types,
+        Err(err) => return ToolOutput::invalid_args(err),
+    };
 
     let pattern = if regex_enabled {
         args.pattern
@@ -396,23 +1244,29 @@ fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
 
     let regex = match RegexBuilder::new(&pattern)
         .case_insensitive(!case_sensitive)
+        .multi_line(multiline)
+        .dot_matches_new_line(multiline)
         .build()
     {
         Ok(regex) => regex,
         Err(err) => return ToolOutput::invalid_args(format!("invalid regex: {err}")),
     };
 
-    let mut builder = WalkBuilder::new(&root_path);
-    builder
-        .hidden(!include_hidden)
-        .follow_links(follow_symlinks);
+    let mut builder = workspace_walker(
+        config,
+        &root_path,
+        include_hidden,
+        follow_symlinks,
+        config.respect_ignore_files,
+    );
     builder.max_depth(Some(max_depth));
+    if let Some(types) = types {
+        builder.types(types);
+    }
 
-    let mut stdout = Vec::new();
     let mut stderr = Vec::new();
-    let mut matches = 0usize;
-
-    'walk: for entry in builder.build() {
+    let mut files = Vec::new();
+    for entry in builder.build() {
         let entry = match entry {
             Ok(entry) => entry,
             Err(err) => {
@@ -420,54 +1274,70 @@ fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
                 continue;
             }
         };
-
         if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-            let path = entry.path();
-            let rel = normalize_rel_path(&config.workspace_root, path);
-            if !globsets_match(&include_set, &exclude_set, &rel) {
-                continue;
+            let rel = normalize_rel_path(&config.workspace_root, entry.path());
+            if globsets_match(&include_set, &exclude_set, &rel) {
+                files.push((entry.into_path(), rel));
             }
+        }
+    }
 
-            let file = match File::open(path) {
-                Ok(file) => file,
-                Err(err) => {
-                    stderr.push(format!("{rel}: {err}"));
-                    continue;
-                }
-            };
-            let mut reader = BufReader::new(file);
-            let mut buffer = String::new();
-            let mut bytes_read = 0usize;
-            let mut line_no = 0usize;
-
-            loop {
-                buffer.clear();
-                let read = match reader.read_line(&mut buffer) {
-                    Ok(0) => break,
-                    Ok(n) => n,
-                    Err(err) => {
-                        stderr.push(format!("{rel}: {err}"));
-                        break;
-                    }
-                };
-                line_no += 1;
-                bytes_read += read;
-                if bytes_read > max_bytes {
-                    break;
-                }
-
-                if buffer.contains('\0') {
-                    break;
-                }
+    let search = GrepSearch {
+        workspace_root: config.workspace_root.clone(),
+        regex,
+        multiline,
+        max_bytes,
+    };
+    let results = search_files(
+        &search,
+        &files,
+        |hits| match mode {
+            GrepOutputMode::Content => hits.hits.len(),
+            _ => usize::from(!hits.hits.is_empty()),
+        },
+        max_results,
+    );
 
-                let line = buffer.trim_end_matches(['\r', '\n']);
-                if regex.is_match(line) {
-                    stdout.push(format!("{rel}:{line_no}:{line}"));
-                    matches += 1;
-                    if matches >= max_results {
-                        break 'walk;
-                    }
-                }
+    let mut stdout = Vec::new();
+    let mut matches = 0usize;
+    let mut matched_files = 0usize;
+    let mut truncated = false;
+    let mut printed_group = false;
+    for ((_, rel), found) in files.iter().zip(results) {
+        stderr.extend(found.error);
+        if found.hits.is_empty() {
+            continue;
+        }
+        let remaining = match mode {
+            GrepOutputMode::Content => max_results - matches,
+            _ => max_results - matched_files,
+        };
+        if remaining == 0 {
+            truncated = true;
+            break;
+        }
+        matched_files += 1;
+        match mode {
+            GrepOutputMode::FilesWithMatches => {
+                matches += found.hits.len();
+                stdout.push(rel.clone());
+            }
+            GrepOutputMode::Count => {
+                matches += found.hits.len();
+                stdout.push(format!("{rel}:{}", found.hits.len()));
+            }
+            GrepOutputMode::Content => {
+                let hits = &found.hits[..found.hits.len().min(remaining)];
+                truncated |= hits.len() < found.hits.len();
+                matches += hits.len();
+                render_hits(
+                    &mut stdout,
+                    rel,
+                    &found.lines,
+                    hits,
+                    (before, after),
+                    &mut printed_group,
+                );
             }
         }
     }
@@ -478,111 +1348,603 @@ fn run_grep(invocation: ToolInvocation, config: &BuiltinToolConfig) -> ToolOutpu
         exit_code: 0,
         artifacts: Some(json!({
             "root": normalize_rel_path(&config.workspace_root, &root_path),
-            "matches": matches
+            "matches": matches,
+            "files": matched_files,
+            "truncated": truncated
         })),
     }
 }
 
-#[derive(Deserialize)]
+/// Searches `files` on a pool of scoped threads. Workers claim files in walk
+/// order and stop claiming once `max_results` units are found, so the claimed
+/// files always form a prefix and the output matches a sequential search.
+fn search_files<F>(
+    search: &GrepSearch,
+    files: &[(PathBuf, String)],
+    units: F,
+    max_results: usize,
+) -> Vec<FileHits>
+where
+    F: Fn(&FileHits) -> usize + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let found = AtomicUsize::new(0);
+    let slots: Vec<Mutex<Option<FileHits>>> = files.iter().map(|_| Mutex::new(None)).collect();
+    let workers = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .min(files.len().max(1));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                if found.load(Ordering::Relaxed) >= max_results {
+                    break;
+                }
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some((path, rel)) = files.get(idx) else {
+                    break;
+                };
+                let hits = search.search(path, rel);
+                found.fetch_add(units(&hits), Ordering::Relaxed);
+                *slots[idx].lock().expect("grep slot") = Some(hits);
+            });
+        }
+    });
+
+    slots
+        .into_iter()
+        .map(|slot| slot.into_inner().expect("grep slot").unwrap_or_default())
+        .collect()
+}
+
+/// Renders hits with context, merging overlapping windows; `--` separates
+/// windows that are not contiguous, as in `grep -C`.
+fn render_hits(
+    stdout: &mut Vec<String>,
+    rel: &str,
+    lines: &[String],
+    hits: &[(usize, usize)],
+    (before, after): (usize, usize),
+    printed_group: &mut bool,
+) {
+    let with_context = before > 0 || after > 0;
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &(first, last) in hits {
+        let start = first.saturating_sub(before);
+        let end = (last + after).min(lines.len().saturating_sub(1));
+        match groups.last_mut() {
+            Some(group) if start <= group.1 + 1 => group.1 = group.1.max(end),
+            _ => groups.push((start, end)),
+        }
+    }
+
+    let matched: HashSet<usize> = hits
+        .iter()
+        .flat_map(|&(first, last)| first..=last)
+        .collect();
+    for (start, end) in groups {
+        if with_context && *printed_group {
+            stdout.push("--".to_string());
+        }
+        *printed_group = true;
+        for (idx, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+            let sep = if matched.contains(&idx) { ':' } else { '-' };
+            stdout.push(format!("{rel}{sep}{}{sep}{line}", idx + 1));
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct ShellArgs {
+    /// Command line passed to `bash -c`.
     command: String,
+    /// Working directory relative to the workspace root (default: the root).
     cwd: Option<String>,
+    /// Extra environment variables.
     env: Option<HashMap<String, String>>,
+    /// Bytes of stdout and of stderr to return; longer output keeps its head
+    /// and tail and is saved in full under `.rip/tool-output/`.
     max_bytes: Option<usize>,
+    /// Start a fresh persistent shell before running the command.
+    restart: Option<bool>,
+    /// Stop the command after this many milliseconds (default: the configured
+    /// timeout); enforced by the tool runner.
+    #[allow(dead_code)]
+    timeout_ms: Option<u64>,
 }
 
-async fn run_bash(invocation: ToolInvocation, config: BuiltinToolConfig) -> ToolOutput {
+async fn run_bash(
+    invocation: ToolInvocation,
+    config: BuiltinToolConfig,
+    sink: ToolSink,
+) -> ToolOutput {
     let args: ShellArgs = match parse_args(invocation.args) {
         Ok(args) => args,
         Err(err) => return err,
     };
 
     let max_bytes = args.max_bytes.unwrap_or(config.max_bytes);
-    let mut cmd = Command::new("bash");
-    cmd.arg("-c").arg(&args.command);
+    let bash = ("bash".to_string(), vec!["-c".to_string()]);
+    match run_shell(bash, &args, &config, max_bytes, &sink).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            match run_shell(default_shell_program(), &args, &config, max_bytes, &sink).await {
+                Ok(output) => output,
+                Err(err) => ToolOutput::failure(vec![format!("shell failed: {err}")]),
+            }
+        }
+        Ok(output) => output,
+        Err(err) => spawn_failed(&config, err),
+    }
+}
+
+/// `program args...` in `cwd` under `tool`'s resource limits, confined to
+/// the configured sandbox if any.
+fn shell_command(
+    tool: &str,
+    (program, args): (String, Vec<String>),
+    cwd: &Path,
+    config: &BuiltinToolConfig,
+) -> Result<(Command, LimitGuard), String> {
+    let mut cmd = Command::new(program);
+    cmd.args(args).current_dir(cwd);
+    // Join the cgroup and set rlimits before the sandbox unshares.
+    let limits = config
+        .resource_limits
+        .get(tool)
+        .cloned()
+        .unwrap_or_default();
+    let mut guard = LimitGuard::apply(&mut cmd, &limits);
+    if let Some(profile) = &config.sandbox {
+        let nproc = guard.sandbox_nproc();
+        sandbox::confine(&mut cmd, profile, &config.workspace_root, cwd, nproc)
+            .map_err(|err| format!("sandbox: {err}"))?;
+    }
+    Ok((cmd, guard))
+}
+
+/// Flags the call as over `limit`; the runner reports it as `tool_failed`.
+fn limit_failure(sink: &ToolSink, limit: Limit, artifacts: Option<Value>) -> ToolOutput {
+    sink.limit_exceeded(limit.as_str());
+    ToolOutput {
+        artifacts,
+        ..ToolOutput::failure(vec![format!("resource limit exceeded: {}", limit.as_str())])
+    }
+}
+
+fn spawn_failed(config: &BuiltinToolConfig, err: std::io::Error) -> ToolOutput {
+    let context = if config.sandbox.is_some() {
+        " in sandbox"
+    } else {
+        ""
+    };
+    ToolOutput::failure(vec![format!("bash failed{context}: {err}")])
+}
+
+/// Runs the command in the session's long-lived shell, starting one if
+/// needed. `cwd` and `env` are applied inside the shell, so they persist too.
+async fn run_persistent_bash(
+    invocation: ToolInvocation,
+    config: BuiltinToolConfig,
+    shells: &ShellSessions,
+    sink: ToolSink,
+) -> ToolOutput {
+    let args: ShellArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+
+    let mut prelude = String::new();
     if let Some(cwd) = args.cwd.as_deref() {
         match resolve_path(&config.workspace_root, cwd) {
             Ok(path) => {
-                cmd.current_dir(path);
+                prelude.push_str(&format!("cd -- {}\n", shell_quote(&path.to_string_lossy())));
             }
             Err(err) => return ToolOutput::failure(vec![err]),
         }
     }
     if let Some(envs) = &args.env {
-        cmd.envs(envs);
+        let mut envs: Vec<_> = envs.iter().collect();
+        envs.sort();
+        for (name, value) in envs {
+            if !is_env_name(name) {
+                return ToolOutput::invalid_args(format!(
+                    "invalid args: env name {name:?} is not a shell identifier"
+                ));
+            }
+            prelude.push_str(&format!("export {name}={}\n", shell_quote(value)));
+        }
     }
 
-    match cmd.output() {
-        Ok(output) => {
-            let stdout = split_output(output.stdout, max_bytes);
-            let stderr = split_output(output.stderr, max_bytes);
-            ToolOutput {
-                stdout,
-                stderr,
-                exit_code: output.status.code().unwrap_or(1),
-                artifacts: None,
+    let slot = shells.slot(sink.session_id());
+    let mut slot = slot.lock().await;
+    if args.restart == Some(true) {
+        *slot = None;
+    }
+    let shell = match &mut *slot {
+        Some(shell) => shell,
+        None => {
+            let bash = (
+                "bash".to_string(),
+                vec!["--noprofile".to_string(), "--norc".to_string()],
+            );
+            let root = &config.workspace_root;
+            let spawned = match shell_command("bash", bash, root, &config) {
+                Ok((cmd, limits)) => match PersistentShell::spawn(cmd, limits) {
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        let (program, _) = default_shell_program();
+                        match shell_command("bash", (program, Vec::new()), root, &config) {
+                            Ok((cmd, limits)) => PersistentShell::spawn(cmd, limits),
+                            Err(err) => return ToolOutput::failure(vec![err]),
+                        }
+                    }
+                    spawned => spawned,
+                },
+                Err(err) => return ToolOutput::failure(vec![err]),
+            };
+            match spawned {
+                Ok(shell) => slot.insert(shell),
+                Err(err) => return spawn_failed(&config, err),
             }
         }
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                return run_shell_with_args(&args, &config, max_bytes);
+    };
+
+    let max_bytes = args.max_bytes.unwrap_or(config.max_bytes);
+    let spill = OutputSpill::new(&config.workspace_root, &sink, max_bytes);
+    let outcome = shell
+        .run(
+            &prelude,
+            &args.command,
+            &sink,
+            |line| {
+                if let Some(line) = spill.push(OutputStream::Stdout, line) {
+                    sink.stdout(line);
+                }
+            },
+            |line| {
+                if let Some(line) = spill.push(OutputStream::Stderr, line) {
+                    sink.stderr(line);
+                }
+            },
+        )
+        .await;
+    let artifacts = spill.finish(&sink);
+
+    let (exit_code, stderr) = match outcome {
+        CommandOutcome::Exited(code) => (code, Vec::new()),
+        CommandOutcome::ShellExited(code) => {
+            *slot = None;
+            (
+                code.unwrap_or(1),
+                vec!["shell exited; the next command starts a new shell".to_string()],
+            )
+        }
+        CommandOutcome::Terminated(signal) => {
+            *slot = None;
+            sink.signalled(signal);
+            (1, vec![format!("terminated by {signal}")])
+        }
+        CommandOutcome::LimitExceeded { limit, stopped } => {
+            if let Some(signal) = stopped {
+                *slot = None;
+                sink.signalled(signal);
             }
-            ToolOutput::failure(vec![format!("bash failed: {err}")])
+            return limit_failure(&sink, limit, artifacts);
         }
+        CommandOutcome::Failed(err) => {
+            *slot = None;
+            (1, vec![format!("shell failed: {err}")])
+        }
+    };
+    ToolOutput {
+        stdout: Vec::new(),
+        stderr,
+        exit_code,
+        artifacts,
     }
 }
 
-fn run_shell_with_args(
+/// Spawns `program` and streams its output line by line into `sink`.
+/// Spawn errors are returned so the caller can fall back to another shell.
+async fn run_shell(
+    (program, mut program_args): (String, Vec<String>),
     args: &ShellArgs,
     config: &BuiltinToolConfig,
     max_bytes: usize,
-) -> ToolOutput {
-    let (program, mut program_args) = default_shell_program();
+    sink: &ToolSink,
+) -> std::io::Result<ToolOutput> {
     program_args.push(args.command.clone());
 
-    let mut cmd = Command::new(program);
-    cmd.args(program_args);
-    if let Some(cwd) = args.cwd.as_deref() {
-        match resolve_path(&config.workspace_root, cwd) {
-            Ok(path) => {
-                cmd.current_dir(path);
+    let cwd = match args.cwd.as_deref() {
+        Some(cwd) => match resolve_path(&config.workspace_root, cwd) {
+            Ok(path) => path,
+            Err(err) => return Ok(ToolOutput::failure(vec![err])),
+        },
+        None => config.workspace_root.clone(),
+    };
+    let (mut cmd, limits) = match shell_command("bash", (program, program_args), &cwd, config) {
+        Ok(command) => command,
+        Err(err) => return Ok(ToolOutput::failure(vec![err])),
+    };
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    ProcessGroup::configure(&mut cmd);
+    if let Some(envs) = &args.env {
+        cmd.envs(envs);
+    }
+
+    let mut child = cmd.spawn()?;
+    let spill = OutputSpill::new(&config.workspace_root, sink, max_bytes);
+    let mut group = ProcessGroup::new(&child);
+    let stdout = child.stdout.take().expect("piped stdout");
+    let stderr = child.stderr.take().expect("piped stderr");
+
+    let pid = child.id();
+    let mut signal = None;
+    let mut exceeded = None;
+    let waiter = async {
+        tokio::select! {
+            status = child.wait() => status,
+            limit = limits.exceeded(pid) => {
+                let sent = group.terminate(&mut child, TERMINATE_GRACE).await;
+                sink.signalled(sent);
+                exceeded = Some(limit);
+                child.wait().await
+            }
+            _ = sink.cancelled() => {
+                let sent = group.terminate(&mut child, TERMINATE_GRACE).await;
+                sink.signalled(sent);
+                signal = Some(sent);
+                child.wait().await
             }
+        }
+    };
+    let (status, _, _) = tokio::join!(
+        waiter,
+        stream_lines(stdout, |line| {
+            if let Some(line) = spill.push(OutputStream::Stdout, line) {
+                sink.stdout(line);
+            }
+        }),
+        stream_lines(stderr, |line| {
+            if let Some(line) = spill.push(OutputStream::Stderr, line) {
+                sink.stderr(line);
+            }
+        }),
+    );
+    group.disarm();
+    let artifacts = spill.finish(sink);
+
+    if let Some(signal) = signal {
+        return Ok(ToolOutput {
+            artifacts,
+            ..ToolOutput::failure(vec![format!("terminated by {signal}")])
+        });
+    }
+    let exceeded = exceeded.or_else(|| {
+        let status = status.as_ref().ok()?;
+        limits.classify(ExitOutcome::from(*status))
+    });
+    if let Some(limit) = exceeded {
+        return Ok(limit_failure(sink, limit, artifacts));
+    }
+    Ok(match status {
+        Ok(status) => ToolOutput {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            exit_code: status.code().unwrap_or(1),
+            artifacts,
+        },
+        Err(err) => ToolOutput::failure(vec![format!("wait failed: {err}")]),
+    })
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct BashBackgroundArgs {
+    /// Command line passed to `bash -c`.
+    command: String,
+    /// Working directory relative to the workspace root.
+    cwd: Option<String>,
+    /// Extra environment variables.
+    env: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct BashOutputArgs {
+    /// Job id returned by `bash_background`.
+    job_id: String,
+    /// Wait up to this many milliseconds for the job to exit before reading.
+    wait_ms: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct BashKillArgs {
+    /// Job id returned by `bash_background`.
+    job_id: String,
+}
+
+fn run_bash_background(
+    invocation: ToolInvocation,
+    config: &BuiltinToolConfig,
+    jobs: &BackgroundJobs,
+    sink: ToolSink,
+) -> ToolOutput {
+    let args: BashBackgroundArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+    let cwd = match args.cwd.as_deref() {
+        Some(cwd) => match resolve_path(&config.workspace_root, cwd) {
+            Ok(path) => path,
             Err(err) => return ToolOutput::failure(vec![err]),
+        },
+        None => config.workspace_root.clone(),
+    };
+
+    let command = |(program, mut program_args): (String, Vec<String>)| {
+        program_args.push(args.command.clone());
+        let (mut cmd, limits) =
+            shell_command("bash_background", (program, program_args), &cwd, config)?;
+        if let Some(envs) = &args.env {
+            cmd.envs(envs);
+        }
+        Ok::<_, String>((cmd, limits))
+    };
+    let start = |program| match command(program) {
+        Ok((cmd, limits)) => Ok(jobs.start(sink.session_id(), cmd, limits, config.max_bytes)),
+        Err(err) => Err(ToolOutput::failure(vec![err])),
+    };
+    let bash = ("bash".to_string(), vec!["-c".to_string()]);
+    let started = match start(bash) {
+        Ok(Err(StartError::Spawn(err))) if err.kind() == std::io::ErrorKind::NotFound => {
+            start(default_shell_program())
+        }
+        started => started,
+    };
+    let job = match started {
+        Ok(Ok(job)) => job,
+        Ok(Err(StartError::TooMany)) => {
+            return ToolOutput::failure(vec![format!(
+                "too many background jobs running (max {MAX_RUNNING_JOBS}); stop one with bash_kill"
+            )])
         }
+        Ok(Err(StartError::Spawn(err))) => return spawn_failed(config, err),
+        Err(output) => return output,
+    };
+
+    let pid = job
+        .pid
+        .map(|pid| format!(" (pid {pid})"))
+        .unwrap_or_default();
+    ToolOutput {
+        stdout: vec![format!("started {}{pid}", job.id)],
+        stderr: Vec::new(),
+        exit_code: 0,
+        artifacts: Some(json!({ "job_id": job.id, "pid": job.pid })),
     }
-    if let Some(envs) = &args.env {
-        cmd.envs(envs);
+}
+
+async fn run_bash_output(
+    invocation: ToolInvocation,
+    jobs: &BackgroundJobs,
+    sink: ToolSink,
+) -> ToolOutput {
+    let args: BashOutputArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+    let Some(job) = jobs.get(sink.session_id(), &args.job_id) else {
+        return ToolOutput::failure(vec![format!("unknown job: {}", args.job_id)]);
+    };
+    if let Some(wait_ms) = args.wait_ms {
+        tokio::select! {
+            _ = job.wait(Duration::from_millis(wait_ms)) => {}
+            _ = sink.cancelled() => {}
+        }
     }
+    report_job(jobs, &job, &sink)
+}
 
-    match cmd.output() {
-        Ok(output) => {
-            let stdout = split_output(output.stdout, max_bytes);
-            let stderr = split_output(output.stderr, max_bytes);
-            ToolOutput {
-                stdout,
-                stderr,
-                exit_code: output.status.code().unwrap_or(1),
-                artifacts: None,
-            }
+async fn run_bash_kill(
+    invocation: ToolInvocation,
+    jobs: &BackgroundJobs,
+    sink: ToolSink,
+) -> ToolOutput {
+    let args: BashKillArgs = match parse_args(invocation.args) {
+        Ok(args) => args,
+        Err(err) => return err,
+    };
+    let Some(job) = jobs.get(sink.session_id(), &args.job_id) else {
+        return ToolOutput::failure(vec![format!("unknown job: {}", args.job_id)]);
+    };
+    if job.status() == JobStatus::Running {
+        job.kill().await;
+    }
+    report_job(jobs, &job, &sink)
+}
+
+/// Emits the job's buffered output as job-tagged frames; a job whose exit
+/// is reported here leaves the table.
+fn report_job(jobs: &BackgroundJobs, job: &Job, sink: &ToolSink) -> ToolOutput {
+    let drained = job.drain();
+    if drained.dropped_lines > 0 {
+        sink.job_stdout(
+            &job.id,
+            format!(
+                "[... {} lines dropped from the job's output buffer ...]",
+                drained.dropped_lines
+            ),
+        );
+    }
+    for (stream, line) in drained.lines {
+        match stream {
+            OutputStream::Stdout => sink.job_stdout(&job.id, line),
+            OutputStream::Stderr => sink.job_stderr(&job.id, line),
         }
-        Err(err) => ToolOutput::failure(vec![format!("shell failed: {err}")]),
+    }
+    if drained.status != JobStatus::Running {
+        jobs.remove(&job.id);
+    }
+
+    let mut artifacts = drained.status.to_json();
+    artifacts["job_id"] = json!(job.id);
+    artifacts["dropped_lines"] = json!(drained.dropped_lines);
+    ToolOutput {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        exit_code: 0,
+        artifacts: Some(artifacts),
     }
 }
 
+/// Forwards each line until the pipe closes; budgeting is up to `emit`.
+pub(crate) async fn stream_lines<R, F>(reader: R, mut emit: F)
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(String),
+{
+    let mut reader = AsyncBufReader::new(reader);
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let text = String::from_utf8_lossy(&buffer);
+        emit(text.trim_end_matches(['\r', '\n']).to_string());
+    }
+}
+
+/// JSON Schema for an args struct, without the draft/title wrapper.
+fn args_schema<T: JsonSchema>() -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(T)).unwrap_or_default();
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+        obj.remove("title");
+    }
+    schema
+}
+
 fn parse_args<T: DeserializeOwned>(args: Value) -> Result<T, ToolOutput> {
     serde_json::from_value(args)
         .map_err(|err| ToolOutput::invalid_args(format!("invalid args: {err}")))
 }
 
+/// A walked `path` (under `root`) resolved again through directory handles,
+/// so an entry swapped for a symlink after the walk is not followed out.
+fn workspace_entry(root: &Path, path: &Path) -> Result<WorkspacePath, String> {
+    let rel = path
+        .strip_prefix(root)
+        .map_err(|_| ESCAPES_ROOT.to_string())?;
+    resolve(root, rel)
+}
+
+/// `raw` resolved inside `root`, for tools that go on to use the path
+/// itself (walkers and shell working directories).
 fn resolve_path(root: &Path, raw: &str) -> Result<PathBuf, String> {
-    let path = PathBuf::from(raw);
-    if path.is_absolute() {
-        return Err("absolute paths are not allowed".to_string());
-    }
-    if path.components().any(|c| matches!(c, Component::ParentDir)) {
-        return Err("path escapes workspace root".to_string());
-    }
-    Ok(root.join(path))
+    resolve(root, raw).map(|resolved| resolved.path)
 }
 
 fn normalize_rel_path(root: &Path, path: &Path) -> String {
@@ -610,13 +1972,6 @@ fn truncate_utf8(bytes: &[u8], max_bytes: usize) -> (String, bool, usize) {
     )
 }
 
-fn split_output(bytes: Vec<u8>, max_bytes: usize) -> Vec<String> {
-    let (text, _truncated, _) = truncate_utf8(&bytes, max_bytes);
-    text.lines()
-        .map(|line| line.trim_end_matches('\r').to_string())
-        .collect()
-}
-
 #[cfg(windows)]
 fn default_shell_program() -> (String, Vec<String>) {
     if let Some(program) = find_program("pwsh") {
@@ -662,6 +2017,64 @@ fn find_program(name: &str) -> Option<String> {
     None
 }
 
+fn workspace_walker(
+    config: &BuiltinToolConfig,
+    root: &Path,
+    include_hidden: bool,
+    follow_symlinks: bool,
+    respect_ignore_files: bool,
+) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .standard_filters(false)
+        .hidden(!include_hidden)
+        .follow_links(follow_symlinks);
+    if follow_symlinks {
+        // Followed links must land inside the workspace; ones that escape,
+        // dangle or cannot be checked (not even the root) are skipped along
+        // with everything below them.
+        let real_root = config.workspace_root.canonicalize().ok();
+        builder.filter_entry(move |entry| {
+            if !entry.path_is_symlink() {
+                return true;
+            }
+            let Some(real_root) = &real_root else {
+                return false;
+            };
+            std::fs::canonicalize(entry.path()).is_ok_and(|target| target.starts_with(real_root))
+        });
+    }
+    if respect_ignore_files {
+        // `.gitignore` applies even outside a git checkout, and rules from
+        // parent directories still apply when walking a subdirectory.
+        builder
+            .git_ignore(true)
+            .git_exclude(true)
+            .ignore(true)
+            .parents(true)
+            .require_git(false)
+            .add_custom_ignore_filename(".ripignore");
+    }
+    builder
+}
+
+fn build_types(names: Option<&[String]>) -> Result<Option<ignore::types::Types>, String> {
+    let names = match names {
+        Some(names) if !names.is_empty() => names,
+        _ => return Ok(None),
+    };
+
+    let mut builder = ignore::types::TypesBuilder::new();
+    builder.add_defaults();
+    for name in names {
+        builder.select(name);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|err| format!("invalid file type: {err}"))
+}
+
 fn build_globset(patterns: Option<&[String]>) -> Result<Option<GlobSet>, String> {
     let patterns = match patterns {
         Some(patterns) if !patterns.is_empty() => patterns,
@@ -727,12 +2140,49 @@ mod tests {
         assert_eq!(used, 0);
     }
 
-    #[test]
-    fn split_output_trims_cr() {
-        let output = split_output(b"one\r\ntwo\r\n".to_vec(), 1024);
+    #[tokio::test]
+    async fn stream_lines_trims_cr() {
+        let mut output = Vec::new();
+        stream_lines(&b"one\r\ntwo\r\n"[..], |line| output.push(line)).await;
         assert_eq!(output, vec!["one".to_string(), "two".to_string()]);
     }
 
+    #[tokio::test]
+    async fn stream_lines_forwards_unterminated_last_line() {
+        let mut output = Vec::new();
+        stream_lines(&b"abc\ndefgh"[..], |line| output.push(line)).await;
+        assert_eq!(output, vec!["abc".to_string(), "defgh".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn grep_reopens_walked_files_through_the_workspace() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).expect("mkdir");
+        std::fs::write(root.join("sub/a.txt"), "needle inside").expect("write");
+        std::fs::write(outside.path().join("a.txt"), "needle outside").expect("write");
+        let search = GrepSearch {
+            workspace_root: root.to_path_buf(),
+            regex: regex::Regex::new("needle").expect("regex"),
+            multiline: false,
+            max_bytes: 1024,
+        };
+        let walked = root.join("sub/a.txt");
+        assert_eq!(
+            search.search(&walked, "sub/a.txt").lines,
+            vec!["needle inside"]
+        );
+
+        // The directory is swapped for a link out after the walk listed it.
+        std::fs::rename(root.join("sub"), root.join("moved")).expect("rename");
+        std::os::unix::fs::symlink(outside.path(), root.join("sub")).expect("symlink");
+        let found = search.search(&walked, "sub/a.txt");
+        assert!(found.lines.is_empty());
+        assert!(found.error.is_some_and(|err| err.contains("escapes")));
+    }
+
     #[test]
     fn globsets_match_exclude_only() {
         let patterns = vec!["**/*.log".to_string()];
@@ -1,5 +1,25 @@
+mod background;
 mod builtins;
+mod limits;
+mod mode;
+mod patch;
+mod permissions;
+mod process;
+mod resolve;
 mod runtime;
+mod sandbox;
+mod shell;
+mod spill;
 
 pub use builtins::{register_builtin_tools, BuiltinToolConfig};
-pub use runtime::{ToolHandler, ToolInvocation, ToolOutput, ToolRegistry, ToolRunner};
+pub use limits::ResourceLimits;
+pub use mode::{ToolMode, ToolModes};
+pub use permissions::{
+    command_subjects, PermissionConfig, PermissionDecision, PermissionPolicy, PermissionSubject,
+    PermissionVerdict, ToolPermissions,
+};
+pub use runtime::{
+    invoke, PermissionSubjects, SessionTeardown, ToolHandler, ToolInvocation, ToolOutput,
+    ToolRegistry, ToolRunner, ToolSink, ToolSpec,
+};
+pub use sandbox::SandboxProfile;
@@ -0,0 +1,454 @@
+//! Resource limits for processes started by shell tools.
+//!
+//! CPU time and file size use rlimits, which the kernel enforces per process
+//! by sending SIGXCPU/SIGXFSZ. Memory (RSS) and process count cover the whole
+//! tree: in a cgroup v2 leaf (`memory.max`, `pids.max`) when ripd's own cgroup
+//! delegates those controllers. Without one, a sandboxed command gets
+//! `RLIMIT_NPROC` inside its own user namespace, which counts only its tree
+//! (unless ripd runs as root, which the rlimit does not apply to).
+//! Whatever is left falls back to a watchdog that samples `/proc` and kills
+//! the process group once a limit is crossed; it is best effort, since a
+//! process that leaves the group or forks between samples escapes it.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// Per-tool resource limits; `None` leaves a resource unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Resident memory of the whole process tree, in bytes.
+    pub max_memory_bytes: Option<u64>,
+    /// CPU time of each process, in seconds.
+    pub max_cpu_seconds: Option<u64>,
+    /// Largest file a process may write, in bytes.
+    pub max_file_size_bytes: Option<u64>,
+    /// Processes alive in the tree at once.
+    pub max_pids: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Limit {
+    Memory,
+    Cpu,
+    FileSize,
+    Pids,
+}
+
+impl Limit {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Limit::Memory => "memory",
+            Limit::Cpu => "cpu",
+            Limit::FileSize => "file_size",
+            Limit::Pids => "pids",
+        }
+    }
+}
+
+/// How often the watchdog samples the process tree.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Limits attached to one spawned command; keep it alive as long as the
+/// process runs.
+pub(crate) struct LimitGuard {
+    limits: ResourceLimits,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    cgroup: Option<imp::Cgroup>,
+    /// The sandbox enforces `max_pids` with `RLIMIT_NPROC`.
+    sandbox_nproc: bool,
+}
+
+impl LimitGuard {
+    /// Arranges for `cmd` to start under `limits`.
+    pub(crate) fn apply(cmd: &mut Command, limits: &ResourceLimits) -> Self {
+        let cgroup = imp::apply(cmd, limits);
+        Self {
+            limits: limits.clone(),
+            cgroup,
+            sandbox_nproc: false,
+        }
+    }
+
+    /// The `RLIMIT_NPROC` a sandbox should set once it is in its own user
+    /// namespace, where the count covers only the sandboxed tree. `None`
+    /// when a cgroup already caps the tree, no process limit is set, or we
+    /// run as root, which the kernel exempts from the rlimit.
+    pub(crate) fn sandbox_nproc(&mut self) -> Option<u64> {
+        if self.cgroup.is_some() || imp::nproc_exempt() {
+            return None;
+        }
+        let max = self.limits.max_pids?;
+        self.sandbox_nproc = true;
+        // One more for the process that waits on the sandboxed command.
+        Some(max.saturating_add(1))
+    }
+
+    /// Resolves when the tree led by process group `pgid` crosses a limit
+    /// the kernel does not enforce for us; pends forever otherwise.
+    pub(crate) async fn exceeded(&self, pgid: Option<u32>) -> Limit {
+        let memory = self.limits.max_memory_bytes;
+        let pids = self.limits.max_pids.filter(|_| !self.sandbox_nproc);
+        let (Some(pgid), false) = (pgid, self.cgroup.is_some()) else {
+            return std::future::pending().await;
+        };
+        if memory.is_none() && pids.is_none() {
+            return std::future::pending().await;
+        }
+        loop {
+            tokio::time::sleep(WATCHDOG_INTERVAL).await;
+            let Some((rss, count)) = imp::group_usage(pgid) else {
+                continue;
+            };
+            if memory.is_some_and(|max| rss > max) {
+                return Limit::Memory;
+            }
+            if pids.is_some_and(|max| count > max) {
+                return Limit::Pids;
+            }
+        }
+    }
+
+    /// The limit that ended a process with `status`, if any. Only a real
+    /// signal termination counts: an exit code of `128 + signal` is just a
+    /// number the command chose (or its shell passed on).
+    pub(crate) fn classify(&self, status: ExitOutcome) -> Option<Limit> {
+        if let Some(limit) = self.cgroup.as_ref().and_then(|cgroup| cgroup.hit()) {
+            return Some(limit);
+        }
+        let ExitOutcome::Signal(signal) = status else {
+            return None;
+        };
+        if self.limits.max_cpu_seconds.is_some() && Some(signal) == imp::SIGXCPU {
+            return Some(Limit::Cpu);
+        }
+        if self.limits.max_file_size_bytes.is_some() && Some(signal) == imp::SIGXFSZ {
+            return Some(Limit::FileSize);
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ExitOutcome {
+    /// Exited on its own, whatever the code.
+    Exited,
+    Signal(i32),
+}
+
+impl From<std::process::ExitStatus> for ExitOutcome {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return ExitOutcome::Signal(signal);
+            }
+        }
+        ExitOutcome::Exited
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use tokio::process::Command;
+
+    use super::{Limit, ResourceLimits};
+
+    pub(super) const SIGXCPU: Option<i32> = Some(libc::SIGXCPU);
+    pub(super) const SIGXFSZ: Option<i32> = Some(libc::SIGXFSZ);
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+    /// How long a killed cgroup gets to empty before it is left behind.
+    const CGROUP_REMOVE_ATTEMPTS: u32 = 100;
+    const CGROUP_REMOVE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+    pub(super) fn apply(cmd: &mut Command, limits: &ResourceLimits) -> Option<Cgroup> {
+        let cgroup = Cgroup::create(limits);
+        let procs = cgroup.as_ref().map(|cgroup| cgroup.procs.as_raw_fd());
+        let cpu = limits.max_cpu_seconds;
+        let fsize = limits.max_file_size_bytes;
+        if procs.is_none() && cpu.is_none() && fsize.is_none() {
+            return cgroup;
+        }
+        // SAFETY: the closure only calls write(2) and setrlimit(2).
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(fd) = procs {
+                    // "0" moves the writing process, i.e. this child.
+                    if libc::write(fd, b"0".as_ptr().cast(), 1) != 1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(seconds) = cpu {
+                    // SIGXCPU at the soft limit, SIGKILL a second later.
+                    set_rlimit(libc::RLIMIT_CPU, seconds, seconds.saturating_add(1))?;
+                }
+                if let Some(bytes) = fsize {
+                    set_rlimit(libc::RLIMIT_FSIZE, bytes, bytes)?;
+                }
+                Ok(())
+            });
+        }
+        cgroup
+    }
+
+    #[cfg(target_env = "gnu")]
+    type Resource = libc::__rlimit_resource_t;
+    #[cfg(not(target_env = "gnu"))]
+    type Resource = libc::c_int;
+
+    fn set_rlimit(resource: Resource, soft: u64, hard: u64) -> io::Result<()> {
+        let limit = libc::rlimit {
+            rlim_cur: soft as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        // SAFETY: `limit` is a valid rlimit for the duration of the call.
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(super) fn nproc_exempt() -> bool {
+        // SAFETY: getuid cannot fail.
+        unsafe { libc::getuid() == 0 }
+    }
+
+    /// Total RSS in bytes and process count of process group `pgid`.
+    pub(super) fn group_usage(pgid: u32) -> Option<(u64, u64)> {
+        // SAFETY: sysconf has no preconditions.
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+        let (mut rss, mut count) = (0, 0);
+        for entry in fs::read_dir("/proc").ok()?.flatten() {
+            let name = entry.file_name();
+            if !name.to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            // Fields after the parenthesised command: state, ppid, pgrp, ...
+            let Some((_, rest)) = stat.rsplit_once(')') else {
+                continue;
+            };
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            if fields.get(2).and_then(|pgrp| pgrp.parse::<u32>().ok()) != Some(pgid) {
+                continue;
+            }
+            count += 1;
+            rss += fields
+                .get(21)
+                .and_then(|pages| pages.parse::<u64>().ok())
+                .unwrap_or(0)
+                * page;
+        }
+        Some((rss, count))
+    }
+
+    /// A cgroup v2 leaf created for one command.
+    pub(crate) struct Cgroup {
+        path: PathBuf,
+        procs: File,
+        /// Event counts already reported, so a long-lived shell is not
+        /// blamed again for an earlier command's hit.
+        seen_oom_kills: AtomicU64,
+        seen_pids_max: AtomicU64,
+    }
+
+    impl Cgroup {
+        fn create(limits: &ResourceLimits) -> Option<Self> {
+            if limits.max_memory_bytes.is_none() && limits.max_pids.is_none() {
+                return None;
+            }
+            let root = Path::new(CGROUP_ROOT);
+            if !root.join("cgroup.controllers").exists() {
+                return None;
+            }
+            let own = fs::read_to_string("/proc/self/cgroup").ok()?;
+            let own = own.lines().find_map(|line| line.strip_prefix("0::"))?;
+            let parent = root.join(own.trim_start_matches('/'));
+            let enabled = fs::read_to_string(parent.join("cgroup.subtree_control")).ok()?;
+            let enabled: Vec<&str> = enabled.split_whitespace().collect();
+            if (limits.max_memory_bytes.is_some() && !enabled.contains(&"memory"))
+                || (limits.max_pids.is_some() && !enabled.contains(&"pids"))
+            {
+                return None;
+            }
+
+            let path = parent.join(format!("rip-tool-{}", uuid::Uuid::new_v4().simple()));
+            fs::create_dir(&path).ok()?;
+            let setup = || -> io::Result<File> {
+                if let Some(bytes) = limits.max_memory_bytes {
+                    fs::write(path.join("memory.max"), bytes.to_string())?;
+                    // Without this, swap would stand in for the capped RSS.
+                    let _ = fs::write(path.join("memory.swap.max"), "0");
+                }
+                if let Some(pids) = limits.max_pids {
+                    fs::write(path.join("pids.max"), pids.to_string())?;
+                }
+                OpenOptions::new()
+                    .write(true)
+                    .open(path.join("cgroup.procs"))
+            };
+            match setup() {
+                Ok(procs) => Some(Self {
+                    path,
+                    procs,
+                    seen_oom_kills: AtomicU64::new(0),
+                    seen_pids_max: AtomicU64::new(0),
+                }),
+                Err(_) => {
+                    let _ = fs::remove_dir(&path);
+                    None
+                }
+            }
+        }
+
+        pub(super) fn hit(&self) -> Option<Limit> {
+            let count = |file: &str, key: &str| -> u64 {
+                fs::read_to_string(self.path.join(file))
+                    .ok()
+                    .and_then(|events| {
+                        events.lines().find_map(|line| {
+                            let (name, value) = line.split_once(' ')?;
+                            (name == key).then(|| value.trim().parse().ok())?
+                        })
+                    })
+                    .unwrap_or(0)
+            };
+            let fresh = |seen: &AtomicU64, now: u64| seen.fetch_max(now, Ordering::Relaxed) < now;
+            let oom_kills = fresh(&self.seen_oom_kills, count("memory.events", "oom_kill"));
+            let pids_max = fresh(&self.seen_pids_max, count("pids.events", "max"));
+            if oom_kills {
+                Some(Limit::Memory)
+            } else if pids_max {
+                Some(Limit::Pids)
+            } else {
+                None
+            }
+        }
+    }
+
+    impl Drop for Cgroup {
+        fn drop(&mut self) {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            // Something in the group outlived the command (e.g. it left the
+            // process group): kill the whole cgroup and remove it once the
+            // kernel has emptied it.
+            let _ = fs::write(self.path.join("cgroup.kill"), "1");
+            let path = self.path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..CGROUP_REMOVE_ATTEMPTS {
+                    match fs::remove_dir(&path) {
+                        Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                            std::thread::sleep(CGROUP_REMOVE_INTERVAL);
+                        }
+                        _ => return,
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use tokio::process::Command;
+
+    use super::{Limit, ResourceLimits};
+
+    pub(super) const SIGXCPU: Option<i32> = None;
+    pub(super) const SIGXFSZ: Option<i32> = None;
+
+    pub(crate) struct Cgroup;
+
+    impl Cgroup {
+        pub(super) fn hit(&self) -> Option<Limit> {
+            None
+        }
+    }
+
+    pub(super) fn apply(_cmd: &mut Command, _limits: &ResourceLimits) -> Option<Cgroup> {
+        None
+    }
+
+    pub(super) fn nproc_exempt() -> bool {
+        true
+    }
+
+    pub(super) fn group_usage(_pgid: u32) -> Option<(u64, u64)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_maps_signals_to_configured_limits() {
+        #[cfg(target_os = "linux")]
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut cmd = Command::new("true");
+        let guard = LimitGuard::apply(
+            &mut cmd,
+            &ResourceLimits {
+                max_cpu_seconds: Some(1),
+                ..ResourceLimits::default()
+            },
+        );
+        assert_eq!(guard.classify(ExitOutcome::Exited), None);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                guard.classify(ExitOutcome::Signal(libc::SIGXCPU)),
+                Some(Limit::Cpu)
+            );
+            // A shell that exits with `128 + SIGXCPU` was not stopped itself.
+            let status = std::process::ExitStatus::from_raw((128 + libc::SIGXCPU) << 8);
+            assert_eq!(guard.classify(ExitOutcome::from(status)), None);
+            // No file size limit configured, so SIGXFSZ is not attributed.
+            assert_eq!(guard.classify(ExitOutcome::Signal(libc::SIGXFSZ)), None);
+        }
+    }
+
+    #[test]
+    fn sandbox_nproc_takes_over_the_pids_watchdog() {
+        let mut cmd = Command::new("true");
+        let mut guard = LimitGuard::apply(&mut cmd, &ResourceLimits::default());
+        assert_eq!(guard.sandbox_nproc(), None);
+
+        let limits = ResourceLimits {
+            max_pids: Some(4),
+            ..ResourceLimits::default()
+        };
+        let mut guard = LimitGuard::apply(&mut cmd, &limits);
+        if guard.cgroup.is_none() && !imp::nproc_exempt() {
+            assert_eq!(guard.sandbox_nproc(), Some(5));
+            assert!(guard.sandbox_nproc);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn group_usage_counts_own_group() {
+        // SAFETY: getpgrp cannot fail.
+        let pgid = unsafe { libc::getpgrp() } as u32;
+        let (rss, count) = imp::group_usage(pgid).expect("proc");
+        assert!(count >= 1);
+        assert!(rss > 0);
+    }
+}
@@ -0,0 +1,48 @@
+//! Per-session tool modes. In plan mode a session only sees and runs the
+//! tools registered as read-only, so the agent can explore and propose
+//! before anything in the workspace changes.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolMode {
+    #[default]
+    Default,
+    /// Only read-only tools are offered to the model and allowed to run.
+    Plan,
+}
+
+impl ToolMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ToolMode::Default => "default",
+            ToolMode::Plan => "plan",
+        }
+    }
+}
+
+/// The mode of each session; sessions never set are in `ToolMode::Default`.
+#[derive(Default)]
+pub struct ToolModes {
+    modes: Mutex<HashMap<String, ToolMode>>,
+}
+
+impl ToolModes {
+    pub fn get(&self, session_id: &str) -> ToolMode {
+        let modes = self.modes.lock().expect("tool modes mutex");
+        modes.get(session_id).copied().unwrap_or_default()
+    }
+
+    /// Switches `session_id` to `mode`, taking effect from the next call.
+    pub fn set(&self, session_id: &str, mode: ToolMode) {
+        let mut modes = self.modes.lock().expect("tool modes mutex");
+        match mode {
+            ToolMode::Default => modes.remove(session_id),
+            mode => modes.insert(session_id.to_string(), mode),
+        };
+    }
+}
@@ -0,0 +1,724 @@
+/// Context lines a hunk may drop from each end when its full context does not match.
+const MAX_CONTEXT_FUZZ: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FilePatch {
+    Add {
+        path: String,
+        lines: Vec<String>,
+        newline: bool,
+    },
+    Delete {
+        path: String,
+    },
+    Update {
+        path: String,
+        move_to: Option<String>,
+        hunks: Vec<Hunk>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Hunk {
+    /// 0-based position in the original file, from a unified `@@ -l,c` header.
+    pub hint: Option<usize>,
+    /// Envelope `@@ line` anchors that must be found, in order, before the hunk.
+    pub anchors: Vec<String>,
+    pub lines: Vec<HunkLine>,
+    /// The hunk must match at the end of the file (`*** End of File`).
+    pub at_eof: bool,
+    pub no_newline_old: bool,
+    pub no_newline_new: bool,
+}
+
+impl Hunk {
+    fn old_side(lines: &[HunkLine]) -> Vec<&str> {
+        lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn added(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Add(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn mark_no_newline(&mut self) {
+        match self.lines.last() {
+            Some(HunkLine::Context(_)) => {
+                self.no_newline_old = true;
+                self.no_newline_new = true;
+            }
+            Some(HunkLine::Remove(_)) => self.no_newline_old = true,
+            Some(HunkLine::Add(_)) => self.no_newline_new = true,
+            None => {}
+        }
+    }
+
+    fn leading_context(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|line| matches!(line, HunkLine::Context(_)))
+            .count()
+    }
+
+    fn trailing_context(&self) -> usize {
+        self.lines
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line, HunkLine::Context(_)))
+            .count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Applied {
+    /// 1-based line where the hunk landed.
+    pub line: usize,
+    /// Whitespace relaxations plus context lines dropped to find a match.
+    pub fuzz: usize,
+}
+
+/// Parses a unified diff or a `*** Begin Patch` envelope.
+pub(crate) fn parse_patch(text: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    match lines
+        .iter()
+        .position(|line| line.trim() == "*** Begin Patch")
+    {
+        Some(begin) => parse_envelope(&lines, begin + 1),
+        None => parse_unified(&lines),
+    }
+}
+
+fn parse_envelope(lines: &[&str], start: usize) -> Result<Vec<FilePatch>, String> {
+    let mut patches = Vec::new();
+    let mut idx = start;
+    while let Some(raw) = lines.get(idx) {
+        let line = raw.trim_end();
+        if line == "*** End Patch" {
+            if patches.is_empty() {
+                return Err("patch has no file operations".to_string());
+            }
+            return Ok(patches);
+        }
+        if line.is_empty() {
+            idx += 1;
+            continue;
+        }
+        idx += 1;
+        if let Some(path) = line.strip_prefix("*** Add File: ") {
+            let mut content = Vec::new();
+            let mut blanks = 0;
+            while let Some(body) = lines.get(idx).filter(|line| !line.starts_with("*** ")) {
+                if body.trim().is_empty() {
+                    blanks += 1;
+                } else if let Some(text) = body.strip_prefix('+') {
+                    content.extend(std::iter::repeat_n(String::new(), blanks));
+                    blanks = 0;
+                    content.push(text.to_string());
+                } else {
+                    return Err(format!(
+                        "line {}: added file lines must start with '+'",
+                        idx + 1
+                    ));
+                }
+                idx += 1;
+            }
+            patches.push(FilePatch::Add {
+                path: path.trim().to_string(),
+                lines: content,
+                newline: true,
+            });
+        } else if let Some(path) = line.strip_prefix("*** Delete File: ") {
+            patches.push(FilePatch::Delete {
+                path: path.trim().to_string(),
+            });
+        } else if let Some(path) = line.strip_prefix("*** Update File: ") {
+            let move_to = lines
+                .get(idx)
+                .and_then(|line| line.strip_prefix("*** Move to: "))
+                .map(|dest| dest.trim().to_string());
+            if move_to.is_some() {
+                idx += 1;
+            }
+            let (hunks, next) = parse_envelope_hunks(lines, idx)?;
+            if hunks.is_empty() {
+                return Err(format!(
+                    "line {idx}: update of {} has no hunks",
+                    path.trim()
+                ));
+            }
+            idx = next;
+            patches.push(FilePatch::Update {
+                path: path.trim().to_string(),
+                move_to,
+                hunks,
+            });
+        } else {
+            return Err(format!(
+                "line {idx}: expected a file operation, found '{line}'"
+            ));
+        }
+    }
+    Err("missing '*** End Patch'".to_string())
+}
+
+fn parse_envelope_hunks(lines: &[&str], start: usize) -> Result<(Vec<Hunk>, usize), String> {
+    let mut hunks = Vec::new();
+    let mut current = Hunk::default();
+    let mut idx = start;
+    while let Some(line) = lines.get(idx) {
+        if line.trim_end() == "*** End of File" {
+            current.at_eof = true;
+            idx += 1;
+            continue;
+        }
+        if line.starts_with("*** ") {
+            break;
+        }
+        if let Some(anchor) = line.strip_prefix("@@") {
+            if !current.lines.is_empty() {
+                push_envelope_hunk(&mut hunks, std::mem::take(&mut current));
+            }
+            let anchor = anchor.trim();
+            if !anchor.is_empty() {
+                current.anchors.push(anchor.to_string());
+            }
+        } else {
+            current.lines.push(
+                parse_hunk_line(line).ok_or_else(|| {
+                    format!("line {}: unexpected line in hunk: '{line}'", idx + 1)
+                })?,
+            );
+        }
+        idx += 1;
+    }
+    if !current.lines.is_empty() {
+        push_envelope_hunk(&mut hunks, current);
+    }
+    Ok((hunks, idx))
+}
+
+fn push_envelope_hunk(hunks: &mut Vec<Hunk>, mut hunk: Hunk) {
+    // Blank separator lines before the next section are not context.
+    while matches!(hunk.lines.last(), Some(HunkLine::Context(text)) if text.is_empty()) {
+        hunk.lines.pop();
+    }
+    if !hunk.lines.is_empty() {
+        hunks.push(hunk);
+    }
+}
+
+fn parse_hunk_line(line: &str) -> Option<HunkLine> {
+    if line.is_empty() {
+        return Some(HunkLine::Context(String::new()));
+    }
+    let kind = match line.as_bytes()[0] {
+        b' ' => HunkLine::Context,
+        b'-' => HunkLine::Remove,
+        b'+' => HunkLine::Add,
+        _ => return None,
+    };
+    Some(kind(line[1..].to_string()))
+}
+
+fn parse_unified(lines: &[&str]) -> Result<Vec<FilePatch>, String> {
+    let mut patches = Vec::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        if !is_file_header(lines, idx) {
+            idx += 1;
+            continue;
+        }
+        let old = &lines[idx]["--- ".len()..];
+        let new = &lines[idx + 1]["+++ ".len()..];
+        idx += 2;
+
+        let mut hunks = Vec::new();
+        while lines.get(idx).is_some_and(|line| line.starts_with("@@")) {
+            let (hunk, next) = parse_unified_hunk(lines, idx)?;
+            hunks.push(hunk);
+            idx = next;
+        }
+        if hunks.is_empty() {
+            return Err(format!("line {idx}: no hunks for {}", new.trim()));
+        }
+        patches.push(unified_file_patch(old, new, hunks)?);
+    }
+    if patches.is_empty() {
+        return Err(
+            "no file headers found (expected '--- '/'+++ ' lines or '*** Begin Patch')".to_string(),
+        );
+    }
+    Ok(patches)
+}
+
+fn is_file_header(lines: &[&str], idx: usize) -> bool {
+    lines[idx].starts_with("--- ")
+        && lines
+            .get(idx + 1)
+            .is_some_and(|line| line.starts_with("+++ "))
+}
+
+fn is_hunk_boundary(lines: &[&str], idx: usize) -> bool {
+    let line = lines[idx];
+    line.starts_with("@@") || line.starts_with("diff ") || is_file_header(lines, idx)
+}
+
+fn parse_unified_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let header = lines[start];
+    let mut ranges = header
+        .trim_start_matches('@')
+        .split("@@")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let old = ranges
+        .next()
+        .map(|range| parse_range(range, '-'))
+        .transpose()
+        .map_err(|err| format!("line {}: {err}", start + 1))?;
+    let new = ranges
+        .next()
+        .map(|range| parse_range(range, '+'))
+        .transpose()
+        .map_err(|err| format!("line {}: {err}", start + 1))?;
+
+    let mut hunk = Hunk {
+        hint: old.map(|(line, count)| {
+            if count == 0 {
+                line
+            } else {
+                line.saturating_sub(1)
+            }
+        }),
+        ..Hunk::default()
+    };
+    // Header counts bound the hunk, but models miscount: keep reading body
+    // lines past them and stop early at the next header.
+    let mut remaining = old.zip(new).map(|((_, old), (_, new))| (old, new));
+    let mut idx = start + 1;
+    while idx < lines.len() && !is_hunk_boundary(lines, idx) {
+        let line = lines[idx];
+        let exhausted = remaining.is_some_and(|(old, new)| old == 0 && new == 0);
+        if line.starts_with('\\') {
+            hunk.mark_no_newline();
+            idx += 1;
+            continue;
+        }
+        if exhausted && line.is_empty() {
+            break;
+        }
+        let Some(parsed) = parse_hunk_line(line) else {
+            break;
+        };
+        if let Some((old, new)) = remaining.as_mut() {
+            match parsed {
+                HunkLine::Context(_) => {
+                    *old = old.saturating_sub(1);
+                    *new = new.saturating_sub(1);
+                }
+                HunkLine::Remove(_) => *old = old.saturating_sub(1),
+                HunkLine::Add(_) => *new = new.saturating_sub(1),
+            }
+        }
+        hunk.lines.push(parsed);
+        idx += 1;
+    }
+    if remaining.is_none() {
+        while matches!(hunk.lines.last(), Some(HunkLine::Context(text)) if text.is_empty()) {
+            hunk.lines.pop();
+        }
+    }
+    if hunk.lines.is_empty() {
+        return Err(format!("line {}: empty hunk", start + 1));
+    }
+    Ok((hunk, idx))
+}
+
+fn parse_range(range: &str, sign: char) -> Result<(usize, usize), String> {
+    let body = range
+        .strip_prefix(sign)
+        .ok_or_else(|| format!("invalid hunk range '{range}'"))?;
+    let (start, count) = body.split_once(',').unwrap_or((body, "1"));
+    let parse = |value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("invalid hunk range '{range}'"))
+    };
+    Ok((parse(start)?, parse(count)?))
+}
+
+fn unified_file_patch(old: &str, new: &str, hunks: Vec<Hunk>) -> Result<FilePatch, String> {
+    let mut old = header_path(old);
+    let mut new = header_path(new);
+    let dev_null = "/dev/null";
+    if (old == dev_null || old.starts_with("a/")) && (new == dev_null || new.starts_with("b/")) {
+        old = old.strip_prefix("a/").unwrap_or(old);
+        new = new.strip_prefix("b/").unwrap_or(new);
+    }
+    match (old == dev_null, new == dev_null) {
+        (true, true) => Err("both sides of a file header are /dev/null".to_string()),
+        (true, false) => Ok(FilePatch::Add {
+            path: new.to_string(),
+            lines: hunks.iter().flat_map(Hunk::added).collect(),
+            newline: !hunks.iter().any(|hunk| hunk.no_newline_new),
+        }),
+        (false, true) => Ok(FilePatch::Delete {
+            path: old.to_string(),
+        }),
+        (false, false) => Ok(FilePatch::Update {
+            path: old.to_string(),
+            move_to: (old != new).then(|| new.to_string()),
+            hunks,
+        }),
+    }
+}
+
+fn header_path(raw: &str) -> &str {
+    raw.split('\t').next().unwrap_or_default().trim()
+}
+
+/// Applies hunks in order. Rejected hunks leave the text untouched and the
+/// rest still apply; callers decide whether a partial result is usable.
+pub(crate) fn apply_hunks(
+    original: &str,
+    hunks: &[Hunk],
+) -> (String, Vec<Result<Applied, String>>) {
+    let eol = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut newline = original.is_empty() || original.ends_with('\n');
+    let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+    let mut cursor = 0;
+    let mut delta = 0isize;
+
+    let mut outcomes = Vec::with_capacity(hunks.len());
+    for hunk in hunks {
+        let outcome = apply_hunk(&mut lines, hunk, &mut cursor, &mut delta);
+        if outcome.is_ok() {
+            if hunk.no_newline_new {
+                newline = false;
+            } else if hunk.no_newline_old {
+                newline = true;
+            }
+        }
+        outcomes.push(outcome);
+    }
+    (render(&lines, eol, newline), outcomes)
+}
+
+pub(crate) fn render(lines: &[String], eol: &str, newline: bool) -> String {
+    let mut text = lines.join(eol);
+    if newline && !lines.is_empty() {
+        text.push_str(eol);
+    }
+    text
+}
+
+fn apply_hunk(
+    lines: &mut Vec<String>,
+    hunk: &Hunk,
+    cursor: &mut usize,
+    delta: &mut isize,
+) -> Result<Applied, String> {
+    let mut from = *cursor;
+    for anchor in &hunk.anchors {
+        let found = lines[from..]
+            .iter()
+            .position(|line| line.trim() == anchor.trim())
+            .ok_or_else(|| format!("anchor '{anchor}' not found"))?;
+        from += found + 1;
+    }
+    let hint = hunk
+        .hint
+        .map(|hint| (hint as isize + *delta).max(0) as usize);
+
+    if Hunk::old_side(&hunk.lines).is_empty() {
+        let at = match hint {
+            Some(hint) => hint.clamp(from, lines.len()),
+            None if !hunk.anchors.is_empty() && !hunk.at_eof => from,
+            None => lines.len(),
+        };
+        let added = hunk.added();
+        let count = added.len();
+        lines.splice(at..at, added);
+        *cursor = at + count;
+        *delta += count as isize;
+        return Ok(Applied {
+            line: at + 1,
+            fuzz: 0,
+        });
+    }
+
+    let leading = hunk.leading_context();
+    let trailing = hunk.trailing_context();
+    let trims = (0..=2 * MAX_CONTEXT_FUZZ).flat_map(|total| {
+        (0..=total)
+            .map(move |lead| (lead, total - lead))
+            .filter(|&(lead, trail)| {
+                lead <= leading.min(MAX_CONTEXT_FUZZ) && trail <= trailing.min(MAX_CONTEXT_FUZZ)
+            })
+    });
+    for (lead, trail) in trims {
+        let body = &hunk.lines[lead..hunk.lines.len() - trail];
+        let old = Hunk::old_side(body);
+        if old.is_empty() {
+            continue;
+        }
+        let at_eof = hunk.at_eof && trail == 0;
+        let Some((pos, level)) = find_block(lines, &old, from, hint.map(|h| h + lead), at_eof)
+        else {
+            continue;
+        };
+
+        // Matched context keeps the file's own text, so whitespace fuzz
+        // never rewrites lines the hunk does not change.
+        let mut replacement = Vec::new();
+        let mut end = pos;
+        for line in body {
+            match line {
+                HunkLine::Context(_) => {
+                    replacement.push(lines[end].clone());
+                    end += 1;
+                }
+                HunkLine::Remove(_) => end += 1,
+                HunkLine::Add(text) => replacement.push(text.clone()),
+            }
+        }
+        let added = replacement.len();
+        lines.splice(pos..end, replacement);
+        *cursor = pos + added;
+        *delta += added as isize - (end - pos) as isize;
+        return Ok(Applied {
+            line: pos + 1,
+            fuzz: level + lead + trail,
+        });
+    }
+
+    Err(match hint {
+        Some(hint) => format!("context not found near line {}", hint + 1),
+        None => "context not found".to_string(),
+    })
+}
+
+/// Finds `block` at or after `from`, trying exact, trailing-whitespace and
+/// surrounding-whitespace comparisons in turn. Among equal matches the one
+/// closest to `hint` wins.
+fn find_block(
+    lines: &[String],
+    block: &[&str],
+    from: usize,
+    hint: Option<usize>,
+    at_eof: bool,
+) -> Option<(usize, usize)> {
+    if block.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - block.len();
+    let first = if at_eof { last } else { from };
+    if first > last {
+        return None;
+    }
+    let comparisons: [fn(&str, &str) -> bool; 3] = [
+        |a, b| a == b,
+        |a, b| a.trim_end() == b.trim_end(),
+        |a, b| a.trim() == b.trim(),
+    ];
+    for (level, same) in comparisons.iter().enumerate() {
+        let found = (first..=last)
+            .filter(|&pos| {
+                block
+                    .iter()
+                    .zip(&lines[pos..])
+                    .all(|(want, have)| same(have, want))
+            })
+            .min_by_key(|&pos| hint.map_or(pos, |hint| pos.abs_diff(hint)));
+        if let Some(pos) = found {
+            return Some((pos, level));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update_hunks(patch: &str) -> Vec<Hunk> {
+        match parse_patch(patch).expect("parse").remove(0) {
+            FilePatch::Update { hunks, .. } => hunks,
+            other => panic!("expected update, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_git_unified_diff() {
+        let patch = concat!(
+            "diff --git a/src/a.rs b/src/a.rs\n",
+            "index 1111111..2222222 100644\n",
+            "--- a/src/a.rs\n",
+            "+++ b/src/a.rs\n",
+            "@@ -1,3 +1,3 @@\n",
+            " one\n",
+            "-two\n",
+            "+TWO\n",
+            " three\n",
+            "--- /dev/null\n",
+            "+++ b/new.txt\n",
+            "@@ -0,0 +1,2 @@\n",
+            "+hello\n",
+            "+world\n",
+            "\\ No newline at end of file\n",
+            "--- a/old.txt\n",
+            "+++ /dev/null\n",
+            "@@ -1 +0,0 @@\n",
+            "-bye\n",
+        );
+        let patches = parse_patch(patch).expect("parse");
+        assert_eq!(patches.len(), 3);
+        assert!(matches!(
+            &patches[0],
+            FilePatch::Update { path, move_to: None, hunks } if path == "src/a.rs" && hunks[0].hint == Some(0)
+        ));
+        assert_eq!(
+            patches[1],
+            FilePatch::Add {
+                path: "new.txt".to_string(),
+                lines: vec!["hello".to_string(), "world".to_string()],
+                newline: false,
+            }
+        );
+        assert_eq!(
+            patches[2],
+            FilePatch::Delete {
+                path: "old.txt".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_envelope_operations() {
+        let patch = "*** Begin Patch\n\
+*** Add File: docs/new.md\n\
++# Title\n\
++\n\
++body\n\
+*** Update File: src/lib.rs\n\
+*** Move to: src/main.rs\n\
+@@ fn main() {\n\
+-    old();\n\
++    new();\n\
+*** End of File\n\
+*** Delete File: gone.txt\n\
+*** End Patch\n";
+        let patches = parse_patch(patch).expect("parse");
+        assert_eq!(patches.len(), 3);
+        assert!(matches!(
+            &patches[0],
+            FilePatch::Add { path, lines, newline: true } if path == "docs/new.md" && lines.len() == 3
+        ));
+        let FilePatch::Update {
+            path,
+            move_to,
+            hunks,
+        } = &patches[1]
+        else {
+            panic!("expected update");
+        };
+        assert_eq!(path, "src/lib.rs");
+        assert_eq!(move_to.as_deref(), Some("src/main.rs"));
+        assert_eq!(hunks[0].anchors, vec!["fn main() {".to_string()]);
+        assert!(hunks[0].at_eof);
+    }
+
+    #[test]
+    fn envelope_requires_end_marker() {
+        let err = parse_patch("*** Begin Patch\n*** Delete File: a.txt\n").unwrap_err();
+        assert!(err.contains("End Patch"));
+    }
+
+    #[test]
+    fn unified_hunk_tolerates_miscounted_header() {
+        let hunks = update_hunks("--- a.txt\n+++ a.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+B\n c\n");
+        assert_eq!(hunks[0].lines.len(), 4);
+    }
+
+    #[test]
+    fn applies_with_whitespace_fuzz_and_keeps_file_context() {
+        let hunks =
+            update_hunks("--- a.txt\n+++ a.txt\n@@ -1,3 +1,3 @@\n fn a() {\n-    1\n+    2\n }\n");
+        let (text, outcomes) = apply_hunks("fn a() {  \n\t1\n}\n", &hunks);
+        assert_eq!(text, "fn a() {  \n    2\n}\n");
+        assert_eq!(outcomes, vec![Ok(Applied { line: 1, fuzz: 2 })]);
+    }
+
+    #[test]
+    fn drops_stale_context_lines() {
+        let hunks = update_hunks("--- a.txt\n+++ a.txt\n@@ -1,4 +1,4 @@\n stale\n b\n-c\n+C\n d\n");
+        let (text, outcomes) = apply_hunks("a\nb\nc\nd\n", &hunks);
+        assert_eq!(text, "a\nb\nC\nd\n");
+        assert_eq!(outcomes, vec![Ok(Applied { line: 2, fuzz: 1 })]);
+    }
+
+    #[test]
+    fn prefers_match_nearest_to_header_line() {
+        let hunks = update_hunks("--- a.txt\n+++ a.txt\n@@ -5 +5 @@\n-x\n+y\n");
+        let (text, _) = apply_hunks("x\na\nb\nc\nx\nd\n", &hunks);
+        assert_eq!(text, "x\na\nb\nc\ny\nd\n");
+    }
+
+    #[test]
+    fn anchors_select_the_target_block() {
+        let patch = "*** Begin Patch\n*** Update File: a.py\n@@ def second():\n-    pass\n+    return 2\n*** End Patch\n";
+        let hunks = update_hunks(patch);
+        let (text, outcomes) =
+            apply_hunks("def first():\n    pass\ndef second():\n    pass\n", &hunks);
+        assert_eq!(
+            text,
+            "def first():\n    pass\ndef second():\n    return 2\n"
+        );
+        assert!(outcomes[0].is_ok());
+    }
+
+    #[test]
+    fn rejected_hunk_leaves_text_and_later_hunks_apply() {
+        let hunks =
+            update_hunks("--- a.txt\n+++ a.txt\n@@ -1 +1 @@\n-missing\n+x\n@@ -3 +3 @@\n-c\n+C\n");
+        let (text, outcomes) = apply_hunks("a\r\nb\r\nc\r\n", &hunks);
+        assert_eq!(text, "a\r\nb\r\nC\r\n");
+        assert!(outcomes[0]
+            .as_ref()
+            .unwrap_err()
+            .contains("context not found near line 1"));
+        assert!(outcomes[1].is_ok());
+    }
+
+    #[test]
+    fn no_newline_marker_controls_trailing_newline() {
+        let hunks = update_hunks(
+            "--- a.txt\n+++ a.txt\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+b\n",
+        );
+        let (text, _) = apply_hunks("a", &hunks);
+        assert_eq!(text, "b\n");
+    }
+}
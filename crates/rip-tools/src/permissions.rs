@@ -0,0 +1,813 @@
+//! Allow / ask / deny rules checked before a tool call reaches its handler.
+//!
+//! A rule is `tool` or `tool(pattern)`, where `tool` may be `*` or the rule
+//! family a tool belongs to (`bash` rules also cover `bash_background`).
+//! Patterns match the subjects a tool registers for its arguments: a shell
+//! line is split into its commands at operators, substitutions, brace groups
+//! and keywords, and each is matched on its unquoted words with `*` standing
+//! for any text, while its redirection targets become paths checked by
+//! `write`/`read` rules; a path is matched as a workspace-relative glob.
+//! Deny beats ask beats allow, a call takes the strictest decision over its
+//! subjects, and a subject no rule matches gets the policy default.
+//! Arguments that cannot be parsed are only matched by bare rules and
+//! otherwise ask.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use globset::{GlobBuilder, GlobMatcher};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionDecision {
+    #[default]
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// What a rule pattern is matched against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PermissionSubject {
+    Command(String),
+    Path(String),
+    /// A file a shell redirection reaches, checked by the rules of `tool`
+    /// (`write` or `read`) besides bare rules of the calling tool.
+    Redirect {
+        tool: String,
+        path: String,
+    },
+    /// Arguments that could not be parsed into subjects; no pattern matches.
+    Unparsed(String),
+}
+
+/// Rules as written in configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionConfig {
+    /// Decision for calls no rule matches.
+    pub default: PermissionDecision,
+    /// Rules (`tool` or `tool(pattern)`) that let a call run.
+    pub allow: Vec<String>,
+    /// Rules that pause a call until a client approves it.
+    pub ask: Vec<String>,
+    /// Rules that refuse a call.
+    pub deny: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+struct PermissionRule {
+    source: String,
+    tool: String,
+    pattern: Option<Pattern>,
+    decision: PermissionDecision,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    text: String,
+    /// Compiled for path subjects; text that is not a valid glob only
+    /// matches with `*` wildcards.
+    glob: Option<GlobMatcher>,
+}
+
+/// The decision for one call and the rule that produced it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermissionVerdict {
+    pub decision: PermissionDecision,
+    /// The rule behind `decision`; none when the default applied.
+    pub rule: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PermissionPolicy {
+    default: PermissionDecision,
+    rules: Vec<PermissionRule>,
+}
+
+impl PermissionPolicy {
+    pub fn new(default: PermissionDecision) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &PermissionConfig) -> Result<Self, String> {
+        let mut policy = Self::new(config.default);
+        for (decision, rules) in [
+            (PermissionDecision::Allow, &config.allow),
+            (PermissionDecision::Ask, &config.ask),
+            (PermissionDecision::Deny, &config.deny),
+        ] {
+            for rule in rules {
+                policy = policy.rule(decision, rule)?;
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Adds `rule` (`tool` or `tool(pattern)`) with `decision`.
+    pub fn rule(mut self, decision: PermissionDecision, rule: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid permission rule: {rule}");
+        let rule = rule.trim();
+        let (tool, pattern) = match rule.split_once('(') {
+            Some((tool, rest)) => {
+                let pattern = rest.strip_suffix(')').ok_or_else(invalid)?;
+                (tool.trim(), Some(pattern))
+            }
+            None => (rule, None),
+        };
+        if tool.is_empty() || tool.contains(|c: char| c.is_whitespace() || c == ')') {
+            return Err(invalid());
+        }
+        let pattern = match pattern {
+            Some(text) if !text.is_empty() => Some(Pattern {
+                text: text.to_string(),
+                glob: GlobBuilder::new(text)
+                    .literal_separator(true)
+                    .build()
+                    .ok()
+                    .map(|glob| glob.compile_matcher()),
+            }),
+            Some(_) => return Err(invalid()),
+            None => None,
+        };
+        self.rules.push(PermissionRule {
+            source: rule.to_string(),
+            tool: tool.to_string(),
+            pattern,
+            decision,
+        });
+        Ok(self)
+    }
+
+    pub fn evaluate(&self, tool: &str, subjects: &[PermissionSubject]) -> PermissionVerdict {
+        self.evaluate_as(tool, tool, subjects)
+    }
+
+    /// Like `evaluate`, also applying the rules written for `family`.
+    pub fn evaluate_as(
+        &self,
+        tool: &str,
+        family: &str,
+        subjects: &[PermissionSubject],
+    ) -> PermissionVerdict {
+        let for_call =
+            |rule: &&PermissionRule| rule.tool == "*" || rule.tool == tool || rule.tool == family;
+        let decide = |subject: Option<&PermissionSubject>| {
+            let matched = self
+                .rules
+                .iter()
+                .filter(|rule| match (subject, &rule.pattern) {
+                    (_, None) if for_call(rule) => true,
+                    (Some(PermissionSubject::Redirect { tool, path }), pattern) => {
+                        (rule.tool == "*" || rule.tool == *tool)
+                            && pattern.as_ref().is_none_or(|pattern| {
+                                pattern.matches(&PermissionSubject::Path(path.clone()))
+                            })
+                    }
+                    (Some(subject), Some(pattern)) => for_call(rule) && pattern.matches(subject),
+                    _ => false,
+                });
+            let fallback = match subject {
+                Some(PermissionSubject::Unparsed(_)) => self.default.max(PermissionDecision::Ask),
+                _ => self.default,
+            };
+            matched
+                .max_by_key(|rule| rule.decision)
+                .map(|rule| PermissionVerdict {
+                    decision: rule.decision,
+                    rule: Some(rule.source.clone()),
+                })
+                .unwrap_or(PermissionVerdict {
+                    decision: fallback,
+                    rule: None,
+                })
+        };
+        if subjects.is_empty() {
+            return decide(None);
+        }
+        subjects
+            .iter()
+            .map(|subject| decide(Some(subject)))
+            .max_by_key(|verdict| verdict.decision)
+            .expect("subjects is not empty")
+    }
+}
+
+impl Pattern {
+    fn matches(&self, subject: &PermissionSubject) -> bool {
+        match subject {
+            PermissionSubject::Command(command) => wildcard_match(&self.text, command),
+            PermissionSubject::Path(path) => match &self.glob {
+                Some(glob) => glob.is_match(path),
+                None => wildcard_match(&self.text, path),
+            },
+            PermissionSubject::Redirect { .. } | PermissionSubject::Unparsed(_) => false,
+        }
+    }
+}
+
+/// `*` matches any run of characters; everything else matches itself.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The commands in a shell line as permission subjects: each command with
+/// its words unquoted and joined by single spaces, leading assignments and
+/// keywords dropped, and every redirection target as a `Redirect` subject.
+/// A line this cannot follow (unbalanced quotes, a substitution inside
+/// double quotes, a program named by an expansion, a redirection without a
+/// target) is one `Unparsed` subject.
+pub fn command_subjects(command: &str) -> Vec<PermissionSubject> {
+    ShellLine::parse(command)
+        .unwrap_or_else(|| vec![PermissionSubject::Unparsed(command.to_string())])
+}
+
+/// Words that start or shape a compound command rather than name one.
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "esac", "!", "time",
+    "coproc",
+];
+
+/// Keywords whose whole clause (`for x in a b`) is not a command.
+const SHELL_HEADERS: &[&str] = &["for", "select", "case", "function"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Redirection {
+    Write,
+    Read,
+    /// `>&`/`<&`: a file descriptor copy unless the target is a name.
+    Duplicate(&'static str),
+    /// The word after `<<` ends a here-document.
+    HereDoc {
+        strip_tabs: bool,
+    },
+    /// `<<<`: the word is the input itself.
+    HereString,
+}
+
+#[derive(Default)]
+struct ShellLine {
+    subjects: Vec<PermissionSubject>,
+    /// Words of the command being read, unquoted, and whether each was
+    /// written without quotes or escapes.
+    words: Vec<(String, bool)>,
+    /// Redirection targets of the command being read.
+    redirects: Vec<PermissionSubject>,
+    /// The word being read, as written and with quotes removed.
+    raw: String,
+    text: String,
+    quoted: bool,
+    redirect: Option<Redirection>,
+    /// Here-document delimiters whose bodies start at the next newline.
+    heredocs: Vec<(String, bool, bool)>,
+}
+
+impl ShellLine {
+    fn parse(line: &str) -> Option<Vec<PermissionSubject>> {
+        let mut parser = Self::default();
+        let chars: Vec<char> = line.chars().collect();
+        let mut idx = 0;
+        while idx < chars.len() {
+            let ch = chars[idx];
+            let next = chars.get(idx + 1).copied();
+            idx += 1;
+            match ch {
+                '\\' => {
+                    let escaped = *chars.get(idx)?;
+                    idx += 1;
+                    if escaped != '\n' {
+                        parser.quote(&[ch, escaped], &[escaped]);
+                    }
+                }
+                '\'' => {
+                    let end = idx + chars[idx..].iter().position(|&c| c == '\'')?;
+                    parser.quote(&chars[idx - 1..=end], &chars[idx..end]);
+                    idx = end + 1;
+                }
+                '"' => {
+                    let mut text = Vec::new();
+                    let start = idx - 1;
+                    loop {
+                        match *chars.get(idx)? {
+                            '"' => break,
+                            // Substitutions run even inside double quotes.
+                            '`' => return None,
+                            '$' if chars.get(idx + 1) == Some(&'(') => return None,
+                            '\\' => {
+                                text.push(*chars.get(idx + 1)?);
+                                idx += 2;
+                            }
+                            c => {
+                                text.push(c);
+                                idx += 1;
+                            }
+                        }
+                    }
+                    parser.quote(&chars[start..=idx], &text);
+                    idx += 1;
+                }
+                '#' if parser.raw.is_empty() => {
+                    while idx < chars.len() && chars[idx] != '\n' {
+                        idx += 1;
+                    }
+                }
+                ' ' | '\t' => parser.end_word()?,
+                '\n' => {
+                    parser.end_command()?;
+                    idx = parser.skip_heredocs(&chars, idx)?;
+                }
+                '$' if next == Some('(') => {
+                    parser.end_command()?;
+                    idx += 1;
+                }
+                '<' | '>' if next == Some('(') => {
+                    parser.end_command()?;
+                    idx += 1;
+                }
+                '&' if next == Some('>') => {
+                    parser.end_word()?;
+                    idx += 1;
+                    if chars.get(idx) == Some(&'>') {
+                        idx += 1;
+                    }
+                    parser.redirect = Some(Redirection::Write);
+                }
+                '>' | '<' => {
+                    // Digits right before the operator name a descriptor.
+                    if !parser.quoted && parser.raw.chars().all(|c| c.is_ascii_digit()) {
+                        parser.raw.clear();
+                        parser.text.clear();
+                    }
+                    parser.end_word()?;
+                    let (kind, len) = match (ch, next, chars.get(idx + 1).copied()) {
+                        ('>', Some('>'), _) | ('>', Some('|'), _) => (Redirection::Write, 1),
+                        ('>', Some('&'), _) => (Redirection::Duplicate("write"), 1),
+                        ('>', _, _) => (Redirection::Write, 0),
+                        ('<', Some('<'), Some('<')) => (Redirection::HereString, 2),
+                        ('<', Some('<'), Some('-')) => {
+                            (Redirection::HereDoc { strip_tabs: true }, 2)
+                        }
+                        ('<', Some('<'), _) => (Redirection::HereDoc { strip_tabs: false }, 1),
+                        ('<', Some('>'), _) => (Redirection::Write, 1),
+                        ('<', Some('&'), _) => (Redirection::Duplicate("read"), 1),
+                        _ => (Redirection::Read, 0),
+                    };
+                    parser.redirect = Some(kind);
+                    idx += len;
+                }
+                ';' | '&' | '|' | '(' | ')' | '`' => parser.end_command()?,
+                c => {
+                    parser.raw.push(c);
+                    parser.text.push(c);
+                }
+            }
+        }
+        parser.end_command()?;
+        if !parser.heredocs.is_empty() {
+            return None;
+        }
+        Some(parser.subjects)
+    }
+
+    fn quote(&mut self, raw: &[char], text: &[char]) {
+        self.raw.extend(raw);
+        self.text.extend(text);
+        self.quoted = true;
+    }
+
+    fn end_word(&mut self) -> Option<()> {
+        let raw = std::mem::take(&mut self.raw);
+        let text = std::mem::take(&mut self.text);
+        let quoted = std::mem::take(&mut self.quoted);
+        if raw.is_empty() {
+            return Some(());
+        }
+        match self.redirect.take() {
+            Some(Redirection::HereDoc { strip_tabs }) => {
+                // Bodies of unquoted delimiters expand substitutions.
+                self.heredocs.push((text, strip_tabs, !quoted));
+            }
+            Some(Redirection::HereString) => {}
+            Some(Redirection::Duplicate(_)) if text == "-" || text.parse::<u32>().is_ok() => {}
+            Some(kind) => self.redirects.push(PermissionSubject::Redirect {
+                tool: match kind {
+                    Redirection::Read | Redirection::Duplicate("read") => "read",
+                    _ => "write",
+                }
+                .to_string(),
+                path: text,
+            }),
+            None if !quoted && (raw == "{" || raw == "}") => self.end_command()?,
+            None => self.words.push((text, !quoted)),
+        }
+        Some(())
+    }
+
+    fn end_command(&mut self) -> Option<()> {
+        self.end_word()?;
+        if self.redirect.is_some() {
+            return None;
+        }
+        let mut words = std::mem::take(&mut self.words);
+        let mut start = 0;
+        // Quoted words are never keywords or assignments.
+        while let Some((word, true)) = words.get(start) {
+            if SHELL_HEADERS.contains(&word.as_str()) {
+                start = words.len();
+                break;
+            }
+            let assignment = word
+                .split_once('=')
+                .is_some_and(|(name, _)| is_shell_name(name));
+            if !assignment && !SHELL_KEYWORDS.contains(&word.as_str()) {
+                break;
+            }
+            start += 1;
+        }
+        words.drain(..start);
+        if let Some((program, _)) = words.first() {
+            // The program an expansion names is only known when it runs.
+            if program.contains('$') {
+                return None;
+            }
+            let words: Vec<&str> = words.iter().map(|(word, _)| word.as_str()).collect();
+            self.subjects
+                .push(PermissionSubject::Command(words.join(" ")));
+        }
+        self.subjects.append(&mut self.redirects);
+        Some(())
+    }
+
+    /// Skips the bodies of pending here-documents starting at `idx`; a body
+    /// that would expand a substitution cannot be followed.
+    fn skip_heredocs(&mut self, chars: &[char], mut idx: usize) -> Option<usize> {
+        for (delimiter, strip_tabs, expands) in std::mem::take(&mut self.heredocs) {
+            loop {
+                if idx >= chars.len() {
+                    return None;
+                }
+                let end = chars[idx..]
+                    .iter()
+                    .position(|&c| c == '\n')
+                    .map_or(chars.len(), |pos| idx + pos);
+                let line: String = chars[idx..end].iter().collect();
+                idx = (end + 1).min(chars.len());
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+                if expands && (line.contains("$(") || line.contains('`')) {
+                    return None;
+                }
+            }
+        }
+        Some(idx)
+    }
+}
+
+fn is_shell_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A policy plus the calls waiting on a client's decision.
+#[derive(Default)]
+pub struct ToolPermissions {
+    policy: PermissionPolicy,
+    pending: Mutex<HashMap<String, PendingRequest>>,
+}
+
+struct PendingRequest {
+    session_id: String,
+    reply: oneshot::Sender<bool>,
+}
+
+impl ToolPermissions {
+    pub fn new(policy: PermissionPolicy) -> Self {
+        Self {
+            policy,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn policy(&self) -> &PermissionPolicy {
+        &self.policy
+    }
+
+    /// Approves or denies the pending call `tool_id` of `session_id`;
+    /// returns false when there is no such call.
+    pub fn decide(&self, session_id: &str, tool_id: &str, approve: bool) -> bool {
+        let mut pending = self.pending.lock().expect("permission requests mutex");
+        match pending.get(tool_id) {
+            Some(request) if request.session_id == session_id => {}
+            _ => return false,
+        }
+        let request = pending.remove(tool_id).expect("checked above");
+        request.reply.send(approve).is_ok()
+    }
+
+    /// Tool ids of the calls `session_id` is waiting on.
+    pub fn pending(&self, session_id: &str) -> Vec<String> {
+        let pending = self.pending.lock().expect("permission requests mutex");
+        let mut ids: Vec<String> = pending
+            .iter()
+            .filter(|(_, request)| request.session_id == session_id)
+            .map(|(tool_id, _)| tool_id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub(crate) fn request(&self, session_id: &str, tool_id: &str) -> oneshot::Receiver<bool> {
+        let (reply, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("permission requests mutex")
+            .insert(
+                tool_id.to_string(),
+                PendingRequest {
+                    session_id: session_id.to_string(),
+                    reply,
+                },
+            );
+        receiver
+    }
+
+    pub(crate) fn forget(&self, tool_id: &str) {
+        self.pending
+            .lock()
+            .expect("permission requests mutex")
+            .remove(tool_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PermissionDecision::{Allow, Ask, Deny};
+
+    fn policy() -> PermissionPolicy {
+        PermissionPolicy::from_config(&PermissionConfig {
+            default: Ask,
+            allow: vec!["bash(git *)".into(), "read".into()],
+            ask: vec![],
+            deny: vec!["write(**/.env)".into(), "bash(rm *)".into()],
+        })
+        .expect("policy")
+    }
+
+    fn path(path: &str) -> Vec<PermissionSubject> {
+        vec![PermissionSubject::Path(path.to_string())]
+    }
+
+    #[test]
+    fn rules_match_commands_and_paths() {
+        let policy = policy();
+        let verdict = policy.evaluate("bash", &command_subjects("git status"));
+        assert_eq!(verdict.decision, Allow);
+        assert_eq!(verdict.rule.as_deref(), Some("bash(git *)"));
+        assert_eq!(
+            policy.evaluate("bash", &command_subjects("ls")).decision,
+            Ask
+        );
+        assert_eq!(policy.evaluate("read", &path(".env")).decision, Allow);
+
+        let verdict = policy.evaluate("write", &path("config/.env"));
+        assert_eq!(verdict.decision, Deny);
+        assert_eq!(verdict.rule.as_deref(), Some("write(**/.env)"));
+        assert_eq!(policy.evaluate("write", &path(".env")).decision, Deny);
+        assert_eq!(policy.evaluate("write", &path("src/main.rs")).decision, Ask);
+        assert_eq!(policy.evaluate("edit", &[]).decision, Ask);
+    }
+
+    #[test]
+    fn every_command_in_a_line_must_be_allowed() {
+        let policy = policy();
+        for line in [
+            "git status && ls",
+            "git log | less",
+            "git diff; rm -rf build",
+            "git show $(ls)",
+            "git show `rm x`",
+        ] {
+            assert_ne!(
+                policy.evaluate("bash", &command_subjects(line)).decision,
+                Allow,
+                "{line}"
+            );
+        }
+        assert_eq!(
+            policy
+                .evaluate("bash", &command_subjects("git diff; rm -rf build"))
+                .decision,
+            Deny
+        );
+        assert_eq!(
+            policy
+                .evaluate("bash", &command_subjects("git add . && git commit -m x"))
+                .decision,
+            Allow
+        );
+    }
+
+    #[test]
+    fn lines_split_into_commands_and_redirections() {
+        let command = |text: &str| PermissionSubject::Command(text.to_string());
+        let write = |path: &str| PermissionSubject::Redirect {
+            tool: "write".to_string(),
+            path: path.to_string(),
+        };
+        assert_eq!(
+            command_subjects("echo hi > out.txt 2>&1 >> 'log file' <in.txt"),
+            vec![
+                command("echo hi"),
+                write("out.txt"),
+                write("log file"),
+                PermissionSubject::Redirect {
+                    tool: "read".to_string(),
+                    path: "in.txt".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            command_subjects("{ git status; rm -rf x; } &>.env"),
+            vec![command("git status"), command("rm -rf x"), write(".env")]
+        );
+        assert_eq!(
+            command_subjects("if true; then FOO=1 rm x; fi; for f in a b; do git add \"$f\"; done"),
+            vec![command("true"), command("rm x"), command("git add $f")]
+        );
+        assert_eq!(
+            command_subjects("cat <<'EOF' > notes\n$(rm x)\nEOF\ngit log # ; rm y"),
+            vec![command("cat"), write("notes"), command("git log")]
+        );
+        assert_eq!(
+            command_subjects("echo 'a; rm x'"),
+            vec![command("echo a; rm x")]
+        );
+        for line in [
+            "echo 'open",
+            "echo \"$(rm x)\"",
+            "echo >",
+            "cat <<EOF\n$(rm x)\nEOF",
+        ] {
+            assert_eq!(
+                command_subjects(line),
+                vec![PermissionSubject::Unparsed(line.to_string())],
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn redirections_and_unparsed_lines_are_checked() {
+        let policy = policy();
+        for (line, decision) in [
+            ("git log > .env", Deny),
+            ("git log > notes", Ask),
+            ("{ git status; rm -rf x; }", Deny),
+            ("if git diff; then rm x; fi", Deny),
+            ("git log \"$(rm x)\"", Ask),
+            ("\"rm\" -rf x", Deny),
+            ("'rm' -rf x", Deny),
+            ("r\\m -rf x", Deny),
+            ("'git' status", Allow),
+            ("$RM -rf x", Ask),
+        ] {
+            assert_eq!(
+                policy.evaluate("bash", &command_subjects(line)).decision,
+                decision,
+                "{line}"
+            );
+        }
+        let allowing = PermissionPolicy::new(Allow)
+            .rule(Allow, "bash(git *)")
+            .expect("policy");
+        assert_eq!(
+            allowing
+                .evaluate("bash", &command_subjects("git log 'open"))
+                .decision,
+            Ask
+        );
+        let trusting = allowing.rule(Allow, "bash").expect("policy");
+        assert_eq!(
+            trusting
+                .evaluate("bash", &command_subjects("git log 'open"))
+                .decision,
+            Allow
+        );
+    }
+
+    #[test]
+    fn family_rules_cover_related_tools() {
+        let policy = policy();
+        assert_eq!(
+            policy
+                .evaluate_as("bash_background", "bash", &command_subjects("git fetch"))
+                .decision,
+            Allow
+        );
+        assert_eq!(
+            policy
+                .evaluate_as("bash_background", "bash", &command_subjects("rm -rf x"))
+                .decision,
+            Deny
+        );
+        assert_eq!(
+            policy
+                .evaluate_as("edit", "write", &path("a/.env"))
+                .decision,
+            Deny
+        );
+        assert_eq!(policy.evaluate("edit", &path("a/.env")).decision, Ask);
+    }
+
+    #[test]
+    fn deny_beats_ask_beats_allow() {
+        let policy = PermissionPolicy::new(Allow)
+            .rule(Allow, "bash")
+            .and_then(|policy| policy.rule(Ask, "bash(cargo *)"))
+            .and_then(|policy| policy.rule(Deny, "*(sudo *)"))
+            .expect("policy");
+        assert_eq!(
+            policy.evaluate("bash", &command_subjects("ls")).decision,
+            Allow
+        );
+        assert_eq!(
+            policy
+                .evaluate("bash", &command_subjects("cargo test"))
+                .decision,
+            Ask
+        );
+        assert_eq!(
+            policy
+                .evaluate("bash", &command_subjects("sudo ls"))
+                .decision,
+            Deny
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for rule in ["", "bash(", "bash()", "ba sh", "bash(git *"] {
+            assert!(
+                PermissionPolicy::default().rule(Allow, rule).is_err(),
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn wildcard_matches_any_run() {
+        assert!(wildcard_match("git *", "git push origin main"));
+        assert!(wildcard_match("*test*", "cargo test --workspace"));
+        assert!(!wildcard_match("git *", "gitk"));
+        assert!(!wildcard_match("npm run *", "npm install"));
+    }
+
+    #[tokio::test]
+    async fn decisions_reach_the_waiting_call() {
+        let permissions = ToolPermissions::new(policy());
+        let reply = permissions.request("s1", "t1");
+        assert_eq!(permissions.pending("s1"), vec!["t1"]);
+        assert!(!permissions.decide("s2", "t1", true));
+        assert!(permissions.decide("s1", "t1", true));
+        assert!(reply.await.expect("reply"));
+        assert!(!permissions.decide("s1", "t1", false));
+        assert!(permissions.pending("s1").is_empty());
+    }
+}
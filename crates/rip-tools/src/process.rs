@@ -0,0 +1,93 @@
+//! Process-group lifecycle for spawned shells.
+//!
+//! Shells are started as the leader of a fresh process group so everything
+//! they fork can be signalled at once. Stopping escalates from SIGTERM to
+//! SIGKILL after a grace period; a group that is dropped while still running
+//! gets SIGKILL so nothing outlives its owner.
+
+use std::time::Duration;
+
+use tokio::process::{Child, Command};
+
+/// Time between SIGTERM and SIGKILL.
+pub(crate) const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+pub(crate) struct ProcessGroup {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pgid: Option<i32>,
+}
+
+impl ProcessGroup {
+    /// Configures `cmd` to start a new process group.
+    pub(crate) fn configure(cmd: &mut Command) {
+        #[cfg(unix)]
+        cmd.process_group(0);
+        #[cfg(not(unix))]
+        cmd.kill_on_drop(true);
+    }
+
+    pub(crate) fn new(child: &Child) -> Self {
+        Self {
+            pgid: child.id().map(|pid| pid as i32),
+        }
+    }
+
+    /// Marks the group as finished so dropping it sends nothing.
+    pub(crate) fn disarm(&mut self) {
+        self.pgid = None;
+    }
+
+    /// Sends SIGTERM, then SIGKILL if the group is still alive after
+    /// `grace`. Returns the last signal sent.
+    #[cfg(unix)]
+    pub(crate) async fn terminate(&mut self, child: &mut Child, grace: Duration) -> &'static str {
+        let Some(pgid) = self.pgid else {
+            return "SIGTERM";
+        };
+        signal_group(pgid, libc::SIGTERM);
+        let deadline = tokio::time::Instant::now() + grace;
+        while tokio::time::Instant::now() < deadline {
+            // Reap the leader so it does not keep the group alive as a zombie.
+            let _ = child.try_wait();
+            if !group_alive(pgid) {
+                self.disarm();
+                return "SIGTERM";
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        signal_group(pgid, libc::SIGKILL);
+        self.disarm();
+        "SIGKILL"
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) async fn terminate(&mut self, child: &mut Child, _grace: Duration) -> &'static str {
+        let _ = child.kill().await;
+        self.disarm();
+        "SIGKILL"
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid.take() {
+            signal_group(pgid, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: i32, signal: i32) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid
+    // addresses the process group.
+    unsafe {
+        libc::kill(-pgid, signal);
+    }
+}
+
+#[cfg(unix)]
+fn group_alive(pgid: i32) -> bool {
+    // SAFETY: signal 0 only checks for existence.
+    unsafe { libc::kill(-pgid, 0) == 0 }
+}
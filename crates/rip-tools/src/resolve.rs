@@ -0,0 +1,711 @@
+//! Workspace path resolution that symlinks and concurrent renames cannot
+//! redirect outside the root.
+//!
+//! A path is walked one component at a time from a handle on the workspace
+//! root: each step is opened relative to the previous directory handle with
+//! `O_NOFOLLOW`, symlinks are read with `readlinkat` and expanded in place,
+//! and `..` steps back to the previous handle, so no step can leave the root.
+//! Tools then open, create, rename and remove through the handle on the
+//! target's directory instead of by path, so swapping an ancestor for a
+//! symlink after resolution does not move the operation elsewhere.
+
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use imp::{Dir, Entry};
+
+/// Symlinks expanded while resolving one path (the kernel's own limit).
+const MAX_SYMLINKS: usize = 40;
+
+pub(crate) const ESCAPES_ROOT: &str = "path escapes workspace root";
+
+/// A path inside the workspace, held open at its deepest existing directory.
+pub(crate) struct WorkspacePath {
+    /// The workspace root joined with the resolved relative path.
+    pub(crate) path: PathBuf,
+    dir: Dir,
+    /// Components below `dir`; only the last one may exist. Empty when the
+    /// path is the workspace root itself.
+    tail: Vec<OsString>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct OpenFlags {
+    /// Only stat the target: never blocks on a FIFO or device.
+    metadata: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    create_new: bool,
+}
+
+enum Step {
+    Parent,
+    Name(OsString),
+}
+
+/// Resolves `raw` (relative to `root`), expanding symlinks that stay inside
+/// the root and rejecting any path or link that leads out of it.
+pub(crate) fn resolve(root: &Path, raw: impl AsRef<Path>) -> Result<WorkspacePath, String> {
+    let raw_path = raw.as_ref();
+    if raw_path.is_absolute() || raw_path.has_root() {
+        return Err("absolute paths are not allowed".to_string());
+    }
+    let real_root = root
+        .canonicalize()
+        .map_err(|err| format!("workspace root unavailable: {err}"))?;
+    let root_dir =
+        Dir::open_root(&real_root).map_err(|err| format!("workspace root unavailable: {err}"))?;
+
+    let mut pending = VecDeque::new();
+    push_front(&mut pending, raw_path)?;
+    let mut stack = vec![(root_dir, OsString::new())];
+    let mut tail = Vec::new();
+    let mut expanded = 0;
+
+    while let Some(step) = pending.pop_front() {
+        let name = match step {
+            Step::Parent => {
+                pop(&mut stack)?;
+                continue;
+            }
+            Step::Name(name) => name,
+        };
+        let top = &stack.last().expect("root stays on the stack").0;
+        match top.lookup(&name) {
+            Ok(Entry::Dir(dir)) => stack.push((dir, name)),
+            Ok(Entry::Symlink(target)) => {
+                expanded += 1;
+                if expanded > MAX_SYMLINKS {
+                    return Err("too many levels of symbolic links".to_string());
+                }
+                if target.is_absolute() {
+                    let inside = target.strip_prefix(&real_root).map_err(|_| ESCAPES_ROOT)?;
+                    stack.truncate(1);
+                    push_front(&mut pending, inside)?;
+                } else {
+                    push_front(&mut pending, &target)?;
+                }
+            }
+            // A file, a missing entry or an unreadable one ends the walk;
+            // whatever is left is opened component by component later, so
+            // the operation itself reports the error.
+            Ok(Entry::Other) | Err(_) => {
+                tail.push(name);
+                for step in pending.drain(..) {
+                    match step {
+                        Step::Name(name) => tail.push(name),
+                        Step::Parent if tail.pop().is_some() => {}
+                        Step::Parent => pop(&mut stack)?,
+                    }
+                }
+            }
+        }
+    }
+    if tail.is_empty() && stack.len() > 1 {
+        let (_, name) = stack.pop().expect("checked length");
+        tail.push(name);
+    }
+
+    let mut path = root.to_path_buf();
+    path.extend(stack.iter().skip(1).map(|(_, name)| name));
+    path.extend(&tail);
+    let (dir, _) = stack.pop().expect("root stays on the stack");
+    Ok(WorkspacePath { path, dir, tail })
+}
+
+fn pop(stack: &mut Vec<(Dir, OsString)>) -> Result<(), String> {
+    if stack.len() == 1 {
+        return Err(ESCAPES_ROOT.to_string());
+    }
+    stack.pop();
+    Ok(())
+}
+
+fn push_front(pending: &mut VecDeque<Step>, path: &Path) -> Result<(), String> {
+    let mut steps = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => steps.push(Step::Parent),
+            Component::Normal(name) => steps.push(Step::Name(name.to_os_string())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err("absolute paths are not allowed".to_string())
+            }
+        }
+    }
+    for step in steps.into_iter().rev() {
+        pending.push_front(step);
+    }
+    Ok(())
+}
+
+impl WorkspacePath {
+    /// Opens the target for reading.
+    pub(crate) fn open(&self) -> io::Result<File> {
+        let (dir, name) = self.parent(false)?;
+        dir.open(name, OpenFlags::default())
+    }
+
+    /// The target's metadata, without following a final symlink.
+    pub(crate) fn metadata(&self) -> io::Result<std::fs::Metadata> {
+        let (dir, name) = self.parent(false)?;
+        let flags = OpenFlags {
+            metadata: true,
+            ..OpenFlags::default()
+        };
+        dir.open(name, flags)?.metadata()
+    }
+
+    pub(crate) fn read_to_string(&self) -> io::Result<String> {
+        let mut text = String::new();
+        self.open()?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// Opens the target for writing in place, creating missing parent
+    /// directories. A file with other hard links is refused: the other
+    /// names may live outside the workspace.
+    pub(crate) fn open_in_place(&self, append: bool, create: bool) -> io::Result<File> {
+        let (dir, name) = self.parent(true)?;
+        let file = dir.open(
+            name,
+            OpenFlags {
+                write: true,
+                append,
+                create,
+                ..OpenFlags::default()
+            },
+        )?;
+        refuse_hard_links(&file)?;
+        if !append {
+            file.set_len(0)?;
+        }
+        Ok(file)
+    }
+
+    /// Creates the target, which must not exist yet, and any missing parent
+    /// directories; nothing already there is followed or reused.
+    pub(crate) fn create_new(&self) -> io::Result<File> {
+        let (dir, name) = self.parent(true)?;
+        dir.open(
+            name,
+            OpenFlags {
+                write: true,
+                create_new: true,
+                ..OpenFlags::default()
+            },
+        )
+    }
+
+    /// Writes `content` to a new temp file beside the target (with the
+    /// target's permissions, if it exists) and returns the temp file's name.
+    pub(crate) fn write_temp(&self, content: &str) -> io::Result<OsString> {
+        let (dir, name) = self.parent(true)?;
+        let mut tmp = OsString::from(".");
+        tmp.push(name);
+        tmp.push(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        let mut file = dir.open(
+            &tmp,
+            OpenFlags {
+                write: true,
+                create_new: true,
+                ..OpenFlags::default()
+            },
+        )?;
+        let written =
+            file.write_all(content.as_bytes())
+                .and_then(|()| match dir.permissions(name) {
+                    Some(permissions) => file.set_permissions(permissions),
+                    None => Ok(()),
+                });
+        if let Err(err) = written {
+            let _ = dir.unlink(&tmp);
+            return Err(err);
+        }
+        Ok(tmp)
+    }
+
+    /// Renames a temp file from `write_temp` over the target.
+    pub(crate) fn rename_temp(&self, tmp: &OsStr) -> io::Result<()> {
+        let (dir, name) = self.parent(false)?;
+        dir.rename(tmp, name)
+    }
+
+    pub(crate) fn remove_temp(&self, tmp: &OsStr) {
+        if let Ok((dir, _)) = self.parent(false) {
+            let _ = dir.unlink(tmp);
+        }
+    }
+
+    /// Replaces the target's content through a temp file and a rename.
+    pub(crate) fn replace(&self, content: &str) -> io::Result<()> {
+        let tmp = self.write_temp(content)?;
+        self.rename_temp(&tmp)
+            .inspect_err(|_| self.remove_temp(&tmp))
+    }
+
+    pub(crate) fn remove(&self) -> io::Result<()> {
+        let (dir, name) = self.parent(false)?;
+        dir.unlink(name)
+    }
+
+    /// The directory holding the target, opened (or, with `create`,
+    /// created) below `dir` without following symlinks.
+    fn parent(&self, create: bool) -> io::Result<(Dir, &OsStr)> {
+        let Some((name, dirs)) = self.tail.split_last() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path is the workspace root",
+            ));
+        };
+        let mut dir = self.dir.clone();
+        for component in dirs {
+            let entry = match dir.lookup(component) {
+                Err(err) if create && err.kind() == io::ErrorKind::NotFound => {
+                    match dir.mkdir(component) {
+                        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+                        _ => dir.lookup(component)?,
+                    }
+                }
+                entry => entry?,
+            };
+            dir = match entry {
+                Entry::Dir(next) => next,
+                Entry::Symlink(_) | Entry::Other => {
+                    return Err(io::Error::from(io::ErrorKind::NotADirectory))
+                }
+            };
+        }
+        Ok((dir, name))
+    }
+}
+
+#[cfg(unix)]
+fn refuse_hard_links(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata()?;
+    if metadata.is_file() && metadata.nlink() > 1 {
+        return Err(io::Error::other(
+            "file has other hard links; only atomic writes may replace it",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn refuse_hard_links(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::{CStr, CString, OsStr, OsString};
+    use std::fs::{File, Permissions};
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::OpenFlags;
+
+    // Directories are only ever used as `*at` anchors.
+    #[cfg(target_os = "linux")]
+    const DIR_FLAGS: libc::c_int = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    const DIR_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+
+    #[derive(Clone)]
+    pub(super) struct Dir(Arc<OwnedFd>);
+
+    pub(super) enum Entry {
+        Dir(Dir),
+        Symlink(PathBuf),
+        Other,
+    }
+
+    fn c_name(name: &OsStr) -> io::Result<CString> {
+        CString::new(name.as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+    }
+
+    fn check(ret: libc::c_int) -> io::Result<()> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    impl Dir {
+        pub(super) fn open_root(path: &Path) -> io::Result<Self> {
+            let path = c_name(path.as_os_str())?;
+            // SAFETY: `path` is a valid C string; the fd is owned below.
+            let fd = unsafe { libc::open(path.as_ptr(), DIR_FLAGS) };
+            check(fd)?;
+            // SAFETY: `fd` was just opened and is owned by nobody else.
+            Ok(Self(Arc::new(unsafe { OwnedFd::from_raw_fd(fd) })))
+        }
+
+        fn openat(&self, name: &CStr, flags: libc::c_int) -> io::Result<OwnedFd> {
+            // SAFETY: `name` is a valid C string and `self.0` an open fd.
+            let fd = unsafe {
+                libc::openat(
+                    self.0.as_raw_fd(),
+                    name.as_ptr(),
+                    flags,
+                    0o666 as libc::c_uint,
+                )
+            };
+            check(fd)?;
+            // SAFETY: `fd` was just opened and is owned by nobody else.
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        }
+
+        fn stat(&self, name: &CStr) -> io::Result<libc::stat> {
+            let mut stat = MaybeUninit::<libc::stat>::uninit();
+            // SAFETY: `stat` is written by the call before being read.
+            check(unsafe {
+                libc::fstatat(
+                    self.0.as_raw_fd(),
+                    name.as_ptr(),
+                    stat.as_mut_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            })?;
+            // SAFETY: fstatat succeeded, so `stat` is initialised.
+            Ok(unsafe { stat.assume_init() })
+        }
+
+        pub(super) fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+            let name = c_name(name)?;
+            match self.stat(&name)?.st_mode & libc::S_IFMT {
+                // `O_NOFOLLOW` fails if the entry became a symlink since the
+                // stat, so the walk never lands outside through a swap.
+                libc::S_IFDIR => self
+                    .openat(&name, DIR_FLAGS | libc::O_NOFOLLOW)
+                    .map(|fd| Entry::Dir(Dir(Arc::new(fd)))),
+                libc::S_IFLNK => self.readlink(&name).map(Entry::Symlink),
+                _ => Ok(Entry::Other),
+            }
+        }
+
+        fn readlink(&self, name: &CStr) -> io::Result<PathBuf> {
+            let mut buf = vec![0u8; 256];
+            loop {
+                // SAFETY: `buf` is valid for `buf.len()` bytes.
+                let len = unsafe {
+                    libc::readlinkat(
+                        self.0.as_raw_fd(),
+                        name.as_ptr(),
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                    )
+                };
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if (len as usize) < buf.len() {
+                    buf.truncate(len as usize);
+                    return Ok(PathBuf::from(OsString::from_vec(buf)));
+                }
+                buf.resize(buf.len() * 2, 0);
+            }
+        }
+
+        pub(super) fn permissions(&self, name: &OsStr) -> Option<Permissions> {
+            let stat = self.stat(&c_name(name).ok()?).ok()?;
+            // `st_mode` is narrower than `u32` on some platforms.
+            #[allow(clippy::useless_conversion)]
+            let mode = u32::from(stat.st_mode);
+            Some(Permissions::from_mode(mode & 0o7777))
+        }
+
+        pub(super) fn mkdir(&self, name: &OsStr) -> io::Result<()> {
+            let name = c_name(name)?;
+            // SAFETY: `name` is a valid C string and `self.0` an open fd.
+            check(unsafe { libc::mkdirat(self.0.as_raw_fd(), name.as_ptr(), 0o777) })
+        }
+
+        pub(super) fn open(&self, name: &OsStr, flags: OpenFlags) -> io::Result<File> {
+            let mut bits = libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            bits |= match (flags.write, flags.append) {
+                (false, _) => libc::O_RDONLY,
+                (true, false) => libc::O_WRONLY,
+                (true, true) => libc::O_WRONLY | libc::O_APPEND,
+            };
+            if flags.metadata {
+                #[cfg(target_os = "linux")]
+                {
+                    bits |= libc::O_PATH;
+                }
+                #[cfg(not(target_os = "linux"))]
+                {
+                    bits |= libc::O_NONBLOCK;
+                }
+            }
+            if flags.create {
+                bits |= libc::O_CREAT;
+            }
+            if flags.create_new {
+                bits |= libc::O_CREAT | libc::O_EXCL;
+            }
+            self.openat(&c_name(name)?, bits).map(File::from)
+        }
+
+        pub(super) fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+            let (from, to) = (c_name(from)?, c_name(to)?);
+            let fd = self.0.as_raw_fd();
+            // SAFETY: both names are valid C strings and `fd` an open fd.
+            check(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })
+        }
+
+        pub(super) fn unlink(&self, name: &OsStr) -> io::Result<()> {
+            let name = c_name(name)?;
+            // SAFETY: `name` is a valid C string and `self.0` an open fd.
+            check(unsafe { libc::unlinkat(self.0.as_raw_fd(), name.as_ptr(), 0) })
+        }
+    }
+}
+
+/// Path-based fallback: the same walk, without the handle guarantees.
+#[cfg(not(unix))]
+mod imp {
+    use std::ffi::OsStr;
+    use std::fs::{self, File, OpenOptions, Permissions};
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::OpenFlags;
+
+    #[derive(Clone)]
+    pub(super) struct Dir(PathBuf);
+
+    pub(super) enum Entry {
+        Dir(Dir),
+        Symlink(PathBuf),
+        Other,
+    }
+
+    impl Dir {
+        pub(super) fn open_root(path: &Path) -> io::Result<Self> {
+            Ok(Self(path.to_path_buf()))
+        }
+
+        pub(super) fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+            let path = self.0.join(name);
+            let metadata = fs::symlink_metadata(&path)?;
+            if metadata.file_type().is_symlink() {
+                fs::read_link(&path).map(Entry::Symlink)
+            } else if metadata.is_dir() {
+                Ok(Entry::Dir(Dir(path)))
+            } else {
+                Ok(Entry::Other)
+            }
+        }
+
+        pub(super) fn permissions(&self, name: &OsStr) -> Option<Permissions> {
+            fs::symlink_metadata(self.0.join(name))
+                .ok()
+                .map(|metadata| metadata.permissions())
+        }
+
+        pub(super) fn mkdir(&self, name: &OsStr) -> io::Result<()> {
+            fs::create_dir(self.0.join(name))
+        }
+
+        pub(super) fn open(&self, name: &OsStr, flags: OpenFlags) -> io::Result<File> {
+            OpenOptions::new()
+                .read(!flags.write)
+                .write(flags.write && !flags.append)
+                .append(flags.append)
+                .create(flags.create)
+                .create_new(flags.create_new)
+                .open(self.0.join(name))
+        }
+
+        pub(super) fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+            fs::rename(self.0.join(from), self.0.join(to))
+        }
+
+        pub(super) fn unlink(&self, name: &OsStr) -> io::Result<()> {
+            fs::remove_file(self.0.join(name))
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::ffi::OsStringExt;
+    use std::os::unix::fs::{symlink, FileTypeExt};
+
+    use super::*;
+
+    #[test]
+    fn parent_steps_stay_inside_the_root() {
+        let dir = tempfile::tempdir().expect("tmp");
+        fs::create_dir_all(dir.path().join("a/b")).expect("mkdir");
+        fs::write(dir.path().join("top.txt"), "top").expect("write");
+
+        let path = resolve(dir.path(), "a/b/../../top.txt").expect("resolve");
+        assert_eq!(path.path, dir.path().join("top.txt"));
+        assert_eq!(path.read_to_string().expect("read"), "top");
+
+        for raw in ["..", "a/../..", "a/b/../../../top.txt", "missing/../../x"] {
+            assert_eq!(
+                resolve(dir.path(), raw).err().as_deref(),
+                Some(ESCAPES_ROOT),
+                "{raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn symlinks_are_expanded_only_inside_the_root() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        fs::create_dir(root.join("real")).expect("mkdir");
+        fs::write(root.join("real/file.txt"), "inside").expect("write");
+        symlink("real", root.join("rel")).expect("symlink");
+        symlink(root.canonicalize().unwrap().join("real"), root.join("abs")).expect("symlink");
+        symlink("../real/file.txt", root.join("real/up.txt")).expect("symlink");
+        symlink(outside.path(), root.join("out")).expect("symlink");
+        symlink("../../../../../../../..", root.join("real/climb")).expect("symlink");
+        symlink("loop", root.join("loop")).expect("symlink");
+
+        for raw in [
+            "rel/file.txt",
+            "abs/file.txt",
+            "real/up.txt",
+            "rel/./up.txt",
+        ] {
+            let path = resolve(root, raw).expect(raw);
+            assert_eq!(path.path, root.join("real/file.txt"), "{raw}");
+            assert_eq!(path.read_to_string().expect("read"), "inside");
+        }
+        for raw in ["out", "out/x.txt", "real/climb/etc/passwd"] {
+            assert_eq!(
+                resolve(root, raw).err().as_deref(),
+                Some(ESCAPES_ROOT),
+                "{raw}"
+            );
+        }
+        assert!(resolve(root, "loop").is_err());
+    }
+
+    #[test]
+    fn metadata_does_not_follow_or_block() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        fs::write(root.join("file.txt"), "inside").expect("write");
+        let path = resolve(root, Path::new("file.txt")).expect("resolve");
+        assert_eq!(path.metadata().expect("metadata").len(), 6);
+
+        fs::remove_file(root.join("file.txt")).expect("remove");
+        symlink(outside.path(), root.join("file.txt")).expect("symlink");
+        // Either refused or the link itself, never the directory it names.
+        assert!(path
+            .metadata()
+            .map_or(true, |meta| meta.file_type().is_symlink()));
+
+        let fifo =
+            std::ffi::CString::new(root.join("fifo").into_os_string().into_vec()).expect("path");
+        // SAFETY: `fifo` is a valid C string.
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let path = resolve(root, "fifo").expect("resolve");
+        assert!(path.metadata().expect("metadata").file_type().is_fifo());
+    }
+
+    #[test]
+    fn swapped_ancestor_does_not_redirect_writes() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).expect("mkdir");
+
+        let path = resolve(root, "sub/file.txt").expect("resolve");
+        fs::rename(root.join("sub"), root.join("moved")).expect("rename");
+        symlink(outside.path(), root.join("sub")).expect("symlink");
+
+        path.replace("data").expect("replace");
+        let mut file = path.open_in_place(true, false).expect("open");
+        file.write_all(b"+").expect("append");
+        assert_eq!(
+            fs::read_to_string(root.join("moved/file.txt")).unwrap(),
+            "data+"
+        );
+        assert!(fs::read_dir(outside.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn swapped_target_is_not_followed() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        let secret = outside.path().join("secret.txt");
+        fs::write(&secret, "secret").expect("write");
+        fs::write(root.join("file.txt"), "inside").expect("write");
+
+        let path = resolve(root, "file.txt").expect("resolve");
+        fs::remove_file(root.join("file.txt")).expect("remove");
+        symlink(&secret, root.join("file.txt")).expect("symlink");
+
+        assert!(path.open().is_err());
+        assert!(path.open_in_place(false, true).is_err());
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+    }
+
+    #[test]
+    fn missing_parents_are_created_without_following_links() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+
+        let path = resolve(root, "new/deeper/file.txt").expect("resolve");
+        path.replace("x").expect("replace");
+        assert_eq!(
+            fs::read_to_string(root.join("new/deeper/file.txt")).unwrap(),
+            "x"
+        );
+
+        let path = resolve(root, "later/file.txt").expect("resolve");
+        symlink(outside.path(), root.join("later")).expect("symlink");
+        assert!(path.replace("x").is_err());
+        assert!(fs::read_dir(outside.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn in_place_writes_refuse_hard_links() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("tmp");
+        let root = dir.path();
+        let shared = outside.path().join("shared.txt");
+        fs::write(&shared, "original").expect("write");
+        if fs::hard_link(&shared, root.join("linked.txt")).is_err() {
+            // Different filesystems; nothing to test.
+            return;
+        }
+
+        let path = resolve(root, "linked.txt").expect("resolve");
+        assert!(path.open_in_place(false, false).is_err());
+        assert!(path.open_in_place(true, false).is_err());
+        assert_eq!(fs::read_to_string(&shared).unwrap(), "original");
+
+        path.replace("replaced").expect("replace");
+        assert_eq!(
+            fs::read_to_string(root.join("linked.txt")).unwrap(),
+            "replaced"
+        );
+        assert_eq!(fs::read_to_string(&shared).unwrap(), "original");
+    }
+}
@@ -1,15 +1,25 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use futures_util::future::BoxFuture;
-use rip_kernel::{Event, EventKind};
-use serde_json::Value;
-use tokio::sync::Semaphore;
+use futures_util::future::{BoxFuture, FutureExt};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use jsonschema::JSONSchema;
+use rip_kernel::{CancellationToken, Event, EventKind, ToolCall, ToolDispatcher};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, Semaphore};
 use uuid::Uuid;
 
+use crate::mode::{ToolMode, ToolModes};
+use crate::permissions::{PermissionDecision, PermissionSubject, ToolPermissions};
+
+/// How long a timed-out handler gets to stop its work and report back.
+const TERMINATE_WAIT: Duration = Duration::from_secs(3);
+/// Tail of streamed output kept for `tool_failed` frames.
+const PARTIAL_OUTPUT_BYTES: usize = 16 * 1024;
+
 #[derive(Clone, Debug)]
 pub struct ToolInvocation {
     pub name: String,
@@ -54,12 +64,209 @@ impl ToolOutput {
     }
 }
 
-pub type ToolHandler = Arc<dyn Fn(ToolInvocation) -> BoxFuture<'static, ToolOutput> + Send + Sync>;
+/// Live output channel handed to a tool handler.
+///
+/// Chunks pushed here are emitted as `tool_stdout` / `tool_stderr` frames as
+/// soon as they arrive; the `ToolOutput` a handler returns should only carry
+/// chunks it did not already stream. Long-running handlers watch
+/// `cancelled()` and stop their work when the runner gives up on them.
+#[derive(Clone, Default)]
+pub struct ToolSink {
+    session_id: String,
+    tool_id: String,
+    sender: Option<mpsc::UnboundedSender<EventKind>>,
+    cancel: CancellationToken,
+    partial: Arc<Mutex<PartialOutput>>,
+}
+
+#[derive(Default)]
+struct PartialOutput {
+    stdout: String,
+    stderr: String,
+    signal: Option<String>,
+    limit: Option<String>,
+}
+
+impl ToolSink {
+    pub fn new(tool_id: impl Into<String>, sender: mpsc::UnboundedSender<EventKind>) -> Self {
+        Self {
+            tool_id: tool_id.into(),
+            sender: Some(sender),
+            ..Self::default()
+        }
+    }
+
+    /// A sink that drops every chunk.
+    pub fn discard() -> Self {
+        Self::default()
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn tool_id(&self) -> &str {
+        &self.tool_id
+    }
+
+    pub fn stdout(&self, chunk: impl Into<String>) {
+        self.push_stdout(chunk.into(), None);
+    }
+
+    pub fn stderr(&self, chunk: impl Into<String>) {
+        self.push_stderr(chunk.into(), None);
+    }
+
+    /// Like `stdout`, for output produced by background job `job_id`.
+    pub fn job_stdout(&self, job_id: impl Into<String>, chunk: impl Into<String>) {
+        self.push_stdout(chunk.into(), Some(job_id.into()));
+    }
+
+    pub fn job_stderr(&self, job_id: impl Into<String>, chunk: impl Into<String>) {
+        self.push_stderr(chunk.into(), Some(job_id.into()));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Resolves once the runner asks the handler to stop.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Records the signal a handler sent while stopping its process.
+    pub fn signalled(&self, signal: impl Into<String>) {
+        self.partial().signal = Some(signal.into());
+    }
+
+    /// Records that the call broke resource limit `limit`; the runner then
+    /// reports `tool_failed { error: "limit_exceeded" }` instead of
+    /// `tool_ended`.
+    pub fn limit_exceeded(&self, limit: impl Into<String>) {
+        self.partial().limit = Some(limit.into());
+    }
+
+    fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    fn push_stdout(&self, chunk: String, job_id: Option<String>) {
+        push_tail(&mut self.partial().stdout, &chunk);
+        self.send(EventKind::ToolStdout {
+            tool_id: self.tool_id.clone(),
+            chunk,
+            job_id,
+        });
+    }
+
+    fn push_stderr(&self, chunk: String, job_id: Option<String>) {
+        push_tail(&mut self.partial().stderr, &chunk);
+        self.send(EventKind::ToolStderr {
+            tool_id: self.tool_id.clone(),
+            chunk,
+            job_id,
+        });
+    }
+
+    fn partial(&self) -> std::sync::MutexGuard<'_, PartialOutput> {
+        self.partial.lock().expect("tool sink mutex")
+    }
+
+    fn send(&self, kind: EventKind) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(kind);
+        }
+    }
+}
+
+fn push_tail(buffer: &mut String, chunk: &str) {
+    if !buffer.is_empty() {
+        buffer.push('\n');
+    }
+    buffer.push_str(chunk);
+    if buffer.len() > PARTIAL_OUTPUT_BYTES {
+        let mut start = buffer.len() - PARTIAL_OUTPUT_BYTES;
+        while !buffer.is_char_boundary(start) {
+            start += 1;
+        }
+        buffer.drain(..start);
+    }
+}
+
+/// What the model is told about a tool: a description and a JSON Schema for
+/// its arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolSpec {
+    pub description: String,
+    pub parameters: Value,
+}
+
+impl ToolSpec {
+    pub fn new(description: impl Into<String>, parameters: Value) -> Self {
+        Self {
+            description: description.into(),
+            parameters,
+        }
+    }
+
+    /// The OpenResponses `function` tool entry for `name`.
+    pub fn function_tool(&self, name: &str) -> Value {
+        json!({
+            "type": "function",
+            "name": name,
+            "description": self.description,
+            "parameters": self.parameters
+        })
+    }
+}
+
+struct RegisteredSpec {
+    spec: ToolSpec,
+    validator: Arc<JSONSchema>,
+}
+
+pub type ToolHandler =
+    Arc<dyn Fn(ToolInvocation, ToolSink) -> BoxFuture<'static, ToolOutput> + Send + Sync>;
+
+/// Maps a call's arguments to what permission rules are matched against.
+pub type PermissionSubjects = Arc<dyn Fn(&Value) -> Vec<PermissionSubject> + Send + Sync>;
+
+/// Releases per-session state a tool keeps between calls.
+pub type SessionTeardown = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Runs a handler outside a `ToolRunner`, folding streamed chunks back into
+/// the returned output (streamed chunks first).
+pub async fn invoke(handler: &ToolHandler, invocation: ToolInvocation) -> ToolOutput {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut output = handler(invocation, ToolSink::new("", sender)).await;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    while let Ok(kind) = receiver.try_recv() {
+        match kind {
+            EventKind::ToolStdout { chunk, .. } => stdout.push(chunk),
+            EventKind::ToolStderr { chunk, .. } => stderr.push(chunk),
+            _ => {}
+        }
+    }
+    stdout.append(&mut output.stdout);
+    stderr.append(&mut output.stderr);
+    output.stdout = stdout;
+    output.stderr = stderr;
+    output
+}
 
 #[derive(Default)]
 pub struct ToolRegistry {
     tools: Mutex<HashMap<String, ToolHandler>>,
     aliases: Mutex<HashMap<String, String>>,
+    specs: Mutex<HashMap<String, RegisteredSpec>>,
+    subjects: Mutex<HashMap<String, PermissionSubjects>>,
+    families: Mutex<HashMap<String, String>>,
+    timeouts: Mutex<HashMap<String, u64>>,
+    teardowns: Mutex<Vec<SessionTeardown>>,
+    read_only: Mutex<HashSet<String>>,
 }
 
 impl ToolRegistry {
@@ -68,6 +275,191 @@ impl ToolRegistry {
         tools.insert(name.into(), handler);
     }
 
+    /// Registers a tool the model can discover; invocations are validated
+    /// against `spec.parameters` before the handler runs.
+    /// A schema that does not compile is an error and nothing is registered.
+    pub fn register_with_spec(
+        &self,
+        name: impl Into<String>,
+        spec: ToolSpec,
+        handler: ToolHandler,
+    ) -> Result<(), String> {
+        let name = name.into();
+        let validator = JSONSchema::compile(&spec.parameters)
+            .map_err(|err| format!("invalid parameters schema for `{name}`: {err}"))?;
+        self.specs.lock().expect("tool spec mutex").insert(
+            name.clone(),
+            RegisteredSpec {
+                spec,
+                validator: Arc::new(validator),
+            },
+        );
+        self.register(name, handler);
+        Ok(())
+    }
+
+    pub fn spec(&self, name: &str) -> Option<ToolSpec> {
+        let name = self.resolve(name);
+        let specs = self.specs.lock().expect("tool spec mutex");
+        specs.get(&name).map(|entry| entry.spec.clone())
+    }
+
+    /// Checks `args` against the tool's schema; tools without one accept
+    /// anything.
+    pub fn validate(&self, name: &str, args: &Value) -> Result<(), Vec<String>> {
+        let name = self.resolve(name);
+        let validator = {
+            let specs = self.specs.lock().expect("tool spec mutex");
+            match specs.get(&name).map(|entry| entry.validator.clone()) {
+                Some(validator) => validator,
+                None => return Ok(()),
+            }
+        };
+        let result = validator.validate(args).map_err(|errors| {
+            errors
+                .map(|err| {
+                    let path = err.instance_path.to_string();
+                    if path.is_empty() {
+                        err.to_string()
+                    } else {
+                        format!("{path}: {err}")
+                    }
+                })
+                .collect()
+        });
+        result
+    }
+
+    /// Function tool entries for every registered tool (aliases excluded),
+    /// sorted by name.
+    pub fn function_tools(&self) -> Vec<Value> {
+        self.function_tools_for(ToolMode::Default)
+    }
+
+    /// Like `function_tools`, leaving out tools `mode` does not allow.
+    pub fn function_tools_for(&self, mode: ToolMode) -> Vec<Value> {
+        let mut names: Vec<String> = self
+            .tools
+            .lock()
+            .expect("tool registry mutex")
+            .keys()
+            .cloned()
+            .collect();
+        names.retain(|name| self.allowed_in(mode, name));
+        names.sort();
+        let specs = self.specs.lock().expect("tool spec mutex");
+        names
+            .iter()
+            .map(|name| match specs.get(name) {
+                Some(entry) => entry.spec.function_tool(name),
+                None => json!({"type": "function", "name": name}),
+            })
+            .collect()
+    }
+
+    /// Sets how calls to `name` are matched by `tool(pattern)` rules; tools
+    /// without subjects are only matched by bare `tool` rules.
+    pub fn register_permission_subjects(
+        &self,
+        name: impl Into<String>,
+        subjects: PermissionSubjects,
+    ) {
+        let mut entries = self.subjects.lock().expect("permission subjects mutex");
+        entries.insert(name.into(), subjects);
+    }
+
+    pub fn permission_subjects(&self, name: &str, args: &Value) -> Vec<PermissionSubject> {
+        let name = self.resolve(name);
+        let subjects = {
+            let entries = self.subjects.lock().expect("permission subjects mutex");
+            match entries.get(&name) {
+                Some(subjects) => subjects.clone(),
+                None => return Vec::new(),
+            }
+        };
+        subjects(args)
+    }
+
+    /// Lets rules written for `family` (e.g. `write`) also govern `name`.
+    pub fn set_permission_family(&self, name: impl Into<String>, family: impl Into<String>) {
+        let mut families = self.families.lock().expect("permission families mutex");
+        families.insert(name.into(), family.into());
+    }
+
+    /// The rule family `name` belongs to; a tool is its own family by default.
+    pub fn permission_family(&self, name: &str) -> String {
+        let name = self.resolve(name);
+        let families = self.families.lock().expect("permission families mutex");
+        families.get(&name).cloned().unwrap_or(name)
+    }
+
+    /// Timeout applied to model-issued calls of `name` that do not pass their
+    /// own `timeout_ms`.
+    pub fn set_default_timeout(&self, name: impl Into<String>, timeout_ms: u64) {
+        let mut timeouts = self.timeouts.lock().expect("tool timeouts mutex");
+        timeouts.insert(name.into(), timeout_ms);
+    }
+
+    pub fn default_timeout(&self, name: &str) -> Option<u64> {
+        let name = self.resolve(name);
+        let timeouts = self.timeouts.lock().expect("tool timeouts mutex");
+        timeouts.get(&name).copied()
+    }
+
+    /// Runs `teardown` whenever a session ends (`ToolRunner` forwards
+    /// `ToolDispatcher::end_session`).
+    pub fn on_session_end(&self, teardown: SessionTeardown) {
+        let mut teardowns = self.teardowns.lock().expect("session teardowns mutex");
+        teardowns.push(teardown);
+    }
+
+    pub fn end_session(&self, session_id: &str) {
+        let teardowns = self
+            .teardowns
+            .lock()
+            .expect("session teardowns mutex")
+            .clone();
+        for teardown in teardowns {
+            teardown(session_id);
+        }
+    }
+
+    /// Marks `name` as never changing the workspace, so it stays available
+    /// in plan mode.
+    pub fn mark_read_only(&self, name: impl Into<String>) {
+        let mut read_only = self.read_only.lock().expect("read-only tools mutex");
+        read_only.insert(name.into());
+    }
+
+    pub fn is_read_only(&self, name: &str) -> bool {
+        let name = self.resolve(name);
+        let read_only = self.read_only.lock().expect("read-only tools mutex");
+        read_only.contains(&name)
+    }
+
+    pub fn allowed_in(&self, mode: ToolMode, name: &str) -> bool {
+        match mode {
+            ToolMode::Default => true,
+            ToolMode::Plan => self.is_read_only(name),
+        }
+    }
+
+    fn resolve(&self, name: &str) -> String {
+        if self
+            .tools
+            .lock()
+            .expect("tool registry mutex")
+            .contains_key(name)
+        {
+            return name.to_string();
+        }
+        let aliases = self.aliases.lock().expect("tool alias mutex");
+        aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
     pub fn register_alias(&self, alias: impl Into<String>, target: impl Into<String>) {
         let mut aliases = self.aliases.lock().expect("tool alias mutex");
         aliases.insert(alias.into(), target.into());
@@ -87,9 +479,12 @@ impl ToolRegistry {
     }
 }
 
+#[derive(Clone)]
 pub struct ToolRunner {
     registry: Arc<ToolRegistry>,
     semaphore: Arc<Semaphore>,
+    permissions: Option<Arc<ToolPermissions>>,
+    modes: Arc<ToolModes>,
 }
 
 impl ToolRunner {
@@ -97,99 +492,317 @@ impl ToolRunner {
         Self {
             registry,
             semaphore: Arc::new(Semaphore::new(max_concurrency.max(1))),
+            permissions: None,
+            modes: Arc::new(ToolModes::default()),
         }
     }
 
+    /// Shares `modes` with whoever switches sessions in and out of plan mode.
+    pub fn with_modes(mut self, modes: Arc<ToolModes>) -> Self {
+        self.modes = modes;
+        self
+    }
+
+    pub fn modes(&self) -> &Arc<ToolModes> {
+        &self.modes
+    }
+
+    /// Checks every call against `permissions` before it runs; a runner
+    /// without them allows everything.
+    pub fn with_permissions(mut self, permissions: Arc<ToolPermissions>) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
     pub async fn run(
         &self,
         session_id: &str,
         seq: &mut u64,
         invocation: ToolInvocation,
+        cancel: CancellationToken,
     ) -> Vec<Event> {
-        let _permit = self.semaphore.acquire().await.expect("semaphore");
+        let kinds: Vec<EventKind> = self.stream(session_id, invocation, cancel).collect().await;
+        kinds
+            .into_iter()
+            .map(|kind| self.emit(session_id, seq, kind))
+            .collect()
+    }
+
+    /// Runs a tool, yielding frames as the handler produces them. Firing
+    /// `cancel` stops the handler and ends the stream with
+    /// `tool_failed { error: "cancelled" }`.
+    pub fn stream(
+        &self,
+        session_id: &str,
+        invocation: ToolInvocation,
+        cancel: CancellationToken,
+    ) -> BoxStream<'static, EventKind> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let runner = self.clone();
+        let session_id = session_id.to_string();
+        let driver =
+            async move { runner.execute(session_id, invocation, cancel, sender).await }.boxed();
+
+        stream::unfold(
+            (Some(driver), receiver),
+            |(mut driver, mut receiver)| async move {
+                loop {
+                    match driver.as_mut() {
+                        Some(future) => {
+                            tokio::select! {
+                                biased;
+                                Some(kind) = receiver.recv() => {
+                                    return Some((kind, (driver, receiver)));
+                                }
+                                _ = future => driver = None,
+                            }
+                        }
+                        None => {
+                            let kind = receiver.recv().await?;
+                            return Some((kind, (None, receiver)));
+                        }
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    async fn execute(
+        &self,
+        session_id: String,
+        invocation: ToolInvocation,
+        cancel: CancellationToken,
+        events: mpsc::UnboundedSender<EventKind>,
+    ) {
         let tool_id = Uuid::new_v4().to_string();
         let started_at = Instant::now();
 
-        let mut events = Vec::new();
-        events.push(self.emit(
-            session_id,
-            seq,
-            EventKind::ToolStarted {
-                tool_id: tool_id.clone(),
-                name: invocation.name.clone(),
-                args: invocation.args.clone(),
-                timeout_ms: invocation.timeout_ms,
-            },
-        ));
+        let _ = events.send(EventKind::ToolStarted {
+            tool_id: tool_id.clone(),
+            name: invocation.name.clone(),
+            args: invocation.args.clone(),
+            timeout_ms: invocation.timeout_ms,
+        });
 
         let handler = match self.registry.get(&invocation.name) {
             Some(handler) => handler,
             None => {
-                events.push(self.emit(
-                    session_id,
-                    seq,
-                    EventKind::ToolFailed {
-                        tool_id,
-                        error: "unknown tool".to_string(),
-                    },
-                ));
-                return events;
+                let _ = events.send(EventKind::ToolFailed {
+                    tool_id,
+                    error: "unknown tool".to_string(),
+                    signal: None,
+                    limit: None,
+                    stdout: None,
+                    stderr: None,
+                });
+                return;
             }
         };
 
-        let output = if let Some(timeout_ms) = invocation.timeout_ms {
-            match tokio::time::timeout(
-                Duration::from_millis(timeout_ms),
-                (handler)(invocation.clone()),
-            )
+        let mode = self.modes.get(&session_id);
+        if !self.registry.allowed_in(mode, &invocation.name) {
+            let _ = events.send(EventKind::ToolStderr {
+                tool_id: tool_id.clone(),
+                chunk: format!(
+                    "`{}` is not available in {} mode; only read-only tools run",
+                    invocation.name,
+                    mode.as_str()
+                ),
+                job_id: None,
+            });
+            let _ = events.send(EventKind::ToolFailed {
+                tool_id,
+                error: "plan_mode".to_string(),
+                signal: None,
+                limit: None,
+                stdout: None,
+                stderr: None,
+            });
+            return;
+        }
+
+        if let Err(errors) = self.registry.validate(&invocation.name, &invocation.args) {
+            let _ = events.send(EventKind::ToolStderr {
+                tool_id: tool_id.clone(),
+                chunk: format!("invalid args: {}", errors.join("; ")),
+                job_id: None,
+            });
+            let _ = events.send(EventKind::ToolEnded {
+                tool_id,
+                exit_code: 2,
+                duration_ms: started_at.elapsed().as_millis() as u64,
+                artifacts: None,
+            });
+            return;
+        }
+
+        // Checked before taking a permit so calls waiting on a client do not
+        // hold up the others.
+        if let Err(error) = self
+            .check_permission(&session_id, &tool_id, &invocation, &cancel, &events)
             .await
-            {
-                Ok(output) => Ok(output),
-                Err(_) => Err("timeout".to_string()),
+        {
+            let _ = events.send(EventKind::ToolFailed {
+                tool_id,
+                error,
+                signal: None,
+                limit: None,
+                stdout: None,
+                stderr: None,
+            });
+            return;
+        }
+        let permit = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => None,
+            permit = self.semaphore.acquire() => Some(permit.expect("semaphore")),
+        };
+        if permit.is_none() {
+            let _ = events.send(EventKind::ToolFailed {
+                tool_id,
+                error: "cancelled".to_string(),
+                signal: None,
+                limit: None,
+                stdout: None,
+                stderr: None,
+            });
+            return;
+        }
+
+        let sink = ToolSink {
+            session_id,
+            cancel: cancel.child_token(),
+            ..ToolSink::new(tool_id.clone(), events.clone())
+        };
+        let mut future = (handler)(invocation.clone(), sink.clone());
+        let deadline = async {
+            match invocation.timeout_ms {
+                Some(timeout_ms) => tokio::time::sleep(Duration::from_millis(timeout_ms)).await,
+                None => std::future::pending().await,
             }
-        } else {
-            Ok((handler)(invocation.clone()).await)
+        };
+        // Cancellation also reaches the handler through its sink, so a
+        // handler may finish in the same poll; the session cancel wins.
+        let output = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err("cancelled".to_string()),
+            _ = deadline => Err("timeout".to_string()),
+            output = &mut future => Ok(output),
         };
 
         match output {
             Ok(output) => {
                 for chunk in output.stdout {
-                    events.push(self.emit(
-                        session_id,
-                        seq,
-                        EventKind::ToolStdout {
-                            tool_id: tool_id.clone(),
-                            chunk,
-                        },
-                    ));
+                    let _ = events.send(EventKind::ToolStdout {
+                        tool_id: tool_id.clone(),
+                        chunk,
+                        job_id: None,
+                    });
                 }
                 for chunk in output.stderr {
-                    events.push(self.emit(
-                        session_id,
-                        seq,
-                        EventKind::ToolStderr {
-                            tool_id: tool_id.clone(),
-                            chunk,
-                        },
-                    ));
+                    let _ = events.send(EventKind::ToolStderr {
+                        tool_id: tool_id.clone(),
+                        chunk,
+                        job_id: None,
+                    });
                 }
-                events.push(self.emit(
-                    session_id,
-                    seq,
-                    EventKind::ToolEnded {
+                let partial = std::mem::take(&mut *sink.partial());
+                if let Some(limit) = partial.limit {
+                    let _ = events.send(EventKind::ToolFailed {
                         tool_id,
-                        exit_code: output.exit_code,
-                        duration_ms: started_at.elapsed().as_millis() as u64,
-                        artifacts: output.artifacts,
-                    },
-                ));
+                        error: "limit_exceeded".to_string(),
+                        signal: partial.signal,
+                        limit: Some(limit),
+                        stdout: Some(partial.stdout),
+                        stderr: Some(partial.stderr),
+                    });
+                    return;
+                }
+                let _ = events.send(EventKind::ToolEnded {
+                    tool_id,
+                    exit_code: output.exit_code,
+                    duration_ms: started_at.elapsed().as_millis() as u64,
+                    artifacts: output.artifacts,
+                });
             }
             Err(error) => {
-                events.push(self.emit(session_id, seq, EventKind::ToolFailed { tool_id, error }));
+                // Let the handler stop (and reap) whatever it started before
+                // reporting; handlers that ignore cancellation are dropped.
+                sink.cancel();
+                let _ = tokio::time::timeout(TERMINATE_WAIT, future).await;
+                let partial = std::mem::take(&mut *sink.partial());
+                let _ = events.send(EventKind::ToolFailed {
+                    tool_id,
+                    error,
+                    signal: partial.signal,
+                    limit: None,
+                    stdout: Some(partial.stdout),
+                    stderr: Some(partial.stderr),
+                });
             }
         }
+    }
 
-        events
+    /// Applies the permission policy to a call, asking the client through a
+    /// `tool_permission_requested` frame when a rule says so. A refusal is
+    /// explained on stderr and returned as the `tool_failed` error.
+    async fn check_permission(
+        &self,
+        session_id: &str,
+        tool_id: &str,
+        invocation: &ToolInvocation,
+        cancel: &CancellationToken,
+        events: &mpsc::UnboundedSender<EventKind>,
+    ) -> Result<(), String> {
+        let Some(permissions) = &self.permissions else {
+            return Ok(());
+        };
+        let subjects = self
+            .registry
+            .permission_subjects(&invocation.name, &invocation.args);
+        let verdict = permissions.policy().evaluate_as(
+            &self.registry.resolve(&invocation.name),
+            &self.registry.permission_family(&invocation.name),
+            &subjects,
+        );
+        let denied = |reason: String| {
+            let _ = events.send(EventKind::ToolStderr {
+                tool_id: tool_id.to_string(),
+                chunk: format!("permission denied: {reason}"),
+                job_id: None,
+            });
+            Err("permission_denied".to_string())
+        };
+        match verdict.decision {
+            PermissionDecision::Allow => Ok(()),
+            PermissionDecision::Deny => denied(match verdict.rule {
+                Some(rule) => format!("denied by rule `{rule}`"),
+                None => "denied by default policy".to_string(),
+            }),
+            PermissionDecision::Ask => {
+                let reply = permissions.request(session_id, tool_id);
+                let _ = events.send(EventKind::ToolPermissionRequested {
+                    tool_id: tool_id.to_string(),
+                    name: invocation.name.clone(),
+                    args: invocation.args.clone(),
+                    rule: verdict.rule,
+                });
+                let approved = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => {
+                        permissions.forget(tool_id);
+                        return Err("cancelled".to_string());
+                    }
+                    reply = reply => reply.unwrap_or(false),
+                };
+                if approved {
+                    Ok(())
+                } else {
+                    denied("denied by the user".to_string())
+                }
+            }
+        }
     }
 
     fn emit(&self, session_id: &str, seq: &mut u64, kind: EventKind) -> Event {
@@ -205,6 +818,67 @@ impl ToolRunner {
     }
 }
 
+impl ToolDispatcher for ToolRunner {
+    fn dispatch(
+        &self,
+        session_id: &str,
+        call: ToolCall,
+        cancel: CancellationToken,
+    ) -> BoxStream<'static, EventKind> {
+        let args: Value =
+            serde_json::from_str(&call.arguments).unwrap_or(Value::String(call.arguments));
+        let timeout_ms = args
+            .get("timeout_ms")
+            .and_then(Value::as_u64)
+            .or_else(|| self.registry.default_timeout(&call.name));
+        // Handlers stream one line per chunk, while the session concatenates
+        // chunks exactly as streamed, so each line keeps its terminator.
+        self.stream(
+            session_id,
+            ToolInvocation {
+                name: call.name,
+                args,
+                timeout_ms,
+            },
+            cancel,
+        )
+        .map(|kind| match kind {
+            EventKind::ToolStdout {
+                tool_id,
+                chunk,
+                job_id,
+            } => EventKind::ToolStdout {
+                tool_id,
+                chunk: chunk + "\n",
+                job_id,
+            },
+            EventKind::ToolStderr {
+                tool_id,
+                chunk,
+                job_id,
+            } => EventKind::ToolStderr {
+                tool_id,
+                chunk: chunk + "\n",
+                job_id,
+            },
+            kind => kind,
+        })
+        .boxed()
+    }
+
+    fn tools(&self) -> Vec<Value> {
+        self.registry.function_tools()
+    }
+
+    fn session_tools(&self, session_id: &str) -> Vec<Value> {
+        self.registry.function_tools_for(self.modes.get(session_id))
+    }
+
+    fn end_session(&self, session_id: &str) {
+        self.registry.end_session(session_id);
+    }
+}
+
 fn now_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -223,7 +897,7 @@ mod tests {
         let registry = Arc::new(ToolRegistry::default());
         registry.register(
             "echo",
-            Arc::new(|invocation| {
+            Arc::new(|invocation, _sink| {
                 Box::pin(async move {
                     ToolOutput {
                         stdout: vec![format!("hi:{}", invocation.args)],
@@ -246,6 +920,7 @@ mod tests {
                     args: serde_json::json!("world"),
                     timeout_ms: None,
                 },
+                CancellationToken::new(),
             )
             .await;
 
@@ -267,18 +942,21 @@ mod tests {
         let registry = Arc::new(ToolRegistry::default());
         registry.register(
             "bash",
-            Arc::new(|_invocation| {
+            Arc::new(|_invocation, _sink| {
                 Box::pin(async move { ToolOutput::success(vec!["ok".to_string()]) })
             }),
         );
         registry.register_alias("shell", "bash");
 
         let handler = registry.get("shell").expect("alias");
-        let output = handler(ToolInvocation {
-            name: "shell".to_string(),
-            args: serde_json::json!({}),
-            timeout_ms: None,
-        })
+        let output = invoke(
+            &handler,
+            ToolInvocation {
+                name: "shell".to_string(),
+                args: serde_json::json!({}),
+                timeout_ms: None,
+            },
+        )
         .await;
 
         assert_eq!(output.exit_code, 0);
@@ -290,7 +968,7 @@ mod tests {
         let registry = Arc::new(ToolRegistry::default());
         registry.register(
             "slow",
-            Arc::new(|_invocation| Box::pin(async move { pending::<ToolOutput>().await })),
+            Arc::new(|_invocation, _sink| Box::pin(async move { pending::<ToolOutput>().await })),
         );
 
         let runner = ToolRunner::new(registry, 1);
@@ -304,6 +982,7 @@ mod tests {
                     args: serde_json::json!({}),
                     timeout_ms: Some(10),
                 },
+                CancellationToken::new(),
             )
             .await;
 
@@ -317,7 +996,7 @@ mod tests {
         let registry = Arc::new(ToolRegistry::default());
         registry.register(
             "fast",
-            Arc::new(|_invocation| {
+            Arc::new(|_invocation, _sink| {
                 Box::pin(async move { ToolOutput::success(vec!["ok".to_string()]) })
             }),
         );
@@ -333,6 +1012,7 @@ mod tests {
                     args: serde_json::json!({}),
                     timeout_ms: Some(50),
                 },
+                CancellationToken::new(),
             )
             .await;
 
@@ -355,6 +1035,7 @@ mod tests {
                     args: serde_json::json!({}),
                     timeout_ms: None,
                 },
+                CancellationToken::new(),
             )
             .await;
 
@@ -373,7 +1054,7 @@ mod tests {
         let max_clone = max_seen.clone();
         registry.register(
             "block",
-            Arc::new(move |_invocation| {
+            Arc::new(move |_invocation, _sink| {
                 let active = active_clone.clone();
                 let max_seen = max_clone.clone();
                 Box::pin(async move {
@@ -409,6 +1090,7 @@ mod tests {
                 args: serde_json::json!({}),
                 timeout_ms: None,
             },
+            CancellationToken::new(),
         );
         let second = runner.run(
             "session-1",
@@ -418,9 +1100,336 @@ mod tests {
                 args: serde_json::json!({}),
                 timeout_ms: None,
             },
+            CancellationToken::new(),
         );
 
         let _ = tokio::join!(first, second);
         assert_eq!(max_seen.load(Ordering::SeqCst), 1);
     }
+
+    #[tokio::test]
+    async fn dispatches_tool_calls_as_frames() {
+        let registry = Arc::new(ToolRegistry::default());
+        registry.register(
+            "echo",
+            Arc::new(|invocation, _sink| {
+                Box::pin(async move { ToolOutput::success(vec![invocation.args.to_string()]) })
+            }),
+        );
+
+        let runner = ToolRunner::new(registry, 1);
+        let frames: Vec<EventKind> = runner
+            .dispatch(
+                "session-1",
+                ToolCall {
+                    call_id: "call_1".to_string(),
+                    name: "echo".to_string(),
+                    arguments: "{\"msg\":\"hi\"}".to_string(),
+                },
+                CancellationToken::new(),
+            )
+            .collect()
+            .await;
+
+        match &frames[0] {
+            EventKind::ToolStarted { name, args, .. } => {
+                assert_eq!(name, "echo");
+                assert_eq!(args, &serde_json::json!({"msg": "hi"}));
+            }
+            _ => panic!("expected tool_started"),
+        }
+        assert!(matches!(
+            &frames[1],
+            EventKind::ToolStdout { chunk, .. } if chunk == "{\"msg\":\"hi\"}\n"
+        ));
+        assert!(matches!(
+            frames[2],
+            EventKind::ToolEnded { exit_code: 0, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn dispatch_applies_call_and_default_timeouts() {
+        let registry = Arc::new(ToolRegistry::default());
+        registry.register(
+            "hang",
+            Arc::new(|_invocation, _sink| {
+                Box::pin(async move {
+                    std::future::pending::<()>().await;
+                    ToolOutput::success(Vec::new())
+                })
+            }),
+        );
+        registry.register_alias("stall", "hang");
+        registry.set_default_timeout("hang", 20);
+
+        let runner = ToolRunner::new(registry, 1);
+        for (name, arguments, expected) in [
+            ("hang", "{}", 20),
+            ("stall", "{}", 20),
+            ("hang", "{\"timeout_ms\":10}", 10),
+        ] {
+            let frames: Vec<EventKind> = runner
+                .dispatch(
+                    "session-1",
+                    ToolCall {
+                        call_id: "call_1".to_string(),
+                        name: name.to_string(),
+                        arguments: arguments.to_string(),
+                    },
+                    CancellationToken::new(),
+                )
+                .collect()
+                .await;
+            assert!(matches!(
+                &frames[0],
+                EventKind::ToolStarted { timeout_ms: Some(ms), .. } if *ms == expected
+            ));
+            assert!(matches!(
+                frames.last(),
+                Some(EventKind::ToolFailed { error, .. }) if error == "timeout"
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_chunks_before_handler_completes() {
+        let registry = Arc::new(ToolRegistry::default());
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let released = Arc::new(Mutex::new(Some(released)));
+        registry.register(
+            "live",
+            Arc::new(move |_invocation, sink| {
+                let released = released.lock().expect("lock").take();
+                Box::pin(async move {
+                    sink.stdout("first");
+                    if let Some(released) = released {
+                        let _ = released.await;
+                    }
+                    ToolOutput::success(vec!["last".to_string()])
+                })
+            }),
+        );
+
+        let runner = ToolRunner::new(registry, 1);
+        let mut frames = runner.stream(
+            "session-1",
+            ToolInvocation {
+                name: "live".to_string(),
+                args: serde_json::json!({}),
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        );
+
+        assert!(matches!(
+            frames.next().await,
+            Some(EventKind::ToolStarted { .. })
+        ));
+        assert!(matches!(
+            frames.next().await,
+            Some(EventKind::ToolStdout { chunk, .. }) if chunk == "first"
+        ));
+        release.send(()).expect("release");
+        let rest: Vec<EventKind> = frames.collect().await;
+        assert!(matches!(
+            &rest[0],
+            EventKind::ToolStdout { chunk, .. } if chunk == "last"
+        ));
+        assert!(matches!(rest[1], EventKind::ToolEnded { .. }));
+    }
+
+    #[tokio::test]
+    async fn timeout_reports_signal_and_partial_output() {
+        let registry = Arc::new(ToolRegistry::default());
+        registry.register(
+            "stubborn",
+            Arc::new(|_invocation, sink| {
+                Box::pin(async move {
+                    sink.stdout("working");
+                    sink.cancelled().await;
+                    sink.signalled("SIGTERM");
+                    ToolOutput::failure(vec!["terminated".to_string()])
+                })
+            }),
+        );
+
+        let runner = ToolRunner::new(registry, 1);
+        let mut seq = 0;
+        let events = runner
+            .run(
+                "session-1",
+                &mut seq,
+                ToolInvocation {
+                    name: "stubborn".to_string(),
+                    args: serde_json::json!({}),
+                    timeout_ms: Some(20),
+                },
+                CancellationToken::new(),
+            )
+            .await;
+
+        match &events.last().expect("event").kind {
+            EventKind::ToolFailed {
+                error,
+                signal,
+                stdout,
+                ..
+            } => {
+                assert_eq!(error, "timeout");
+                assert_eq!(signal.as_deref(), Some("SIGTERM"));
+                assert_eq!(stdout.as_deref(), Some("working"));
+            }
+            other => panic!("expected tool_failed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn partial_output_keeps_tail() {
+        let mut buffer = String::new();
+        push_tail(&mut buffer, &"a".repeat(PARTIAL_OUTPUT_BYTES));
+        push_tail(&mut buffer, "end");
+        assert_eq!(buffer.len(), PARTIAL_OUTPUT_BYTES);
+        assert!(buffer.ends_with("a\nend"));
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_handler_with_cancelled_failure() {
+        let registry = Arc::new(ToolRegistry::default());
+        registry.register(
+            "wait",
+            Arc::new(|_invocation, sink| {
+                Box::pin(async move {
+                    sink.cancelled().await;
+                    ToolOutput::failure(vec!["stopped".to_string()])
+                })
+            }),
+        );
+
+        let runner = ToolRunner::new(registry, 1);
+        let cancel = CancellationToken::new();
+        let mut frames = runner.stream(
+            "session-1",
+            ToolInvocation {
+                name: "wait".to_string(),
+                args: serde_json::json!({}),
+                timeout_ms: None,
+            },
+            cancel.clone(),
+        );
+
+        assert!(matches!(
+            frames.next().await,
+            Some(EventKind::ToolStarted { .. })
+        ));
+        cancel.cancel();
+        let rest: Vec<EventKind> = frames.collect().await;
+        assert!(matches!(
+            rest.as_slice(),
+            [EventKind::ToolFailed { error, .. }] if error == "cancelled"
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_args_that_fail_schema() {
+        let registry = Arc::new(ToolRegistry::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        registry
+            .register_with_spec(
+                "echo",
+                ToolSpec::new(
+                    "Echo a message",
+                    serde_json::json!({
+                        "type": "object",
+                        "properties": {"msg": {"type": "string"}},
+                        "required": ["msg"]
+                    }),
+                ),
+                Arc::new(move |_invocation, _sink| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Box::pin(async move { ToolOutput::success(vec!["ok".to_string()]) })
+                }),
+            )
+            .expect("schema");
+        registry.register_alias("say", "echo");
+
+        let runner = ToolRunner::new(registry.clone(), 1);
+        let mut seq = 0;
+        let events = runner
+            .run(
+                "session-1",
+                &mut seq,
+                ToolInvocation {
+                    name: "say".to_string(),
+                    args: serde_json::json!({"msg": 42}),
+                    timeout_ms: None,
+                },
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert!(matches!(
+            &events[1].kind,
+            EventKind::ToolStderr { chunk, .. } if chunk.starts_with("invalid args: /msg")
+        ));
+        assert!(matches!(
+            events[2].kind,
+            EventKind::ToolEnded { exit_code: 2, .. }
+        ));
+        assert!(registry
+            .validate("echo", &serde_json::json!({"msg": "hi"}))
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_schema_is_rejected_at_registration() {
+        let registry = ToolRegistry::default();
+        let noop: ToolHandler =
+            Arc::new(|_invocation, _sink| Box::pin(async move { ToolOutput::success(Vec::new()) }));
+        let err = registry
+            .register_with_spec(
+                "broken",
+                ToolSpec::new(
+                    "Broken schema",
+                    serde_json::json!({"type": "object", "properties": {"n": {"type": 7}}}),
+                ),
+                noop,
+            )
+            .expect_err("invalid schema");
+        assert!(err.contains("`broken`"), "{err}");
+        assert!(registry.spec("broken").is_none());
+        assert!(registry.function_tools().is_empty());
+    }
+
+    #[test]
+    fn exports_function_tools_sorted() {
+        let registry = Arc::new(ToolRegistry::default());
+        let noop: ToolHandler =
+            Arc::new(|_invocation, _sink| Box::pin(async move { ToolOutput::success(Vec::new()) }));
+        registry.register("zeta", noop.clone());
+        registry
+            .register_with_spec(
+                "alpha",
+                ToolSpec::new("First tool", serde_json::json!({"type": "object"})),
+                noop,
+            )
+            .expect("schema");
+        registry.register_alias("beta", "alpha");
+
+        let runner = ToolRunner::new(registry, 1);
+        assert_eq!(
+            runner.tools(),
+            vec![
+                serde_json::json!({
+                    "type": "function",
+                    "name": "alpha",
+                    "description": "First tool",
+                    "parameters": {"type": "object"}
+                }),
+                serde_json::json!({"type": "function", "name": "zeta"}),
+            ]
+        );
+    }
 }
@@ -0,0 +1,501 @@
+//! Linux sandbox for shell tools.
+//!
+//! A sandboxed command starts in fresh user, mount and PID namespaces (plus a
+//! network namespace unless the profile allows network). The whole mount tree
+//! is made read-only except `workspace_root` and the profile's
+//! `writable_paths`, and a seccomp filter makes namespace, mount, tracing and
+//! kernel-administration syscalls fail with `EPERM`; `clone` is only refused
+//! when it asks for a new namespace, and `clone3`, whose flags a filter cannot
+//! read, fails with `ENOSYS` so libc falls back to `clone`. Everything is set
+//! up in the forked child before `exec`, so nothing is left behind when it
+//! exits.
+//!
+//! Entering a PID namespace only moves later children, so the forked child
+//! forks once more: the command runs as PID 1 of the namespace and the
+//! process in between waits for it and exits with its status.
+
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+/// Sandbox applied to `bash`, persistent shells and background jobs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SandboxProfile {
+    /// Keep the host network; otherwise commands only see an isolated
+    /// loopback-less network namespace.
+    pub network: bool,
+    /// Paths, besides `workspace_root`, that stay writable.
+    pub writable_paths: Vec<PathBuf>,
+    /// Install the seccomp filter (on by default).
+    pub seccomp: bool,
+}
+
+impl Default for SandboxProfile {
+    fn default() -> Self {
+        Self {
+            network: false,
+            writable_paths: Vec::new(),
+            seccomp: true,
+        }
+    }
+}
+
+impl SandboxProfile {
+    /// Built-in profiles: `workspace-write` and `workspace-write-network`.
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "workspace-write" => Some(Self::default()),
+            "workspace-write-network" => Some(Self {
+                network: true,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Arranges for `cmd` to enter `profile` before it execs; `cwd` must be the
+/// command's working directory. `nproc` caps the processes of the sandboxed
+/// tree with `RLIMIT_NPROC`, set inside the new user namespace.
+pub(crate) fn confine(
+    cmd: &mut Command,
+    profile: &SandboxProfile,
+    workspace_root: &Path,
+    cwd: &Path,
+    nproc: Option<u64>,
+) -> Result<(), String> {
+    imp::confine(cmd, profile, workspace_root, cwd, nproc)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use tokio::process::Command;
+
+    use super::SandboxProfile;
+
+    const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x04;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// `clone` flags that fail with `EPERM` inside the sandbox.
+    const NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWNS
+        | libc::CLONE_NEWUSER
+        | libc::CLONE_NEWPID
+        | libc::CLONE_NEWNET
+        | libc::CLONE_NEWUTS
+        | libc::CLONE_NEWIPC
+        | libc::CLONE_NEWCGROUP;
+
+    /// Syscalls that fail with `EPERM` inside the sandbox.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_open_tree,
+        libc::SYS_move_mount,
+        libc::SYS_mount_setattr,
+        libc::SYS_fsopen,
+        libc::SYS_fsconfig,
+        libc::SYS_fsmount,
+        libc::SYS_fspick,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_reboot,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_userfaultfd,
+    ];
+
+    /// Everything the child needs, allocated before fork.
+    struct Prepared {
+        flags: libc::c_int,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        writable: Vec<CString>,
+        trees: Vec<libc::c_int>,
+        cwd: CString,
+        nproc: Option<libc::rlimit>,
+        filter: Option<Vec<libc::sock_filter>>,
+    }
+
+    pub(super) fn confine(
+        cmd: &mut Command,
+        profile: &SandboxProfile,
+        workspace_root: &Path,
+        cwd: &Path,
+        nproc: Option<u64>,
+    ) -> Result<(), String> {
+        let mut writable = Vec::new();
+        for path in std::iter::once(workspace_root)
+            .chain(profile.writable_paths.iter().map(|p| p.as_path()))
+        {
+            let path = path
+                .canonicalize()
+                .map_err(|err| format!("writable path {}: {err}", path.display()))?;
+            writable.push(c_path(&path)?);
+        }
+        let cwd = c_path(&cwd.canonicalize().map_err(|err| format!("cwd: {err}"))?)?;
+
+        let mut flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+        if !profile.network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        // SAFETY: getuid/getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut prepared = Prepared {
+            flags,
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            trees: vec![-1; writable.len()],
+            writable,
+            cwd,
+            nproc: nproc.map(|max| libc::rlimit {
+                rlim_cur: max as libc::rlim_t,
+                rlim_max: max as libc::rlim_t,
+            }),
+            filter: if profile.seccomp {
+                Some(seccomp_filter()?)
+            } else {
+                None
+            },
+        };
+        // SAFETY: `enter` only makes raw syscalls on memory prepared above;
+        // it does not allocate or take locks.
+        unsafe {
+            cmd.pre_exec(move || enter(&mut prepared));
+        }
+        Ok(())
+    }
+
+    fn c_path(path: &Path) -> Result<CString, String> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| format!("path contains a NUL byte: {}", path.display()))
+    }
+
+    fn check(ret: libc::c_long) -> io::Result<libc::c_long> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Runs in the forked child: namespaces, read-only mounts, seccomp.
+    fn enter(prepared: &mut Prepared) -> io::Result<()> {
+        // SAFETY: plain syscalls with pointers to live, NUL-terminated data.
+        unsafe {
+            check(libc::unshare(prepared.flags) as libc::c_long)?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &prepared.uid_map)?;
+            write_file(c"/proc/self/gid_map", &prepared.gid_map)?;
+            // Counted per user namespace, so only this tree's processes.
+            if let Some(limit) = &prepared.nproc {
+                check(libc::setrlimit(libc::RLIMIT_NPROC, limit) as libc::c_long)?;
+            }
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ) as libc::c_long)?;
+
+            match check(libc::fork() as libc::c_long)? {
+                0 => {
+                    // Do not outlive the process that reports our status.
+                    check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) as libc::c_long)?;
+                }
+                pid => relay_exit(pid as libc::pid_t),
+            }
+            // A fresh /proc shows only this namespace's processes. Hosts
+            // that forbid it (e.g. a container with masked /proc paths)
+            // keep the old one, which no longer matches our PIDs.
+            libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            );
+
+            // Detached copies of the writable trees are not touched by the
+            // recursive read-only flag and get mounted back on top.
+            for (path, tree) in prepared.writable.iter().zip(prepared.trees.iter_mut()) {
+                *tree = check(libc::syscall(
+                    libc::SYS_open_tree,
+                    libc::AT_FDCWD,
+                    path.as_ptr(),
+                    libc::OPEN_TREE_CLONE
+                        | libc::O_CLOEXEC as libc::c_uint
+                        | libc::AT_RECURSIVE as libc::c_uint,
+                ))? as libc::c_int;
+            }
+            let attr = libc::mount_attr {
+                attr_set: libc::MOUNT_ATTR_RDONLY,
+                attr_clr: 0,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            check(libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                c"/".as_ptr(),
+                libc::AT_RECURSIVE as libc::c_uint,
+                &attr as *const libc::mount_attr,
+                std::mem::size_of::<libc::mount_attr>(),
+            ))?;
+            for (path, tree) in prepared.writable.iter().zip(prepared.trees.iter()) {
+                check(libc::syscall(
+                    libc::SYS_move_mount,
+                    *tree,
+                    c"".as_ptr(),
+                    libc::AT_FDCWD,
+                    path.as_ptr(),
+                    MOVE_MOUNT_F_EMPTY_PATH,
+                ))?;
+                libc::close(*tree);
+            }
+            // The old working directory still points below the mounts that
+            // were just covered.
+            check(libc::chdir(prepared.cwd.as_ptr()) as libc::c_long)?;
+
+            if let Some(filter) = &prepared.filter {
+                check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) as libc::c_long)?;
+                let program = libc::sock_fprog {
+                    len: filter.len() as libc::c_ushort,
+                    filter: filter.as_ptr() as *mut libc::sock_filter,
+                };
+                check(libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ) as libc::c_long)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs in the process between the forked child and the command: waits
+    /// for `pid` and exits the same way it did.
+    unsafe fn relay_exit(pid: libc::pid_t) -> ! {
+        // Only the command may hold the stdio pipes and the pipe `spawn`
+        // waits on for `exec`.
+        libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0);
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                libc::_exit(1);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            libc::signal(signal, libc::SIG_DFL);
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, signal);
+            libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+            libc::raise(signal);
+            libc::_exit(128 + signal);
+        }
+        libc::_exit(libc::WEXITSTATUS(status))
+    }
+
+    unsafe fn write_file(path: &std::ffi::CStr, data: &[u8]) -> io::Result<()> {
+        let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) as libc::c_long)?
+            as libc::c_int;
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        let result = if written == data.len() as isize {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        };
+        libc::close(fd);
+        result
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn seccomp_filter() -> Result<Vec<libc::sock_filter>, String> {
+        const ARCH_OFFSET: u32 = 4;
+        const NR_OFFSET: u32 = 0;
+        // Low half of `args[0]`, which holds the flags of `clone`.
+        const ARG0_OFFSET: u32 = 16;
+        // x32 syscalls share numbers with x86_64 ones but set this bit.
+        const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+        let stmt = |code: u32, k: u32| libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        };
+        let ret = |k: u32| stmt(libc::BPF_RET | libc::BPF_K, k);
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let denied = DENIED_SYSCALLS.len();
+
+        // Layout after the syscall checks, counted back from the end:
+        // ENOSYS, load flags, flag test, ALLOW, EPERM.
+        let mut filter = vec![
+            stmt(load, ARCH_OFFSET),
+            jump(AUDIT_ARCH, 1, 0),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(load, NR_OFFSET),
+            libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+                jt: (denied + 7) as u8,
+                jf: 0,
+                k: X32_SYSCALL_BIT,
+            },
+        ];
+        for (idx, nr) in DENIED_SYSCALLS.iter().enumerate() {
+            filter.push(jump(*nr as u32, (denied - idx + 6) as u8, 0));
+        }
+        filter.push(jump(libc::SYS_clone3 as u32, 2, 0));
+        filter.push(jump(libc::SYS_clone as u32, 2, 0));
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+        filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+        filter.push(stmt(load, ARG0_OFFSET));
+        filter.push(libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K) as u16,
+            jt: 1,
+            jf: 0,
+            k: NAMESPACE_FLAGS as u32,
+        });
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+        filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+        Ok(filter)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn seccomp_filter() -> Result<Vec<libc::sock_filter>, String> {
+        Err("seccomp filter is not available on this architecture".to_string())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::path::Path;
+
+    use tokio::process::Command;
+
+    use super::SandboxProfile;
+
+    pub(super) fn confine(
+        _cmd: &mut Command,
+        _profile: &SandboxProfile,
+        _workspace_root: &Path,
+        _cwd: &Path,
+        _nproc: Option<u64>,
+    ) -> Result<(), String> {
+        Err("sandbox profiles require Linux".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_profiles() {
+        assert_eq!(
+            SandboxProfile::named("workspace-write"),
+            Some(SandboxProfile::default())
+        );
+        assert!(SandboxProfile::named("workspace-write-network").is_some_and(|p| p.network));
+        assert_eq!(SandboxProfile::named("nope"), None);
+    }
+
+    /// Evaluates the subset of classic BPF the filter uses.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn run_filter(filter: &[libc::sock_filter], arch: u32, nr: u32, arg0: u32) -> u32 {
+        let (mut pc, mut acc) = (0, 0);
+        loop {
+            let insn = filter[pc];
+            let code = u32::from(insn.code);
+            pc += 1;
+            if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS {
+                acc = match insn.k {
+                    0 => nr,
+                    4 => arch,
+                    16 => arg0,
+                    offset => panic!("unexpected load at {offset}"),
+                };
+            } else if code == libc::BPF_RET | libc::BPF_K {
+                return insn.k;
+            } else {
+                let taken = match code {
+                    c if c == libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K => acc == insn.k,
+                    c if c == libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K => acc >= insn.k,
+                    c if c == libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K => acc & insn.k != 0,
+                    c => panic!("unexpected instruction {c:#x}"),
+                };
+                pc += usize::from(if taken { insn.jt } else { insn.jf });
+            }
+        }
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn seccomp_filter_decisions() {
+        let filter = imp::seccomp_filter().expect("filter");
+        let arch = run_filter(&filter, 0, 0, 0);
+        assert_eq!(arch, libc::SECCOMP_RET_KILL_PROCESS);
+        let native = filter[1].k;
+        let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let run = |nr: libc::c_long, arg0: libc::c_int| {
+            run_filter(&filter, native, nr as u32, arg0 as u32)
+        };
+
+        assert_eq!(run(libc::SYS_getpid, 0), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(run(libc::SYS_mount, 0), eperm);
+        assert_eq!(run(libc::SYS_userfaultfd, 0), eperm);
+        assert_eq!(run(libc::SYS_unshare, 0), eperm);
+        assert_eq!(run(0x4000_0000 | libc::SYS_getpid, 0), eperm);
+        assert_eq!(
+            run(libc::SYS_clone3, 0),
+            libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32
+        );
+        let fork = libc::SIGCHLD | libc::CLONE_CHILD_SETTID | libc::CLONE_CHILD_CLEARTID;
+        assert_eq!(run(libc::SYS_clone, fork), libc::SECCOMP_RET_ALLOW);
+        let thread = libc::CLONE_VM | libc::CLONE_FS | libc::CLONE_FILES | libc::CLONE_THREAD;
+        assert_eq!(run(libc::SYS_clone, thread), libc::SECCOMP_RET_ALLOW);
+        for flag in [libc::CLONE_NEWUSER, libc::CLONE_NEWNS, libc::CLONE_NEWPID] {
+            assert_eq!(run(libc::SYS_clone, fork | flag), eperm);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Long-lived shells for `bash` when `BuiltinToolConfig::persistent_shell`
+//! is set.
+//!
+//! One shell runs per session. Each command is fed through stdin wrapped in
+//! `eval`, followed by `printf`s of a per-shell sentinel on stdout (with the
+//! exit status) and on stderr, so output framing never depends on EOF and
+//! `cd`/`export` carry over to the next command. A shell that exits or is
+//! stopped by a timeout or cancellation is dropped and the next command
+//! starts a fresh one.
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::limits::{ExitOutcome, Limit, LimitGuard};
+use crate::process::{ProcessGroup, TERMINATE_GRACE};
+use crate::ToolSink;
+
+/// Shells idle for longer than this are stopped on the next lookup.
+const SHELL_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+
+pub(crate) type ShellSlot = Arc<AsyncMutex<Option<PersistentShell>>>;
+
+#[derive(Default)]
+pub(crate) struct ShellSessions {
+    shells: Mutex<HashMap<String, ShellSlot>>,
+}
+
+impl ShellSessions {
+    /// The shell slot for `session_id`; holding its lock serializes commands.
+    ///
+    /// Idle shells are evicted here, but only from slots no caller holds: a
+    /// slot handed out and not yet locked must stay the one shared by every
+    /// command of its session.
+    pub(crate) fn slot(&self, session_id: &str) -> ShellSlot {
+        let mut shells = self.shells.lock().expect("shell sessions mutex");
+        shells.retain(|_, slot| {
+            if Arc::strong_count(slot) > 1 {
+                return true;
+            }
+            match slot.try_lock() {
+                Ok(shell) => shell
+                    .as_ref()
+                    .is_some_and(|shell| shell.last_used.elapsed() < SHELL_IDLE_TTL),
+                Err(_) => true,
+            }
+        });
+        shells.entry(session_id.to_string()).or_default().clone()
+    }
+
+    /// Forgets the shell of `session_id`. An idle shell is killed as its
+    /// slot drops; one running a command goes once that call is cancelled.
+    pub(crate) fn end_session(&self, session_id: &str) {
+        let slot = self
+            .shells
+            .lock()
+            .expect("shell sessions mutex")
+            .remove(session_id);
+        drop(slot);
+    }
+}
+
+pub(crate) enum CommandOutcome {
+    Exited(i32),
+    /// The shell itself exited (e.g. `exit 3`).
+    ShellExited(Option<i32>),
+    /// The shell was stopped because the call was cancelled or timed out.
+    Terminated(&'static str),
+    /// The command broke a resource limit; `stopped` is the signal sent
+    /// when the whole shell had to be stopped for it.
+    LimitExceeded {
+        limit: Limit,
+        stopped: Option<&'static str>,
+    },
+    Failed(std::io::Error),
+}
+
+pub(crate) struct PersistentShell {
+    child: Child,
+    group: ProcessGroup,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: BufReader<ChildStderr>,
+    sentinel: String,
+    last_used: Instant,
+    limits: LimitGuard,
+}
+
+impl PersistentShell {
+    /// Spawns `cmd` (program, args and working directory already set).
+    pub(crate) fn spawn(mut cmd: Command, limits: LimitGuard) -> std::io::Result<Self> {
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        ProcessGroup::configure(&mut cmd);
+        let mut child = cmd.spawn()?;
+        let group = ProcessGroup::new(&child);
+        Ok(Self {
+            stdin: child.stdin.take().expect("piped stdin"),
+            stdout: BufReader::new(child.stdout.take().expect("piped stdout")),
+            stderr: BufReader::new(child.stderr.take().expect("piped stderr")),
+            child,
+            group,
+            sentinel: format!("__RIP_DONE_{}__", uuid::Uuid::new_v4().simple()),
+            last_used: Instant::now(),
+            limits,
+        })
+    }
+
+    /// Runs `prelude` (trusted setup such as `cd`/`export`) and then
+    /// `command`, passing each output line to `stdout`/`stderr`.
+    pub(crate) async fn run(
+        &mut self,
+        prelude: &str,
+        command: &str,
+        sink: &ToolSink,
+        mut stdout: impl FnMut(String),
+        mut stderr: impl FnMut(String),
+    ) -> CommandOutcome {
+        self.last_used = Instant::now();
+        let Self {
+            child,
+            group,
+            stdin,
+            stdout: out,
+            stderr: err,
+            sentinel,
+            limits,
+            ..
+        } = self;
+        let pid = child.id();
+
+        let script = format!(
+            "{prelude}eval {} < /dev/null\n\
+             __rip_status=$?\n\
+             printf '%s %d\\n' '{sentinel}' \"$__rip_status\"\n\
+             printf '%s\\n' '{sentinel}' >&2\n",
+            shell_quote(command)
+        );
+        if let Err(err) = write_script(stdin, &script).await {
+            return match child.try_wait() {
+                Ok(Some(status)) => CommandOutcome::ShellExited(status.code()),
+                _ => CommandOutcome::Failed(err),
+            };
+        }
+
+        let reading = async {
+            tokio::join!(
+                read_until_sentinel(out, sentinel, &mut stdout),
+                read_until_sentinel(err, sentinel, &mut stderr),
+            )
+        };
+        let outcome = tokio::select! {
+            (status, _) = reading => match status {
+                Ok(Some(status)) => {
+                    let code = status.parse().unwrap_or(1);
+                    // The shell survived, so only a cgroup can report a hit.
+                    match limits.classify(ExitOutcome::Exited) {
+                        Some(limit) => CommandOutcome::LimitExceeded { limit, stopped: None },
+                        None => CommandOutcome::Exited(code),
+                    }
+                }
+                Ok(None) => CommandOutcome::ShellExited(
+                    child.wait().await.ok().and_then(|status| status.code()),
+                ),
+                Err(err) => CommandOutcome::Failed(err),
+            },
+            limit = limits.exceeded(pid) => CommandOutcome::LimitExceeded {
+                limit,
+                stopped: Some(group.terminate(child, TERMINATE_GRACE).await),
+            },
+            _ = sink.cancelled() => {
+                CommandOutcome::Terminated(group.terminate(child, TERMINATE_GRACE).await)
+            }
+        };
+        self.last_used = Instant::now();
+        outcome
+    }
+}
+
+async fn write_script(stdin: &mut ChildStdin, script: &str) -> std::io::Result<()> {
+    stdin.write_all(script.as_bytes()).await?;
+    stdin.flush().await
+}
+
+/// Forwards lines until one contains `sentinel`; returns the text after it,
+/// or `None` when the stream ends first.
+async fn read_until_sentinel<R>(
+    reader: &mut BufReader<R>,
+    sentinel: &str,
+    emit: &mut impl FnMut(String),
+) -> std::io::Result<Option<String>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer).await? == 0 {
+            return Ok(None);
+        }
+        let text = String::from_utf8_lossy(&buffer);
+        let line = text.trim_end_matches(['\r', '\n']);
+        if let Some(pos) = line.find(sentinel) {
+            // Output without a trailing newline shares the sentinel's line.
+            if pos > 0 {
+                emit(line[..pos].to_string());
+            }
+            return Ok(Some(line[pos + sentinel.len()..].trim().to_string()));
+        }
+        emit(line.to_string());
+    }
+}
+
+/// `value` as a single-quoted shell word.
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+pub(crate) fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn env_names_are_identifiers() {
+        assert!(is_env_name("PATH"));
+        assert!(is_env_name("_x1"));
+        assert!(!is_env_name("1X"));
+        assert!(!is_env_name("A-B"));
+        assert!(!is_env_name(""));
+    }
+
+    #[test]
+    fn held_slots_survive_eviction() {
+        let sessions = ShellSessions::default();
+        let held = sessions.slot("a");
+        sessions.slot("b");
+        // "a" is still held (its shell not spawned yet) so it is kept; the
+        // released empty slot for "b" is evicted.
+        assert!(Arc::ptr_eq(&held, &sessions.slot("a")));
+        let shells = sessions.shells.lock().expect("shell sessions mutex");
+        assert!(!shells.contains_key("b"));
+    }
+
+    #[tokio::test]
+    async fn read_until_sentinel_splits_unterminated_output() {
+        let mut reader = BufReader::new(&b"one\ntwo__S__ 3\nrest\n"[..]);
+        let mut lines = Vec::new();
+        let status = read_until_sentinel(&mut reader, "__S__", &mut |line| lines.push(line))
+            .await
+            .expect("read");
+        assert_eq!(status.as_deref(), Some("3"));
+        assert_eq!(lines, vec!["one".to_string(), "two".to_string()]);
+    }
+}
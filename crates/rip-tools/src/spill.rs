@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::resolve::resolve;
+use crate::ToolSink;
+
+/// Directory, under the workspace root, that holds spilled tool output.
+pub(crate) const SPILL_DIR: &str = ".rip/tool-output";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputStream {
+    Stdout = 0,
+    Stderr = 1,
+}
+
+/// Output budget shared by a tool's stdout and stderr.
+///
+/// Each stream forwards lines until half of `max_bytes` is used. Past that,
+/// the combined output (from the first line on) is written to
+/// `.rip/tool-output/{session}/{tool_id}.log` and only the last half of the
+/// budget is kept, to be emitted by `finish` after a marker naming the file.
+pub(crate) struct OutputSpill {
+    head_bytes: usize,
+    tail_bytes: usize,
+    workspace_root: PathBuf,
+    display: String,
+    state: Mutex<SpillState>,
+}
+
+#[derive(Default)]
+struct SpillState {
+    /// Combined output seen before the first overflow.
+    pending: Vec<u8>,
+    file: Option<Result<File, String>>,
+    total_bytes: usize,
+    streams: [StreamBudget; 2],
+}
+
+#[derive(Default)]
+struct StreamBudget {
+    used: usize,
+    overflowed: bool,
+    tail: VecDeque<String>,
+    tail_used: usize,
+    omitted: usize,
+}
+
+impl OutputSpill {
+    pub(crate) fn new(workspace_root: &Path, sink: &ToolSink, max_bytes: usize) -> Self {
+        let session = path_segment(sink.session_id(), "local");
+        let tool_id = match sink.tool_id() {
+            "" => uuid::Uuid::new_v4().to_string(),
+            id => path_segment(id, "tool"),
+        };
+        let display = format!("{SPILL_DIR}/{session}/{tool_id}.log");
+        let head_bytes = max_bytes / 2;
+        Self {
+            head_bytes,
+            tail_bytes: max_bytes - head_bytes,
+            workspace_root: workspace_root.to_path_buf(),
+            display,
+            state: Mutex::new(SpillState::default()),
+        }
+    }
+
+    /// Records a line; returns it when it fits the head budget and should be
+    /// emitted right away.
+    pub(crate) fn push(&self, stream: OutputStream, line: String) -> Option<String> {
+        let mut state = self.state.lock().expect("spill mutex");
+        let SpillState {
+            pending,
+            file,
+            total_bytes,
+            streams,
+        } = &mut *state;
+        let size = line.len() + 1;
+        *total_bytes += size;
+        match file {
+            Some(Ok(file)) => {
+                let _ = writeln!(file, "{line}");
+            }
+            Some(Err(_)) => {}
+            None => {
+                pending.extend_from_slice(line.as_bytes());
+                pending.push(b'\n');
+            }
+        }
+
+        let budget = &mut streams[stream as usize];
+        if !budget.overflowed && budget.used + size <= self.head_bytes {
+            budget.used += size;
+            return Some(line);
+        }
+        budget.overflowed = true;
+        if file.is_none() {
+            *file = Some(self.open(pending));
+            pending.clear();
+        }
+
+        let line = keep_suffix(line, self.tail_bytes.saturating_sub(1));
+        budget.omitted += size - (line.len() + 1);
+        budget.tail_used += line.len() + 1;
+        budget.tail.push_back(line);
+        while budget.tail_used > self.tail_bytes {
+            let Some(dropped) = budget.tail.pop_front() else {
+                break;
+            };
+            budget.tail_used -= dropped.len() + 1;
+            budget.omitted += dropped.len() + 1;
+        }
+        None
+    }
+
+    /// Emits the kept tails; returns artifacts naming the spill file when
+    /// either stream overflowed.
+    pub(crate) fn finish(&self, sink: &ToolSink) -> Option<Value> {
+        let mut state = self.state.lock().expect("spill mutex");
+        let SpillState {
+            file,
+            total_bytes,
+            streams,
+            ..
+        } = &mut *state;
+        let file = file.as_mut()?;
+        if let Ok(file) = file {
+            let _ = file.flush();
+        }
+
+        for (idx, budget) in streams.iter_mut().enumerate() {
+            if !budget.overflowed {
+                continue;
+            }
+            let emit = |line: String| match idx {
+                0 => sink.stdout(line),
+                _ => sink.stderr(line),
+            };
+            if budget.omitted > 0 {
+                emit(match &file {
+                    Ok(_) => format!(
+                        "[... {} bytes omitted; full output in {} ...]",
+                        budget.omitted, self.display
+                    ),
+                    Err(err) => format!(
+                        "[... {} bytes omitted; spill to {} failed: {err} ...]",
+                        budget.omitted, self.display
+                    ),
+                });
+            }
+            for line in budget.tail.drain(..) {
+                emit(line);
+            }
+        }
+
+        Some(match file {
+            Ok(_) => json!({ "spill_path": self.display, "total_bytes": *total_bytes }),
+            Err(err) => json!({ "spill_error": err, "total_bytes": *total_bytes }),
+        })
+    }
+
+    /// Creates the spill file through the workspace resolver, so a command
+    /// that swaps `.rip` (or the file) for a symlink cannot redirect it.
+    fn open(&self, pending: &[u8]) -> Result<File, String> {
+        let mut file = resolve(&self.workspace_root, &self.display)?
+            .create_new()
+            .map_err(|err| err.to_string())?;
+        file.write_all(pending).map_err(|err| err.to_string())?;
+        Ok(file)
+    }
+}
+
+/// `raw` reduced to characters that are safe in a single path segment.
+fn path_segment(raw: &str, fallback: &str) -> String {
+    let cleaned: String = raw
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if cleaned.is_empty() {
+        fallback.to_string()
+    } else {
+        cleaned
+    }
+}
+
+fn keep_suffix(line: String, max_bytes: usize) -> String {
+    if line.len() <= max_bytes {
+        return line;
+    }
+    let mut start = line.len() - max_bytes;
+    while !line.is_char_boundary(start) {
+        start += 1;
+    }
+    line[start..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rip_kernel::EventKind;
+    use tokio::sync::mpsc;
+
+    fn drain(receiver: &mut mpsc::UnboundedReceiver<EventKind>) -> Vec<(usize, String)> {
+        let mut out = Vec::new();
+        while let Ok(kind) = receiver.try_recv() {
+            match kind {
+                EventKind::ToolStdout { chunk, .. } => out.push((0, chunk)),
+                EventKind::ToolStderr { chunk, .. } => out.push((1, chunk)),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn small_output_is_not_spilled() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let sink = ToolSink::new("t1", sender);
+        let spill = OutputSpill::new(dir.path(), &sink, 64);
+
+        assert_eq!(
+            spill.push(OutputStream::Stdout, "hello".to_string()),
+            Some("hello".to_string())
+        );
+        assert!(spill.finish(&sink).is_none());
+        assert!(!dir.path().join(SPILL_DIR).exists());
+    }
+
+    #[test]
+    fn overflow_spills_everything_and_keeps_head_and_tail() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let sink = ToolSink::new("t1", sender);
+        let spill = OutputSpill::new(dir.path(), &sink, 16);
+
+        let mut emitted = Vec::new();
+        for idx in 0..10 {
+            let stream = if idx == 4 {
+                OutputStream::Stderr
+            } else {
+                OutputStream::Stdout
+            };
+            if let Some(line) = spill.push(stream, format!("line{idx}")) {
+                emitted.push(line);
+            }
+        }
+        assert_eq!(emitted, vec!["line0", "line4"]);
+
+        let artifacts = spill.finish(&sink).expect("spilled");
+        assert_eq!(artifacts["spill_path"], ".rip/tool-output/local/t1.log");
+        assert_eq!(artifacts["total_bytes"], 60);
+        assert_eq!(
+            drain(&mut receiver),
+            vec![
+                (
+                    0,
+                    "[... 42 bytes omitted; full output in .rip/tool-output/local/t1.log ...]"
+                        .to_string()
+                ),
+                (0, "line9".to_string()),
+            ]
+        );
+        let log = std::fs::read_to_string(dir.path().join(".rip/tool-output/local/t1.log"))
+            .expect("spill file");
+        let expected: Vec<String> = (0..10).map(|idx| format!("line{idx}\n")).collect();
+        assert_eq!(log, expected.concat());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spills_never_follow_links_out_of_the_workspace() {
+        let dir = tempfile::tempdir().expect("tmp");
+        let outside = tempfile::tempdir().expect("outside");
+        let root = dir.path();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let sink = ToolSink::new("t1", sender);
+
+        std::os::unix::fs::symlink(outside.path(), root.join(".rip")).expect("link");
+        let spill = OutputSpill::new(root, &sink, 4);
+        spill.push(OutputStream::Stdout, "overflowing".to_string());
+        let artifacts = spill.finish(&sink).expect("overflowed");
+        assert!(artifacts["spill_error"].is_string(), "{artifacts}");
+        assert!(std::fs::read_dir(outside.path())
+            .expect("outside")
+            .next()
+            .is_none());
+
+        std::fs::remove_file(root.join(".rip")).expect("unlink");
+        let target = outside.path().join("target.log");
+        std::fs::write(&target, "keep").expect("write");
+        std::fs::create_dir_all(root.join(".rip/tool-output/local")).expect("mkdir");
+        std::os::unix::fs::symlink(&target, root.join(".rip/tool-output/local/t1.log"))
+            .expect("link");
+        let spill = OutputSpill::new(root, &sink, 4);
+        spill.push(OutputStream::Stdout, "overflowing".to_string());
+        let artifacts = spill.finish(&sink).expect("overflowed");
+        assert!(artifacts["spill_error"].is_string(), "{artifacts}");
+        assert_eq!(std::fs::read_to_string(&target).expect("read"), "keep");
+    }
+
+    #[test]
+    fn path_segments_are_sanitized() {
+        assert_eq!(path_segment("../etc", "x"), "___etc");
+        assert_eq!(path_segment("", "local"), "local");
+    }
+}
@@ -1,9 +1,17 @@
+use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs;
 use std::path::Path;
-use std::sync::OnceLock;
-
-use rip_tools::{register_builtin_tools, BuiltinToolConfig, ToolInvocation, ToolRegistry};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use rip_kernel::{CancellationToken, EventKind, ToolCall, ToolDispatcher};
+use rip_tools::{
+    invoke, register_builtin_tools, BuiltinToolConfig, PermissionConfig, PermissionDecision,
+    PermissionPolicy, ResourceLimits, SandboxProfile, ToolInvocation, ToolMode, ToolPermissions,
+    ToolRegistry, ToolRunner,
+};
 use serde_json::json;
 use tempfile::tempdir;
 use tokio::sync::Mutex;
@@ -11,17 +19,25 @@ use tokio::sync::Mutex;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-fn setup_registry(root: &Path) -> ToolRegistry {
-    let registry = ToolRegistry::default();
-    let config = BuiltinToolConfig {
+fn test_config(root: &Path) -> BuiltinToolConfig {
+    BuiltinToolConfig {
         workspace_root: root.to_path_buf(),
         max_bytes: 1024 * 1024,
         max_results: 100,
         max_depth: 16,
         follow_symlinks: false,
         include_hidden: false,
-    };
-    register_builtin_tools(&registry, config);
+        respect_ignore_files: false,
+        persistent_shell: false,
+        sandbox: None,
+        resource_limits: HashMap::new(),
+        default_timeout_ms: None,
+    }
+}
+
+fn setup_registry(root: &Path) -> ToolRegistry {
+    let registry = ToolRegistry::default();
+    register_builtin_tools(&registry, test_config(root));
     registry
 }
 
@@ -60,11 +76,14 @@ async fn read_respects_line_ranges() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "notes.txt", "start_line": 2, "end_line": 3}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "notes.txt", "start_line": 2, "end_line": 3}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -80,11 +99,14 @@ async fn read_respects_max_bytes() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "short.txt", "max_bytes": 3}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "short.txt", "max_bytes": 3}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -99,11 +121,14 @@ async fn read_rejects_invalid_range() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "bad.txt", "start_line": 3, "end_line": 2}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "bad.txt", "start_line": 3, "end_line": 2}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -117,11 +142,14 @@ async fn read_rejects_zero_end_line() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "bad.txt", "end_line": 0}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "bad.txt", "end_line": 0}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -135,11 +163,14 @@ async fn read_rejects_zero_line() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "bad.txt", "start_line": 0}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "bad.txt", "start_line": 0}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -152,11 +183,14 @@ async fn read_invalid_args() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -169,11 +203,14 @@ async fn read_rejects_parent_path() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "../nope.txt"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "../nope.txt"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -187,11 +224,14 @@ async fn read_stops_at_end_line() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "notes.txt", "end_line": 1}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "notes.txt", "end_line": 1}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.stdout[0], "one\n");
@@ -205,11 +245,14 @@ async fn read_rejects_absolute_path() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": abs.to_string_lossy()}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": abs.to_string_lossy()}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -222,11 +265,14 @@ async fn read_missing_file() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "missing.txt"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "missing.txt"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -240,11 +286,14 @@ async fn read_invalid_utf8_reports_failure() {
 
     let registry = setup_registry(root);
     let handler = registry.get("read").expect("read tool");
-    let output = handler(ToolInvocation {
-        name: "read".to_string(),
-        args: json!({"path": "bad.bin"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({"path": "bad.bin"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -258,19 +307,25 @@ async fn write_overwrites_and_appends() {
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
 
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "out.txt", "content": "hello"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "out.txt", "content": "hello"}),
+            timeout_ms: None,
+        },
+    )
     .await;
     assert_eq!(output.exit_code, 0);
 
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "out.txt", "content": " world", "append": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "out.txt", "content": " world", "append": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
     assert_eq!(output.exit_code, 0);
 
@@ -285,11 +340,14 @@ async fn write_non_atomic() {
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
 
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "plain.txt", "content": "hi", "atomic": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "plain.txt", "content": "hi", "atomic": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -304,11 +362,14 @@ async fn write_invalid_args() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -321,11 +382,14 @@ async fn write_append_requires_existing_file_when_create_false() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "missing.txt", "content": "hi", "append": true, "create": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "missing.txt", "content": "hi", "append": true, "create": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -338,18 +402,24 @@ async fn write_atomic_overwrites_existing_file() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "atomic.txt", "content": "first"}),
-        timeout_ms: None,
-    })
+    invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "atomic.txt", "content": "first"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "atomic.txt", "content": "second"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "atomic.txt", "content": "second"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -365,11 +435,14 @@ async fn write_non_atomic_directory_fails() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "dir", "content": "hi", "atomic": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "dir", "content": "hi", "atomic": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -383,11 +456,14 @@ async fn write_rejects_absolute_path() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": abs.to_string_lossy(), "content": "nope"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": abs.to_string_lossy(), "content": "nope"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -400,16 +476,226 @@ async fn write_rejects_parent_path() {
 
     let registry = setup_registry(root);
     let write = registry.get("write").expect("write tool");
-    let output = write(ToolInvocation {
-        name: "write".to_string(),
-        args: json!({"path": "../nope.txt", "content": "nope"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &write,
+        ToolInvocation {
+            name: "write".to_string(),
+            args: json!({"path": "../nope.txt", "content": "nope"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
 }
 
+#[cfg(unix)]
+async fn run_builtin(root: &Path, name: &str, args: serde_json::Value) -> rip_tools::ToolOutput {
+    let registry = setup_registry(root);
+    invoke(
+        &registry.get(name).expect("tool"),
+        ToolInvocation {
+            name: name.to_string(),
+            args,
+            timeout_ms: None,
+        },
+    )
+    .await
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn file_tools_reject_symlinks_out_of_the_workspace() {
+    let dir = tempdir().expect("tmp");
+    let outside = tempdir().expect("tmp");
+    let root = dir.path();
+    fs::write(outside.path().join("secret.txt"), "secret\n").expect("write");
+    std::os::unix::fs::symlink(outside.path(), root.join("out")).expect("symlink");
+    std::os::unix::fs::symlink(outside.path().join("secret.txt"), root.join("leak.txt"))
+        .expect("symlink");
+    fs::create_dir(root.join("sub")).expect("mkdir");
+    std::os::unix::fs::symlink("../../", root.join("sub/up")).expect("symlink");
+
+    let attempts = [
+        ("read", json!({ "path": "leak.txt" })),
+        ("read", json!({ "path": "out/secret.txt" })),
+        ("read", json!({ "path": "sub/up/secret.txt" })),
+        ("write", json!({ "path": "out/new.txt", "content": "x" })),
+        (
+            "write",
+            json!({ "path": "leak.txt", "content": "x", "atomic": false }),
+        ),
+        (
+            "edit",
+            json!({ "path": "leak.txt", "old_string": "secret", "new_string": "x" }),
+        ),
+        (
+            "apply_patch",
+            json!({ "patch": "*** Begin Patch\n*** Add File: out/new.txt\n+x\n*** End Patch\n" }),
+        ),
+        ("ls", json!({ "path": "out" })),
+        ("grep", json!({ "pattern": "secret", "path": "sub/up" })),
+        ("bash", json!({ "command": "pwd", "cwd": "out" })),
+    ];
+    for (name, args) in attempts {
+        let output = run_builtin(root, name, args.clone()).await;
+        assert_eq!(output.exit_code, 1, "{name} {args}");
+        assert!(
+            output.stderr[0].ends_with("path escapes workspace root"),
+            "{name} {args}: {:?}",
+            output.stderr
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(outside.path().join("secret.txt")).expect("read"),
+        "secret\n"
+    );
+    assert!(!outside.path().join("new.txt").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn file_tools_follow_symlinks_inside_the_workspace() {
+    let dir = tempdir().expect("tmp");
+    let root = dir.path();
+    fs::create_dir(root.join("real")).expect("mkdir");
+    fs::write(root.join("real/notes.txt"), "hello\n").expect("write");
+    std::os::unix::fs::symlink("real", root.join("alias")).expect("symlink");
+
+    let output = run_builtin(root, "read", json!({ "path": "alias/notes.txt" })).await;
+    assert_eq!(output.stdout, vec!["hello\n"]);
+    assert_eq!(
+        output.artifacts.as_ref().expect("artifacts")["path"],
+        "real/notes.txt"
+    );
+
+    let output = run_builtin(
+        root,
+        "edit",
+        json!({ "path": "alias/notes.txt", "old_string": "hello", "new_string": "bye" }),
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(
+        fs::read_to_string(root.join("real/notes.txt")).expect("read"),
+        "bye\n"
+    );
+    assert!(fs::symlink_metadata(root.join("alias"))
+        .expect("alias")
+        .file_type()
+        .is_symlink());
+}
+
+#[tokio::test]
+async fn file_tools_resolve_parent_chains_inside_the_workspace() {
+    let dir = tempdir().expect("tmp");
+    let root = dir.path();
+    fs::create_dir_all(root.join("a/b")).expect("mkdir");
+
+    let output = run_builtin(
+        root,
+        "write",
+        json!({ "path": "a/b/../../top.txt", "content": "top" }),
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.artifacts.expect("artifacts")["path"], "top.txt");
+    assert_eq!(
+        fs::read_to_string(root.join("top.txt")).expect("read"),
+        "top"
+    );
+
+    for path in [
+        "a/b/../../../top.txt",
+        "a/../../top.txt",
+        "missing/../../top.txt",
+    ] {
+        let output = run_builtin(root, "read", json!({ "path": path })).await;
+        assert_eq!(output.stderr, vec!["path escapes workspace root"], "{path}");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn write_never_modifies_a_hard_link_target_in_place() {
+    let dir = tempdir().expect("tmp");
+    let root = dir.path();
+    let outside = root.join("outside");
+    fs::create_dir(&outside).expect("mkdir");
+    let workspace = root.join("workspace");
+    fs::create_dir(&workspace).expect("mkdir");
+    fs::write(outside.join("shared.txt"), "original").expect("write");
+    fs::hard_link(outside.join("shared.txt"), workspace.join("linked.txt")).expect("link");
+
+    for args in [
+        json!({ "path": "linked.txt", "content": "x", "append": true }),
+        json!({ "path": "linked.txt", "content": "x", "atomic": false }),
+    ] {
+        let output = run_builtin(&workspace, "write", args).await;
+        assert_eq!(output.exit_code, 1);
+        assert!(
+            output.stderr[0].contains("hard links"),
+            "{:?}",
+            output.stderr
+        );
+    }
+
+    let output = run_builtin(
+        &workspace,
+        "write",
+        json!({ "path": "linked.txt", "content": "replaced" }),
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    let output = run_builtin(
+        &workspace,
+        "edit",
+        json!({ "path": "linked.txt", "old_string": "replaced", "new_string": "edited" }),
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(
+        fs::read_to_string(workspace.join("linked.txt")).expect("read"),
+        "edited"
+    );
+    assert_eq!(
+        fs::read_to_string(outside.join("shared.txt")).expect("read"),
+        "original"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn walkers_skip_followed_symlinks_that_leave_the_workspace() {
+    let dir = tempdir().expect("tmp");
+    let outside = tempdir().expect("tmp");
+    let root = dir.path();
+    fs::write(outside.path().join("secret.txt"), "needle\n").expect("write");
+    fs::create_dir(root.join("real")).expect("mkdir");
+    fs::write(root.join("real/notes.txt"), "needle\n").expect("write");
+    std::os::unix::fs::symlink(outside.path(), root.join("out")).expect("symlink");
+    std::os::unix::fs::symlink("real", root.join("alias")).expect("symlink");
+
+    let output = run_builtin(
+        root,
+        "grep",
+        json!({ "pattern": "needle", "follow_symlinks": true, "output_mode": "files_with_matches" }),
+    )
+    .await;
+    let mut files = output.stdout.clone();
+    files.sort();
+    assert_eq!(files, vec!["alias/notes.txt", "real/notes.txt"]);
+
+    let output = run_builtin(
+        root,
+        "ls",
+        json!({ "recursive": true, "follow_symlinks": true }),
+    )
+    .await;
+    assert!(output.stdout.iter().all(|path| !path.starts_with("out")));
+    assert!(output.stdout.contains(&"alias/notes.txt".to_string()));
+}
+
 #[tokio::test]
 async fn ls_lists_entries() {
     let dir = tempdir().expect("tmp");
@@ -421,22 +707,28 @@ async fn ls_lists_entries() {
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "recursive": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "recursive": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
     assert!(joined.contains("root.txt"));
     assert!(joined.contains("a"));
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "recursive": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "recursive": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -452,16 +744,19 @@ async fn ls_respects_include_exclude() {
 
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({
-            "path": ".",
-            "recursive": false,
-            "include": ["**/*.txt"],
-            "exclude": ["**/*.log"]
-        }),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({
+                "path": ".",
+                "recursive": false,
+                "include": ["**/*.txt"],
+                "exclude": ["**/*.log"]
+            }),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -477,11 +772,14 @@ async fn ls_includes_hidden_when_requested() {
 
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "include_hidden": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "include_hidden": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -495,11 +793,14 @@ async fn ls_rejects_invalid_glob() {
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "include": ["["]}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "include": ["["]}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -512,11 +813,14 @@ async fn ls_invalid_args() {
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -529,11 +833,14 @@ async fn ls_rejects_parent_path() {
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": "../"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": "../"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -546,11 +853,14 @@ async fn ls_rejects_invalid_exclude_glob() {
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
 
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "exclude": ["["]}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "exclude": ["["]}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -568,11 +878,14 @@ async fn ls_reports_unreadable_entries() {
 
     let registry = setup_registry(root);
     let ls = registry.get("ls").expect("ls tool");
-    let output = ls(ToolInvocation {
-        name: "ls".to_string(),
-        args: json!({"path": ".", "recursive": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &ls,
+        ToolInvocation {
+            name: "ls".to_string(),
+            args: json!({"path": ".", "recursive": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     fs::set_permissions(&locked, fs::Permissions::from_mode(0o700)).expect("chmod");
@@ -588,11 +901,14 @@ async fn grep_finds_matches() {
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
 
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "alpha", "path": ".", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "alpha", "path": ".", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -609,16 +925,19 @@ async fn grep_respects_max_results() {
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
 
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({
-            "pattern": "foo",
-            "path": ".",
-            "regex": false,
-            "max_results": 1
-        }),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({
+                "pattern": "foo",
+                "path": ".",
+                "regex": false,
+                "max_results": 1
+            }),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.stdout.len(), 1);
@@ -632,11 +951,14 @@ async fn grep_regex_enabled() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "al.*a", "path": ".", "regex": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "al.*a", "path": ".", "regex": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -651,11 +973,14 @@ async fn grep_skips_binary() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "foo", "path": ".", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "foo", "path": ".", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert!(output.stdout.is_empty());
@@ -669,11 +994,14 @@ async fn grep_respects_max_bytes() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "match", "path": ".", "regex": false, "max_bytes": 4}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "match", "path": ".", "regex": false, "max_bytes": 4}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert!(output.stdout.is_empty());
@@ -687,11 +1015,14 @@ async fn grep_rejects_invalid_regex() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "[", "path": ".", "regex": true}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "[", "path": ".", "regex": true}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -706,17 +1037,20 @@ async fn grep_respects_include_exclude() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({
-            "pattern": "match",
-            "path": ".",
-            "regex": false,
-            "include": ["**/*.txt"],
-            "exclude": ["**/*.log"]
-        }),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({
+                "pattern": "match",
+                "path": ".",
+                "regex": false,
+                "include": ["**/*.txt"],
+                "exclude": ["**/*.log"]
+            }),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -730,11 +1064,14 @@ async fn grep_invalid_args() {
     let root = dir.path();
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -746,11 +1083,14 @@ async fn grep_rejects_parent_path() {
     let root = dir.path();
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "a", "path": "../", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "a", "path": "../", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -762,11 +1102,14 @@ async fn grep_rejects_invalid_include_glob() {
     let root = dir.path();
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "a", "path": ".", "regex": false, "include": ["["]}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "a", "path": ".", "regex": false, "include": ["["]}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -778,11 +1121,14 @@ async fn grep_rejects_invalid_exclude_glob() {
     let root = dir.path();
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "a", "path": ".", "regex": false, "exclude": ["["]}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "a", "path": ".", "regex": false, "exclude": ["["]}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -796,11 +1142,14 @@ async fn grep_invalid_utf8_reports_failure() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "a", "path": ".", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "a", "path": ".", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert!(output.stdout.is_empty());
@@ -818,11 +1167,14 @@ async fn grep_reports_unreadable_file() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "match", "path": ".", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "match", "path": ".", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     fs::set_permissions(&locked, fs::Permissions::from_mode(0o600)).expect("chmod");
@@ -842,11 +1194,14 @@ async fn grep_reports_unreadable_entries() {
 
     let registry = setup_registry(root);
     let grep = registry.get("grep").expect("grep tool");
-    let output = grep(ToolInvocation {
-        name: "grep".to_string(),
-        args: json!({"pattern": "match", "path": ".", "regex": false}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &grep,
+        ToolInvocation {
+            name: "grep".to_string(),
+            args: json!({"pattern": "match", "path": ".", "regex": false}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     fs::set_permissions(&locked, fs::Permissions::from_mode(0o700)).expect("chmod");
@@ -861,11 +1216,14 @@ async fn shell_runs_command() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!({"command": "echo hello"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!({"command": "echo hello"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
@@ -880,11 +1238,14 @@ async fn shell_accepts_env() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!({"command": "echo hello", "env": {"RIP_TEST": "1"}}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!({"command": "echo hello", "env": {"RIP_TEST": "1"}}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -898,11 +1259,14 @@ async fn shell_rejects_invalid_cwd() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!({"command": "echo hello", "cwd": "../"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!({"command": "echo hello", "cwd": "../"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -916,11 +1280,14 @@ async fn shell_invalid_args() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -935,11 +1302,14 @@ async fn shell_accepts_cwd() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!({"command": "echo hello", "cwd": "subdir"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!({"command": "echo hello", "cwd": "subdir"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 0);
@@ -957,11 +1327,14 @@ async fn shell_reports_missing_program() {
     let registry = setup_registry(root);
     let shell = registry.get("shell").expect("shell tool");
 
-    let output = shell(ToolInvocation {
-        name: "shell".to_string(),
-        args: json!({"command": "echo hello"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &shell,
+        ToolInvocation {
+            name: "shell".to_string(),
+            args: json!({"command": "echo hello"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -984,17 +1357,42 @@ async fn bash_runs_command_if_available() {
     let registry = setup_registry(root);
     let bash = registry.get("bash").expect("bash tool");
 
-    let output = bash(ToolInvocation {
-        name: "bash".to_string(),
-        args: json!({"command": "echo bash", "cwd": ".", "env": {"RIP_TEST": "1"}}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &bash,
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({"command": "echo bash", "cwd": ".", "env": {"RIP_TEST": "1"}}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     let joined = output.stdout.join("\n");
     assert!(joined.contains("bash"));
 }
 
+#[tokio::test]
+async fn bash_defaults_to_the_workspace_root() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let root = dir.path().canonicalize().expect("canonical root");
+    let registry = setup_registry(&root);
+    let bash = registry.get("bash").expect("bash tool");
+
+    let output = invoke(
+        &bash,
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({"command": "pwd -P"}),
+            timeout_ms: None,
+        },
+    )
+    .await;
+
+    assert_eq!(output.exit_code, 0);
+    assert_eq!(output.stdout.join("\n").trim(), root.to_string_lossy());
+}
+
 #[tokio::test]
 async fn bash_invalid_args() {
     let _lock = env_lock().lock().await;
@@ -1003,11 +1401,14 @@ async fn bash_invalid_args() {
     let registry = setup_registry(root);
     let bash = registry.get("bash").expect("bash tool");
 
-    let output = bash(ToolInvocation {
-        name: "bash".to_string(),
-        args: json!("nope"),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &bash,
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!("nope"),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 2);
@@ -1021,11 +1422,14 @@ async fn bash_rejects_invalid_cwd() {
     let registry = setup_registry(root);
     let bash = registry.get("bash").expect("bash tool");
 
-    let output = bash(ToolInvocation {
-        name: "bash".to_string(),
-        args: json!({"command": "echo ok", "cwd": "../"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &bash,
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({"command": "echo ok", "cwd": "../"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
@@ -1043,12 +1447,1803 @@ async fn bash_reports_missing_program() {
     let registry = setup_registry(root);
     let bash = registry.get("bash").expect("bash tool");
 
-    let output = bash(ToolInvocation {
-        name: "bash".to_string(),
-        args: json!({"command": "echo ok"}),
-        timeout_ms: None,
-    })
+    let output = invoke(
+        &bash,
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({"command": "echo ok"}),
+            timeout_ms: None,
+        },
+    )
     .await;
 
     assert_eq!(output.exit_code, 1);
 }
+
+#[cfg(unix)]
+#[tokio::test]
+async fn bash_streams_lines_before_exit() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let registry = Arc::new(setup_registry(dir.path()));
+    let runner = ToolRunner::new(registry, 1);
+
+    let started = Instant::now();
+    let mut frames = runner.stream(
+        "session-1",
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({"command": "echo first; sleep 1; echo second"}),
+            timeout_ms: None,
+        },
+        CancellationToken::new(),
+    );
+
+    let first = loop {
+        match frames.next().await.expect("frame") {
+            EventKind::ToolStdout { chunk, .. } => break (chunk, started.elapsed()),
+            EventKind::ToolStarted { .. } => continue,
+            other => panic!("unexpected frame: {other:?}"),
+        }
+    };
+    assert_eq!(first.0, "first");
+    assert!(first.1 < Duration::from_millis(900), "{:?}", first.1);
+
+    let rest: Vec<EventKind> = frames.collect().await;
+    assert!(matches!(
+        &rest[0],
+        EventKind::ToolStdout { chunk, .. } if chunk == "second"
+    ));
+    assert!(matches!(rest[1], EventKind::ToolEnded { exit_code: 0, .. }));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn bash_spills_oversized_output_to_workspace_file() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let registry = Arc::new(setup_registry(dir.path()));
+    let runner = ToolRunner::new(registry.clone(), 1);
+
+    let frames: Vec<EventKind> = runner
+        .stream(
+            "session-1",
+            ToolInvocation {
+                name: "bash".to_string(),
+                args: json!({
+                    "command": "for i in $(seq 1 500); do echo line$i; done",
+                    "cwd": ".",
+                    "max_bytes": 200
+                }),
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+
+    let stdout: Vec<&str> = frames
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::ToolStdout { chunk, .. } => Some(chunk.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(stdout.first(), Some(&"line1"));
+    assert_eq!(stdout.last(), Some(&"line500"));
+    assert!(stdout.len() < 40, "{}", stdout.len());
+    let marker = stdout
+        .iter()
+        .find(|line| line.contains("bytes omitted"))
+        .expect("omission marker");
+
+    let (tool_id, artifacts) = match frames.last() {
+        Some(EventKind::ToolEnded {
+            tool_id,
+            exit_code: 0,
+            artifacts: Some(artifacts),
+            ..
+        }) => (tool_id, artifacts),
+        other => panic!("unexpected end frame: {other:?}"),
+    };
+    let spill_path = artifacts["spill_path"].as_str().expect("spill_path");
+    assert_eq!(
+        spill_path,
+        format!(".rip/tool-output/session-1/{tool_id}.log")
+    );
+    assert!(marker.contains(spill_path));
+
+    let full = fs::read_to_string(dir.path().join(spill_path)).expect("spill file");
+    assert_eq!(full.lines().count(), 500);
+    assert_eq!(full.lines().nth(249), Some("line250"));
+
+    let read = invoke(
+        &registry.get("read").expect("read"),
+        ToolInvocation {
+            name: "read".to_string(),
+            args: json!({ "path": spill_path, "start_line": 250, "end_line": 251 }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(read.stdout, vec!["line250\nline251\n"]);
+}
+
+#[cfg(target_os = "linux")]
+fn process_running(pid: &str) -> bool {
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => !stat
+            .rsplit_once(')')
+            .map(|(_, rest)| rest.trim_start().starts_with('Z'))
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_timeout_terminates_process_group() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let registry = Arc::new(setup_registry(dir.path()));
+    let runner = ToolRunner::new(registry, 1);
+
+    let mut seq = 0;
+    let events = runner
+        .run(
+            "session-1",
+            &mut seq,
+            ToolInvocation {
+                name: "bash".to_string(),
+                args: json!({
+                    "command": "sleep 30 & echo $! > child.pid; echo started; wait",
+                    "cwd": "."
+                }),
+                timeout_ms: Some(300),
+            },
+            CancellationToken::new(),
+        )
+        .await;
+
+    match &events.last().expect("event").kind {
+        EventKind::ToolFailed {
+            error,
+            signal,
+            stdout,
+            ..
+        } => {
+            assert_eq!(error, "timeout");
+            assert_eq!(signal.as_deref(), Some("SIGTERM"));
+            assert_eq!(stdout.as_deref(), Some("started"));
+        }
+        other => panic!("expected tool_failed, got {other:?}"),
+    }
+    let pid = fs::read_to_string(dir.path().join("child.pid")).expect("pid");
+    assert!(!process_running(pid.trim()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn model_bash_calls_take_their_own_or_the_configured_timeout() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let registry = ToolRegistry::default();
+    register_builtin_tools(
+        &registry,
+        BuiltinToolConfig {
+            default_timeout_ms: Some(300),
+            ..test_config(dir.path())
+        },
+    );
+    let runner = ToolRunner::new(Arc::new(registry), 1);
+
+    for (name, arguments, expected) in [
+        ("bash", r#"{"command":"sleep 30"}"#, 300),
+        ("shell", r#"{"command":"sleep 30","timeout_ms":200}"#, 200),
+    ] {
+        let started = Instant::now();
+        let frames: Vec<EventKind> = runner
+            .dispatch(
+                "session-1",
+                ToolCall {
+                    call_id: "call_1".to_string(),
+                    name: name.to_string(),
+                    arguments: arguments.to_string(),
+                },
+                CancellationToken::new(),
+            )
+            .collect()
+            .await;
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(
+            &frames[0],
+            EventKind::ToolStarted { timeout_ms: Some(ms), .. } if *ms == expected
+        ));
+        assert!(matches!(
+            frames.last(),
+            Some(EventKind::ToolFailed { error, .. }) if error == "timeout"
+        ));
+    }
+}
+
+fn persistent_runner(root: &Path) -> ToolRunner {
+    let registry = ToolRegistry::default();
+    register_builtin_tools(
+        &registry,
+        BuiltinToolConfig {
+            persistent_shell: true,
+            ..test_config(root)
+        },
+    );
+    ToolRunner::new(Arc::new(registry), 1)
+}
+
+/// Runs `bash` and returns (stdout, stderr, exit code) from its frames.
+async fn run_persistent(
+    runner: &ToolRunner,
+    session_id: &str,
+    args: serde_json::Value,
+    timeout_ms: Option<u64>,
+) -> (Vec<String>, Vec<String>, Option<i32>) {
+    let frames: Vec<EventKind> = runner
+        .stream(
+            session_id,
+            ToolInvocation {
+                name: "bash".to_string(),
+                args,
+                timeout_ms,
+            },
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+    let (mut stdout, mut stderr, mut exit_code) = (Vec::new(), Vec::new(), None);
+    for frame in frames {
+        match frame {
+            EventKind::ToolStdout { chunk, .. } => stdout.push(chunk),
+            EventKind::ToolStderr { chunk, .. } => stderr.push(chunk),
+            EventKind::ToolEnded {
+                exit_code: code, ..
+            } => exit_code = Some(code),
+            _ => {}
+        }
+    }
+    (stdout, stderr, exit_code)
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn persistent_bash_keeps_cwd_and_env_per_session() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    fs::create_dir(dir.path().join("sub")).expect("mkdir");
+    let runner = persistent_runner(dir.path());
+
+    let (_, _, code) = run_persistent(
+        &runner,
+        "s1",
+        json!({ "command": "cd sub && export RIP_TEST_VAR=kept" }),
+        None,
+    )
+    .await;
+    assert_eq!(code, Some(0));
+
+    let (stdout, _, code) = run_persistent(
+        &runner,
+        "s1",
+        json!({ "command": "basename \"$PWD\"; echo \"$RIP_TEST_VAR\"" }),
+        None,
+    )
+    .await;
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, vec!["sub", "kept"]);
+
+    let (stdout, _, _) = run_persistent(
+        &runner,
+        "s2",
+        json!({ "command": "echo \"[$RIP_TEST_VAR]\"" }),
+        None,
+    )
+    .await;
+    assert_eq!(stdout, vec!["[]"]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn persistent_bash_applies_env_and_reports_status() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let runner = persistent_runner(dir.path());
+
+    let (stdout, stderr, code) = run_persistent(
+        &runner,
+        "s1",
+        json!({
+            "command": "printf '%s' \"$GREETING\"; echo oops >&2; false",
+            "env": { "GREETING": "it's here" }
+        }),
+        None,
+    )
+    .await;
+    assert_eq!(stdout, vec!["it's here"]);
+    assert_eq!(stderr, vec!["oops"]);
+    assert_eq!(code, Some(1));
+
+    let (_, stderr, code) =
+        run_persistent(&runner, "s1", json!({ "command": "if then" }), None).await;
+    assert_eq!(code, Some(2));
+    assert!(!stderr.is_empty());
+
+    let (stdout, _, code) = run_persistent(
+        &runner,
+        "s1",
+        json!({ "command": "echo \"$GREETING\"" }),
+        None,
+    )
+    .await;
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, vec!["it's here"]);
+
+    let (_, _, code) = run_persistent(
+        &runner,
+        "s1",
+        json!({ "command": "x", "env": { "BAD-NAME": "1" } }),
+        None,
+    )
+    .await;
+    assert_eq!(code, Some(2));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn persistent_bash_restarts_after_exit_timeout_and_on_request() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let runner = persistent_runner(dir.path());
+
+    let set = json!({ "command": "export RIP_STATE=1" });
+    let probe = json!({ "command": "echo \"[$RIP_STATE]\"" });
+
+    run_persistent(&runner, "s1", set.clone(), None).await;
+    let (_, stderr, code) =
+        run_persistent(&runner, "s1", json!({ "command": "exit 3" }), None).await;
+    assert_eq!(code, Some(3));
+    assert!(stderr.iter().any(|line| line.contains("shell exited")));
+    let (stdout, _, _) = run_persistent(&runner, "s1", probe.clone(), None).await;
+    assert_eq!(stdout, vec!["[]"]);
+
+    run_persistent(&runner, "s1", set.clone(), None).await;
+    let (_, _, code) =
+        run_persistent(&runner, "s1", json!({ "command": "sleep 30" }), Some(200)).await;
+    assert_eq!(code, None);
+    let (stdout, _, code) = run_persistent(&runner, "s1", probe.clone(), None).await;
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, vec!["[]"]);
+
+    run_persistent(&runner, "s1", set, None).await;
+    let (stdout, _, _) = run_persistent(
+        &runner,
+        "s1",
+        json!({ "command": "echo \"[$RIP_STATE]\"", "restart": true }),
+        None,
+    )
+    .await;
+    assert_eq!(stdout, vec!["[]"]);
+}
+
+async fn stream_tool(
+    runner: &ToolRunner,
+    session_id: &str,
+    name: &str,
+    args: serde_json::Value,
+) -> Vec<EventKind> {
+    runner
+        .stream(
+            session_id,
+            ToolInvocation {
+                name: name.to_string(),
+                args,
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        )
+        .collect()
+        .await
+}
+
+fn ended_artifacts(frames: &[EventKind]) -> serde_json::Value {
+    match frames.last() {
+        Some(EventKind::ToolEnded {
+            exit_code: 0,
+            artifacts: Some(artifacts),
+            ..
+        }) => artifacts.clone(),
+        other => panic!("expected tool_ended, got {other:?}"),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn bash_background_buffers_output_until_polled() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let runner = ToolRunner::new(Arc::new(setup_registry(dir.path())), 1);
+
+    let frames = stream_tool(
+        &runner,
+        "s1",
+        "bash_background",
+        json!({ "command": "echo one; echo two >&2; sleep 0.2; echo three; exit 4", "cwd": "." }),
+    )
+    .await;
+    let job_id = ended_artifacts(&frames)["job_id"]
+        .as_str()
+        .expect("job id")
+        .to_string();
+
+    let frames = stream_tool(&runner, "s2", "bash_output", json!({ "job_id": job_id })).await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolEnded { exit_code: 1, .. })
+    ));
+
+    let frames = stream_tool(
+        &runner,
+        "s1",
+        "bash_output",
+        json!({ "job_id": job_id, "wait_ms": 5000 }),
+    )
+    .await;
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    for frame in &frames {
+        match frame {
+            EventKind::ToolStdout {
+                chunk, job_id: tag, ..
+            } => {
+                assert_eq!(tag.as_deref(), Some(job_id.as_str()));
+                stdout.push(chunk.as_str());
+            }
+            EventKind::ToolStderr {
+                chunk, job_id: tag, ..
+            } => {
+                assert_eq!(tag.as_deref(), Some(job_id.as_str()));
+                stderr.push(chunk.as_str());
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(stdout, vec!["one", "three"]);
+    assert_eq!(stderr, vec!["two"]);
+    let artifacts = ended_artifacts(&frames);
+    assert_eq!(artifacts["state"], "exited");
+    assert_eq!(artifacts["exit_code"], 4);
+
+    let frames = stream_tool(&runner, "s1", "bash_output", json!({ "job_id": job_id })).await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolEnded { exit_code: 1, .. })
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_kill_stops_background_job() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let runner = ToolRunner::new(Arc::new(setup_registry(dir.path())), 1);
+
+    let frames = stream_tool(
+        &runner,
+        "s1",
+        "bash_background",
+        json!({ "command": "sleep 30 & echo $! > child.pid; echo ready; wait", "cwd": "." }),
+    )
+    .await;
+    let job_id = ended_artifacts(&frames)["job_id"]
+        .as_str()
+        .expect("job id")
+        .to_string();
+
+    let frames = stream_tool(
+        &runner,
+        "s1",
+        "bash_output",
+        json!({ "job_id": job_id, "wait_ms": 200 }),
+    )
+    .await;
+    assert_eq!(ended_artifacts(&frames)["state"], "running");
+
+    let frames = stream_tool(&runner, "s1", "bash_kill", json!({ "job_id": job_id })).await;
+    let artifacts = ended_artifacts(&frames);
+    assert_eq!(artifacts["state"], "killed");
+    assert_eq!(artifacts["signal"], "SIGTERM");
+    let pid = fs::read_to_string(dir.path().join("child.pid")).expect("pid");
+    assert!(!process_running(pid.trim()));
+}
+
+/// Whether this host lets an unprivileged process create the namespaces the
+/// sandbox needs (e.g. unprivileged user namespaces may be disabled).
+#[cfg(target_os = "linux")]
+fn sandbox_supported() -> bool {
+    use std::os::unix::process::CommandExt;
+
+    let mut probe = std::process::Command::new("true");
+    // SAFETY: unshare(2) only affects the forked child.
+    unsafe {
+        probe.pre_exec(|| {
+            let flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+            if libc::unshare(flags) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    probe.status().is_ok_and(|status| status.success())
+}
+
+/// Runs `command` through a sandboxed `bash`; `None` when the host cannot
+/// sandbox at all. Once the probe passes, a sandbox failure is a failure.
+#[cfg(target_os = "linux")]
+async fn run_sandboxed(
+    root: &Path,
+    profile: SandboxProfile,
+    command: &str,
+) -> Option<rip_tools::ToolOutput> {
+    let config = BuiltinToolConfig {
+        sandbox: Some(profile),
+        ..test_config(root)
+    };
+    run_sandboxed_with(config, command).await
+}
+
+#[cfg(target_os = "linux")]
+async fn run_sandboxed_with(
+    config: BuiltinToolConfig,
+    command: &str,
+) -> Option<rip_tools::ToolOutput> {
+    if !sandbox_supported() {
+        eprintln!("skipping: host cannot create user namespaces");
+        return None;
+    }
+    let registry = ToolRegistry::default();
+    register_builtin_tools(&registry, config);
+    let output = invoke(
+        &registry.get("bash").expect("bash"),
+        ToolInvocation {
+            name: "bash".to_string(),
+            args: json!({ "command": command, "cwd": "." }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert!(
+        !output
+            .stderr
+            .iter()
+            .any(|line| line.starts_with("bash failed in sandbox")),
+        "sandbox setup failed: {:?}",
+        output.stderr
+    );
+    Some(output)
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandbox_limits_writes_to_workspace() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let outside = tempdir().expect("tmp");
+    let command = format!(
+        "touch inside.txt && mkdir sub && ! touch {0}/escape.txt 2>/dev/null && cat {0}/seen.txt",
+        outside.path().display()
+    );
+    fs::write(outside.path().join("seen.txt"), "readable").expect("write");
+
+    let Some(output) = run_sandboxed(dir.path(), SandboxProfile::default(), &command).await else {
+        return;
+    };
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.stdout, vec!["readable"]);
+    assert!(dir.path().join("inside.txt").exists());
+    assert!(dir.path().join("sub").is_dir());
+    assert!(!outside.path().join("escape.txt").exists());
+
+    let profile = SandboxProfile {
+        writable_paths: vec![outside.path().to_path_buf()],
+        ..SandboxProfile::default()
+    };
+    let command = format!("touch {}/allowed.txt", outside.path().display());
+    let output = run_sandboxed(dir.path(), profile, &command)
+        .await
+        .expect("sandbox");
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert!(outside.path().join("allowed.txt").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandbox_denies_network_unless_profile_allows_it() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("addr").port();
+    let command = format!("echo hi > /dev/tcp/127.0.0.1/{port}");
+
+    let Some(output) = run_sandboxed(dir.path(), SandboxProfile::default(), &command).await else {
+        return;
+    };
+    assert_ne!(output.exit_code, 0);
+
+    let network = SandboxProfile::named("workspace-write-network").expect("profile");
+    let output = run_sandboxed(dir.path(), network, &command)
+        .await
+        .expect("sandbox");
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandbox_seccomp_blocks_namespace_syscalls() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let command = "if command -v unshare >/dev/null; then ! unshare --user true 2>/dev/null; fi";
+
+    let Some(output) = run_sandboxed(dir.path(), SandboxProfile::default(), command).await else {
+        return;
+    };
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandbox_runs_in_its_own_pid_namespace() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let command = format!(
+        "echo $$; kill -0 {} 2>/dev/null && echo host-visible; exit 3",
+        std::process::id()
+    );
+
+    let Some(output) = run_sandboxed(dir.path(), SandboxProfile::default(), &command).await else {
+        return;
+    };
+    assert_eq!(output.stdout, vec!["1"]);
+    assert_eq!(output.exit_code, 3);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandbox_caps_processes_with_rlimit_nproc() {
+    let _lock = env_lock().lock().await;
+    // A delegated cgroup takes precedence, and root is exempt from the rlimit.
+    // SAFETY: getuid cannot fail.
+    if Path::new("/sys/fs/cgroup/cgroup.controllers").exists() || unsafe { libc::getuid() } == 0 {
+        eprintln!("skipping: pids are not capped by RLIMIT_NPROC on this host");
+        return;
+    }
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_pids: Some(4),
+        ..ResourceLimits::default()
+    };
+    let config = BuiltinToolConfig {
+        sandbox: Some(SandboxProfile::default()),
+        resource_limits: HashMap::from([("bash".to_string(), limits)]),
+        ..test_config(dir.path())
+    };
+    // bash retries the refused forks until earlier children exit.
+    let command = "ulimit -u; for i in 1 2 3 4 5 6; do sleep 0.5 & done; wait";
+
+    let Some(output) = run_sandboxed_with(config, command).await else {
+        return;
+    };
+    assert_eq!(output.exit_code, 0, "{output:?}");
+    assert_eq!(output.stdout, vec!["5"]);
+    assert!(
+        output.stderr.iter().any(|line| line.contains("fork")),
+        "{:?}",
+        output.stderr
+    );
+}
+
+#[cfg(target_os = "linux")]
+async fn run_limited(root: &Path, limits: ResourceLimits, command: &str) -> EventKind {
+    let registry = ToolRegistry::default();
+    register_builtin_tools(
+        &registry,
+        BuiltinToolConfig {
+            resource_limits: HashMap::from([("bash".to_string(), limits)]),
+            ..test_config(root)
+        },
+    );
+    let runner = ToolRunner::new(Arc::new(registry), 1);
+    let mut seq = 0;
+    let events = runner
+        .run(
+            "session-1",
+            &mut seq,
+            ToolInvocation {
+                name: "bash".to_string(),
+                args: json!({ "command": command, "cwd": "." }),
+                timeout_ms: Some(20_000),
+            },
+            CancellationToken::new(),
+        )
+        .await;
+    events.last().expect("event").kind.clone()
+}
+
+#[cfg(target_os = "linux")]
+fn assert_limit_exceeded(kind: &EventKind, expected: &str) {
+    match kind {
+        EventKind::ToolFailed { error, limit, .. } => {
+            assert_eq!(error, "limit_exceeded");
+            assert_eq!(limit.as_deref(), Some(expected));
+        }
+        other => panic!("expected tool_failed, got {other:?}"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_cpu_limit_reports_limit_exceeded() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_cpu_seconds: Some(1),
+        ..ResourceLimits::default()
+    };
+    let kind = run_limited(dir.path(), limits, "while :; do :; done").await;
+    assert_limit_exceeded(&kind, "cpu");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_file_size_limit_reports_limit_exceeded() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_file_size_bytes: Some(1024),
+        ..ResourceLimits::default()
+    };
+    let kind = run_limited(
+        dir.path(),
+        limits.clone(),
+        "exec head -c 100000 /dev/zero > big",
+    )
+    .await;
+    assert_limit_exceeded(&kind, "file_size");
+    let written = fs::metadata(dir.path().join("big")).expect("big").len();
+    assert!(written <= 1024, "wrote {written} bytes");
+
+    // Exiting with `128 + SIGXFSZ` is not the same as being stopped by it.
+    let kind = run_limited(dir.path(), limits, "exit 153").await;
+    assert!(
+        matches!(kind, EventKind::ToolEnded { exit_code: 153, .. }),
+        "{kind:?}"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_memory_limit_reports_limit_exceeded() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_memory_bytes: Some(64 * 1024 * 1024),
+        ..ResourceLimits::default()
+    };
+    let kind = run_limited(dir.path(), limits, "head -c 1G /dev/zero | tail").await;
+    assert_limit_exceeded(&kind, "memory");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_pids_limit_reports_limit_exceeded() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_pids: Some(5),
+        ..ResourceLimits::default()
+    };
+    let kind = run_limited(
+        dir.path(),
+        limits,
+        "for i in $(seq 20); do sleep 5 & done; wait",
+    )
+    .await;
+    assert_limit_exceeded(&kind, "pids");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_within_limits_succeeds() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let limits = ResourceLimits {
+        max_memory_bytes: Some(256 * 1024 * 1024),
+        max_cpu_seconds: Some(10),
+        max_file_size_bytes: Some(1024 * 1024),
+        max_pids: Some(32),
+    };
+    let kind = run_limited(dir.path(), limits, "echo ok > out.txt && cat out.txt").await;
+    assert!(
+        matches!(kind, EventKind::ToolEnded { exit_code: 0, .. }),
+        "{kind:?}"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn bash_timeout_escalates_to_sigkill() {
+    let _lock = env_lock().lock().await;
+    let dir = tempdir().expect("tmp");
+    let registry = Arc::new(setup_registry(dir.path()));
+    let runner = ToolRunner::new(registry, 1);
+
+    let mut seq = 0;
+    let events = runner
+        .run(
+            "session-1",
+            &mut seq,
+            ToolInvocation {
+                name: "bash".to_string(),
+                args: json!({
+                    "command": "trap '' TERM; echo $$ > shell.pid; echo ignoring; sleep 30",
+                    "cwd": "."
+                }),
+                timeout_ms: Some(300),
+            },
+            CancellationToken::new(),
+        )
+        .await;
+
+    assert!(matches!(
+        &events.last().expect("event").kind,
+        EventKind::ToolFailed { signal, .. } if signal.as_deref() == Some("SIGKILL")
+    ));
+    let pid = fs::read_to_string(dir.path().join("shell.pid")).expect("pid");
+    assert!(!process_running(pid.trim()));
+}
+
+#[test]
+fn builtin_tools_export_schemas() {
+    let dir = tempdir().expect("tmp");
+    let registry = setup_registry(dir.path());
+    let tools = registry.function_tools();
+    let names: Vec<&str> = tools
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "apply_patch",
+            "bash",
+            "bash_background",
+            "bash_kill",
+            "bash_output",
+            "edit",
+            "glob",
+            "grep",
+            "ls",
+            "read",
+            "write"
+        ]
+    );
+
+    for tool in &tools {
+        assert_eq!(tool["type"], "function");
+        assert!(!tool["description"].as_str().unwrap_or_default().is_empty());
+        assert_eq!(tool["parameters"]["type"], "object");
+        assert!(tool["parameters"].get("$schema").is_none());
+    }
+    let read = &tools[9]["parameters"];
+    assert_eq!(read["required"], json!(["path"]));
+    assert!(read["properties"]["start_line"]["description"]
+        .as_str()
+        .unwrap_or_default()
+        .contains("1-based"));
+
+    let shell = registry.spec("shell").expect("alias spec");
+    assert_eq!(shell.parameters["required"], json!(["command"]));
+}
+
+#[test]
+fn builtin_schemas_accept_valid_and_reject_invalid_args() {
+    let dir = tempdir().expect("tmp");
+    let registry = setup_registry(dir.path());
+
+    assert!(registry
+        .validate(
+            "read",
+            &json!({"path": "a.txt", "start_line": 2, "end_line": null})
+        )
+        .is_ok());
+    assert!(registry
+        .validate(
+            "grep",
+            &json!({"pattern": "x", "include": ["*.rs"], "max_results": 3})
+        )
+        .is_ok());
+    assert!(registry
+        .validate("bash", &json!({"command": "ls", "env": {"A": "1"}}))
+        .is_ok());
+
+    assert!(registry.validate("read", &json!({})).is_err());
+    assert!(registry.validate("read", &json!({"path": 5})).is_err());
+    assert!(registry
+        .validate(
+            "write",
+            &json!({"path": "a", "content": "b", "append": "yes"})
+        )
+        .is_err());
+    assert!(registry.validate("ls", &json!({"max_depth": -1})).is_err());
+}
+
+#[tokio::test]
+async fn runner_rejects_invalid_builtin_args_before_running() {
+    let dir = tempdir().expect("tmp");
+    let registry = Arc::new(setup_registry(dir.path()));
+    let runner = ToolRunner::new(registry, 1);
+
+    let mut seq = 0;
+    let events = runner
+        .run(
+            "session-1",
+            &mut seq,
+            ToolInvocation {
+                name: "write".to_string(),
+                args: json!({"path": "out.txt"}),
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        )
+        .await;
+
+    assert!(events.iter().any(|event| matches!(
+        &event.kind,
+        EventKind::ToolStderr { chunk, .. } if chunk.contains("content")
+    )));
+    assert!(matches!(
+        events.last().map(|event| &event.kind),
+        Some(EventKind::ToolEnded { exit_code: 2, .. })
+    ));
+    assert!(!dir.path().join("out.txt").exists());
+}
+
+#[tokio::test]
+async fn apply_patch_applies_unified_diff() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("a.txt"), "one\ntwo\nthree\n").expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("apply_patch").expect("apply_patch");
+
+    let patch = "--- a/a.txt\n+++ b/a.txt\n@@ -1,3 +1,3 @@\n one\n-two\n+TWO\n three\n\
+--- /dev/null\n+++ b/sub/new.txt\n@@ -0,0 +1 @@\n+fresh\n";
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({ "patch": patch }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.stdout, vec!["M a.txt", "A sub/new.txt"]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).expect("read"),
+        "one\nTWO\nthree\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("sub/new.txt")).expect("read"),
+        "fresh\n"
+    );
+    let artifacts = output.artifacts.expect("artifacts");
+    assert_eq!(artifacts["applied"], true);
+    assert_eq!(artifacts["files"][0]["hunks_applied"], 1);
+    assert_eq!(artifacts["files"][0]["hunks"][0]["line"], 1);
+}
+
+#[tokio::test]
+async fn apply_patch_applies_envelope_operations() {
+    let dir = tempdir().expect("tmp");
+    fs::write(
+        dir.path().join("lib.py"),
+        "def a():\n    return 1\n\ndef b():\n    return 1\n",
+    )
+    .expect("write");
+    fs::write(dir.path().join("gone.txt"), "bye\n").expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("apply_patch").expect("apply_patch");
+
+    let patch = "*** Begin Patch\n\
+*** Update File: lib.py\n\
+*** Move to: pkg/lib.py\n\
+@@ def b():\n\
+-    return 1\n\
++    return 2\n\
+*** Delete File: gone.txt\n\
+*** Add File: notes.md\n\
++# Notes\n\
+*** End Patch\n";
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({ "patch": patch }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert!(!dir.path().join("lib.py").exists());
+    assert!(!dir.path().join("gone.txt").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("pkg/lib.py")).expect("read"),
+        "def a():\n    return 1\n\ndef b():\n    return 2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.md")).expect("read"),
+        "# Notes\n"
+    );
+    let files = &output.artifacts.expect("artifacts")["files"];
+    assert_eq!(files[0]["move_to"], "pkg/lib.py");
+    assert_eq!(files[1]["action"], "delete");
+    assert_eq!(files[2]["action"], "add");
+}
+
+#[tokio::test]
+async fn apply_patch_is_atomic_when_a_hunk_is_rejected() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("a.txt"), "alpha\nbeta\n").expect("write");
+    fs::write(dir.path().join("b.txt"), "gamma\n").expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("apply_patch").expect("apply_patch");
+
+    let patch = "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-alpha\n+ALPHA\n\
+--- a/b.txt\n+++ b/b.txt\n@@ -1 +1 @@\n-delta\n+DELTA\n";
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({ "patch": patch }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+
+    assert_eq!(output.exit_code, 1);
+    assert!(output
+        .stderr
+        .iter()
+        .any(|line| line.starts_with("b.txt: hunk 1: context not found")));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).expect("read"),
+        "alpha\nbeta\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.txt")).expect("read"),
+        "gamma\n"
+    );
+    let artifacts = output.artifacts.expect("artifacts");
+    assert_eq!(artifacts["applied"], false);
+    assert_eq!(artifacts["files"][0]["hunks_applied"], 1);
+    assert_eq!(artifacts["files"][1]["hunks_rejected"], 1);
+    assert_eq!(artifacts["files"][1]["hunks"][0]["status"], "rejected");
+    let leftovers: Vec<_> = fs::read_dir(dir.path())
+        .expect("read_dir")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().contains(".tmp-"))
+        .collect();
+    assert!(leftovers.is_empty());
+}
+
+#[tokio::test]
+async fn apply_patch_rejects_paths_outside_workspace() {
+    let dir = tempdir().expect("tmp");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("apply_patch").expect("apply_patch");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({
+                "patch": "*** Begin Patch\n*** Add File: ../escape.txt\n+x\n*** End Patch\n"
+            }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 1);
+    assert!(output.stderr[0].contains("escapes workspace root"));
+    assert!(!dir
+        .path()
+        .parent()
+        .expect("parent")
+        .join("escape.txt")
+        .exists());
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({ "patch": "not a patch" }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 2);
+    assert!(output.stderr[0].starts_with("invalid patch"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn apply_patch_preserves_file_mode() {
+    let dir = tempdir().expect("tmp");
+    let script = dir.path().join("run.sh");
+    fs::write(&script, "#!/bin/sh\necho one\n").expect("write");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("chmod");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("apply_patch").expect("apply_patch");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "apply_patch".to_string(),
+            args: json!({
+                "patch": "--- run.sh\n+++ run.sh\n@@ -2 +2 @@\n-echo one\n+echo two\n"
+            }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    let mode = fs::metadata(&script).expect("meta").permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+}
+
+#[tokio::test]
+async fn edit_replaces_unique_match_and_returns_diff() {
+    let dir = tempdir().expect("tmp");
+    fs::write(
+        dir.path().join("main.rs"),
+        "fn main() {\n    println!(\"hi\");\n}\n",
+    )
+    .expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("edit").expect("edit");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "edit".to_string(),
+            args: json!({
+                "path": "main.rs",
+                "old_string": "println!(\"hi\");",
+                "new_string": "println!(\"hello\");"
+            }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.stdout, vec!["replaced 1 occurrence in main.rs"]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("main.rs")).expect("read"),
+        "fn main() {\n    println!(\"hello\");\n}\n"
+    );
+    let artifacts = output.artifacts.expect("artifacts");
+    assert_eq!(artifacts["replacements"], 1);
+    let diff = artifacts["diff"].as_str().expect("diff");
+    assert!(diff.starts_with("--- a/main.rs\n+++ b/main.rs\n@@ -1,3 +1,3 @@\n"));
+    assert!(diff.contains("-    println!(\"hi\");\n+    println!(\"hello\");\n"));
+}
+
+#[tokio::test]
+async fn edit_rejects_missing_and_ambiguous_matches() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("a.txt"), "x = 1\ny = 2\nx = 1\n").expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("edit").expect("edit");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "edit".to_string(),
+            args: json!({ "path": "a.txt", "old_string": "z = 3", "new_string": "z = 4" }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 2);
+    assert_eq!(output.stderr, vec!["old_string not found in a.txt"]);
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "edit".to_string(),
+            args: json!({ "path": "a.txt", "old_string": "x = 1", "new_string": "x = 9" }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 2);
+    assert!(output.stderr[0].contains("matches 2 times"));
+    let artifacts = output.artifacts.expect("artifacts");
+    assert_eq!(artifacts["lines"], json!([1, 3]));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).expect("read"),
+        "x = 1\ny = 2\nx = 1\n"
+    );
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "edit".to_string(),
+            args: json!({ "path": "a.txt", "old_string": "same", "new_string": "same" }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 2);
+}
+
+#[tokio::test]
+async fn edit_replace_all_updates_every_occurrence() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("a.txt"), "x = 1\ny = 2\nx = 1\n").expect("write");
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("edit").expect("edit");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "edit".to_string(),
+            args: json!({
+                "path": "a.txt",
+                "old_string": "x = 1",
+                "new_string": "x = 9",
+                "replace_all": true
+            }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.stdout, vec!["replaced 2 occurrences in a.txt"]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).expect("read"),
+        "x = 9\ny = 2\nx = 9\n"
+    );
+    assert_eq!(output.artifacts.expect("artifacts")["replacements"], 2);
+}
+
+fn write_ignore_fixture(root: &Path) {
+    fs::create_dir_all(root.join("src/gen")).expect("mkdir");
+    fs::write(root.join(".gitignore"), "target/\n*.log\n").expect("write");
+    fs::write(root.join(".ignore"), "src/gen/\n").expect("write");
+    fs::write(root.join(".ripignore"), "secret.rs\n").expect("write");
+    fs::create_dir_all(root.join("target")).expect("mkdir");
+    fs::write(root.join("target/out.rs"), "fn hidden() {}\n").expect("write");
+    fs::write(root.join("src/lib.rs"), "fn visible() {}\n").expect("write");
+    fs::write(root.join("src/gen/api.rs"), "fn generated() {}\n").expect("write");
+    fs::write(root.join("src/secret.rs"), "fn secret() {}\n").expect("write");
+    fs::write(root.join("build.log"), "fn log() {}\n").expect("write");
+}
+
+#[tokio::test]
+async fn glob_honours_ignore_files() {
+    let dir = tempdir().expect("tmp");
+    write_ignore_fixture(dir.path());
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("glob").expect("glob");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "glob".to_string(),
+            args: json!({ "pattern": "**/*.rs" }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.exit_code, 0, "{:?}", output.stderr);
+    assert_eq!(output.stdout, vec!["src/lib.rs"]);
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "glob".to_string(),
+            args: json!({ "pattern": "**/*.rs", "no_ignore": true }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    let mut paths = output.stdout.clone();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            "src/gen/api.rs",
+            "src/lib.rs",
+            "src/secret.rs",
+            "target/out.rs"
+        ]
+    );
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "glob".to_string(),
+            args: json!({ "pattern": "*.rs", "path": "src/gen", "no_ignore": true }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.stdout, vec!["src/gen/api.rs"]);
+}
+
+#[tokio::test]
+async fn glob_sorts_newest_first_and_truncates() {
+    let dir = tempdir().expect("tmp");
+    let now = std::time::SystemTime::now();
+    for (name, age) in [("old.txt", 300), ("new.txt", 0), ("mid.txt", 100)] {
+        let path = dir.path().join(name);
+        fs::write(&path, name).expect("write");
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .expect("open")
+            .set_modified(now - Duration::from_secs(age))
+            .expect("set mtime");
+    }
+    let registry = setup_registry(dir.path());
+    let handler = registry.get("glob").expect("glob");
+
+    let output = invoke(
+        &handler,
+        ToolInvocation {
+            name: "glob".to_string(),
+            args: json!({ "pattern": "*.txt", "max_results": 2 }),
+            timeout_ms: None,
+        },
+    )
+    .await;
+    assert_eq!(output.stdout, vec!["new.txt", "mid.txt"]);
+    let artifacts = output.artifacts.expect("artifacts");
+    assert_eq!(artifacts["matches"], 3);
+    assert_eq!(artifacts["truncated"], true);
+}
+
+#[tokio::test]
+async fn ls_and_grep_opt_into_ignore_files() {
+    let dir = tempdir().expect("tmp");
+    write_ignore_fixture(dir.path());
+
+    let default_registry = setup_registry(dir.path());
+    let registry = ToolRegistry::default();
+    register_builtin_tools(
+        &registry,
+        BuiltinToolConfig {
+            respect_ignore_files: true,
+            ..test_config(dir.path())
+        },
+    );
+
+    let ls_args = json!({ "recursive": true });
+    let grep_args = json!({ "pattern": "fn " });
+    for (registry, ignored) in [(&default_registry, false), (&registry, true)] {
+        let ls = invoke(
+            &registry.get("ls").expect("ls"),
+            ToolInvocation {
+                name: "ls".to_string(),
+                args: ls_args.clone(),
+                timeout_ms: None,
+            },
+        )
+        .await;
+        let grep = invoke(
+            &registry.get("grep").expect("grep"),
+            ToolInvocation {
+                name: "grep".to_string(),
+                args: grep_args.clone(),
+                timeout_ms: None,
+            },
+        )
+        .await;
+
+        for hidden in [
+            "target/out.rs",
+            "src/gen/api.rs",
+            "src/secret.rs",
+            "build.log",
+        ] {
+            assert_eq!(
+                !ls.stdout.iter().any(|line| line == hidden),
+                ignored,
+                "ls {hidden}"
+            );
+            assert_eq!(
+                !grep.stdout.iter().any(|line| line.starts_with(hidden)),
+                ignored,
+                "grep {hidden}"
+            );
+        }
+        assert!(ls.stdout.iter().any(|line| line == "src/lib.rs"));
+        assert!(grep
+            .stdout
+            .iter()
+            .any(|line| line.starts_with("src/lib.rs:1:")));
+    }
+}
+
+async fn run_grep_tool(root: &Path, args: serde_json::Value) -> rip_tools::ToolOutput {
+    let registry = setup_registry(root);
+    invoke(
+        &registry.get("grep").expect("grep"),
+        ToolInvocation {
+            name: "grep".to_string(),
+            args,
+            timeout_ms: None,
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+async fn grep_prints_context_lines() {
+    let dir = tempdir().expect("tmp");
+    fs::write(
+        dir.path().join("a.txt"),
+        "one\ntwo\nhit\nthree\nfour\nfive\nsix\nhit\nseven\n",
+    )
+    .expect("write");
+
+    let output = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": "hit", "before": 1, "after": 1 }),
+    )
+    .await;
+    assert_eq!(
+        output.stdout,
+        vec![
+            "a.txt-2-two",
+            "a.txt:3:hit",
+            "a.txt-4-three",
+            "--",
+            "a.txt-7-six",
+            "a.txt:8:hit",
+            "a.txt-9-seven",
+        ]
+    );
+
+    let output = run_grep_tool(dir.path(), json!({ "pattern": "hit", "context": 3 })).await;
+    assert_eq!(output.stdout.len(), 9);
+    assert!(!output.stdout.contains(&"--".to_string()));
+}
+
+#[tokio::test]
+async fn grep_multiline_matches_span_lines() {
+    let dir = tempdir().expect("tmp");
+    fs::write(
+        dir.path().join("lib.rs"),
+        "fn a() {\n    todo!()\n}\n\nfn b() {}\n",
+    )
+    .expect("write");
+
+    let output = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": r"fn a\(\) \{\n\s+todo", "multiline": true }),
+    )
+    .await;
+    assert_eq!(
+        output.stdout,
+        vec!["lib.rs:1:fn a() {", "lib.rs:2:    todo!()"]
+    );
+    assert_eq!(output.artifacts.expect("artifacts")["matches"], 1);
+
+    let output = run_grep_tool(dir.path(), json!({ "pattern": r"\{\n\s+todo" })).await;
+    assert!(output.stdout.is_empty());
+}
+
+#[tokio::test]
+async fn grep_output_modes_and_type_filters() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("a.rs"), "needle\nneedle\n").expect("write");
+    fs::write(dir.path().join("b.py"), "needle\n").expect("write");
+    fs::write(dir.path().join("c.txt"), "hay\n").expect("write");
+
+    let output = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": "needle", "output_mode": "files_with_matches" }),
+    )
+    .await;
+    let mut files = output.stdout.clone();
+    files.sort();
+    assert_eq!(files, vec!["a.rs", "b.py"]);
+
+    let output = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": "needle", "output_mode": "count", "types": ["rust"] }),
+    )
+    .await;
+    assert_eq!(output.stdout, vec!["a.rs:2"]);
+
+    let output = run_grep_tool(dir.path(), json!({ "pattern": "needle", "types": ["py"] })).await;
+    assert_eq!(output.stdout, vec!["b.py:1:needle"]);
+
+    let output = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": "needle", "types": ["nope"] }),
+    )
+    .await;
+    assert_eq!(output.exit_code, 2);
+    assert!(output.stderr[0].contains("invalid file type"));
+}
+
+#[tokio::test]
+async fn grep_parallel_search_keeps_walk_order_and_limit() {
+    let dir = tempdir().expect("tmp");
+    for idx in 0..64 {
+        fs::write(
+            dir.path().join(format!("f{idx:02}.txt")),
+            "match\nmatch\nmatch\n",
+        )
+        .expect("write");
+    }
+
+    let full = run_grep_tool(
+        dir.path(),
+        json!({ "pattern": "match", "max_results": 1000 }),
+    )
+    .await;
+    assert_eq!(full.stdout.len(), 192);
+    for _ in 0..4 {
+        let limited =
+            run_grep_tool(dir.path(), json!({ "pattern": "match", "max_results": 50 })).await;
+        assert_eq!(limited.stdout, full.stdout[..50].to_vec());
+        let artifacts = limited.artifacts.expect("artifacts");
+        assert_eq!(artifacts["matches"], 50);
+        assert_eq!(artifacts["truncated"], true);
+    }
+}
+
+fn permission_runner(root: &Path) -> (ToolRunner, Arc<ToolPermissions>) {
+    let registry = ToolRegistry::default();
+    register_builtin_tools(&registry, test_config(root));
+    let policy = PermissionPolicy::from_config(&PermissionConfig {
+        default: PermissionDecision::Ask,
+        allow: vec!["bash(echo *)".to_string()],
+        ask: Vec::new(),
+        deny: vec!["write(**/.env)".to_string(), "bash(rm *)".to_string()],
+    })
+    .expect("policy");
+    let permissions = Arc::new(ToolPermissions::new(policy));
+    let runner = ToolRunner::new(Arc::new(registry), 1).with_permissions(permissions.clone());
+    (runner, permissions)
+}
+
+async fn run_frames(runner: &ToolRunner, name: &str, args: serde_json::Value) -> Vec<EventKind> {
+    runner
+        .stream(
+            "s1",
+            ToolInvocation {
+                name: name.to_string(),
+                args,
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        )
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn permission_rules_allow_and_deny_before_dispatch() {
+    let dir = tempdir().expect("tmp");
+    let (runner, _) = permission_runner(dir.path());
+
+    let frames = run_frames(&runner, "bash", json!({ "command": "echo hi" })).await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolEnded { exit_code: 0, .. })
+    ));
+
+    let frames = run_frames(
+        &runner,
+        "write",
+        json!({ "path": "./config/../.env", "content": "SECRET=1" }),
+    )
+    .await;
+    assert!(matches!(frames[0], EventKind::ToolStarted { .. }));
+    match &frames[1] {
+        EventKind::ToolStderr { chunk, .. } => {
+            assert_eq!(chunk, "permission denied: denied by rule `write(**/.env)`")
+        }
+        other => panic!("expected tool_stderr, got {other:?}"),
+    }
+    match &frames[2] {
+        EventKind::ToolFailed { error, .. } => assert_eq!(error, "permission_denied"),
+        other => panic!("expected tool_failed, got {other:?}"),
+    }
+    assert!(!dir.path().join(".env").exists());
+
+    let patch = "*** Begin Patch\n*** Add File: sub/.env\n+SECRET=1\n*** End Patch";
+    let frames = run_frames(&runner, "apply_patch", json!({ "patch": patch })).await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolFailed { error, .. }) if error == "permission_denied"
+    ));
+    assert!(!dir.path().join("sub").exists());
+}
+
+#[tokio::test]
+async fn write_and_bash_rules_cover_related_tools_and_redirections() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join(".env"), "SECRET=0").expect("write");
+    let (runner, _) = permission_runner(dir.path());
+
+    for (name, args) in [
+        (
+            "edit",
+            json!({ "path": ".env", "old_string": "0", "new_string": "1" }),
+        ),
+        ("bash", json!({ "command": "echo SECRET=1 > .env" })),
+        (
+            "bash",
+            json!({ "command": "{ echo SECRET=1; } >> ../.env", "cwd": "sub" }),
+        ),
+        ("bash", json!({ "command": "\"rm\" -rf .env" })),
+        ("bash", json!({ "command": "'rm' -rf .env" })),
+        ("bash", json!({ "command": "r\\m -rf .env" })),
+        (
+            "bash_background",
+            json!({ "command": "echo ok; rm -rf .env" }),
+        ),
+    ] {
+        let frames = run_frames(&runner, name, args.clone()).await;
+        assert!(
+            matches!(
+                frames.last(),
+                Some(EventKind::ToolFailed { error, .. }) if error == "permission_denied"
+            ),
+            "{name} {args}"
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join(".env")).expect("read"),
+        "SECRET=0"
+    );
+
+    let registry = ToolRegistry::default();
+    register_builtin_tools(&registry, test_config(dir.path()));
+    assert_eq!(registry.permission_family("bash_background"), "bash");
+    assert_eq!(registry.permission_family("apply_patch"), "write");
+    let policy = PermissionPolicy::new(PermissionDecision::Allow)
+        .rule(PermissionDecision::Allow, "write(src/**)")
+        .expect("policy");
+    let subjects = registry.permission_subjects("apply_patch", &json!({ "patch": "nonsense" }));
+    assert_eq!(
+        policy
+            .evaluate_as("apply_patch", "write", &subjects)
+            .decision,
+        PermissionDecision::Ask
+    );
+}
+
+#[tokio::test]
+async fn ask_rules_wait_for_a_client_decision() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("notes.txt"), "milk").expect("write");
+    let (runner, permissions) = permission_runner(dir.path());
+
+    for approve in [true, false] {
+        let mut stream = runner.stream(
+            "s1",
+            ToolInvocation {
+                name: "read".to_string(),
+                args: json!({ "path": "notes.txt" }),
+                timeout_ms: None,
+            },
+            CancellationToken::new(),
+        );
+        let mut frames = Vec::new();
+        while let Some(frame) = stream.next().await {
+            if let EventKind::ToolPermissionRequested {
+                tool_id,
+                name,
+                rule,
+                ..
+            } = &frame
+            {
+                assert_eq!(name, "read");
+                assert_eq!(rule, &None);
+                assert_eq!(permissions.pending("s1"), vec![tool_id.clone()]);
+                assert!(permissions.decide("s1", tool_id, approve));
+            }
+            frames.push(frame);
+        }
+        assert!(matches!(
+            frames[1],
+            EventKind::ToolPermissionRequested { .. }
+        ));
+        if approve {
+            assert!(frames.iter().any(
+                |frame| matches!(frame, EventKind::ToolStdout { chunk, .. } if chunk == "milk")
+            ));
+            assert!(matches!(
+                frames.last(),
+                Some(EventKind::ToolEnded { exit_code: 0, .. })
+            ));
+        } else {
+            assert!(matches!(
+                frames.last(),
+                Some(EventKind::ToolFailed { error, .. }) if error == "permission_denied"
+            ));
+        }
+        assert!(permissions.pending("s1").is_empty());
+    }
+}
+
+#[tokio::test]
+async fn plan_mode_only_offers_and_runs_read_only_tools() {
+    let dir = tempdir().expect("tmp");
+    fs::write(dir.path().join("notes.txt"), "milk").expect("write");
+    let registry = ToolRegistry::default();
+    register_builtin_tools(&registry, test_config(dir.path()));
+    let runner = ToolRunner::new(Arc::new(registry), 1);
+    runner.modes().set("s1", ToolMode::Plan);
+
+    let names: Vec<String> = runner
+        .session_tools("s1")
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap_or_default().to_string())
+        .collect();
+    assert_eq!(names, vec!["glob", "grep", "ls", "read"]);
+    assert!(runner.session_tools("s2").len() > names.len());
+
+    let frames = run_frames(&runner, "read", json!({ "path": "notes.txt" })).await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolEnded { exit_code: 0, .. })
+    ));
+
+    for (name, args) in [
+        ("write", json!({ "path": "notes.txt", "content": "eggs" })),
+        ("shell", json!({ "command": "echo eggs > notes.txt" })),
+    ] {
+        let frames = run_frames(&runner, name, args).await;
+        match &frames[1] {
+            EventKind::ToolStderr { chunk, .. } => assert!(chunk.contains("plan mode"), "{chunk}"),
+            other => panic!("expected tool_stderr, got {other:?}"),
+        }
+        assert!(matches!(
+            frames.last(),
+            Some(EventKind::ToolFailed { error, .. }) if error == "plan_mode"
+        ));
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).expect("read"),
+        "milk"
+    );
+
+    runner.modes().set("s1", ToolMode::Default);
+    let frames = run_frames(
+        &runner,
+        "write",
+        json!({ "path": "notes.txt", "content": "eggs" }),
+    )
+    .await;
+    assert!(matches!(
+        frames.last(),
+        Some(EventKind::ToolEnded { exit_code: 0, .. })
+    ));
+}
@@ -1,22 +1,39 @@
-use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use axum::{
-    extract::{Path, State},
-    http::{header::CONTENT_TYPE, StatusCode},
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
     response::{sse::Event as SseEvent, IntoResponse, Sse},
     routing::get,
     Json, Router,
 };
-use futures_util::StreamExt;
-use rip_kernel::Runtime;
-use rip_log::{write_snapshot, EventLog};
+use clap::Parser;
+use rip_config::{ConfigLoader, LoadedConfig, RipConfig, ToolsConfig};
+use rip_kernel::{transcript_from_events, CancellationToken, Event, EventKind, Runtime};
+use rip_log::{read_snapshot, write_snapshot, EventLog};
+use rip_provider_chat_completions::ChatCompletionsProvider;
+use rip_provider_openresponses::{
+    EndpointApi, EndpointConfig, OpenResponsesAdapter, Provider, ProviderRouter, RouterConfig,
+    ScriptedProvider,
+};
+use rip_tools::{
+    register_builtin_tools, BuiltinToolConfig, PermissionConfig, PermissionPolicy, SandboxProfile,
+    ToolMode, ToolModes, ToolPermissions, ToolRegistry, ToolRunner,
+};
 use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpListener,
-    sync::{broadcast, Mutex},
+    sync::{broadcast, mpsc, Mutex},
 };
-use tokio_stream::wrappers::BroadcastStream;
-use utoipa::{OpenApi, ToSchema};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use utoipa_axum::{router::OpenApiRouter, routes};
 use uuid::Uuid;
 
@@ -26,13 +43,171 @@ struct AppState {
     event_log: Arc<EventLog>,
     snapshot_dir: Arc<std::path::PathBuf>,
     runtime: Arc<Runtime>,
+    tools: ToolControls,
     openapi_json: Arc<String>,
 }
 
+/// Per-session tool state shared by the tool runner and the handlers that
+/// change it.
+#[derive(Clone, Default)]
+struct ToolControls {
+    permissions: Arc<ToolPermissions>,
+    modes: Arc<ToolModes>,
+}
+
+impl ToolControls {
+    fn attach(&self, runner: ToolRunner) -> ToolRunner {
+        runner
+            .with_permissions(self.permissions.clone())
+            .with_modes(self.modes.clone())
+    }
+}
+
 #[derive(Clone)]
 struct SessionHandle {
     sender: broadcast::Sender<rip_kernel::Event>,
     events: Arc<Mutex<Vec<rip_kernel::Event>>>,
+    /// Held for the whole run so inputs execute one at a time.
+    run: Arc<Mutex<RunState>>,
+    /// Queue of the session's worker; inputs run in the order they arrive.
+    inputs: mpsc::UnboundedSender<InputPayload>,
+    /// Inputs accepted but not finished (queued or running).
+    pending: Arc<AtomicUsize>,
+    /// Once cancelled the handle stays in the session map as a tombstone
+    /// until its closing frames are written.
+    cancel: CancellationToken,
+    /// Fires when the worker has stopped and can write no more frames.
+    finished: CancellationToken,
+}
+
+/// What the next run of a session continues from.
+#[derive(Default)]
+struct RunState {
+    next_seq: u64,
+    transcript: Vec<serde_json::Value>,
+}
+
+impl SessionHandle {
+    /// A handle whose history is `events` (the next frame follows the last
+    /// one and the next run continues their conversation), with a worker
+    /// that runs its inputs.
+    fn start(state: &AppState, session_id: &str, events: Vec<Event>) -> Self {
+        let (sender, _receiver) = broadcast::channel(128);
+        let (inputs, queue) = mpsc::unbounded_channel();
+        let run = RunState {
+            next_seq: events.last().map_or(0, |event| event.seq + 1),
+            transcript: transcript_from_events(&events),
+        };
+        let handle = Self {
+            sender,
+            events: Arc::new(Mutex::new(events)),
+            run: Arc::new(Mutex::new(run)),
+            inputs,
+            pending: Arc::new(AtomicUsize::new(0)),
+            cancel: CancellationToken::new(),
+            finished: CancellationToken::new(),
+        };
+        let worker = SessionWorker {
+            session_id: session_id.to_string(),
+            sender: handle.sender.clone(),
+            events: handle.events.clone(),
+            run: handle.run.clone(),
+            pending: handle.pending.clone(),
+            cancel: handle.cancel.clone(),
+            finished: handle.finished.clone(),
+            runtime: state.runtime.clone(),
+            event_log: state.event_log.clone(),
+            snapshot_dir: state.snapshot_dir.clone(),
+        };
+        tokio::spawn(worker.run_inputs(queue));
+        handle
+    }
+}
+
+/// Runs a session's queued inputs one after another; it ends once the
+/// session is cancelled or no handle can queue more.
+struct SessionWorker {
+    session_id: String,
+    sender: broadcast::Sender<Event>,
+    events: Arc<Mutex<Vec<Event>>>,
+    run: Arc<Mutex<RunState>>,
+    pending: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+    finished: CancellationToken,
+    runtime: Arc<Runtime>,
+    event_log: Arc<EventLog>,
+    snapshot_dir: Arc<std::path::PathBuf>,
+}
+
+impl SessionWorker {
+    /// Once the worker stops, the session's shells and background jobs go.
+    async fn run_inputs(self, queue: mpsc::UnboundedReceiver<InputPayload>) {
+        let _finished = self.finished.clone().drop_guard();
+        self.run_queue(queue).await;
+        self.runtime.end_session(&self.session_id);
+    }
+
+    async fn run_queue(&self, mut queue: mpsc::UnboundedReceiver<InputPayload>) {
+        loop {
+            // Inputs queued before a cancel still run (and end `cancelled`).
+            let payload = tokio::select! {
+                biased;
+                payload = queue.recv() => match payload {
+                    Some(payload) => payload,
+                    None => return,
+                },
+                _ = self.cancel.cancelled() => return,
+            };
+            let mut run = self.run.lock().await;
+            if self.cancel.is_cancelled() && self.closed().await {
+                return;
+            }
+            let mut session = self
+                .runtime
+                .start_session(payload.input)
+                .with_id(self.session_id.clone())
+                .with_start_seq(run.next_seq)
+                .with_transcript(std::mem::take(&mut run.transcript))
+                .with_cancel(self.cancel.clone());
+            if let Some(model) = payload.model {
+                session = session.with_model(model);
+            }
+            while let Some(event) = session.next_event().await {
+                // Recorded before it is broadcast, so a subscriber that
+                // snapshots the history under this lock sees every frame once.
+                let mut events = self.events.lock().await;
+                events.push(event.clone());
+                let _ = self.sender.send(event.clone());
+                drop(events);
+                let _ = self.event_log.append(&event);
+            }
+            run.next_seq = session.next_seq();
+            run.transcript = session.transcript().to_vec();
+
+            let events = self.events.lock().await;
+            let _ = write_snapshot(&*self.snapshot_dir, &self.session_id, &events);
+            drop(events);
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            // A queued input that met a cancel ran as `started` + `cancelled`
+            // above; anything still queued is dropped.
+            if self.cancel.is_cancelled() {
+                return;
+            }
+        }
+    }
+
+    /// Whether the last run already ended with `cancelled`.
+    async fn closed(&self) -> bool {
+        let events = self.events.lock().await;
+        ended_by_cancel(&events)
+    }
+}
+
+fn ended_by_cancel(events: &[Event]) -> bool {
+    matches!(
+        events.last().map(|event| &event.kind),
+        Some(EventKind::SessionEnded { reason }) if reason == "cancelled"
+    )
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
@@ -40,9 +215,72 @@ struct SessionCreated {
     session_id: String,
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EventsQuery {
+    /// First `seq` to deliver; earlier frames still in the session history
+    /// are replayed before live ones. `Last-Event-ID` takes precedence.
+    from_seq: Option<u64>,
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 struct InputPayload {
     input: String,
+    /// Overrides the configured default model for this input.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ModePayload {
+    mode: SessionMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SessionMode {
+    Default,
+    /// Only read-only tools (`read`, `ls`, `glob`, `grep`) are offered and run.
+    Plan,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct PermissionPayload {
+    decision: PermissionChoice,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum PermissionChoice {
+    Approve,
+    Deny,
+}
+
+#[derive(Parser)]
+#[command(name = "ripd", about = "RIP agent server")]
+struct Args {
+    /// Address to listen on (`server.bind`).
+    #[arg(long)]
+    bind: Option<String>,
+    /// Directory for the event log and snapshots (`data_dir`).
+    #[arg(long)]
+    data_dir: Option<std::path::PathBuf>,
+    /// Root the builtin tools work in (`workspace_root`).
+    #[arg(long)]
+    workspace_root: Option<std::path::PathBuf>,
+    /// Default model (`model`).
+    #[arg(long)]
+    model: Option<String>,
+    /// Sets any config key, e.g. `--set tools.max_bytes=65536`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    /// Lets the workspace's `.rip/config.toml` set the bind address, data
+    /// dir, sandbox, limits, permissions and providers.
+    #[arg(long)]
+    trust_project: bool,
+    /// Prints the merged config and the layer behind each value, then exits.
+    #[arg(long)]
+    print_config: bool,
 }
 
 #[derive(OpenApi)]
@@ -55,22 +293,56 @@ struct ApiDoc;
 
 #[tokio::main]
 async fn main() {
-    let app = build_app(data_dir());
+    let args = Args::parse();
+    let cwd =
+        std::env::current_dir().unwrap_or_else(|err| exit_with(&format!("current dir: {err}")));
+    let loaded = load_config(&args, &cwd, std::env::vars()).unwrap_or_else(|err| exit_with(&err));
+    for (key, source) in loaded.ignored() {
+        eprintln!("ripd: ignoring {key} from {source} (pass --trust-project to apply it)");
+    }
+    if args.print_config {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&loaded).expect("config json")
+        );
+        return;
+    }
+    let config = loaded.config;
+    let addr: SocketAddr = config
+        .server
+        .bind
+        .parse()
+        .unwrap_or_else(|err| exit_with(&format!("server.bind {}: {err}", config.server.bind)));
+    let tools = ToolControls {
+        permissions: Arc::new(
+            tool_permissions(&config.permissions).unwrap_or_else(|err| exit_with(&err)),
+        ),
+        modes: Arc::default(),
+    };
+    let app = build_app_with_runtime(
+        config.data_dir.clone(),
+        build_runtime(&config, &tools).unwrap_or_else(|err| exit_with(&err)),
+        tools,
+    );
 
-    let addr: SocketAddr = "127.0.0.1:7341".parse().expect("addr");
     eprintln!("ripd listening on http://{addr}");
 
     let listener = TcpListener::bind(addr).await.expect("bind");
     axum::serve(listener, app).await.expect("server");
 }
 
-fn build_app(data_dir: std::path::PathBuf) -> Router {
+fn build_app_with_runtime(
+    data_dir: std::path::PathBuf,
+    runtime: Runtime,
+    tools: ToolControls,
+) -> Router {
     let (router, openapi_json) = build_openapi_router();
     let state = AppState {
         sessions: Arc::new(Mutex::new(HashMap::new())),
         event_log: Arc::new(EventLog::new(data_dir.join("events.jsonl")).expect("event log")),
         snapshot_dir: Arc::new(data_dir.join("snapshots")),
-        runtime: Arc::new(Runtime::new()),
+        runtime: Arc::new(runtime),
+        tools,
         openapi_json: Arc::new(openapi_json),
     };
 
@@ -85,6 +357,8 @@ fn build_openapi_router() -> (Router<AppState>, String) {
         .routes(routes!(send_input))
         .routes(routes!(stream_events))
         .routes(routes!(cancel_session))
+        .routes(routes!(decide_permission))
+        .routes(routes!(set_mode))
         .split_for_parts();
     let json = api
         .to_pretty_json()
@@ -102,16 +376,12 @@ fn build_openapi_router() -> (Router<AppState>, String) {
 )]
 async fn create_session(State(state): State<AppState>) -> impl IntoResponse {
     let session_id = Uuid::new_v4().to_string();
-    let (sender, _receiver) = broadcast::channel(128);
-
+    // An empty snapshot lets a restarted server resume the session before
+    // its first input reaches the log.
+    let _ = write_snapshot(&*state.snapshot_dir, &session_id, &[]);
     let mut sessions = state.sessions.lock().await;
-    sessions.insert(
-        session_id.clone(),
-        SessionHandle {
-            sender,
-            events: Arc::new(Mutex::new(Vec::new())),
-        },
-    );
+    let handle = SessionHandle::start(&state, &session_id, Vec::new());
+    sessions.insert(session_id.clone(), handle);
 
     (StatusCode::CREATED, Json(SessionCreated { session_id }))
 }
@@ -133,39 +403,15 @@ async fn send_input(
     State(state): State<AppState>,
     Json(payload): Json<InputPayload>,
 ) -> impl IntoResponse {
-    let sender = {
-        let sessions = state.sessions.lock().await;
-        match sessions.get(&session_id) {
-            Some(handle) => handle.sender.clone(),
-            None => return StatusCode::NOT_FOUND.into_response(),
-        }
-    };
-
-    let events = {
-        let sessions = state.sessions.lock().await;
-        match sessions.get(&session_id) {
-            Some(handle) => handle.events.clone(),
-            None => return StatusCode::NOT_FOUND.into_response(),
-        }
+    let Some(handle) = session_handle(&state, &session_id).await else {
+        return StatusCode::NOT_FOUND.into_response();
     };
-
-    let event_log = state.event_log.clone();
-    let snapshot_dir = state.snapshot_dir.clone();
-    let runtime = state.runtime.clone();
-
-    tokio::spawn(async move {
-        let mut session = runtime.start_session(payload.input);
-        while let Some(event) = session.next_event() {
-            let _ = sender.send(event.clone());
-            let mut guard = events.lock().await;
-            guard.push(event.clone());
-            let _ = event_log.append(&event);
-        }
-
-        let guard = events.lock().await;
-        let _ = write_snapshot(&*snapshot_dir, &session_id, &guard);
-    });
-
+    handle.pending.fetch_add(1, Ordering::SeqCst);
+    if handle.inputs.send(payload).is_err() {
+        // The worker is gone: the session was cancelled meanwhile.
+        handle.pending.fetch_sub(1, Ordering::SeqCst);
+        return StatusCode::NOT_FOUND.into_response();
+    }
     StatusCode::ACCEPTED.into_response()
 }
 
@@ -173,36 +419,50 @@ async fn send_input(
     get,
     path = "/sessions/{id}/events",
     params(
-        ("id" = String, Path, description = "Session id")
+        ("id" = String, Path, description = "Session id"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Resume after this `seq`"),
+        EventsQuery
     ),
     responses(
-        (status = 200, description = "SSE stream of event frames"),
+        (status = 200, description = "SSE stream of event frames, each with `id: {seq}`; a `lagged` event marks frames re-sent from history after the stream fell behind"),
         (status = 404, description = "Session not found")
     )
 )]
 async fn stream_events(
     Path(session_id): Path<String>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    let receiver = {
-        let sessions = state.sessions.lock().await;
-        match sessions.get(&session_id) {
-            Some(handle) => handle.sender.subscribe(),
-            None => return StatusCode::NOT_FOUND.into_response(),
-        }
+    let Some(handle) = session_handle(&state, &session_id).await else {
+        return StatusCode::NOT_FOUND.into_response();
     };
+    let resume_from = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|seq| seq + 1)
+        .or(query.from_seq);
 
-    let stream = BroadcastStream::new(receiver).filter_map(|result| async move {
-        match result {
-            Ok(event) => {
-                let json = match serde_json::to_string(&event) {
-                    Ok(value) => value,
-                    Err(_) => return None,
-                };
-                Some(Ok::<SseEvent, Infallible>(SseEvent::default().data(json)))
-            }
-            Err(_) => None,
+    let replay = {
+        let events = handle.events.lock().await;
+        let receiver = handle.sender.subscribe();
+        let next_seq =
+            resume_from.unwrap_or_else(|| events.last().map_or(0, |event| event.seq + 1));
+        EventReplay {
+            pending: events
+                .iter()
+                .filter(|event| event.seq >= next_seq)
+                .cloned()
+                .collect(),
+            receiver,
+            history: handle.events.clone(),
+            next_seq,
         }
+    };
+    let stream = futures_util::stream::unfold(replay, |mut replay| async move {
+        let message = replay.next_message().await?;
+        Some((Ok::<SseEvent, Infallible>(message), replay))
     });
 
     Sse::new(stream)
@@ -210,6 +470,52 @@ async fn stream_events(
         .into_response()
 }
 
+/// One SSE subscriber: frames from the session history first, then live
+/// ones, each delivered once and in `seq` order.
+struct EventReplay {
+    pending: VecDeque<Event>,
+    receiver: broadcast::Receiver<Event>,
+    history: Arc<Mutex<Vec<Event>>>,
+    next_seq: u64,
+}
+
+impl EventReplay {
+    async fn next_message(&mut self) -> Option<SseEvent> {
+        loop {
+            let event = match self.pending.pop_front() {
+                Some(event) => event,
+                None => match self.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        // The history has every frame the channel dropped.
+                        let history = self.history.lock().await;
+                        self.pending.extend(
+                            history
+                                .iter()
+                                .filter(|event| event.seq >= self.next_seq)
+                                .cloned(),
+                        );
+                        let notice = serde_json::json!({
+                            "missed": missed,
+                            "replay_from": self.next_seq,
+                        });
+                        return Some(SseEvent::default().event("lagged").data(notice.to_string()));
+                    }
+                },
+            };
+            if event.seq < self.next_seq {
+                continue;
+            }
+            self.next_seq = event.seq + 1;
+            let Ok(json) = serde_json::to_string(&event) else {
+                continue;
+            };
+            return Some(SseEvent::default().id(event.seq.to_string()).data(json));
+        }
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/sessions/{id}/cancel",
@@ -218,21 +524,176 @@ async fn stream_events(
     ),
     responses(
         (status = 204, description = "Session canceled"),
-        (status = 404, description = "Session not found")
+        (status = 404, description = "Session not found"),
+        (status = 409, description = "Nothing to cancel: the last run already ended and no input is queued")
     )
 )]
 async fn cancel_session(
     Path(session_id): Path<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    let mut sessions = state.sessions.lock().await;
-    if sessions.remove(&session_id).is_some() {
+    let Some(handle) = session_handle(&state, &session_id).await else {
+        return StatusCode::NOT_FOUND;
+    };
+    {
+        let events = handle.events.lock().await;
+        let run_ended = matches!(
+            events.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { .. })
+        );
+        if run_ended && handle.pending.load(Ordering::SeqCst) == 0 {
+            return StatusCode::CONFLICT;
+        }
+    }
+    {
+        // Checked and set under the map lock so only one cancel proceeds.
+        let _sessions = state.sessions.lock().await;
+        if handle.cancel.is_cancelled() {
+            return StatusCode::NOT_FOUND;
+        }
+        handle.cancel.cancel();
+    }
+    state.tools.modes.set(&session_id, ToolMode::Default);
+    // Background jobs stop now; anything started by the run still ending is
+    // torn down when its worker stops.
+    state.runtime.end_session(&session_id);
+
+    // Runs end themselves with `cancelled`, including a queued one that had
+    // not started; a session that never ran gets the closing frame here.
+    // The tombstone goes once the log shows the cancel, so a lazy resume
+    // cannot start a second worker meanwhile.
+    tokio::spawn(async move {
+        handle.finished.cancelled().await;
+        let mut run = handle.run.lock().await;
+        let mut events = handle.events.lock().await;
+        if events.is_empty() && handle.pending.load(Ordering::SeqCst) == 0 {
+            let event = Event {
+                id: Uuid::new_v4().to_string(),
+                session_id: session_id.clone(),
+                timestamp_ms: now_ms(),
+                seq: run.next_seq,
+                kind: EventKind::SessionEnded {
+                    reason: "cancelled".to_string(),
+                },
+            };
+            run.next_seq += 1;
+            let _ = handle.sender.send(event.clone());
+            let _ = state.event_log.append(&event);
+            events.push(event);
+        }
+        let _ = write_snapshot(&*state.snapshot_dir, &session_id, &events);
+        drop(events);
+        drop(run);
+        state.sessions.lock().await.remove(&session_id);
+    });
+
+    StatusCode::NO_CONTENT
+}
+
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/permissions/{tool_id}",
+    params(
+        ("id" = String, Path, description = "Session id"),
+        ("tool_id" = String, Path, description = "Tool call awaiting a decision")
+    ),
+    request_body = PermissionPayload,
+    responses(
+        (status = 204, description = "Decision delivered"),
+        (status = 404, description = "No pending call with this id in the session")
+    )
+)]
+async fn decide_permission(
+    Path((session_id, tool_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(payload): Json<PermissionPayload>,
+) -> impl IntoResponse {
+    let approve = payload.decision == PermissionChoice::Approve;
+    if state
+        .tools
+        .permissions
+        .decide(&session_id, &tool_id, approve)
+    {
         StatusCode::NO_CONTENT
     } else {
         StatusCode::NOT_FOUND
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/mode",
+    params(
+        ("id" = String, Path, description = "Session id")
+    ),
+    request_body = ModePayload,
+    responses(
+        (status = 204, description = "Mode set; applies from the next tool call"),
+        (status = 404, description = "Session not found")
+    )
+)]
+async fn set_mode(
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+    Json(payload): Json<ModePayload>,
+) -> impl IntoResponse {
+    if session_handle(&state, &session_id).await.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let mode = match payload.mode {
+        SessionMode::Default => ToolMode::Default,
+        SessionMode::Plan => ToolMode::Plan,
+    };
+    state.tools.modes.set(&session_id, mode);
+    StatusCode::NO_CONTENT
+}
+
+/// The handle for `session_id`, resumed from disk when an earlier process
+/// ran the session.
+async fn session_handle(state: &AppState, session_id: &str) -> Option<SessionHandle> {
+    if let Some(handle) = state.sessions.lock().await.get(session_id) {
+        return (!handle.cancel.is_cancelled()).then(|| handle.clone());
+    }
+    // Reading the log can take a while; other sessions stay reachable.
+    let event_log = state.event_log.clone();
+    let snapshot_dir = state.snapshot_dir.clone();
+    let id = session_id.to_string();
+    let events = tokio::task::spawn_blocking(move || resume_events(&event_log, &snapshot_dir, &id))
+        .await
+        .ok()??;
+    let mut sessions = state.sessions.lock().await;
+    // Another request may have resumed it meanwhile.
+    let handle = sessions
+        .entry(session_id.to_string())
+        .or_insert_with(|| SessionHandle::start(state, session_id, events));
+    (!handle.cancel.is_cancelled()).then(|| handle.clone())
+}
+
+/// History of a session from the event log, or from its snapshot when the
+/// log has none or cannot be replayed. Sessions without a snapshot or frames,
+/// or closed by a cancel, are not resumed.
+fn resume_events(
+    event_log: &EventLog,
+    snapshot_dir: &std::path::Path,
+    session_id: &str,
+) -> Option<Vec<Event>> {
+    // Ids are always UUIDs; this also keeps the snapshot path inside its dir.
+    Uuid::parse_str(session_id).ok()?;
+    let events = match event_log.replay_session(session_id) {
+        Ok(events) if !events.is_empty() => events,
+        // Created but never run: only its (empty) snapshot exists.
+        _ => read_snapshot(snapshot_dir.join(format!("{session_id}.json"))).ok()?,
+    };
+    (!ended_by_cancel(&events)).then_some(events)
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 async fn openapi_spec(State(state): State<AppState>) -> impl IntoResponse {
     (
         StatusCode::OK,
@@ -241,11 +702,123 @@ async fn openapi_spec(State(state): State<AppState>) -> impl IntoResponse {
     )
 }
 
-fn data_dir() -> std::path::PathBuf {
-    if let Ok(value) = std::env::var("RIP_DATA_DIR") {
-        return std::path::PathBuf::from(value);
+fn exit_with(err: &str) -> ! {
+    eprintln!("ripd: {err}");
+    std::process::exit(2);
+}
+
+/// Config layers for this process: the user file and `RIP_*` variables
+/// from `env`, the flags in `args`, and the project file of the workspace
+/// root those settle on (relative to `cwd`, which is also the default).
+fn load_config(
+    args: &Args,
+    cwd: &std::path::Path,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<LoadedConfig, String> {
+    let mut loader = ConfigLoader::standard(env).trust_project(args.trust_project);
+    if let Some(bind) = &args.bind {
+        loader = loader.cli("--bind", "server.bind", bind.as_str());
+    }
+    if let Some(dir) = &args.data_dir {
+        loader = loader.cli("--data-dir", "data_dir", dir.to_string_lossy().into_owned());
+    }
+    if let Some(root) = &args.workspace_root {
+        let root = root.to_string_lossy().into_owned();
+        loader = loader.cli("--workspace-root", "workspace_root", root);
+    }
+    if let Some(model) = &args.model {
+        loader = loader.cli("--model", "model", model.as_str());
+    }
+    for assignment in &args.overrides {
+        loader = loader.cli_override(assignment)?;
+    }
+    let root = match loader.load()?.config.workspace_root {
+        Some(root) => cwd.join(root),
+        None => cwd.to_path_buf(),
+    };
+    loader
+        .project_file(root.join(".rip").join("config.toml"))
+        .load()
+}
+
+fn build_runtime(config: &RipConfig, controls: &ToolControls) -> Result<Runtime, String> {
+    let workspace_root = config
+        .workspace_root
+        .clone()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| ".".into()));
+    let tools = controls.attach(build_tool_runner(&config.tools, workspace_root)?);
+    let runtime = Runtime::new().with_tools(Arc::new(tools));
+    if let Some(path) = &config.provider.script {
+        let provider =
+            ScriptedProvider::from_script(path).map_err(|err| format!("provider.script: {err}"))?;
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider)).model("scripted");
+        return Ok(runtime.with_provider(Arc::new(adapter)));
+    }
+    let Some(path) = &config.provider.config else {
+        return Ok(runtime);
+    };
+    let router_config = provider_config(path)?;
+    let router = ProviderRouter::from_config_with(&router_config, build_provider)
+        .map_err(|err| format!("provider.config {}: {err}", path.display()))?;
+    let mut adapter = OpenResponsesAdapter::new(Arc::new(router));
+    if let Some(model) = config.model.clone().or(router_config.model) {
+        adapter = adapter.model(model);
+    }
+    Ok(runtime.with_provider(Arc::new(adapter)))
+}
+
+fn build_provider(endpoint: &EndpointConfig) -> Result<Arc<dyn Provider>, String> {
+    Ok(match endpoint.api {
+        EndpointApi::Responses => Arc::new(endpoint.build_client()?),
+        EndpointApi::ChatCompletions => Arc::new(ChatCompletionsProvider::from_endpoint(endpoint)?),
+    })
+}
+
+fn provider_config(path: &std::path::Path) -> Result<RouterConfig, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|err| format!("provider.config {}: {err}", path.display()))?;
+    serde_json::from_str(&raw).map_err(|err| format!("provider.config {}: {err}", path.display()))
+}
+
+fn build_tool_runner(
+    tools: &ToolsConfig,
+    workspace_root: std::path::PathBuf,
+) -> Result<ToolRunner, String> {
+    let mut config = BuiltinToolConfig {
+        workspace_root,
+        respect_ignore_files: true,
+        persistent_shell: tools.persistent_shell,
+        sandbox: sandbox_profile(tools.sandbox.as_deref())?,
+        resource_limits: tools.limits.clone().into_iter().collect(),
+        default_timeout_ms: tools.timeout_ms,
+        ..BuiltinToolConfig::default()
+    };
+    if let Some(max_bytes) = tools.max_bytes {
+        config.max_bytes = max_bytes;
+    }
+    if let Some(max_results) = tools.max_results {
+        config.max_results = max_results;
+    }
+    if let Some(max_depth) = tools.max_depth {
+        config.max_depth = max_depth;
+    }
+    let registry = Arc::new(ToolRegistry::default());
+    register_builtin_tools(&registry, config);
+    Ok(ToolRunner::new(registry, tools.concurrency))
+}
+
+/// An empty rule set (the default) allows every call.
+fn tool_permissions(config: &PermissionConfig) -> Result<ToolPermissions, String> {
+    PermissionPolicy::from_config(config).map(ToolPermissions::new)
+}
+
+fn sandbox_profile(name: Option<&str>) -> Result<Option<SandboxProfile>, String> {
+    match name {
+        None | Some("" | "off") => Ok(None),
+        Some(name) => SandboxProfile::named(name)
+            .map(Some)
+            .ok_or_else(|| format!("tools.sandbox: unknown sandbox profile `{name}`")),
     }
-    std::path::PathBuf::from("data")
 }
 
 #[cfg(test)]
@@ -253,12 +826,17 @@ mod tests {
     use super::*;
     use axum::body::Body;
     use axum::http::Request;
+    use futures_util::StreamExt;
     use http_body_util::BodyExt;
     use std::path::PathBuf;
     use tempfile::tempdir;
     use tokio::time::{sleep, timeout, Duration};
     use tower::util::ServiceExt;
 
+    fn build_app(data_dir: PathBuf) -> Router {
+        build_app_with_runtime(data_dir, Runtime::new(), ToolControls::default())
+    }
+
     async fn create_session_id(app: &Router) -> String {
         let response = app
             .clone()
@@ -542,9 +1120,19 @@ mod tests {
         let json = data_line.trim_start_matches("data:").trim();
         let value: serde_json::Value = serde_json::from_str(json).expect("json");
         assert!(value.get("type").is_some());
+        let id_line = message
+            .lines()
+            .find(|line| line.starts_with("id:"))
+            .expect("id line");
+        assert_eq!(
+            id_line.trim_start_matches("id:").trim(),
+            value["seq"].to_string()
+        );
 
         for line in message.lines() {
-            assert!(line.starts_with("data:") || line.starts_with("event:"));
+            assert!(
+                line.starts_with("data:") || line.starts_with("event:") || line.starts_with("id:")
+            );
         }
     }
 
@@ -596,62 +1184,1298 @@ mod tests {
         assert!(second_seq > first_seq);
     }
 
-    struct TestSseReader {
-        body: Body,
-        buffer: String,
+    async fn post_input(app: &Router, session_id: &str, input: &str) -> StatusCode {
+        let body = serde_json::json!({ "input": input }).to_string();
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/input"))
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .expect("response")
+            .status()
     }
 
-    impl TestSseReader {
-        fn new(body: Body) -> Self {
-            Self {
-                body,
-                buffer: String::new(),
-            }
-        }
-
-        async fn next_data_message(&mut self) -> Option<String> {
+    async fn wait_for_log_frames(log_path: &std::path::Path, count: usize) {
+        timeout(Duration::from_secs(5), async {
             loop {
-                if let Some((message, remainder)) = split_sse_message(&self.buffer) {
-                    self.buffer = remainder;
-                    if message.lines().any(|line| line.starts_with("data:")) {
-                        return Some(message);
-                    }
-                }
-
-                let frame = match timeout(Duration::from_secs(1), self.body.frame()).await {
-                    Ok(Some(Ok(frame))) => frame,
-                    Ok(Some(Err(_))) => return None,
-                    Ok(None) => return None,
-                    Err(_) => return None,
-                };
-                if let Ok(data) = frame.into_data() {
-                    let payload = String::from_utf8_lossy(&data).to_string();
-                    self.buffer.push_str(&payload);
+                let lines = std::fs::read_to_string(log_path)
+                    .map(|raw| raw.lines().count())
+                    .unwrap_or(0);
+                if lines >= count {
+                    break;
                 }
+                sleep(Duration::from_millis(20)).await;
             }
-        }
+        })
+        .await
+        .expect("log timeout");
     }
 
-    fn split_sse_message(buffer: &str) -> Option<(String, String)> {
-        buffer.find("\n\n").map(|idx| {
-            let message = buffer[..idx].to_string();
-            let remainder = buffer[idx + 2..].to_string();
-            (message, remainder)
+    #[tokio::test]
+    async fn restarted_server_resumes_session_and_continues_seq() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let log_path = data_dir.join("events.jsonl");
+        let first = build_app(data_dir.clone());
+        let session_id = create_session_id(&first).await;
+        assert_eq!(
+            post_input(&first, &session_id, "hi").await,
+            StatusCode::ACCEPTED
+        );
+        wait_for_log_frames(&log_path, 3).await;
+        let snapshot_path = data_dir
+            .join("snapshots")
+            .join(format!("{session_id}.json"));
+        timeout(Duration::from_secs(5), async {
+            while !snapshot_path.exists() {
+                sleep(Duration::from_millis(20)).await;
+            }
         })
-    }
+        .await
+        .expect("snapshot timeout");
+        drop(first);
 
-    fn extract_data_json(message: &str) -> Option<serde_json::Value> {
-        let data_line = message.lines().find(|line| line.starts_with("data:"))?;
-        let json = data_line.trim_start_matches("data:").trim();
-        serde_json::from_str(json).ok()
+        let second = build_app(data_dir.clone());
+        let response = second
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/sessions/{session_id}/events"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut reader = TestSseReader::new(response.into_body());
+
+        assert_eq!(
+            post_input(&second, &session_id, "again").await,
+            StatusCode::ACCEPTED
+        );
+        let message = reader.next_data_message().await.expect("frame");
+        let frame = extract_data_json(&message).expect("json");
+        assert_eq!(frame["type"], "session_started");
+        assert_eq!(frame["input"], "again");
+        assert_eq!(frame["seq"], 3);
+
+        wait_for_log_frames(&log_path, 6).await;
+        let log = EventLog::new(&log_path).expect("log");
+        let replayed = log.replay_session(&session_id).expect("replay");
+        assert_eq!(replayed.len(), 6);
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let snapshot = read_snapshot(&snapshot_path).unwrap_or_default();
+                if snapshot.len() == replayed.len() {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("snapshot timeout");
+        rip_log::verify_snapshot(&log, &snapshot_path).expect("snapshot matches log");
     }
 
-    #[test]
-    fn data_dir_prefers_env_var() {
+    #[tokio::test]
+    async fn resumed_sessions_keep_their_conversation() {
         let dir = tempdir().expect("tmp");
-        std::env::set_var("RIP_DATA_DIR", dir.path());
-        let value = data_dir();
-        std::env::remove_var("RIP_DATA_DIR");
-        assert_eq!(value, dir.path());
+        let data_dir = dir.path().join("data");
+        let log_path = data_dir.join("events.jsonl");
+        let answer = serde_json::json!({
+            "type": "response.output_item.done",
+            "sequence_number": 1,
+            "output_index": 0,
+            "item": {
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "output_text", "text": "noted: milk"}]
+            }
+        });
+        let scripted = |provider: Arc<ScriptedProvider>| {
+            let adapter = OpenResponsesAdapter::new(provider);
+            Runtime::new().with_provider(Arc::new(adapter))
+        };
+        let first = Arc::new(ScriptedProvider::new().turn(format!(
+            "event: response.output_item.done\ndata: {answer}\n\ndata: [DONE]\n\n"
+        )));
+        let app =
+            build_app_with_runtime(data_dir.clone(), scripted(first), ToolControls::default());
+        let session_id = create_session_id(&app).await;
+        assert_eq!(
+            post_input(&app, &session_id, "remember milk").await,
+            StatusCode::ACCEPTED
+        );
+        wait_for_log_frames(&log_path, 4).await;
+        drop(app);
+
+        let second = Arc::new(ScriptedProvider::new().turn("data: [DONE]\n\n"));
+        let app = build_app_with_runtime(
+            data_dir.clone(),
+            scripted(second.clone()),
+            ToolControls::default(),
+        );
+        assert_eq!(
+            post_input(&app, &session_id, "what did I say?").await,
+            StatusCode::ACCEPTED
+        );
+        wait_for_log_frames(&log_path, 7).await;
+        let requests = second.requests();
+        let input = requests[0].body()["input"].as_array().expect("input");
+        let roles: Vec<&str> = input
+            .iter()
+            .map(|item| item["role"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+        assert_eq!(input[0]["content"], "remember milk");
+    }
+
+    #[tokio::test]
+    async fn sessions_created_before_any_input_are_resumed() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let first = build_app(data_dir.clone());
+        let session_id = create_session_id(&first).await;
+        drop(first);
+
+        let second = build_app(data_dir.clone());
+        let mut reader = open_events(&second, format!("/sessions/{session_id}/events"), None).await;
+        assert_eq!(
+            post_input(&second, &session_id, "hi").await,
+            StatusCode::ACCEPTED
+        );
+        let message = reader.next_data_message().await.expect("frame");
+        let frame = extract_data_json(&message).expect("json");
+        assert_eq!(frame["type"], "session_started");
+        assert_eq!(frame["seq"], 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_sessions_stay_gone_while_they_close() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let log_path = data_dir.join("events.jsonl");
+        let app = build_app(data_dir.clone());
+        for _ in 0..20 {
+            let session_id = create_session_id(&app).await;
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/sessions/{session_id}/cancel"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            // Before the closing frame is logged too.
+            assert_eq!(
+                post_input(&app, &session_id, "hi").await,
+                StatusCode::NOT_FOUND
+            );
+        }
+        wait_for_log_frames(&log_path, 20).await;
+        sleep(Duration::from_millis(100)).await;
+        let log = std::fs::read_to_string(&log_path).expect("log");
+        let frames: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("frame"))
+            .collect();
+        assert_eq!(frames.len(), 20);
+        assert!(frames
+            .iter()
+            .all(|frame| frame["type"] == "session_ended" && frame["seq"] == 0));
+    }
+
+    #[tokio::test]
+    async fn cancelled_or_unknown_sessions_are_not_resumed() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let first = build_app(data_dir.clone());
+        let session_id = create_session_id(&first).await;
+        let response = first
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/cancel"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        wait_for_log_frames(&data_dir.join("events.jsonl"), 1).await;
+
+        let second = build_app(data_dir);
+        assert_eq!(
+            post_input(&second, &session_id, "hi").await,
+            StatusCode::NOT_FOUND
+        );
+        let unknown = Uuid::new_v4().to_string();
+        assert_eq!(
+            post_input(&second, &unknown, "hi").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            post_input(&second, "../events", "hi").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    async fn open_events(app: &Router, uri: String, last_event_id: Option<&str>) -> TestSseReader {
+        let mut request = Request::builder().method("GET").uri(uri);
+        if let Some(id) = last_event_id {
+            request = request.header("last-event-id", id);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        TestSseReader::new(response.into_body())
+    }
+
+    fn message_seq(message: &str) -> u64 {
+        let frame = extract_data_json(message).expect("json");
+        let seq = frame["seq"].as_u64().expect("seq");
+        let id = message
+            .lines()
+            .find_map(|line| line.strip_prefix("id:"))
+            .expect("id line");
+        assert_eq!(id.trim(), seq.to_string());
+        seq
+    }
+
+    #[tokio::test]
+    async fn late_subscribers_replay_from_seq_or_last_event_id() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let app = build_app(data_dir.clone());
+        let session_id = create_session_id(&app).await;
+        assert_eq!(
+            post_input(&app, &session_id, "hi").await,
+            StatusCode::ACCEPTED
+        );
+        wait_for_log_frames(&data_dir.join("events.jsonl"), 3).await;
+
+        let uri = format!("/sessions/{session_id}/events?from_seq=0");
+        let mut reader = open_events(&app, uri, None).await;
+        for expected in 0..3 {
+            let message = reader.next_data_message().await.expect("replayed frame");
+            assert_eq!(message_seq(&message), expected);
+        }
+
+        // A reconnecting EventSource repeats its URL and adds `Last-Event-ID`.
+        let uri = format!("/sessions/{session_id}/events?from_seq=0");
+        let mut reader = open_events(&app, uri, Some("1")).await;
+        let message = reader.next_data_message().await.expect("replayed frame");
+        assert_eq!(message_seq(&message), 2);
+
+        assert_eq!(
+            post_input(&app, &session_id, "again").await,
+            StatusCode::ACCEPTED
+        );
+        let message = reader.next_data_message().await.expect("live frame");
+        assert_eq!(message_seq(&message), 3);
+    }
+
+    #[tokio::test]
+    async fn lagged_subscribers_get_a_notice_and_the_missed_frames() {
+        let (sender, receiver) = broadcast::channel(2);
+        let frames: Vec<Event> = (0..5)
+            .map(|seq| Event {
+                id: format!("e{seq}"),
+                session_id: "s1".to_string(),
+                timestamp_ms: 0,
+                seq,
+                kind: EventKind::OutputTextDelta {
+                    delta: format!("d{seq}"),
+                },
+            })
+            .collect();
+        for frame in &frames {
+            let _ = sender.send(frame.clone());
+        }
+        let replay = EventReplay {
+            pending: VecDeque::new(),
+            receiver,
+            history: Arc::new(Mutex::new(frames)),
+            next_seq: 0,
+        };
+        drop(sender);
+        let stream = futures_util::stream::unfold(replay, |mut replay| async move {
+            let message = replay.next_message().await?;
+            Some((Ok::<SseEvent, Infallible>(message), replay))
+        });
+        let mut reader = TestSseReader::new(Sse::new(stream).into_response().into_body());
+
+        let notice = reader.next_data_message().await.expect("notice");
+        assert!(notice.lines().any(|line| line == "event: lagged"));
+        let notice = extract_data_json(&notice).expect("json");
+        assert_eq!(notice["missed"], 3);
+        assert_eq!(notice["replay_from"], 0);
+        for expected in 0..5 {
+            let message = reader.next_data_message().await.expect("frame");
+            assert_eq!(message_seq(&message), expected);
+        }
+        assert!(reader.next_data_message().await.is_none());
+    }
+
+    struct TestSseReader {
+        body: Body,
+        buffer: String,
+    }
+
+    impl TestSseReader {
+        fn new(body: Body) -> Self {
+            Self {
+                body,
+                buffer: String::new(),
+            }
+        }
+
+        async fn next_data_message(&mut self) -> Option<String> {
+            loop {
+                if let Some((message, remainder)) = split_sse_message(&self.buffer) {
+                    self.buffer = remainder;
+                    if message.lines().any(|line| line.starts_with("data:")) {
+                        return Some(message);
+                    }
+                }
+
+                let frame = match timeout(Duration::from_secs(1), self.body.frame()).await {
+                    Ok(Some(Ok(frame))) => frame,
+                    Ok(Some(Err(_))) => return None,
+                    Ok(None) => return None,
+                    Err(_) => return None,
+                };
+                if let Ok(data) = frame.into_data() {
+                    let payload = String::from_utf8_lossy(&data).to_string();
+                    self.buffer.push_str(&payload);
+                }
+            }
+        }
+    }
+
+    fn split_sse_message(buffer: &str) -> Option<(String, String)> {
+        buffer.find("\n\n").map(|idx| {
+            let message = buffer[..idx].to_string();
+            let remainder = buffer[idx + 2..].to_string();
+            (message, remainder)
+        })
+    }
+
+    fn extract_data_json(message: &str) -> Option<serde_json::Value> {
+        let data_line = message.lines().find(|line| line.starts_with("data:"))?;
+        let json = data_line.trim_start_matches("data:").trim();
+        serde_json::from_str(json).ok()
+    }
+
+    #[tokio::test]
+    async fn send_input_forwards_model_to_provider() {
+        let dir = tempdir().expect("tmp");
+        let models = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = models.clone();
+        let provider = move |payload: rip_provider_openresponses::CreateResponsePayload| {
+            let model = payload.body()["model"].as_str().unwrap_or_default();
+            recorded.lock().expect("lock").push(model.to_string());
+            futures_util::stream::iter(
+                rip_provider_openresponses::SseDecoder::new().push("data: [DONE]\n\n"),
+            )
+            .boxed()
+        };
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider)).model("default-model");
+        let runtime = Runtime::new().with_provider(Arc::new(adapter));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+
+        for body in [
+            "{\"input\":\"hi\"}",
+            "{\"input\":\"hi\",\"model\":\"local/llama3\"}",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/sessions/{session_id}/input"))
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+
+        timeout(Duration::from_secs(1), async {
+            while models.lock().expect("lock").len() < 2 {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("provider timeout");
+        let models = models.lock().expect("lock").clone();
+        assert_eq!(models, vec!["default-model", "local/llama3"]);
+    }
+
+    async fn post_cancel(app: &Router, session_id: &str) -> StatusCode {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/cancel"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response")
+            .status()
+    }
+
+    #[tokio::test]
+    async fn cancel_after_the_run_ended_conflicts_without_logging() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let log_path = data_dir.join("events.jsonl");
+        let app = build_app(data_dir.clone());
+        let session_id = create_session_id(&app).await;
+        assert_eq!(
+            post_input(&app, &session_id, "hi").await,
+            StatusCode::ACCEPTED
+        );
+        wait_for_log_frames(&log_path, 3).await;
+
+        assert_eq!(post_cancel(&app, &session_id).await, StatusCode::CONFLICT);
+        sleep(Duration::from_millis(100)).await;
+        let log = EventLog::new(&log_path).expect("log");
+        let frames = log.replay_session(&session_id).expect("replay");
+        assert_eq!(frames.len(), 3);
+        assert!(matches!(
+            frames.last().map(|event| &event.kind),
+            Some(EventKind::SessionEnded { reason }) if reason == "completed"
+        ));
+        // The session stays usable.
+        assert_eq!(
+            post_input(&app, &session_id, "again").await,
+            StatusCode::ACCEPTED
+        );
+    }
+
+    #[tokio::test]
+    async fn inputs_run_in_the_order_they_were_sent() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let app = build_app(data_dir.clone());
+        let session_id = create_session_id(&app).await;
+        let sent: Vec<String> = (0..8).map(|idx| format!("input {idx}")).collect();
+        for input in &sent {
+            assert_eq!(
+                post_input(&app, &session_id, input).await,
+                StatusCode::ACCEPTED
+            );
+        }
+
+        let log_path = data_dir.join("events.jsonl");
+        wait_for_log_frames(&log_path, sent.len() * 3).await;
+        let log = EventLog::new(&log_path).expect("log");
+        let started: Vec<String> = log
+            .replay_session(&session_id)
+            .expect("replay")
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                EventKind::SessionStarted { input } => Some(input),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(started, sent);
+    }
+
+    #[tokio::test]
+    async fn later_inputs_continue_the_session_transcript() {
+        let dir = tempdir().expect("tmp");
+        let inputs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = inputs.clone();
+        let provider = move |payload: rip_provider_openresponses::CreateResponsePayload| {
+            recorded
+                .lock()
+                .expect("lock")
+                .push(payload.body()["input"].clone());
+            futures_util::stream::iter(
+                rip_provider_openresponses::SseDecoder::new().push("data: [DONE]\n\n"),
+            )
+            .boxed()
+        };
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider)).model("default-model");
+        let runtime = Runtime::new().with_provider(Arc::new(adapter));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+
+        for (count, input) in [(1, "first"), (2, "second")] {
+            assert_eq!(
+                post_input(&app, &session_id, input).await,
+                StatusCode::ACCEPTED
+            );
+            timeout(Duration::from_secs(1), async {
+                while inputs.lock().expect("lock").len() < count {
+                    sleep(Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("provider timeout");
+        }
+
+        let inputs = inputs.lock().expect("lock").clone();
+        let second = inputs[1].as_array().expect("input items");
+        assert_eq!(second.len(), 2);
+        assert_eq!(second[0]["content"], "first");
+        assert_eq!(second[1]["content"], "second");
+    }
+
+    #[tokio::test]
+    async fn scripted_provider_streams_over_sse() {
+        let dir = tempdir().expect("tmp");
+        let provider = ScriptedProvider::new().turn(
+            "event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"item_id\":\"m\",\"output_index\":0,\"content_index\":0,\"delta\":\"scripted\"}\n\n\
+data: [DONE]\n\n",
+        );
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider));
+        let runtime = Runtime::new().with_provider(Arc::new(adapter));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/sessions/{session_id}/events"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        let mut reader = TestSseReader::new(response.into_body());
+
+        let send_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/input"))
+                    .header("content-type", "application/json")
+                    .body(Body::from("{\"input\":\"hi\"}"))
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(send_response.status(), StatusCode::ACCEPTED);
+
+        let mut types = Vec::new();
+        let mut deltas = Vec::new();
+        while let Some(message) = reader.next_data_message().await {
+            let value = extract_data_json(&message).expect("json");
+            let kind = value["type"].as_str().unwrap_or_default().to_string();
+            if kind == "output_text_delta" {
+                deltas.push(value["delta"].as_str().unwrap_or_default().to_string());
+            }
+            types.push(kind.clone());
+            if kind == "session_ended" {
+                assert_eq!(value["reason"], "completed");
+                break;
+            }
+        }
+        assert_eq!(types.first().map(String::as_str), Some("session_started"));
+        assert_eq!(types.last().map(String::as_str), Some("session_ended"));
+        assert_eq!(deltas, vec!["scripted".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancel_stops_running_tool_and_ends_session() {
+        let dir = tempdir().expect("tmp");
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir_all(&workspace).expect("workspace");
+        let data_dir = dir.path().join("data");
+
+        let call = serde_json::json!({
+            "type": "response.output_item.done",
+            "sequence_number": 1,
+            "output_index": 0,
+            "item": {
+                "type": "function_call",
+                "id": "fc_1",
+                "call_id": "call_1",
+                "name": "bash",
+                "arguments": "{\"command\":\"echo started; sleep 30\",\"cwd\":\".\"}",
+                "status": "completed"
+            }
+        });
+        let provider = ScriptedProvider::new().turn(format!(
+            "event: response.output_item.done\ndata: {call}\n\ndata: [DONE]\n\n"
+        ));
+        let runtime = Runtime::new()
+            .with_provider(Arc::new(OpenResponsesAdapter::new(Arc::new(provider))))
+            .with_tools(Arc::new(
+                build_tool_runner(&ToolsConfig::default(), workspace).expect("tool runner"),
+            ));
+        let app = build_app_with_runtime(data_dir.clone(), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/sessions/{session_id}/events"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        let mut reader = TestSseReader::new(response.into_body());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/input"))
+                    .header("content-type", "application/json")
+                    .body(Body::from("{\"input\":\"run it\"}"))
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        loop {
+            let message = reader.next_data_message().await.expect("frame");
+            let value = extract_data_json(&message).expect("json");
+            if value["type"] == "tool_stdout" {
+                break;
+            }
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/cancel"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let failed =
+            extract_data_json(&reader.next_data_message().await.expect("frame")).expect("json");
+        assert_eq!(failed["type"], "tool_failed");
+        assert_eq!(failed["error"], "cancelled");
+        assert_eq!(failed["stdout"], "started");
+        let ended =
+            extract_data_json(&reader.next_data_message().await.expect("frame")).expect("json");
+        assert_eq!(ended["type"], "session_ended");
+        assert_eq!(ended["reason"], "cancelled");
+
+        let snapshot_path = data_dir
+            .join("snapshots")
+            .join(format!("{session_id}.json"));
+        let snapshot = timeout(Duration::from_secs(5), async {
+            loop {
+                let last = std::fs::read_to_string(&snapshot_path)
+                    .ok()
+                    .and_then(|raw| serde_json::from_str::<Vec<serde_json::Value>>(&raw).ok())
+                    .and_then(|events| events.last().cloned());
+                if let Some(last) = last.filter(|last| last["type"] == "session_ended") {
+                    break last;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("snapshot timeout");
+        assert_eq!(snapshot["reason"], "cancelled");
+        assert_eq!(snapshot["seq"], ended["seq"]);
+
+        let log = std::fs::read_to_string(data_dir.join("events.jsonl")).expect("log");
+        let cancelled_frames = log
+            .lines()
+            .filter(|line| line.contains("\"reason\":\"cancelled\""))
+            .count();
+        assert_eq!(cancelled_frames, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn cancel_stops_the_session_background_jobs() {
+        let dir = tempdir().expect("tmp");
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir_all(&workspace).expect("workspace");
+
+        let item = |id: &str, name: &str, arguments: &str| {
+            serde_json::json!({
+                "type": "response.output_item.done",
+                "sequence_number": 1,
+                "output_index": 0,
+                "item": {
+                    "type": "function_call",
+                    "id": format!("fc_{id}"),
+                    "call_id": format!("call_{id}"),
+                    "name": name,
+                    "arguments": arguments,
+                    "status": "completed"
+                }
+            })
+        };
+        let background = item(
+            "1",
+            "bash_background",
+            "{\"command\":\"echo $$ > job.pid; exec sleep 30\"}",
+        );
+        let foreground = item("2", "bash", "{\"command\":\"sleep 30\"}");
+        let provider = ScriptedProvider::new().turn(format!(
+            "event: response.output_item.done\ndata: {background}\n\n\
+             event: response.output_item.done\ndata: {foreground}\n\ndata: [DONE]\n\n"
+        ));
+        let runtime = Runtime::new()
+            .with_provider(Arc::new(OpenResponsesAdapter::new(Arc::new(provider))))
+            .with_tools(Arc::new(
+                build_tool_runner(&ToolsConfig::default(), workspace.clone()).expect("tool runner"),
+            ));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+        assert_eq!(
+            post_input(&app, &session_id, "start the server").await,
+            StatusCode::ACCEPTED
+        );
+
+        let pid_path = workspace.join("job.pid");
+        let pid = timeout(Duration::from_secs(5), async {
+            loop {
+                let pid = std::fs::read_to_string(&pid_path).unwrap_or_default();
+                if pid.ends_with('\n') {
+                    break pid.trim().to_string();
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("job pid");
+        let proc_path = PathBuf::from(format!("/proc/{pid}"));
+        assert!(proc_path.exists());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/cancel"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        timeout(Duration::from_secs(5), async {
+            while proc_path.exists() {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("background job still running");
+    }
+
+    #[tokio::test]
+    async fn cancel_idle_session_logs_closing_frame() {
+        let dir = tempdir().expect("tmp");
+        let data_dir = dir.path().join("data");
+        let app = build_app(data_dir.clone());
+        let session_id = create_session_id(&app).await;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/cancel"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let log_path = data_dir.join("events.jsonl");
+        let line = timeout(Duration::from_secs(1), async {
+            loop {
+                if let Some(line) = std::fs::read_to_string(&log_path)
+                    .ok()
+                    .and_then(|raw| raw.lines().next().map(str::to_string))
+                {
+                    break line;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("log timeout");
+        let event: serde_json::Value = serde_json::from_str(&line).expect("json");
+        assert_eq!(event["type"], "session_ended");
+        assert_eq!(event["reason"], "cancelled");
+        assert_eq!(event["seq"], 0);
+    }
+
+    #[tokio::test]
+    async fn tool_frames_share_session_seq_and_log() {
+        let dir = tempdir().expect("tmp");
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir_all(&workspace).expect("workspace");
+        std::fs::write(workspace.join("notes.txt"), "remember the milk").expect("write");
+        let data_dir = dir.path().join("data");
+
+        let call = serde_json::json!({
+            "type": "response.output_item.done",
+            "sequence_number": 1,
+            "output_index": 0,
+            "item": {
+                "type": "function_call",
+                "id": "fc_1",
+                "call_id": "call_1",
+                "name": "read",
+                "arguments": "{\"path\":\"notes.txt\"}",
+                "status": "completed"
+            }
+        });
+        let provider = ScriptedProvider::new()
+            .turn(format!(
+                "event: response.output_item.done\ndata: {call}\n\ndata: [DONE]\n\n"
+            ))
+            .on_tool_output("milk", "data: [DONE]\n\n")
+            .turn("data: [DONE]\n\n");
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider));
+        let runtime = Runtime::new()
+            .with_provider(Arc::new(adapter))
+            .with_tools(Arc::new(
+                build_tool_runner(&ToolsConfig::default(), workspace).expect("tool runner"),
+            ));
+        let app = build_app_with_runtime(data_dir.clone(), runtime, ToolControls::default());
+        let session_id = create_session_id(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/sessions/{session_id}/events"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        let mut reader = TestSseReader::new(response.into_body());
+
+        for input in ["read my notes", "again"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/sessions/{session_id}/input"))
+                        .header("content-type", "application/json")
+                        .body(Body::from(format!("{{\"input\":\"{input}\"}}")))
+                        .unwrap(),
+                )
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+
+        let mut frames = Vec::new();
+        let mut ended = 0;
+        while ended < 2 {
+            let message = reader.next_data_message().await.expect("frame");
+            let value = extract_data_json(&message).expect("json");
+            if value["type"] == "session_ended" {
+                ended += 1;
+            }
+            frames.push(value);
+        }
+
+        for (idx, frame) in frames.iter().enumerate() {
+            assert_eq!(frame["seq"], idx as u64);
+            assert_eq!(frame["session_id"], session_id.as_str());
+        }
+        let types: Vec<&str> = frames
+            .iter()
+            .map(|frame| frame["type"].as_str().unwrap_or_default())
+            .collect();
+        let started = types.iter().position(|kind| *kind == "tool_started");
+        let stdout = types.iter().position(|kind| *kind == "tool_stdout");
+        let ended = types.iter().position(|kind| *kind == "tool_ended");
+        assert!(started < stdout && stdout < ended, "{types:?}");
+        let stdout = &frames[stdout.expect("stdout")];
+        assert!(stdout["chunk"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("remember the milk"));
+
+        let log_path = data_dir.join("events.jsonl");
+        let snapshot_path = data_dir
+            .join("snapshots")
+            .join(format!("{session_id}.json"));
+        timeout(Duration::from_secs(1), async {
+            loop {
+                let lines = std::fs::read_to_string(&log_path)
+                    .map(|raw| raw.lines().count())
+                    .unwrap_or(0);
+                if lines == frames.len() && snapshot_path.exists() {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("log timeout");
+        let logged: Vec<serde_json::Value> = std::fs::read_to_string(&log_path)
+            .expect("log")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json"))
+            .collect();
+        for (idx, frame) in logged.iter().enumerate() {
+            assert_eq!(frame["seq"], idx as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn plan_mode_narrows_tools_offered_to_the_model() {
+        let dir = tempdir().expect("tmp");
+        let offered = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = offered.clone();
+        let provider = move |payload: rip_provider_openresponses::CreateResponsePayload| {
+            let names: Vec<String> = payload.body()["tools"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|tool| tool["name"].as_str().map(str::to_string))
+                .collect();
+            recorded.lock().expect("lock").push(names);
+            futures_util::stream::iter(
+                rip_provider_openresponses::SseDecoder::new().push("data: [DONE]\n\n"),
+            )
+            .boxed()
+        };
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider));
+        let controls = ToolControls::default();
+        let runtime = Runtime::new()
+            .with_provider(Arc::new(adapter))
+            .with_tools(Arc::new(
+                controls.attach(
+                    build_tool_runner(&ToolsConfig::default(), dir.path().to_path_buf())
+                        .expect("tool runner"),
+                ),
+            ));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, controls);
+        let session_id = create_session_id(&app).await;
+
+        let post = |uri: String, body: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let response = post("/sessions/missing/mode".to_string(), "{\"mode\":\"plan\"}")
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        for (body, count) in [("{\"mode\":\"plan\"}", 1), ("{\"mode\":\"default\"}", 2)] {
+            let response = post(format!("/sessions/{session_id}/mode"), body)
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            let response = post(
+                format!("/sessions/{session_id}/input"),
+                "{\"input\":\"hi\"}",
+            )
+            .await
+            .expect("response");
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            timeout(Duration::from_secs(1), async {
+                while offered.lock().expect("lock").len() < count {
+                    sleep(Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("provider timeout");
+        }
+
+        let offered = offered.lock().expect("lock").clone();
+        assert_eq!(offered[0], vec!["glob", "grep", "ls", "read"]);
+        assert!(offered[1].iter().any(|name| name == "write"));
+        assert!(offered[1].iter().any(|name| name == "bash"));
+    }
+
+    #[tokio::test]
+    async fn ask_rules_pause_tools_until_a_client_decides() {
+        let dir = tempdir().expect("tmp");
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir_all(&workspace).expect("workspace");
+        std::fs::write(workspace.join("notes.txt"), "remember the milk").expect("write");
+
+        let call = serde_json::json!({
+            "type": "response.output_item.done",
+            "sequence_number": 1,
+            "output_index": 0,
+            "item": {
+                "type": "function_call",
+                "id": "fc_1",
+                "call_id": "call_1",
+                "name": "read",
+                "arguments": "{\"path\":\"notes.txt\"}",
+                "status": "completed"
+            }
+        });
+        let provider = ScriptedProvider::new()
+            .turn(format!(
+                "event: response.output_item.done\ndata: {call}\n\ndata: [DONE]\n\n"
+            ))
+            .on_tool_output("milk", "data: [DONE]\n\n");
+        let adapter = OpenResponsesAdapter::new(Arc::new(provider));
+        let policy = PermissionPolicy::from_config(&PermissionConfig {
+            ask: vec!["read(*.txt)".to_string()],
+            ..PermissionConfig::default()
+        })
+        .expect("policy");
+        let controls = ToolControls {
+            permissions: Arc::new(ToolPermissions::new(policy)),
+            ..ToolControls::default()
+        };
+        let runtime = Runtime::new()
+            .with_provider(Arc::new(adapter))
+            .with_tools(Arc::new(controls.attach(
+                build_tool_runner(&ToolsConfig::default(), workspace).expect("tool runner"),
+            )));
+        let app = build_app_with_runtime(dir.path().join("data"), runtime, controls);
+        let session_id = create_session_id(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/sessions/{session_id}/events"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        let mut reader = TestSseReader::new(response.into_body());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/input"))
+                    .header("content-type", "application/json")
+                    .body(Body::from("{\"input\":\"read my notes\"}"))
+                    .unwrap(),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let requested = loop {
+            let message = reader.next_data_message().await.expect("frame");
+            let value = extract_data_json(&message).expect("json");
+            assert_ne!(value["type"], "tool_ended");
+            if value["type"] == "tool_permission_requested" {
+                break value;
+            }
+        };
+        assert_eq!(requested["name"], "read");
+        assert_eq!(requested["args"]["path"], "notes.txt");
+        assert_eq!(requested["rule"], "read(*.txt)");
+        let tool_id = requested["tool_id"].as_str().expect("tool id").to_string();
+
+        let decide = |tool_id: String| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{session_id}/permissions/{tool_id}"))
+                    .header("content-type", "application/json")
+                    .body(Body::from("{\"decision\":\"approve\"}"))
+                    .unwrap(),
+            )
+        };
+        let response = decide("missing".to_string()).await.expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = decide(tool_id.clone()).await.expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = decide(tool_id.clone()).await.expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let ended = loop {
+            let message = reader.next_data_message().await.expect("frame");
+            let value = extract_data_json(&message).expect("json");
+            if value["type"] == "tool_ended" || value["type"] == "tool_failed" {
+                break value;
+            }
+        };
+        assert_eq!(ended["type"], "tool_ended");
+        assert_eq!(ended["tool_id"], tool_id.as_str());
+        assert_eq!(ended["exit_code"], 0);
+    }
+
+    #[test]
+    fn provider_config_reads_env_file() {
+        let dir = tempdir().expect("tmp");
+        let path = dir.path().join("providers.json");
+        std::fs::write(
+            &path,
+            r#"{"model":"local/llama3","routes":[{"prefix":"local/","strip_prefix":true,"base_url":"http://127.0.0.1:1/v1"}]}"#,
+        )
+        .expect("write");
+        let loaded = ConfigLoader::new()
+            .env([("RIP_PROVIDER_CONFIG", path.to_string_lossy().into_owned())])
+            .load()
+            .expect("config");
+
+        let config = provider_config(loaded.config.provider.config.as_deref().expect("path"))
+            .expect("provider config");
+        assert_eq!(config.model.as_deref(), Some("local/llama3"));
+        assert_eq!(config.routes.len(), 1);
+        assert!(config.routes[0].strip_prefix);
+    }
+
+    #[test]
+    fn malformed_provider_config_is_an_error() {
+        let dir = tempdir().expect("tmp");
+        let path = dir.path().join("providers.json");
+        std::fs::write(&path, "{not json").expect("write");
+        let err = provider_config(&path).expect_err("parse error");
+        assert!(err.starts_with("provider.config "), "{err}");
+
+        let mut config = RipConfig::default();
+        config.provider.config = Some(dir.path().join("missing.json"));
+        let err = build_runtime(&config, &ToolControls::default())
+            .err()
+            .expect("read error");
+        assert!(err.contains("missing.json"), "{err}");
+    }
+
+    #[test]
+    fn unknown_sandbox_profile_is_a_config_error() {
+        assert_eq!(sandbox_profile(Some("off")), Ok(None));
+        assert!(matches!(
+            sandbox_profile(Some("workspace-write")),
+            Ok(Some(_))
+        ));
+
+        let mut config = RipConfig::default();
+        config.tools.sandbox = Some("nope".to_string());
+        let err = build_runtime(&config, &ToolControls::default())
+            .err()
+            .expect("unknown profile");
+        assert_eq!(err, "tools.sandbox: unknown sandbox profile `nope`");
+    }
+
+    #[test]
+    fn data_dir_prefers_env_var() {
+        let dir = tempdir().expect("tmp");
+        let project = dir.path().join(".rip").join("config.toml");
+        std::fs::create_dir_all(project.parent().expect("parent")).expect("mkdir");
+        std::fs::write(&project, "data_dir = \"from-project\"\n").expect("write");
+        let loader = ConfigLoader::new()
+            .project_file(&project)
+            .trust_project(true);
+        let value = loader.clone().load().expect("config").config.data_dir;
+        assert_eq!(value, PathBuf::from("from-project"));
+
+        let env = [("RIP_DATA_DIR", dir.path().to_string_lossy().into_owned())];
+        let value = loader.env(env).load().expect("config").config.data_dir;
+        assert_eq!(value, dir.path());
+    }
+
+    #[test]
+    fn flags_override_config_layers() {
+        let dir = tempdir().expect("tmp");
+        let home = dir.path().join("home");
+        std::fs::create_dir_all(home.join(".config/rip")).expect("mkdir");
+        std::fs::write(
+            home.join(".config/rip/config.toml"),
+            "[server]\nbind = \"127.0.0.1:1\"\n\n[tools]\nconcurrency = 8\nmax_bytes = 1024\n",
+        )
+        .expect("write");
+        let env = [("HOME".to_string(), home.to_string_lossy().into_owned())];
+        let args = Args::parse_from([
+            "ripd",
+            "--bind",
+            "0.0.0.0:9000",
+            "--set",
+            "tools.concurrency=2",
+            "--set",
+            "permissions.deny=[\"bash(rm *)\"]",
+        ]);
+        let loaded = load_config(&args, dir.path(), env).expect("config");
+        assert_eq!(loaded.config.tools.max_bytes, Some(1024));
+        assert_eq!(loaded.config.server.bind, "0.0.0.0:9000");
+        assert_eq!(loaded.config.tools.concurrency, 2);
+        assert_eq!(loaded.config.permissions.deny, vec!["bash(rm *)"]);
+        assert_eq!(loaded.source("server.bind").to_string(), "flag --bind");
+        assert_eq!(loaded.source("tools.concurrency").to_string(), "flag --set");
+        assert!(tool_permissions(&loaded.config.permissions).is_ok());
+    }
+
+    #[test]
+    fn project_config_comes_from_the_workspace_root() {
+        let dir = tempdir().expect("tmp");
+        let workspace = dir.path().join("workspace");
+        std::fs::create_dir_all(workspace.join(".rip")).expect("mkdir");
+        std::fs::write(
+            workspace.join(".rip/config.toml"),
+            "model = \"from-workspace\"\n\n[tools]\nsandbox = \"off\"\n",
+        )
+        .expect("write");
+        std::fs::create_dir_all(dir.path().join(".rip")).expect("mkdir");
+        std::fs::write(
+            dir.path().join(".rip/config.toml"),
+            "model = \"from-cwd\"\n",
+        )
+        .expect("write");
+        let env = || [("RIP_SANDBOX".to_string(), "workspace-write".to_string())];
+
+        let args = Args::parse_from(["ripd", "--workspace-root", "workspace"]);
+        let loaded = load_config(&args, dir.path(), env()).expect("config");
+        assert_eq!(loaded.config.model.as_deref(), Some("from-workspace"));
+        assert_eq!(
+            loaded.config.tools.sandbox.as_deref(),
+            Some("workspace-write")
+        );
+        assert!(loaded.ignored().contains_key("tools.sandbox"));
+
+        let args = Args::parse_from(["ripd"]);
+        let loaded = load_config(&args, dir.path(), env()).expect("config");
+        assert_eq!(loaded.config.model.as_deref(), Some("from-cwd"));
+
+        let args = Args::parse_from(["ripd", "--workspace-root", "workspace", "--trust-project"]);
+        let loaded = load_config(&args, dir.path(), []).expect("config");
+        assert_eq!(loaded.config.tools.sandbox.as_deref(), Some("off"));
     }
 }